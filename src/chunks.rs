@@ -0,0 +1,228 @@
+//! Spatially sorted layout: vertices grouped into chunks, recorded in an extra `chunk` element.
+//!
+//! `sort_into_chunks()` reorders the vertices of a `Ply` so that nearby vertices are stored together
+//! and adds one `chunk` entry per group, holding its bounding box and range of vertex entries.
+//! The result is a plain PLY file, readers unaware of the convention simply see another element.
+//! Written in a binary encoding, `parser::IndexedReader::query_box()` answers box queries
+//! by reading only the chunks overlapping the box, requires the `pread` feature.
+
+use std::cmp::Ordering;
+use std::mem;
+use std::ops::Range;
+
+use faces;
+use faces::VERTEX_ELEMENT;
+use ply::{ Ply, DefaultElement, ElementDef, PropertyDef, PropertyType, ScalarType, Property, PropertyAccess, ConsistencyError, Addable };
+
+/// Name of the element recording the chunks.
+pub const CHUNK_ELEMENT: &'static str = "chunk";
+/// Properties of a chunk entry holding its range of vertex entries.
+const RANGE_PROPERTIES: [&'static str; 2] = ["first", "count"];
+/// Properties of a chunk entry holding its bounding box.
+const BOUNDS_PROPERTIES: [&'static str; 6] = ["min_x", "min_y", "min_z", "max_x", "max_y", "max_z"];
+
+/// Consecutive vertex entries and their bounding box, see `sort_into_chunks()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// Index of the first vertex.
+    pub first: u64,
+    /// Number of vertices.
+    pub count: u64,
+    /// Minimum corner of the bounding box.
+    pub min: [f64; 3],
+    /// Maximum corner of the bounding box.
+    pub max: [f64; 3],
+}
+
+impl Chunk {
+    /// Indices of the vertices in the chunk.
+    pub fn entries(&self) -> Range<u64> {
+        self.first..self.first + self.count
+    }
+    /// True if the bounding box overlaps the box from `min` to `max`, touching counts.
+    pub fn intersects(&self, min: &[f64; 3], max: &[f64; 3]) -> bool {
+        (0..3).all(|i| self.min[i] <= max[i] && min[i] <= self.max[i])
+    }
+    /// Reads a chunk from an entry of the `chunk` element, `None` if a value is missing.
+    pub fn from_entry<E: PropertyAccess>(entry: &E) -> Option<Self> {
+        let mut bounds = [0.0; 6];
+        for (b, name) in bounds.iter_mut().zip(BOUNDS_PROPERTIES.iter()) {
+            *b = match entry.get_double(&name.to_string()) {
+                Some(v) => v,
+                None => return None,
+            };
+        }
+        Some(Chunk {
+            first: match entry.get_uint(&RANGE_PROPERTIES[0].to_string()) { Some(v) => v as u64, None => return None },
+            count: match entry.get_uint(&RANGE_PROPERTIES[1].to_string()) { Some(v) => v as u64, None => return None },
+            min: [bounds[0], bounds[1], bounds[2]],
+            max: [bounds[3], bounds[4], bounds[5]],
+        })
+    }
+    fn to_entry(&self) -> DefaultElement {
+        let mut entry = DefaultElement::new();
+        entry.insert(RANGE_PROPERTIES[0].to_string(), Property::UInt(self.first as u32));
+        entry.insert(RANGE_PROPERTIES[1].to_string(), Property::UInt(self.count as u32));
+        for (name, v) in BOUNDS_PROPERTIES.iter().zip(self.min.iter().chain(self.max.iter())) {
+            entry.insert(name.to_string(), Property::Double(*v));
+        }
+        entry
+    }
+}
+
+/// Sorts the vertices into chunks of at most `max_points` vertices and records them in the `chunk` element.
+///
+/// Vertices are split at the median of the axis with the largest extent until every chunk is small enough,
+/// so each chunk covers a compact region. Faces are remapped to the new vertex order,
+/// an existing `chunk` element is replaced. Coordinates are read with `Ply::positions_f64()`.
+///
+/// Fails without changing anything if `max_points` is zero, the vertices have no position,
+/// or there are more vertices than a `uint` can index.
+pub fn sort_into_chunks(ply: &mut Ply<DefaultElement>, max_points: usize) -> Result<Vec<Chunk>, ConsistencyError> {
+    if max_points == 0 {
+        return Err(ConsistencyError::new("Chunks need room for at least one vertex."));
+    }
+    let positions = try!(ply.positions_f64());
+    if positions.len() as u64 > u32::max_value() as u64 {
+        return Err(ConsistencyError::new(&format!("{} vertices can't be indexed by a `uint`.", positions.len())));
+    }
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    let mut leaves = Vec::new();
+    // Ranges are popped in file order, the lower half of a split is pushed last.
+    let mut pending = vec![0..order.len()];
+    while let Some(range) = pending.pop() {
+        if range.end - range.start <= max_points {
+            leaves.push(range);
+            continue;
+        }
+        let (min, max) = bounds(&positions, &order[range.clone()]);
+        let axis = (0..3).fold(0, |a, i| if max[i] - min[i] > max[a] - min[a] { i } else { a });
+        let mid = (range.end - range.start) / 2;
+        order[range.clone()].select_nth_unstable_by(mid, |&a, &b| compare(positions[a][axis], positions[b][axis]));
+        pending.push(range.start + mid..range.end);
+        pending.push(range.start..range.start + mid);
+    }
+
+    let mut mapping = vec![None; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        mapping[old] = Some(new as u32);
+    }
+    try!(faces::remap_indices(ply, &mapping));
+    if let Some(vertices) = ply.payload.get_mut(VERTEX_ELEMENT) {
+        let mut old: Vec<Option<DefaultElement>> = mem::replace(vertices, Vec::with_capacity(order.len())).into_iter().map(Some).collect();
+        for &i in &order {
+            vertices.push(old[i].take().unwrap());
+        }
+    }
+
+    let chunks: Vec<Chunk> = leaves.into_iter().map(|range| {
+        let (min, max) = bounds(&positions, &order[range.clone()]);
+        Chunk {
+            first: range.start as u64,
+            count: (range.end - range.start) as u64,
+            min: min,
+            max: max,
+        }
+    }).collect();
+    let mut chunk_def = ElementDef::new(CHUNK_ELEMENT.to_string());
+    for name in &RANGE_PROPERTIES {
+        chunk_def.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(ScalarType::UInt)));
+    }
+    for name in &BOUNDS_PROPERTIES {
+        chunk_def.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(ScalarType::Double)));
+    }
+    chunk_def.count = chunks.len() as u64;
+    ply.header.elements.remove(CHUNK_ELEMENT);
+    ply.header.elements.add(chunk_def);
+    ply.payload.remove(CHUNK_ELEMENT);
+    ply.payload.insert(CHUNK_ELEMENT.to_string(), chunks.iter().map(Chunk::to_entry).collect());
+    Ok(chunks)
+}
+
+/// Bounding box of the positions with the given indices, NaN coordinates are ignored.
+fn bounds(positions: &[[f64; 3]], indices: &[usize]) -> ([f64; 3], [f64; 3]) {
+    let mut min = [::std::f64::INFINITY; 3];
+    let mut max = [::std::f64::NEG_INFINITY; 3];
+    for &i in indices {
+        for a in 0..3 {
+            min[a] = min[a].min(positions[i][a]);
+            max[a] = max[a].max(positions[i][a]);
+        }
+    }
+    (min, max)
+}
+
+/// Total order of coordinates, NaNs sort last.
+fn compare(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::{ Ply, DefaultElement, ElementDef, PropertyDef, PropertyType, ScalarType, Property, Addable };
+
+    fn grid_ply(n: usize) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        let mut v = ElementDef::new("vertex".to_string());
+        for name in &["x", "y", "z"] {
+            v.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(ScalarType::Float)));
+        }
+        ply.header.elements.add(v);
+        let mut f = ElementDef::new("face".to_string());
+        f.properties.add(PropertyDef::new("vertex_indices".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::UInt)));
+        ply.header.elements.add(f);
+        let mut vertices = Vec::new();
+        for i in 0..n * n {
+            let mut e = DefaultElement::new();
+            // Interleaved, so the file order isn't spatial already.
+            let (a, b) = (i % n, (i * 7) % n);
+            e.insert("x".to_string(), Property::Float(a as f32));
+            e.insert("y".to_string(), Property::Float(b as f32));
+            e.insert("z".to_string(), Property::Float((a + b) as f32 * 0.5));
+            vertices.push(e);
+        }
+        let faces = (0..n * n - 2).map(|i| {
+            let mut e = DefaultElement::new();
+            e.insert("vertex_indices".to_string(), Property::ListUInt(vec![i as u32, i as u32 + 1, i as u32 + 2]));
+            e
+        }).collect();
+        ply.payload.insert("vertex".to_string(), vertices);
+        ply.payload.insert("face".to_string(), faces);
+        ply.make_consistent().unwrap();
+        ply
+    }
+
+    fn face_positions(ply: &Ply<DefaultElement>) -> Vec<Vec<[f64; 3]>> {
+        let positions = ply.positions_f64().unwrap();
+        ply.column::<Vec<u32>>("face", "vertex_indices").unwrap().iter()
+            .map(|f| f.iter().map(|&i| positions[i as usize]).collect())
+            .collect()
+    }
+
+    #[test]
+    fn chunks_cover_sorted_vertices() {
+        let mut ply = grid_ply(10);
+        let faces_before = face_positions(&ply);
+        let chunks = sort_into_chunks(&mut ply, 16).unwrap();
+        assert_eq!(face_positions(&ply), faces_before);
+        assert_eq!(ply.header.elements[CHUNK_ELEMENT].count, chunks.len() as u64);
+        assert!(chunks.iter().all(|c| c.count > 0 && c.count <= 16));
+        assert_eq!(chunks.iter().map(|c| c.count).sum::<u64>(), 100);
+        let positions = ply.positions_f64().unwrap();
+        for (c, entry) in chunks.iter().zip(&ply.payload[CHUNK_ELEMENT]) {
+            assert_eq!(Chunk::from_entry(entry).as_ref(), Some(c));
+            for p in &positions[c.first as usize..(c.first + c.count) as usize] {
+                assert!(c.intersects(p, p));
+            }
+        }
+        let next = chunks.iter().skip(1);
+        assert!(chunks.iter().zip(next).all(|(a, b)| a.first + a.count == b.first));
+
+        let again = sort_into_chunks(&mut ply, 50).unwrap();
+        assert_eq!(again.len(), 2);
+        assert_eq!(ply.header.elements.keys().collect::<Vec<_>>(), vec!["vertex", "face", "chunk"]);
+        assert!(sort_into_chunks(&mut ply, 0).is_err());
+        assert!(sort_into_chunks(&mut Ply::new(), 10).is_err());
+    }
+}
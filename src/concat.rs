@@ -0,0 +1,173 @@
+//! Joins files of the same schema, for example the tiles of a large scan.
+
+use std::io;
+use std::io::{ BufReader, Write, Seek, SeekFrom, Result, ErrorKind };
+use std::fs::File;
+use std::path::Path;
+
+use faces::{ VERTEX_ELEMENT, FACE_ELEMENT, INDEX_PROPERTIES };
+use parser::Parser;
+use raw_payload::{ pass_entries, pass_element };
+use sequence::same_schema;
+use writer::Writer;
+use ply::{ Header, DefaultElement, ElementDef, Encoding, Error as PlyError };
+
+/// Concatenates the binary files at `paths` into one, copying their payloads without decoding them.
+///
+/// All files must share encoding, elements and properties, see `sequence::same_schema()`.
+/// The header of the first file is written with the summed element counts.
+/// Faces are the only entries that need to change:
+/// their vertex indices are shifted by the number of vertices in the preceding files.
+/// This makes merging tiles of a large scan mostly a matter of copying bytes.
+///
+/// Since the output holds the entries of each element from all files before the next element starts,
+/// every file is opened once per element.
+/// Elements with fixed size entries are skipped by seeking, elements with lists have to be scanned once
+/// to locate the elements following them.
+/// Pass a buffered `out` for good performance.
+///
+/// Returns number of bytes written.
+pub fn binary<P: AsRef<Path>, W: Write>(paths: &[P], out: &mut W) -> Result<u64> {
+    let parser = Parser::<DefaultElement>::new();
+    let mut header: Option<Header> = None;
+    let mut buffer = Vec::new();
+    // Start position and definition of every element, per file.
+    let mut layouts = Vec::with_capacity(paths.len());
+    for path in paths {
+        let (file_header, layout) = try!(locate_elements(&parser, path.as_ref(), &mut buffer).map_err(|e| PlyError::in_file(e, path)));
+        match header {
+            None => header = Some(file_header.clone()),
+            Some(ref mut h) => {
+                if !same_schema(h, &file_header) {
+                    return Err(PlyError::in_file(io::Error::new(ErrorKind::InvalidInput, "Schema differs from the first file."), path));
+                }
+                for (name, element_def) in h.elements.iter_mut() {
+                    element_def.count = match element_def.count.checked_add(file_header.elements[name].count) {
+                        Some(c) => c,
+                        None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` has too many entries.", name))),
+                    };
+                }
+            },
+        }
+        layouts.push(layout);
+    }
+    let header = match header {
+        Some(h) => h,
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, "No files to concatenate.")),
+    };
+    let big_endian = header.encoding == Encoding::BinaryBigEndian;
+    let index_property = header.elements.get(FACE_ELEMENT)
+        .and_then(|f| INDEX_PROPERTIES.iter().find(|p| f.properties.contains_key(**p)))
+        .map(|p| *p);
+    let mut written = try!(Writer::<DefaultElement>::new().write_header(out, &header)) as u64;
+    for (i, name) in header.elements.keys().enumerate() {
+        let mut vertex_offset = 0i64;
+        for (path, layout) in paths.iter().zip(&layouts) {
+            let (start, ref element_def) = layout[i];
+            let copied = File::open(path).and_then(|file| {
+                let mut reader = BufReader::new(file);
+                try!(reader.seek(SeekFrom::Start(start)));
+                match index_property {
+                    Some(property) if name == FACE_ELEMENT && vertex_offset != 0 =>
+                        pass_entries(&mut reader, out, element_def, big_endian, false, Some((property, vertex_offset)), parser.max_list_len(), &mut buffer),
+                    _ => pass_element(&mut reader, out, element_def, &header.encoding, parser.max_list_len(), &mut buffer),
+                }
+            });
+            written += try!(copied.map_err(|e| PlyError::in_file(e, path)));
+            if let Some(&(_, ref vertex_def)) = layout.iter().find(|&&(_, ref d)| d.name == VERTEX_ELEMENT) {
+                vertex_offset += vertex_def.count as i64;
+            }
+        }
+    }
+    try!(out.flush());
+    Ok(written)
+}
+
+/// Reads the header of the binary file at `path` and the start position and definition of each of its elements.
+fn locate_elements(parser: &Parser<DefaultElement>, path: &Path, buffer: &mut Vec<u8>) -> Result<(Header, Vec<(u64, ElementDef)>)> {
+    let mut reader = BufReader::new(try!(File::open(path)));
+    let header = try!(parser.read_header(&mut reader));
+    let big_endian = match header.encoding {
+        Encoding::BinaryBigEndian => true,
+        Encoding::BinaryLittleEndian => false,
+        Encoding::Ascii => return Err(io::Error::new(ErrorKind::InvalidInput, "File is ascii, only binary files can be concatenated.")),
+    };
+    let mut layout = Vec::with_capacity(header.elements.len());
+    for (i, element_def) in header.elements.values().enumerate() {
+        layout.push((try!(reader.seek(SeekFrom::Current(0))), element_def.clone()));
+        if i + 1 == header.elements.len() {
+            break;
+        }
+        let sizes: Option<Vec<usize>> = element_def.properties.values().map(|p| p.data_type.fixed_record_size()).collect();
+        match sizes.and_then(|s| element_def.count.checked_mul(s.iter().sum::<usize>() as u64)) {
+            Some(size) if size <= i64::max_value() as u64 => { try!(reader.seek(SeekFrom::Current(size as i64))); },
+            _ => { try!(pass_entries(&mut reader, &mut io::sink(), element_def, big_endian, false, None, parser.max_list_len(), buffer)); },
+        }
+    }
+    Ok((header, layout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+    fn ply() -> Ply<DefaultElement> {
+        let mut p = Ply::<DefaultElement>::new();
+        p.header.encoding = Encoding::BinaryBigEndian;
+        let mut v = ElementDef::new("vertex".to_string());
+        v.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        v.properties.add(PropertyDef::new("c".to_string(), PropertyType::Scalar(ScalarType::UChar)));
+        p.header.elements.add(v);
+        let mut f = ElementDef::new("face".to_string());
+        f.properties.add(PropertyDef::new("i".to_string(), PropertyType::List(ScalarType::Short, ScalarType::Int)));
+        f.properties.add(PropertyDef::new("d".to_string(), PropertyType::Scalar(ScalarType::Double)));
+        p.header.elements.add(f);
+        let vertices = (0..5000).map(|i| {
+            let mut e = DefaultElement::new();
+            e.insert("x".to_string(), Property::Float(i as f32 * 0.5));
+            e.insert("c".to_string(), Property::UChar(i as u8));
+            e
+        }).collect();
+        p.payload.insert("vertex".to_string(), vertices);
+        let mut face = DefaultElement::new();
+        face.insert("i".to_string(), Property::ListInt(vec![1, -2, 300]));
+        face.insert("d".to_string(), Property::Double(-1.25));
+        p.payload.insert("face".to_string(), vec![face.clone(), face]);
+        p
+    }
+    #[test]
+    fn concat_shifts_faces() {
+        let mut p = ply();
+        p.header.elements.get_mut("face").unwrap().properties.add(
+            PropertyDef::new("vertex_indices".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::UInt))
+        );
+        for f in p.payload.get_mut("face").unwrap() {
+            f.insert("i".to_string(), Property::ListInt(vec![7]));
+            f.insert("vertex_indices".to_string(), Property::ListUInt(vec![0, 1, 4999]));
+        }
+        let paths: Vec<_> = (0..3).map(|i| env::temp_dir().join(format!("ply_rs_concat_{}_{}.ply", process::id(), i))).collect();
+        for path in &paths {
+            Writer::new().write_ply(&mut File::create(path).unwrap(), &mut p).unwrap();
+        }
+        let mut out = Vec::new();
+        let written = binary(&paths, &mut out).unwrap();
+        assert_eq!(written, out.len() as u64);
+        let c = Parser::<DefaultElement>::new().read_ply(&mut &out[..]).unwrap();
+        assert_eq!(c.header.elements["vertex"].count, 15000);
+        assert_eq!(c.payload["vertex"][5000], p.payload["vertex"][0]);
+        assert_eq!(c.payload["face"].len(), 6);
+        assert_eq!(c.payload["face"][0]["vertex_indices"], Property::ListUInt(vec![0, 1, 4999]));
+        assert_eq!(c.payload["face"][5]["vertex_indices"], Property::ListUInt(vec![10000, 10001, 14999]));
+        assert_eq!(c.payload["face"][5]["i"], Property::ListInt(vec![7]));
+
+        p.header.encoding = Encoding::Ascii;
+        Writer::new().write_ply(&mut File::create(&paths[2]).unwrap(), &mut p).unwrap();
+        assert!(binary(&paths, &mut Vec::new()).is_err());
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
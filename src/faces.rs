@@ -0,0 +1,338 @@
+//! Utilities for the vertex indices stored in the `face` element.
+//!
+//! Follows the common convention of a `vertex` element and a `face` element
+//! with a list property named `vertex_indices` (or `vertex_index`) pointing into the vertices.
+
+use ply::{ Ply, PropertyAccess, PropertyDef, PropertyType, ScalarType, Property, ConsistencyError };
+
+/// Name of the element holding the vertices.
+pub const VERTEX_ELEMENT: &'static str = "vertex";
+/// Name of the element holding the faces.
+pub const FACE_ELEMENT: &'static str = "face";
+/// Accepted names of the face property holding the vertex indices.
+pub const INDEX_PROPERTIES: [&'static str; 2] = ["vertex_indices", "vertex_index"];
+
+/// Checks that every face only references existing vertices.
+///
+/// A `Ply` without a face element is valid.
+/// The error names the first face and list position with an invalid index.
+pub fn validate_indices<E: PropertyAccess>(ply: &Ply<E>) -> Result<(), ConsistencyError> {
+    let prop = match try!(index_property(ply)) {
+        None => return Ok(()),
+        Some(p) => p,
+    };
+    let vertex_count = vertex_count(ply);
+    let faces = match ply.payload.get(FACE_ELEMENT) {
+        None => return Ok(()),
+        Some(f) => f,
+    };
+    for (fi, face) in faces.iter().enumerate() {
+        let indices = try!(get_indices(face, prop, fi));
+        for (pos, &i) in indices.iter().enumerate() {
+            if i < 0 || i as u64 >= vertex_count {
+                return Err(ConsistencyError::new(&format!(
+                    "Face {} references vertex {} at position {}, but there are only {} vertices.",
+                    fi, i, pos, vertex_count)));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the vertex indices of all faces: Index `i` becomes `mapping[i]`.
+///
+/// Use this after vertices have been sorted or filtered,
+/// `None` marks a vertex that was removed.
+/// Faces referencing a removed vertex are removed as well, and the face count of the header is updated.
+/// Every referenced index must be covered by `mapping`, every new index must point
+/// into the current vertex element and fit into the scalar type of the index list,
+/// otherwise an error is returned and no face is changed.
+///
+/// Returns the number of removed faces.
+pub fn remap_indices<E: PropertyAccess>(ply: &mut Ply<E>, mapping: &[Option<u32>]) -> Result<usize, ConsistencyError> {
+    let prop = match try!(index_property(ply)) {
+        None => return Ok(0),
+        Some(p) => p.clone(),
+    };
+    let scalar_type = match prop.data_type {
+        PropertyType::List(_, ref t) => t.clone(),
+        PropertyType::Scalar(_) => unreachable!(),
+    };
+    let vertex_count = vertex_count(ply);
+    let mut remapped = Vec::new();
+    if let Some(faces) = ply.payload.get(FACE_ELEMENT) {
+        remapped.reserve(faces.len());
+        'faces: for (fi, face) in faces.iter().enumerate() {
+            let indices = try!(get_indices(face, &prop, fi));
+            let mut new_indices = Vec::with_capacity(indices.len());
+            for (pos, &i) in indices.iter().enumerate() {
+                if i < 0 || i as u64 >= mapping.len() as u64 {
+                    return Err(ConsistencyError::new(&format!(
+                        "Face {} references vertex {} at position {}, which is not covered by the mapping of length {}.",
+                        fi, i, pos, mapping.len())));
+                }
+                let n = match mapping[i as usize] {
+                    None => {
+                        remapped.push(None);
+                        continue 'faces;
+                    },
+                    Some(n) => n,
+                };
+                if n as u64 >= vertex_count {
+                    return Err(ConsistencyError::new(&format!(
+                        "Vertex {} of face {} is mapped to {}, but there are only {} vertices.",
+                        i, fi, n, vertex_count)));
+                }
+                new_indices.push(n as i64);
+            }
+            remapped.push(Some(try!(make_list(&scalar_type, new_indices, fi))));
+        }
+    }
+    let mut removed = 0;
+    if let Some(faces) = ply.payload.get_mut(FACE_ELEMENT) {
+        let old_faces = ::std::mem::replace(faces, Vec::with_capacity(remapped.len()));
+        for (mut face, list) in old_faces.into_iter().zip(remapped.into_iter()) {
+            match list {
+                Some(list) => {
+                    face.set_property(prop.name.clone(), list);
+                    faces.push(face);
+                },
+                None => removed += 1,
+            }
+        }
+        ply.header.elements.get_mut(FACE_ELEMENT).unwrap().count = faces.len() as u64;
+    }
+    Ok(removed)
+}
+
+/// Converts the vertex indices of all faces to `scalar_type`, e.g. to `ushort` or `uint` as used by GPU index buffers.
+///
+/// Header and payload are changed together, the list's length type is kept.
+/// Every index must be representable in `scalar_type`,
+/// otherwise an error names the first index out of range and no face is changed.
+pub fn convert_indices<E: PropertyAccess>(ply: &mut Ply<E>, scalar_type: ScalarType) -> Result<(), ConsistencyError> {
+    if !scalar_type.is_integer() {
+        return Err(ConsistencyError::new(&format!("Vertex indices can't be converted to {}.", scalar_type)));
+    }
+    let prop = match try!(index_property(ply)) {
+        None => return Ok(()),
+        Some(p) => p.clone(),
+    };
+    let mut converted = Vec::new();
+    if let Some(faces) = ply.payload.get(FACE_ELEMENT) {
+        converted.reserve(faces.len());
+        for (fi, face) in faces.iter().enumerate() {
+            let indices = try!(get_indices(face, &prop, fi));
+            converted.push(try!(make_list(&scalar_type, indices, fi)));
+        }
+    }
+    if let Some(faces) = ply.payload.get_mut(FACE_ELEMENT) {
+        for (face, list) in faces.iter_mut().zip(converted.into_iter()) {
+            face.set_property(prop.name.clone(), list);
+        }
+    }
+    let face_def = ply.header.elements.get_mut(FACE_ELEMENT).unwrap();
+    let property_def = face_def.properties.get_mut(&prop.name).unwrap();
+    property_def.data_type = match prop.data_type {
+        PropertyType::List(index_type, _) => PropertyType::List(index_type, scalar_type),
+        PropertyType::Scalar(_) => unreachable!(),
+    };
+    Ok(())
+}
+
+/// Converts the vertex indices of all faces to the smallest of `ushort` and `uint` holding the largest index.
+///
+/// Meant to be called before writing, to keep files small and ready for GPU index buffers.
+/// Fails on negative indices and if an index exceeds `uint`.
+/// Returns the chosen type.
+pub fn narrow_indices<E: PropertyAccess>(ply: &mut Ply<E>) -> Result<ScalarType, ConsistencyError> {
+    let prop = match try!(index_property(ply)) {
+        None => return Ok(ScalarType::UShort),
+        Some(p) => p.clone(),
+    };
+    let mut max = 0;
+    if let Some(faces) = ply.payload.get(FACE_ELEMENT) {
+        for (fi, face) in faces.iter().enumerate() {
+            for (pos, i) in try!(get_indices(face, &prop, fi)).into_iter().enumerate() {
+                if i < 0 {
+                    return Err(ConsistencyError::new(&format!("Face {} has the negative index {} at position {}.", fi, i, pos)));
+                }
+                if i > max {
+                    max = i;
+                }
+            }
+        }
+    }
+    let scalar_type = if max <= u16::max_value() as i64 { ScalarType::UShort } else { ScalarType::UInt };
+    try!(convert_indices(ply, scalar_type.clone()));
+    Ok(scalar_type)
+}
+
+fn vertex_count<E: PropertyAccess>(ply: &Ply<E>) -> u64 {
+    match ply.payload.get(VERTEX_ELEMENT) {
+        Some(v) => v.len() as u64,
+        None => ply.header.elements.get(VERTEX_ELEMENT).map(|e| e.count).unwrap_or(0),
+    }
+}
+
+fn index_property<E: PropertyAccess>(ply: &Ply<E>) -> Result<Option<&PropertyDef>, ConsistencyError> {
+    let face = match ply.header.elements.get(FACE_ELEMENT) {
+        None => return Ok(None),
+        Some(f) => f,
+    };
+    for name in INDEX_PROPERTIES.iter() {
+        if let Some(p) = face.properties.get(*name) {
+            return match p.data_type {
+                PropertyType::List(_, ScalarType::Float) | PropertyType::List(_, ScalarType::Double) | PropertyType::Scalar(_) =>
+                    Err(ConsistencyError::new(&format!("Property `{}` of `{}` must be a list of integers.", name, FACE_ELEMENT))),
+                PropertyType::List(_, _) => Ok(Some(p)),
+            };
+        }
+    }
+    Err(ConsistencyError::new(&format!("Element `{}` has no vertex index property.", FACE_ELEMENT)))
+}
+
+fn get_indices<E: PropertyAccess>(face: &E, prop: &PropertyDef, face_index: usize) -> Result<Vec<i64>, ConsistencyError> {
+    fn widen<T: Copy + Into<i64>>(list: Option<&[T]>) -> Option<Vec<i64>> {
+        list.map(|l| l.iter().map(|&v| v.into()).collect())
+    }
+    let k = &prop.name;
+    let indices = match prop.data_type {
+        PropertyType::List(_, ScalarType::Char) => widen(face.get_list_char(k)),
+        PropertyType::List(_, ScalarType::UChar) => widen(face.get_list_uchar(k)),
+        PropertyType::List(_, ScalarType::Short) => widen(face.get_list_short(k)),
+        PropertyType::List(_, ScalarType::UShort) => widen(face.get_list_ushort(k)),
+        PropertyType::List(_, ScalarType::Int) => widen(face.get_list_int(k)),
+        PropertyType::List(_, ScalarType::UInt) => widen(face.get_list_uint(k)),
+        _ => None,
+    };
+    match indices {
+        Some(i) => Ok(i),
+        None => Err(ConsistencyError::new(&format!("Face {} has no property `{}` of type {:?}.", face_index, k, prop.data_type))),
+    }
+}
+
+fn make_list(scalar_type: &ScalarType, indices: Vec<i64>, face_index: usize) -> Result<Property, ConsistencyError> {
+    macro_rules! narrow(
+        ($t:ty, $variant:ident) => ({
+            let mut list = Vec::with_capacity(indices.len());
+            for &i in &indices {
+                let v = i as $t;
+                if v as i64 != i {
+                    return Err(ConsistencyError::new(&format!(
+                        "Index {} of face {} does not fit into the list type {:?}.", i, face_index, scalar_type)));
+                }
+                list.push(v);
+            }
+            Property::$variant(list)
+        })
+    );
+    Ok(match *scalar_type {
+        ScalarType::Char => narrow!(i8, ListChar),
+        ScalarType::UChar => narrow!(u8, ListUChar),
+        ScalarType::Short => narrow!(i16, ListShort),
+        ScalarType::UShort => narrow!(u16, ListUShort),
+        ScalarType::Int => narrow!(i32, ListInt),
+        ScalarType::UInt => narrow!(u32, ListUInt),
+        ScalarType::Float | ScalarType::Double => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    type P = Ply<DefaultElement>;
+
+    fn create_ply(index_type: ScalarType, faces: Vec<Property>) -> P {
+        let mut p = P::new();
+        let mut v = ElementDef::new("vertex".to_string());
+        v.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        p.header.elements.add(v);
+        let mut f = ElementDef::new("face".to_string());
+        f.properties.add(PropertyDef::new("vertex_indices".to_string(), PropertyType::List(ScalarType::UChar, index_type)));
+        p.header.elements.add(f);
+        let mut vertices = Vec::new();
+        for i in 0..4 {
+            let mut e = DefaultElement::new();
+            e.insert("x".to_string(), Property::Float(i as f32));
+            vertices.push(e);
+        }
+        p.payload.insert("vertex".to_string(), vertices);
+        let faces = faces.into_iter().map(|l| {
+            let mut e = DefaultElement::new();
+            e.insert("vertex_indices".to_string(), l);
+            e
+        }).collect();
+        p.payload.insert("face".to_string(), faces);
+        p.make_consistent().unwrap();
+        p
+    }
+    #[test]
+    fn validate_ok() {
+        let p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, 1, 2]), Property::ListInt(vec![3, 2, 1])]);
+        assert!(validate_indices(&p).is_ok());
+    }
+    #[test]
+    fn validate_fail() {
+        let p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, 1, 4])]);
+        assert!(validate_indices(&p).is_err());
+        let p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, -1, 2])]);
+        assert!(validate_indices(&p).is_err());
+    }
+    #[test]
+    fn remap_ok() {
+        let mut p = create_ply(ScalarType::UShort, vec![Property::ListUShort(vec![0, 1, 2]), Property::ListUShort(vec![3, 2, 1])]);
+        assert_eq!(remap_indices(&mut p, &[Some(3), Some(2), Some(1), Some(0)]).unwrap(), 0);
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListUShort(vec![3, 2, 1]));
+        assert_eq!(p.payload["face"][1]["vertex_indices"], Property::ListUShort(vec![0, 1, 2]));
+    }
+    #[test]
+    fn remap_removed_vertex() {
+        let mut p = create_ply(ScalarType::UShort, vec![Property::ListUShort(vec![0, 1, 2]), Property::ListUShort(vec![3, 2, 1])]);
+        p.payload.get_mut("vertex").unwrap().remove(0);
+        assert_eq!(remap_indices(&mut p, &[None, Some(0), Some(1), Some(2)]).unwrap(), 1);
+        assert_eq!(p.payload["face"].len(), 1);
+        assert_eq!(p.header.elements["face"].count, 1);
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListUShort(vec![2, 1, 0]));
+        assert!(validate_indices(&p).is_ok());
+    }
+    #[test]
+    fn convert_ok() {
+        let mut p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, 1, 3])]);
+        assert_eq!(narrow_indices(&mut p).unwrap(), ScalarType::UShort);
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListUShort(vec![0, 1, 3]));
+        assert_eq!(p.header.elements["face"].properties["vertex_indices"].data_type, PropertyType::List(ScalarType::UChar, ScalarType::UShort));
+        convert_indices(&mut p, ScalarType::UInt).unwrap();
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListUInt(vec![0, 1, 3]));
+        assert!(p.make_consistent().is_ok());
+        let mut p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, 70000, 3])]);
+        assert_eq!(narrow_indices(&mut p).unwrap(), ScalarType::UInt);
+    }
+    #[test]
+    fn convert_fail() {
+        let mut p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, -1, 3])]);
+        assert!(narrow_indices(&mut p).is_err());
+        assert!(convert_indices(&mut p, ScalarType::UShort).is_err());
+        assert!(convert_indices(&mut p, ScalarType::Float).is_err());
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListInt(vec![0, -1, 3]));
+        assert_eq!(p.header.elements["face"].properties["vertex_indices"].data_type, PropertyType::List(ScalarType::UChar, ScalarType::Int));
+    }
+    #[test]
+    fn remap_overflow_fail() {
+        let mut p = create_ply(ScalarType::Char, vec![Property::ListChar(vec![0, 1, 2])]);
+        for _ in 0..197 {
+            let v = p.payload["vertex"][0].clone();
+            p.payload.get_mut("vertex").unwrap().push(v);
+        }
+        assert!(remap_indices(&mut p, &[Some(0), Some(1), Some(200)]).is_err());
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListChar(vec![0, 1, 2]));
+        assert!(remap_indices(&mut p, &[Some(0), Some(1)]).is_err());
+    }
+    #[test]
+    fn remap_out_of_range_fail() {
+        let mut p = create_ply(ScalarType::Int, vec![Property::ListInt(vec![0, 1, 2])]);
+        assert!(remap_indices(&mut p, &[Some(0), Some(1), Some(4)]).is_err());
+        assert_eq!(p.payload["face"][0]["vertex_indices"], Property::ListInt(vec![0, 1, 2]));
+    }
+}
@@ -28,8 +28,44 @@
 
 extern crate linked_hash_map;
 extern crate byteorder;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "pod")]
+extern crate bytemuck;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "codecs")]
+extern crate lz4_flex;
+#[cfg(all(test, feature = "serde"))]
+#[macro_use]
+extern crate serde_derive;
 pub mod parser;
 pub mod ply;
 pub mod writer;
+pub mod faces;
+pub mod redact;
+pub mod merge;
+pub mod recover;
+pub mod sequence;
+pub mod metadata;
+pub mod transcode;
+pub mod concat;
+pub mod migrate;
+pub mod validate;
+pub mod patchfile;
+pub mod pipeline;
+pub mod shuffle;
+pub mod stats;
+pub mod chunks;
+#[cfg(feature = "pod")]
+pub mod pod;
+#[cfg(feature = "serde")]
+pub mod serde_element;
 
 mod util;
+mod raw_payload;
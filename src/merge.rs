@@ -0,0 +1,191 @@
+//! Combines several `Ply` into one, for example the files of a dataset.
+
+use ply::{ Ply, DefaultElement, ElementDef, PropertyDef, Property, ConsistencyError, Addable };
+use faces::{ VERTEX_ELEMENT, FACE_ELEMENT, INDEX_PROPERTIES };
+
+/// Concatenates the payloads of all `plys` under a common superset schema.
+///
+/// Elements and properties are taken in the order they first appear.
+/// Entries lacking a property of the combined schema get the value of `PropertyType::filler()`.
+/// Encoding and version are those of the first `Ply`, comments and object informations
+/// are collected without duplicates.
+///
+/// The vertex indices of faces, see the `faces` module, are shifted by the number of vertices
+/// of the preceding files, so every face keeps pointing at its own vertices.
+///
+/// Fails if a property is declared with different types in different files,
+/// or if a shifted vertex index doesn't fit into the type of the index list.
+pub fn union(plys: &[Ply<DefaultElement>]) -> Result<Ply<DefaultElement>, ConsistencyError> {
+    let mut merged = Ply::<DefaultElement>::new();
+    if let Some(first) = plys.first() {
+        merged.header.encoding = first.header.encoding;
+        merged.header.version = first.header.version;
+    }
+    for ply in plys {
+        for c in &ply.header.comments {
+            if !merged.header.comments.contains(c) {
+                merged.header.comments.push(c.clone());
+            }
+        }
+        for o in &ply.header.obj_infos {
+            if !merged.header.obj_infos.contains(o) {
+                merged.header.obj_infos.push(o.clone());
+            }
+        }
+        for (name, element_def) in &ply.header.elements {
+            if !merged.header.elements.contains_key(name) {
+                merged.header.elements.add(ElementDef::new(name.clone()));
+            }
+            let merged_def = merged.header.elements.get_mut(name).unwrap();
+            for (property_name, property_def) in &element_def.properties {
+                match merged_def.properties.get(property_name) {
+                    None => (),
+                    Some(existing) if existing.data_type == property_def.data_type => continue,
+                    Some(existing) => return Err(ConsistencyError::new(&format!(
+                        "Property `{}` of element `{}` is declared as {:?} and as {:?}.",
+                        property_name, name, existing.data_type, property_def.data_type
+                    ))),
+                }
+                merged_def.properties.add(PropertyDef::new(property_name.clone(), property_def.data_type.clone()));
+            }
+        }
+    }
+    for (name, element_def) in merged.header.elements.iter_mut() {
+        let index_property = if name == FACE_ELEMENT {
+            INDEX_PROPERTIES.iter().find(|p| element_def.properties.contains_key(**p))
+        } else {
+            None
+        };
+        let mut list = Vec::new();
+        let mut vertex_offset = 0u64;
+        for ply in plys {
+            let offset = vertex_offset;
+            vertex_offset += ply.payload.get(VERTEX_ELEMENT).map_or(0, |v| v.len() as u64);
+            let entries = match ply.payload.get(name) {
+                None => continue,
+                Some(l) => l,
+            };
+            for entry in entries {
+                let mut e = DefaultElement::new();
+                for (property_name, property_def) in &element_def.properties {
+                    let value = match entry.get(property_name) {
+                        Some(v) => v.clone(),
+                        None => property_def.data_type.filler(),
+                    };
+                    e.insert(property_name.clone(), value);
+                }
+                if let Some(p) = index_property {
+                    if offset > 0 {
+                        try!(shift_indices(e.get_mut(*p).unwrap(), offset, list.len()));
+                    }
+                }
+                list.push(e);
+            }
+        }
+        element_def.count = list.len() as u64;
+        merged.payload.insert(name.clone(), list);
+    }
+    Ok(merged)
+}
+
+/// Adds `offset` to every vertex index in `indices`, the list of face `face_index`.
+fn shift_indices(indices: &mut Property, offset: u64, face_index: usize) -> Result<(), ConsistencyError> {
+    macro_rules! shift(
+        ($list:expr, $t:ty) => (for v in $list.iter_mut() {
+            let shifted = *v as i64 + offset as i64;
+            if shifted as $t as i64 != shifted {
+                return Err(ConsistencyError::new(&format!(
+                    "Vertex index {} of face {} does not fit into the index list once shifted by {}.", v, face_index, offset)));
+            }
+            *v = shifted as $t;
+        })
+    );
+    match *indices {
+        Property::ListChar(ref mut l) => shift!(l, i8),
+        Property::ListUChar(ref mut l) => shift!(l, u8),
+        Property::ListShort(ref mut l) => shift!(l, i16),
+        Property::ListUShort(ref mut l) => shift!(l, u16),
+        Property::ListInt(ref mut l) => shift!(l, i32),
+        Property::ListUInt(ref mut l) => shift!(l, u32),
+        _ => return Err(ConsistencyError::new(&format!("Face {} has no list of integer vertex indices.", face_index))),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+
+    fn vertex_ply(properties: &[(&str, Property)]) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        let mut v = ElementDef::new("vertex".to_string());
+        let mut e = DefaultElement::new();
+        for &(name, ref value) in properties {
+            let t = match *value {
+                Property::Float(_) => ScalarType::Float,
+                Property::UChar(_) => ScalarType::UChar,
+                _ => ScalarType::Int,
+            };
+            v.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(t)));
+            e.insert(name.to_string(), value.clone());
+        }
+        ply.header.elements.add(v);
+        ply.payload.insert("vertex".to_string(), vec![e]);
+        ply.make_consistent().unwrap();
+        ply
+    }
+    #[test]
+    fn union_fills_missing_columns() {
+        let a = vertex_ply(&[("x", Property::Float(1.0)), ("intensity", Property::Float(0.5))]);
+        let b = vertex_ply(&[("x", Property::Float(2.0)), ("red", Property::UChar(255))]);
+        let mut m = union(&[a, b]).unwrap();
+        let names: Vec<&String> = m.header.elements["vertex"].properties.keys().collect();
+        assert_eq!(names, vec!["x", "intensity", "red"]);
+        assert_eq!(m.header.elements["vertex"].count, 2);
+        let v = &m.payload["vertex"];
+        assert_eq!(v[0]["red"], Property::UChar(0));
+        assert_eq!(v[1]["x"], Property::Float(2.0));
+        match v[1]["intensity"] {
+            Property::Float(f) => assert!(f.is_nan()),
+            ref p => panic!("unexpected {:?}", p),
+        }
+        assert!(m.make_consistent().is_ok());
+    }
+    fn mesh_ply(vertices: usize, faces: Vec<Vec<u8>>) -> Ply<DefaultElement> {
+        let mut ply = vertex_ply(&[("x", Property::Float(0.0))]);
+        let vertex = ply.payload["vertex"][0].clone();
+        ply.payload.insert("vertex".to_string(), vec![vertex; vertices]);
+        let mut f = ElementDef::new("face".to_string());
+        f.properties.add(PropertyDef::new("vertex_indices".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::UChar)));
+        ply.header.elements.add(f);
+        let faces = faces.into_iter().map(|l| {
+            let mut e = DefaultElement::new();
+            e.insert("vertex_indices".to_string(), Property::ListUChar(l));
+            e
+        }).collect();
+        ply.payload.insert("face".to_string(), faces);
+        ply.make_consistent().unwrap();
+        ply
+    }
+    #[test]
+    fn union_shifts_face_indices() {
+        let a = mesh_ply(3, vec![vec![0, 1, 2]]);
+        let b = mesh_ply(4, vec![vec![0, 1, 2], vec![1, 2, 3]]);
+        let m = union(&[a, b]).unwrap();
+        assert_eq!(m.header.elements["vertex"].count, 7);
+        let faces: Vec<&Property> = m.payload["face"].iter().map(|f| &f["vertex_indices"]).collect();
+        assert_eq!(faces, vec![&Property::ListUChar(vec![0, 1, 2]), &Property::ListUChar(vec![3, 4, 5]), &Property::ListUChar(vec![4, 5, 6])]);
+        assert!(::faces::validate_indices(&m).is_ok());
+
+        let a = mesh_ply(250, vec![vec![0, 1, 2]]);
+        let b = mesh_ply(10, vec![vec![7, 8, 9]]);
+        assert!(union(&[a, b]).is_err());
+    }
+    #[test]
+    fn union_type_conflict_err() {
+        let a = vertex_ply(&[("x", Property::Float(1.0))]);
+        let b = vertex_ply(&[("x", Property::Int(1))]);
+        assert!(union(&[a, b]).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Edits the metadata of existing files without touching their payload.
+//!
+//! Metadata are `obj_info` lines of the form `obj_info <key> <value>`.
+//! All other header lines, comments included, are kept byte for byte.
+
+use std::io;
+use std::io::{ BufRead, BufReader, Write, Result, ErrorKind };
+use std::fs::{ File, OpenOptions };
+use std::path::Path;
+
+use parser::Parser;
+use ply::{ DefaultElement, Error as PlyError };
+use util::write_file_atomically;
+
+/// Returns the value stored under `key` in the header of the file at `path`.
+pub fn get<P: AsRef<Path>>(path: P, key: &str) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let header = File::open(path)
+        .and_then(|file| Parser::<DefaultElement>::new().read_header(&mut BufReader::new(file)))
+        .map_err(|e| PlyError::in_file(e, path));
+    Ok(try!(header).obj_infos.iter().filter_map(|o| split_obj_info(o, key)).next().map(|v| v.to_string()))
+}
+
+/// Stores `value` under `key` in the header of the file at `path`.
+///
+/// Replaces the first `obj_info` line with the same key and drops further ones,
+/// otherwise appends a new line right before `end_header`.
+/// If the header keeps its size, only the header region is overwritten.
+/// Otherwise the file is rewritten through a temporary file next to it,
+/// copying the payload bytes unchanged, and only replaced once that succeeded.
+///
+/// The key must not be empty or contain white space, the value must not contain line breaks.
+/// Errors concerning the file are attributed to it, see `ply::Error::in_file()`.
+pub fn set<P: AsRef<Path>>(path: P, key: &str, value: &str) -> Result<()> {
+    if key.is_empty() || key.chars().any(|c| c.is_whitespace()) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("Key `{}` must be non-empty and free of white space.", key)));
+    }
+    if value.contains('\n') || value.contains('\r') {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("Value for key `{}` contains a line break.", key)));
+    }
+    let path = path.as_ref();
+    rewrite_header(path, key, value).map_err(|e| PlyError::in_file(e, path))
+}
+
+fn rewrite_header(path: &Path, key: &str, value: &str) -> Result<()> {
+    let mut reader = BufReader::new(try!(File::open(path)));
+    let old_header = try!(read_raw_header(&mut reader));
+    // Make sure we are editing a valid PLY file.
+    try!(Parser::<DefaultElement>::new().read_header(&mut &old_header[..]));
+    let new_header = edit_header(&old_header, key, value);
+    if new_header.len() == old_header.len() {
+        drop(reader);
+        let mut file = try!(OpenOptions::new().write(true).open(path));
+        return file.write_all(&new_header);
+    }
+    write_file_atomically(path, |out| {
+        try!(out.write_all(&new_header));
+        io::copy(&mut reader, out).map(|_| ())
+    })
+}
+
+/// Reads the raw header lines up to and including `end_header`.
+fn read_raw_header<R: BufRead>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut header = Vec::new();
+    loop {
+        let start = header.len();
+        if try!(reader.read_until(b'\n', &mut header)) == 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "No `end_header` found."));
+        }
+        if String::from_utf8_lossy(&header[start..]).trim_right() == "end_header" {
+            return Ok(header);
+        }
+    }
+}
+
+/// Replaces or inserts the `obj_info` line for `key` in the raw header, keeping its line endings.
+fn edit_header(header: &[u8], key: &str, value: &str) -> Vec<u8> {
+    let new_line = if header.ends_with(b"\r\n") { "\r\n" } else { "\n" };
+    let entry = format!("obj_info {} {}{}", key, value, new_line);
+    let mut edited = Vec::with_capacity(header.len() + entry.len());
+    let mut done = false;
+    let mut lines = header.split(|&b| b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        if lines.peek().is_none() {
+            // Nothing follows the last line break.
+            break;
+        }
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_right();
+        let replaces = text.starts_with("obj_info ") && split_obj_info(&text["obj_info ".len()..], key).is_some();
+        if (replaces || text == "end_header") && !done {
+            edited.extend_from_slice(entry.as_bytes());
+            done = true;
+        }
+        if !replaces {
+            edited.extend_from_slice(line);
+            edited.push(b'\n');
+        }
+    }
+    edited
+}
+
+/// Value part of `obj_info` if it belongs to `key`.
+fn split_obj_info<'a>(obj_info: &'a str, key: &str) -> Option<&'a str> {
+    let mut parts = obj_info.trim_left().splitn(2, char::is_whitespace);
+    if parts.next() != Some(key) {
+        return None;
+    }
+    Some(parts.next().unwrap_or("").trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    #[test]
+    fn edit_header_lines() {
+        let h = b"ply\nformat ascii 1.0\ncomment keep me\nobj_info scanner a\nelement v 0\nend_header\n";
+        let e = edit_header(h, "scanner", "b");
+        assert_eq!(&e[..], &b"ply\nformat ascii 1.0\ncomment keep me\nobj_info scanner b\nelement v 0\nend_header\n"[..]);
+        let e = edit_header(&e, "site", "north field");
+        assert!(e.ends_with(b"obj_info scanner b\nelement v 0\nobj_info site north field\nend_header\n"));
+        let h = b"ply\r\nobj_info site x\r\nobj_info site y\r\nend_header\r\n";
+        assert_eq!(&edit_header(h, "site", "z")[..], &b"ply\r\nobj_info site z\r\nend_header\r\n"[..]);
+    }
+    #[test]
+    fn set_in_file() {
+        let path = env::temp_dir().join("ply_rs_metadata_set.ply");
+        let payload = b"\x01\x00\x02";
+        {
+            let mut f = File::create(&path).unwrap();
+            f.write_all(b"ply\nformat binary_little_endian 1.0\nobj_info id 1\nelement v 3\nproperty uchar x\nend_header\n").unwrap();
+            f.write_all(payload).unwrap();
+        }
+        set(&path, "id", "2").unwrap();
+        set(&path, "tag", "scan of 2017").unwrap();
+        assert_eq!(get(&path, "id").unwrap(), Some("2".to_string()));
+        assert_eq!(get(&path, "tag").unwrap(), Some("scan of 2017".to_string()));
+        assert_eq!(get(&path, "none").unwrap(), None);
+        let mut bytes = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+        assert!(bytes.ends_with(b"end_header\n\x01\x00\x02"));
+        assert!(set(&path, "two words", "x").is_err());
+        assert!(set(&path, "id", "a\nb").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+    #[cfg(unix)]
+    #[test]
+    fn set_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = env::temp_dir().join("ply_rs_metadata_permissions");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("scan.ply");
+        fs::write(&path, b"ply\nformat ascii 1.0\nelement v 0\nend_header\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        set(&path, "site", "north").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+//! Declarative schema migrations, to upgrade datasets from one version of their layout to the next.
+//!
+//! A `Migration` is a list of steps, applied in order to a `Ply` in memory or to a file element by element.
+
+use std::io;
+use std::io::{ BufRead, BufReader, BufWriter, Write, ErrorKind };
+use std::fs::File;
+use std::path::Path;
+
+use parser::Parser;
+use writer::Writer;
+use ply::{ Ply, Header, DefaultElement, PropertyDef, PropertyType, Property, ConsistencyError, Addable, Error as PlyError };
+
+enum Step {
+    RenameProperty(String, String, String),
+    ChangeType(String, String, PropertyType, Box<Fn(&Property) -> Result<Property, String>>),
+    DropElement(String),
+    AddProperty(String, PropertyDef, Box<Fn(&DefaultElement) -> Property>),
+}
+
+/// Sequence of schema changes.
+///
+/// Every step must find what it refers to, otherwise the migration fails,
+/// this way a migration applied to the wrong version of a dataset is noticed.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::migrate::Migration;
+/// # use ply_rs::ply::{ Property, PropertyDef, PropertyType, ScalarType };
+/// let mut migration = Migration::new();
+/// migration.rename_property("vertex", "intensity", "scalar_intensity")
+///          .change_type("vertex", "scalar_intensity", PropertyType::Scalar(ScalarType::Float), |p| match *p {
+///              Property::UChar(v) => Ok(Property::Float(v as f32 / 255.0)),
+///              ref p => Err(format!("Unexpected intensity {:?}.", p)),
+///          })
+///          .drop_element("camera")
+///          .add_property("vertex", PropertyDef::new("w".to_string(), PropertyType::Scalar(ScalarType::Float)), |_| Property::Float(1.0));
+/// ```
+pub struct Migration {
+    steps: Vec<Step>,
+}
+
+impl Migration {
+    /// Creates a migration that changes nothing.
+    pub fn new() -> Self {
+        Migration {
+            steps: Vec::new(),
+        }
+    }
+    /// Renames the property `from` of `element` to `to`, keeping its position.
+    pub fn rename_property(&mut self, element: &str, from: &str, to: &str) -> &mut Self {
+        self.steps.push(Step::RenameProperty(element.to_string(), from.to_string(), to.to_string()));
+        self
+    }
+    /// Declares `property` of `element` as `data_type` and converts every value with `convert`.
+    ///
+    /// The converter must return values matching `data_type`, an error aborts the migration.
+    pub fn change_type<F>(&mut self, element: &str, property: &str, data_type: PropertyType, convert: F) -> &mut Self
+        where F: Fn(&Property) -> Result<Property, String> + 'static {
+        self.steps.push(Step::ChangeType(element.to_string(), property.to_string(), data_type, Box::new(convert)));
+        self
+    }
+    /// Removes `element` with all its entries.
+    pub fn drop_element(&mut self, element: &str) -> &mut Self {
+        self.steps.push(Step::DropElement(element.to_string()));
+        self
+    }
+    /// Appends `property_def` to `element`, its values are computed from each entry by `compute`.
+    pub fn add_property<F>(&mut self, element: &str, property_def: PropertyDef, compute: F) -> &mut Self
+        where F: Fn(&DefaultElement) -> Property + 'static {
+        self.steps.push(Step::AddProperty(element.to_string(), property_def, Box::new(compute)));
+        self
+    }
+    /// Applies all steps to `ply`.
+    ///
+    /// On error `ply` is left unchanged.
+    pub fn apply(&self, ply: &mut Ply<DefaultElement>) -> Result<(), ConsistencyError> {
+        let header = try!(self.migrate_header(&ply.header));
+        let mut payload = ply.payload.clone();
+        for (name, _) in &ply.header.elements {
+            if !header.elements.contains_key(name) {
+                payload.remove(name);
+            } else if let Some(entries) = payload.get_mut(name) {
+                try!(self.migrate_entries(name, entries));
+            }
+        }
+        ply.header = header;
+        ply.payload = payload;
+        Ok(())
+    }
+    /// Migrates the PLY file read from `reader` and writes the result to `writer`.
+    ///
+    /// Only one element is held in memory at a time.
+    /// The encoding of the input is kept.
+    ///
+    /// Returns number of bytes written.
+    pub fn apply_to_stream<R: BufRead, W: Write>(&self, reader: &mut R, writer: &mut W) -> io::Result<usize> {
+        let parser = Parser::<DefaultElement>::new();
+        let w = Writer::<DefaultElement>::new();
+        let old_header = try!(parser.read_header(reader));
+        let header = try!(self.migrate_header(&old_header).map_err(to_io_error));
+        let mut written = try!(w.write_header(writer, &header));
+        for (name, element_def) in &old_header.elements {
+            let mut entries = try!(parser.read_payload_for_element(reader, element_def, &old_header));
+            let element_def = match header.elements.get(name) {
+                Some(e) => e,
+                None => continue,
+            };
+            try!(self.migrate_entries(name, &mut entries).map_err(to_io_error));
+            written += try!(w.write_payload_for_element(writer, &entries, element_def, &header));
+        }
+        try!(writer.flush());
+        Ok(written)
+    }
+    /// Migrates the PLY file at `from` and writes the result to `to`, see `apply_to_stream()`.
+    ///
+    /// Errors are attributed to `from`, unless `to` can't be created, see `ply::Error::in_file()`.
+    pub fn apply_to_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<usize> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let mut reader = BufReader::new(try!(File::open(from).map_err(|e| PlyError::in_file(e, from))));
+        let mut writer = BufWriter::new(try!(File::create(to).map_err(|e| PlyError::in_file(e, to))));
+        self.apply_to_stream(&mut reader, &mut writer).map_err(|e| PlyError::in_file(e, from))
+    }
+
+    fn migrate_header(&self, header: &Header) -> Result<Header, ConsistencyError> {
+        let mut header = header.clone();
+        for step in &self.steps {
+            match *step {
+                Step::RenameProperty(ref element, ref from, ref to) => {
+                    let element_def = try!(header.elements.get_mut(element).ok_or_else(|| missing_element(element)));
+                    if !element_def.properties.contains_key(from) {
+                        return Err(missing_property(element, from));
+                    }
+                    if element_def.properties.contains_key(to) {
+                        return Err(ConsistencyError::new(&format!("Can not rename `{}`, element `{}` already has a property `{}`.", from, element, to)));
+                    }
+                    let order: Vec<String> = element_def.properties.keys().map(|k| if k == from { to.clone() } else { k.clone() }).collect();
+                    let mut property_def = element_def.properties.remove(from).unwrap();
+                    property_def.name = to.clone();
+                    element_def.properties.add(property_def);
+                    let order: Vec<&str> = order.iter().map(|k| k.as_ref()).collect();
+                    element_def.reorder_properties(&order);
+                },
+                Step::ChangeType(ref element, ref property, ref data_type, _) => {
+                    let element_def = try!(header.elements.get_mut(element).ok_or_else(|| missing_element(element)));
+                    let property_def = try!(element_def.properties.get_mut(property).ok_or_else(|| missing_property(element, property)));
+                    property_def.data_type = data_type.clone();
+                },
+                Step::DropElement(ref element) => {
+                    try!(header.elements.remove(element).ok_or_else(|| missing_element(element)));
+                },
+                Step::AddProperty(ref element, ref property_def, _) => {
+                    let element_def = try!(header.elements.get_mut(element).ok_or_else(|| missing_element(element)));
+                    if element_def.properties.contains_key(&property_def.name) {
+                        return Err(ConsistencyError::new(&format!("Element `{}` already has a property `{}`.", element, property_def.name)));
+                    }
+                    element_def.properties.add(property_def.clone());
+                },
+            }
+        }
+        Ok(header)
+    }
+    /// Applies the steps concerning `element` to its entries, the header has been migrated successfully before.
+    fn migrate_entries(&self, element: &str, entries: &mut Vec<DefaultElement>) -> Result<(), ConsistencyError> {
+        for step in &self.steps {
+            match *step {
+                Step::RenameProperty(ref e, ref from, ref to) if e == element => {
+                    for entry in entries.iter_mut() {
+                        if entry.contains_key(from) {
+                            *entry = entry.drain().map(|(k, v)| if k == *from { (to.clone(), v) } else { (k, v) }).collect();
+                        }
+                    }
+                },
+                Step::ChangeType(ref e, ref property, _, ref convert) if e == element => {
+                    for (i, entry) in entries.iter_mut().enumerate() {
+                        let value = match entry.get_mut(property) {
+                            Some(v) => v,
+                            None => return Err(ConsistencyError::new(&format!(
+                                "Entry {} of element `{}` lacks property `{}`.", i, element, property))),
+                        };
+                        *value = try!(convert(value).map_err(|m| ConsistencyError::new(&format!(
+                            "Couldn't convert property `{}` of entry {} of element `{}`: {}", property, i, element, m))));
+                    }
+                },
+                Step::AddProperty(ref e, ref property_def, ref compute) if e == element => {
+                    for entry in entries.iter_mut() {
+                        let value = compute(entry);
+                        entry.insert(property_def.name.clone(), value);
+                    }
+                },
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn missing_element(element: &str) -> ConsistencyError {
+    ConsistencyError::new(&format!("No element `{}` found to migrate.", element))
+}
+
+fn missing_property(element: &str, property: &str) -> ConsistencyError {
+    ConsistencyError::new(&format!("Element `{}` has no property `{}` to migrate.", element, property))
+}
+
+fn to_io_error(e: ConsistencyError) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, format!("Migration failed: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    fn ply() -> Ply<DefaultElement> {
+        let mut p = Ply::<DefaultElement>::new();
+        let mut v = ElementDef::new("vertex".to_string());
+        v.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        v.properties.add(PropertyDef::new("intensity".to_string(), PropertyType::Scalar(ScalarType::UChar)));
+        v.properties.add(PropertyDef::new("y".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        p.header.elements.add(v);
+        let mut c = ElementDef::new("camera".to_string());
+        c.properties.add(PropertyDef::new("f".to_string(), PropertyType::Scalar(ScalarType::Double)));
+        p.header.elements.add(c);
+        let vertices = (0..3).map(|i| {
+            let mut e = DefaultElement::new();
+            e.insert("x".to_string(), Property::Float(i as f32));
+            e.insert("intensity".to_string(), Property::UChar(255));
+            e.insert("y".to_string(), Property::Float(2.0));
+            e
+        }).collect();
+        p.payload.insert("vertex".to_string(), vertices);
+        let mut camera = DefaultElement::new();
+        camera.insert("f".to_string(), Property::Double(35.0));
+        p.payload.insert("camera".to_string(), vec![camera]);
+        p.make_consistent().unwrap();
+        p
+    }
+    fn migration() -> Migration {
+        let mut m = Migration::new();
+        m.rename_property("vertex", "intensity", "scalar_intensity")
+            .change_type("vertex", "scalar_intensity", PropertyType::Scalar(ScalarType::Float), |p| match *p {
+                Property::UChar(v) => Ok(Property::Float(v as f32 / 255.0)),
+                ref p => Err(format!("Unexpected {:?}.", p)),
+            })
+            .drop_element("camera")
+            .add_property("vertex", PropertyDef::new("sum".to_string(), PropertyType::Scalar(ScalarType::Float)), |e| {
+                match (&e["x"], &e["y"]) {
+                    (&Property::Float(x), &Property::Float(y)) => Property::Float(x + y),
+                    _ => Property::Float(0.0),
+                }
+            });
+        m
+    }
+    #[test]
+    fn migrate_in_memory() {
+        let mut p = ply();
+        migration().apply(&mut p).unwrap();
+        let names: Vec<&String> = p.header.elements["vertex"].properties.keys().collect();
+        assert_eq!(names, vec!["x", "scalar_intensity", "y", "sum"]);
+        assert!(!p.header.elements.contains_key("camera"));
+        assert!(!p.payload.contains_key("camera"));
+        assert_eq!(p.payload["vertex"][2]["scalar_intensity"], Property::Float(1.0));
+        assert_eq!(p.payload["vertex"][2]["sum"], Property::Float(4.0));
+        assert!(p.make_consistent().is_ok());
+        let before = p.clone();
+        assert!(migration().apply(&mut p).is_err());
+        assert_eq!(p, before);
+    }
+    #[test]
+    fn migrate_stream() {
+        let mut p = ply();
+        let mut input = Vec::new();
+        Writer::new().write_ply(&mut input, &mut p).unwrap();
+        let mut output = Vec::new();
+        migration().apply_to_stream(&mut &input[..], &mut output).unwrap();
+        let read = Parser::<DefaultElement>::new().read_ply(&mut &output[..]).unwrap();
+        migration().apply(&mut p).unwrap();
+        assert_eq!(read, p);
+    }
+}
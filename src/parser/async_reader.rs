@@ -0,0 +1,380 @@
+//! Asynchronous reading from a `tokio::io::AsyncRead`, requires the `tokio` feature.
+
+use std::future::Future;
+use std::io;
+use std::io::{ ErrorKind, Result };
+use std::pin::Pin;
+use std::str;
+use std::task::{ Context, Poll };
+
+use byteorder::{ BigEndian, LittleEndian, ByteOrder };
+use tokio::io::{ AsyncRead, ReadBuf };
+
+use ply::{ Ply, PropertyAccess, Header, Payload, ElementDef, Encoding, PropertyType, ScalarType, Error as PlyError };
+use util::LocationTracker;
+use super::{ Parser, entry_error, ascii_entry_error };
+
+/// Number of bytes requested from the source at once.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Bytes allowed per ascii value including its separator, generous for padded numbers.
+const ASCII_VALUE_BYTES: u64 = 64;
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Asynchronous variant of `read_ply()`.
+    ///
+    /// The returned future resolves to the complete `Ply`.
+    /// Data is decoded as soon as it arrives, so the runtime thread is never blocked on the source.
+    /// With `set_max_list_len()`, an entry is never buffered beyond the size it can need:
+    /// longer lists and overlong ascii lines fail instead.
+    pub fn read_ply_async<'a, R: AsyncRead + Unpin>(&'a self, source: &'a mut R) -> ReadPly<'a, E, R> {
+        ReadPly {
+            parser: self,
+            source: source,
+            buf: Vec::new(),
+            pos: 0,
+            end: 0,
+            eof: false,
+            location: LocationTracker::new(),
+            header: None,
+            payload: Payload::new(),
+            element: 0,
+            entries: Vec::new(),
+        }
+    }
+    /// Number of bytes the next entry of `element_def` occupies in `bytes`, or `None` if `bytes` doesn't hold it completely.
+    fn __binary_entry_len<B: ByteOrder>(&self, bytes: &[u8], element_def: &ElementDef) -> Result<Option<usize>> {
+        let mut len = 0;
+        for property_def in element_def.properties.values() {
+            match property_def.data_type {
+                PropertyType::Scalar(ref scalar_type) => len += scalar_type.size_bytes(),
+                PropertyType::List(ref index_type, ref scalar_type) => {
+                    let index_len = index_type.size_bytes();
+                    if bytes.len() < len + index_len {
+                        return Ok(None);
+                    }
+                    let index = &bytes[len..len + index_len];
+                    let count: i64 = match *index_type {
+                        ScalarType::Char => index[0] as i8 as i64,
+                        ScalarType::UChar => index[0] as i64,
+                        ScalarType::Short => B::read_i16(index) as i64,
+                        ScalarType::UShort => B::read_u16(index) as i64,
+                        ScalarType::Int => B::read_i32(index) as i64,
+                        ScalarType::UInt => B::read_u32(index) as i64,
+                        // Invalid, let the decoder report it.
+                        ScalarType::Float | ScalarType::Double => return Ok(Some(len)),
+                    };
+                    if count < 0 {
+                        return Ok(Some(len));
+                    }
+                    let count = try!(self.check_list_len(count as u64, property_def));
+                    len = match count.checked_mul(scalar_type.size_bytes()).and_then(|l| l.checked_add(len + index_len)) {
+                        Some(l) => l,
+                        None => return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            format!("List `{}` with {} entries is too large to buffer.", property_def.name, count)
+                        )),
+                    };
+                },
+            }
+        }
+        Ok(if bytes.len() < len { None } else { Some(len) })
+    }
+    /// Longest ascii line an entry of `element_def` can need with the configured `max_list_len`,
+    /// `None` without a limit.
+    fn __max_ascii_line_len(&self, element_def: &ElementDef) -> Option<usize> {
+        if self.max_list_len() == u64::max_value() {
+            return None;
+        }
+        let mut values: u64 = 0;
+        for property_def in element_def.properties.values() {
+            values = match property_def.data_type {
+                PropertyType::Scalar(_) => values.saturating_add(1),
+                PropertyType::List(_, _) => values.saturating_add(1).saturating_add(self.max_list_len()),
+            };
+        }
+        Some(values.saturating_mul(ASCII_VALUE_BYTES).min(usize::max_value() as u64) as usize)
+    }
+}
+
+/// Future returned by `Parser::read_ply_async()`.
+///
+/// The source is read in chunks, the header once `end_header` arrived, entries as soon as they are complete.
+pub struct ReadPly<'a, E: PropertyAccess + 'a, R: 'a> {
+    parser: &'a Parser<E>,
+    source: &'a mut R,
+    buf: Vec<u8>,
+    pos: usize,
+    end: usize,
+    eof: bool,
+    location: LocationTracker,
+    header: Option<Header>,
+    payload: Payload<E>,
+    element: usize,
+    entries: Vec<E>,
+}
+
+// Nothing is pinned structurally, the payload is only moved out once complete.
+impl<'a, E: PropertyAccess, R> Unpin for ReadPly<'a, E, R> {}
+
+impl<'a, E: PropertyAccess, R: AsyncRead + Unpin> ReadPly<'a, E, R> {
+    /// Decodes as much as the buffer allows. Returns `true` once the `Ply` is complete.
+    fn decode(&mut self) -> Result<bool> {
+        if self.header.is_none() {
+            let header_len = match header_len(&self.buf[self.pos..self.end]) {
+                Some(l) => l,
+                None if self.eof => self.end - self.pos,
+                None => return Ok(false),
+            };
+            let header = {
+                let mut bytes = &self.buf[self.pos..self.pos + header_len];
+                try!(self.parser.__read_header(&mut bytes, &mut self.location))
+            };
+            self.pos += header_len;
+            self.header = Some(header);
+        }
+        let header = self.header.as_ref().unwrap();
+        while let Some((name, element_def)) = header.elements.iter().nth(self.element) {
+            while (self.entries.len() as u64) < element_def.count {
+                let i = self.entries.len();
+                let bytes = &self.buf[self.pos..self.end];
+                let (entry, len) = match header.encoding {
+                    Encoding::Ascii => {
+                        let len = match bytes.iter().position(|&b| b == b'\n') {
+                            Some(p) => p + 1,
+                            None if self.eof && !bytes.is_empty() => bytes.len(),
+                            None => {
+                                if let Some(max) = self.parser.__max_ascii_line_len(element_def) {
+                                    if bytes.len() > max {
+                                        return Err(io::Error::new(
+                                            ErrorKind::InvalidData,
+                                            format!("Entry {} of element `{}` exceeds {} bytes without a line break.", i, element_def.name, max)
+                                        ));
+                                    }
+                                }
+                                break;
+                            },
+                        };
+                        let line = match str::from_utf8(&bytes[..len]) {
+                            Ok(l) => l,
+                            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, format!("Line {} isn't valid UTF-8: {}", self.location.line_index, e))),
+                        };
+                        match self.parser.read_ascii_element(line, element_def) {
+                            Ok(entry) => (entry, len),
+                            Err(e) => return ascii_entry_error(&self.location, line, e, element_def, i as u64),
+                        }
+                    },
+                    Encoding::BinaryBigEndian | Encoding::BinaryLittleEndian => {
+                        let len = if header.encoding == Encoding::BinaryBigEndian {
+                            try!(self.parser.__binary_entry_len::<BigEndian>(bytes, element_def))
+                        } else {
+                            try!(self.parser.__binary_entry_len::<LittleEndian>(bytes, element_def))
+                        };
+                        let len = match len {
+                            Some(l) => l,
+                            None => break,
+                        };
+                        let mut entry_bytes = &bytes[..len];
+                        let entry = if header.encoding == Encoding::BinaryBigEndian {
+                            self.parser.read_big_endian_element(&mut entry_bytes, element_def)
+                        } else {
+                            self.parser.read_little_endian_element(&mut entry_bytes, element_def)
+                        };
+                        match entry {
+                            Ok(entry) => (entry, len),
+                            Err(e) => return Err(entry_error(e, element_def, i as u64)),
+                        }
+                    },
+                };
+                self.entries.push(entry);
+                self.pos += len;
+                self.location.next_line();
+            }
+            if (self.entries.len() as u64) < element_def.count {
+                if self.eof {
+                    return Err(PlyError::UnexpectedEof { element: name.clone(), index: self.entries.len() as u64 }.into());
+                }
+                return Ok(false);
+            }
+            let entries = ::std::mem::replace(&mut self.entries, Vec::new());
+            self.payload.insert(name.clone(), entries);
+            self.element += 1;
+        }
+        Ok(true)
+    }
+    /// Moves unconsumed bytes to the front and reads the next chunk from the source.
+    ///
+    /// `buf[end..]` is spare space kept between polls, the buffer only grows when less than a chunk is left.
+    fn poll_fill(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.end, 0);
+            self.end -= self.pos;
+            self.pos = 0;
+        }
+        if self.buf.len() - self.end < CHUNK_SIZE {
+            let len = self.end + CHUNK_SIZE;
+            self.buf.resize(len, 0);
+        }
+        let mut read_buf = ReadBuf::new(&mut self.buf[self.end..]);
+        match Pin::new(&mut *self.source).poll_read(cx, &mut read_buf) {
+            Poll::Ready(Ok(())) => {
+                let n = read_buf.filled().len();
+                self.end += n;
+                self.eof = n == 0;
+                Poll::Ready(Ok(()))
+            },
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<'a, E: PropertyAccess, R: AsyncRead + Unpin> Future for ReadPly<'a, E, R> {
+    type Output = Result<Ply<E>>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Ply<E>>> {
+        let this = self.get_mut();
+        loop {
+            match this.decode() {
+                Ok(true) => {
+                    let mut ply = Ply::new();
+                    ply.header = this.header.take().unwrap();
+                    this.parser.declare_derived_properties(&mut ply.header);
+                    ply.payload = ::std::mem::replace(&mut this.payload, Payload::new());
+                    return Poll::Ready(Ok(ply));
+                },
+                Ok(false) => (),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+            if this.eof {
+                // Decoding doesn't wait for more data once the source ended, but never loop on an exhausted source.
+                return Poll::Ready(Err(io::Error::new(ErrorKind::UnexpectedEof, "The source ended before the header was complete.")));
+            }
+            match this.poll_fill(cx) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Length of the header including the `end_header` line, if `bytes` holds it completely.
+fn header_len(bytes: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    while let Some(p) = bytes[start..].iter().position(|&b| b == b'\n') {
+        let end = start + p + 1;
+        if bytes[start..end].starts_with(b"end_header") {
+            return Some(end);
+        }
+        start = end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::io::Result;
+    use std::pin::Pin;
+    use std::ptr;
+    use std::task::{ Context, Poll, RawWaker, RawWakerVTable, Waker };
+    use tokio::io::{ AsyncRead, ReadBuf };
+    use ply::*;
+    use parser::Parser;
+    use writer::Writer;
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+
+    /// Hands out a few bytes at a time and is pending every other poll.
+    struct Trickle {
+        data: Vec<u8>,
+        pos: usize,
+        pending: bool,
+    }
+    impl AsyncRead for Trickle {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context, buf: &mut ReadBuf) -> Poll<Result<()>> {
+            self.pending = !self.pending;
+            if self.pending {
+                return Poll::Pending;
+            }
+            let n = ::std::cmp::min(7, ::std::cmp::min(buf.remaining(), self.data.len() - self.pos));
+            buf.put_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn create_ply(encoding: Encoding) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        ply.header.encoding = encoding;
+        let mut e = ElementDef::new("point".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        e.properties.add(PropertyDef::new("i".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::Int)));
+        ply.header.elements.add(e);
+        let mut list = Vec::new();
+        for i in 0..100 {
+            let mut pe = DefaultElement::new();
+            pe.insert("x".to_string(), Property::Float(i as f32 * 0.5));
+            pe.insert("i".to_string(), Property::ListInt(vec![i, -i]));
+            list.push(pe);
+        }
+        ply.payload.insert("point".to_string(), list);
+        ply.make_consistent().unwrap();
+        ply
+    }
+
+    fn read_async(data: Vec<u8>) -> Result<Ply<DefaultElement>> {
+        read_async_with(&Parser::<DefaultElement>::new(), data)
+    }
+    fn read_async_with(p: &Parser<DefaultElement>, data: Vec<u8>) -> Result<Ply<DefaultElement>> {
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut source = Trickle { data: data, pos: 0, pending: false };
+        let mut future = p.read_ply_async(&mut source);
+        loop {
+            if let Poll::Ready(r) = Pin::new(&mut future).poll(&mut cx) {
+                return r;
+            }
+        }
+    }
+
+    #[test]
+    fn future_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let p = Parser::<DefaultElement>::new();
+        let mut source = Trickle { data: Vec::new(), pos: 0, pending: false };
+        assert_send(&p.read_ply_async(&mut source));
+    }
+
+    #[test]
+    fn async_matches_sync() {
+        let w = Writer::<DefaultElement>::new();
+        for encoding in &[Encoding::Ascii, Encoding::BinaryBigEndian, Encoding::BinaryLittleEndian] {
+            let mut ply = create_ply(*encoding);
+            let mut buf = Vec::<u8>::new();
+            w.write_ply(&mut buf, &mut ply).unwrap();
+            let read = read_async(buf.clone()).unwrap();
+            assert_eq!(read.header, ply.header);
+            assert_eq!(read.payload, ply.payload);
+
+            buf.truncate(buf.len() - 6);
+            assert!(read_async(buf).is_err());
+        }
+    }
+    #[test]
+    fn long_line_bounded_by_max_list_len() {
+        let mut data = b"ply\nformat ascii 1.0\nelement point 1\nproperty float x\nproperty list uchar int i\nend_header\n0.5 2 1".to_vec();
+        data.extend(::std::iter::repeat(b' ').take(10000));
+        data.extend(b"1\n");
+        assert!(read_async(data.clone()).is_ok());
+        let mut p = Parser::<DefaultElement>::new();
+        p.set_max_list_len(2);
+        let e = read_async_with(&p, data).unwrap_err();
+        assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("exceeds"), "{}", e);
+    }
+}
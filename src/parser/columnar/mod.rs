@@ -0,0 +1,151 @@
+//! Reads the payload into columns, see `ColumnarParser`.
+
+use std::io;
+use std::io::{ Read, BufRead, BufReader, Result, ErrorKind };
+use std::ops::Range;
+use std::slice::Iter;
+
+use byteorder::{ BigEndian, LittleEndian, ByteOrder };
+
+use ply::{ PropertyAccess, DefaultElement, Header, ElementDef, Encoding, Property, Column, ColumnarElement, ColumnarPayload, Error as PlyError };
+use util::LocationTracker;
+use super::{ Parser, BufferSize, VersionPolicy, grammar, entry_error, ascii_entry_error };
+
+#[cfg(feature = "mmap")]
+mod spill;
+#[cfg(feature = "mmap")]
+pub use self::spill::*;
+
+/// Reads the payload into a `ColumnarPayload` instead of a `Payload` of elements.
+///
+/// No map is allocated per entry, each property is collected in a contiguous vector,
+/// which is considerably faster and leaner for large files.
+/// Since no element type is involved, the parser isn't generic.
+/// Element decoders and derived properties of `Parser` don't exist here.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use ply_rs::parser::ColumnarParser;
+/// # use ply_rs::ply::Column;
+/// let mut f = std::fs::File::open("example_plys/greg_turk_example1_ok_ascii.ply").unwrap();
+/// let parser = ColumnarParser::new();
+/// let (header, payload) = parser.read_columnar(&mut f).unwrap();
+/// if let Column::Float(ref x) = payload["vertex"]["x"] {
+///     println!("{} vertices", x.len());
+/// }
+/// ```
+pub struct ColumnarParser {
+    parser: Parser<DefaultElement>,
+}
+
+impl ColumnarParser {
+    /// Creates a new `ColumnarParser` with the same defaults as `Parser::new()`.
+    pub fn new() -> Self {
+        ColumnarParser { parser: Parser::new() }
+    }
+    /// Sets the maximal number of entries accepted for a list property, see `Parser::set_max_list_len()`.
+    pub fn set_max_list_len(&mut self, max_list_len: u64) {
+        self.parser.set_max_list_len(max_list_len);
+    }
+    /// Sets the buffer size used by `read_columnar()`, see `Parser::set_buffer_size()`.
+    pub fn set_buffer_size(&mut self, buffer_size: BufferSize) {
+        self.parser.set_buffer_size(buffer_size);
+    }
+    /// Sets how versions other than 1.0 are handled, see `VersionPolicy`.
+    pub fn set_version_policy(&mut self, policy: VersionPolicy) {
+        self.parser.set_version_policy(policy);
+    }
+    /// Expects the complete content of a PLY file, like `Parser::read_ply()`.
+    pub fn read_columnar<T: Read>(&self, source: &mut T) -> Result<(Header, ColumnarPayload)> {
+        let mut source = BufReader::with_capacity(self.parser.header_buffer_size(), source);
+        let mut location = LocationTracker::new();
+        let header = try!(self.parser.__read_header(&mut source, &mut location));
+        let mut source = self.parser.rebuffer(source, &header);
+        let payload = try!(self.parser.__read_columnar_payload(&mut source, &mut location, &header));
+        Ok((header, payload))
+    }
+    /// Reads the header, see `Parser::read_header()`.
+    pub fn read_header<T: BufRead>(&self, reader: &mut T) -> Result<Header> {
+        self.parser.read_header(reader)
+    }
+    /// Reads the payload into columns. Encoding is chosen according to the encoding field in `header`.
+    pub fn read_columnar_payload<T: BufRead>(&self, reader: &mut T, header: &Header) -> Result<ColumnarPayload> {
+        let mut location = LocationTracker::new();
+        self.parser.__read_columnar_payload(reader, &mut location, header)
+    }
+}
+
+impl<E: PropertyAccess> Parser<E> {
+    fn __read_columnar_payload<T: BufRead>(&self, reader: &mut T, location: &mut LocationTracker, header: &Header) -> Result<ColumnarPayload> {
+        let mut payload = ColumnarPayload::new();
+        for (name, element_def) in &header.elements {
+            let mut columns: Vec<Column> = element_def.properties.values().map(|p| Column::new(&p.data_type)).collect();
+            match header.encoding {
+                Encoding::Ascii => try!(self.__read_ascii_columns(reader, location, element_def, &mut columns)),
+                Encoding::BinaryBigEndian => try!(self.__read_binary_columns::<T, BigEndian>(reader, location, element_def, &mut columns)),
+                Encoding::BinaryLittleEndian => try!(self.__read_binary_columns::<T, LittleEndian>(reader, location, element_def, &mut columns)),
+            }
+            let element: ColumnarElement = element_def.properties.keys().cloned().zip(columns).collect();
+            payload.insert(name.clone(), element);
+        }
+        Ok(payload)
+    }
+    fn __read_ascii_columns<T: BufRead>(&self, reader: &mut T, location: &mut LocationTracker, element_def: &ElementDef, columns: &mut [Column]) -> Result<()> {
+        self.__read_ascii_rows(reader, location, element_def, 0..element_def.count, columns)
+    }
+    /// Appends the entries with an index in `rows` to `columns`, they must be next in `reader`.
+    fn __read_ascii_rows<T: BufRead>(&self, reader: &mut T, location: &mut LocationTracker, element_def: &ElementDef, rows: Range<u64>, columns: &mut [Column]) -> Result<()> {
+        let mut line_str = String::new();
+        for i in rows {
+            line_str.clear();
+            if try!(reader.read_line(&mut line_str)) == 0 {
+                return Err(PlyError::UnexpectedEof { element: element_def.name.clone(), index: i }.into());
+            }
+            if let Err(e) = self.__read_ascii_row(&line_str, element_def, columns) {
+                return ascii_entry_error(location, &line_str, e, element_def, i);
+            }
+            location.next_line();
+        }
+        Ok(())
+    }
+    fn __read_ascii_row(&self, line: &str, element_def: &ElementDef, columns: &mut [Column]) -> Result<()> {
+        let elems = match grammar::data_line(line) {
+            Ok(e) => e,
+            Err(ref e) => return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Couldn't parse element line.\n\tString: '{}'\n\tError: {}", line, e)
+                )),
+        };
+        let mut elem_it : Iter<String> = elems.iter();
+        for (column, property_def) in columns.iter_mut().zip(element_def.properties.values()) {
+            let property = try!(self.__read_ascii_property(&mut elem_it, property_def));
+            push(column, property);
+        }
+        Ok(())
+    }
+    fn __read_binary_columns<T: Read, B: ByteOrder>(&self, reader: &mut T, location: &mut LocationTracker, element_def: &ElementDef, columns: &mut [Column]) -> Result<()> {
+        self.__read_binary_rows::<T, B>(reader, location, element_def, 0..element_def.count, columns)
+    }
+    /// Appends the entries with an index in `rows` to `columns`, they must be next in `reader`.
+    fn __read_binary_rows<T: Read, B: ByteOrder>(&self, reader: &mut T, location: &mut LocationTracker, element_def: &ElementDef, rows: Range<u64>, columns: &mut [Column]) -> Result<()> {
+        for i in rows {
+            for (column, property_def) in columns.iter_mut().zip(element_def.properties.values()) {
+                match self.__read_binary_property::<T, B>(reader, property_def) {
+                    Ok(property) => push(column, property),
+                    Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Err(
+                        PlyError::UnexpectedEof { element: element_def.name.clone(), index: i }.into()
+                    ),
+                    Err(e) => return Err(entry_error(e, element_def, i)),
+                }
+            }
+            location.next_line();
+        }
+        Ok(())
+    }
+}
+
+/// Columns are created from the property definitions the values are decoded with, so their types always match.
+fn push(column: &mut Column, property: Property) {
+    column.push(property).expect("Column type doesn't match its property definition.");
+}
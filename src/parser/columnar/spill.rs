@@ -0,0 +1,249 @@
+//! Keeps columns in temporary files instead of memory, requires the `mmap` feature.
+
+use std::io::{ Read, Write, BufReader, BufWriter, Result };
+use std::ops::Range;
+use std::path::Path;
+
+use byteorder::{ BigEndian, LittleEndian, NativeEndian, ByteOrder };
+use memmap2::Mmap;
+
+use ply::{ Header, Encoding, Property, PropertyType, ScalarType, Column, KeyMap };
+use util::{ LocationTracker, TempFiles, create_temp_file };
+use super::ColumnarParser;
+
+/// Number of entries decoded into memory before the values of spilled columns are written out.
+const CHUNK_ENTRIES: u64 = 64 * 1024;
+
+/// Payload read by `ColumnarParser::read_columnar_spilled()`, partly kept in temporary files.
+///
+/// The temporary files are removed once it is dropped.
+#[derive(Debug)]
+pub struct SpilledPayload {
+    elements: KeyMap<KeyMap<SpilledColumn>>,
+    _files: TempFiles,
+}
+
+/// Column of a `SpilledPayload`, either in memory or mapped from a temporary file.
+#[derive(Debug)]
+pub enum SpilledColumn {
+    InMemory(Column),
+    Mapped(MappedColumn),
+}
+
+/// Scalar values stored in a temporary file in native byte order, mapped into memory.
+#[derive(Debug)]
+pub struct MappedColumn {
+    scalar_type: ScalarType,
+    len: usize,
+    map: Mmap,
+}
+
+impl ColumnarParser {
+    /// Like `read_columnar()`, but keeps scalar columns in temporary files in `dir` if the payload exceeds `memory_budget`.
+    ///
+    /// Scalar columns are kept in memory in header order as long as their sizes add up to at most `memory_budget` bytes.
+    /// The values of the others are written to temporary files while reading and mapped into memory afterwards,
+    /// the operating system then pages them in and out as needed.
+    /// List columns are always kept in memory and don't count towards the budget.
+    ///
+    /// Use it for payloads larger than the available memory, `dir` needs room for the spilled columns.
+    pub fn read_columnar_spilled<T: Read, P: AsRef<Path>>(&self, source: &mut T, memory_budget: u64, dir: P) -> Result<(Header, SpilledPayload)> {
+        let mut source = BufReader::with_capacity(self.parser.header_buffer_size(), source);
+        let mut location = LocationTracker::new();
+        let header = try!(self.parser.__read_header(&mut source, &mut location));
+        let mut source = self.parser.rebuffer(source, &header);
+        let mut files = TempFiles(Vec::new());
+        let mut elements = KeyMap::new();
+        let mut budget = memory_budget;
+        for (name, element_def) in &header.elements {
+            let mut columns: Vec<Column> = element_def.properties.values().map(|p| Column::new(&p.data_type)).collect();
+            let mut sinks = Vec::with_capacity(columns.len());
+            for property_def in element_def.properties.values() {
+                let sink = match property_def.data_type {
+                    PropertyType::Scalar(ref scalar_type) => {
+                        let size = element_def.count.saturating_mul(scalar_type.size_bytes() as u64);
+                        if size <= budget {
+                            budget -= size;
+                            None
+                        } else {
+                            let (path, file) = try!(create_temp_file(&dir.as_ref().join("ply_rs_column")));
+                            files.0.push(path);
+                            Some((scalar_type.clone(), BufWriter::new(file)))
+                        }
+                    },
+                    PropertyType::List(_, _) => None,
+                };
+                sinks.push(sink);
+            }
+            let mut start = 0;
+            while start < element_def.count {
+                let rows = start..element_def.count.min(start + CHUNK_ENTRIES);
+                start = rows.end;
+                match header.encoding {
+                    Encoding::Ascii => try!(self.parser.__read_ascii_rows(&mut source, &mut location, element_def, rows, &mut columns)),
+                    Encoding::BinaryBigEndian => try!(self.parser.__read_binary_rows::<_, BigEndian>(&mut source, &mut location, element_def, rows, &mut columns)),
+                    Encoding::BinaryLittleEndian => try!(self.parser.__read_binary_rows::<_, LittleEndian>(&mut source, &mut location, element_def, rows, &mut columns)),
+                }
+                for ((column, sink), property_def) in columns.iter_mut().zip(sinks.iter_mut()).zip(element_def.properties.values()) {
+                    if let Some((_, ref mut out)) = *sink {
+                        try!(write_native(out, column));
+                        *column = Column::new(&property_def.data_type);
+                    }
+                }
+            }
+            let mut element = KeyMap::new();
+            for ((column, sink), key) in columns.into_iter().zip(sinks).zip(element_def.properties.keys()) {
+                let column = match sink {
+                    None => SpilledColumn::InMemory(column),
+                    Some((scalar_type, out)) => {
+                        let file = try!(out.into_inner().map_err(|e| e.into_error()));
+                        SpilledColumn::Mapped(MappedColumn {
+                            scalar_type: scalar_type,
+                            len: element_def.count as usize,
+                            map: try!(unsafe { Mmap::map(&file) }),
+                        })
+                    },
+                };
+                element.insert(key.clone(), column);
+            }
+            elements.insert(name.clone(), element);
+        }
+        Ok((header, SpilledPayload { elements: elements, _files: files }))
+    }
+}
+
+impl SpilledPayload {
+    /// Columns of `element`, in the order of the properties in the header.
+    pub fn element(&self, element: &str) -> Option<&KeyMap<SpilledColumn>> {
+        self.elements.get(element)
+    }
+    /// Column of `property` in `element`, if there is one.
+    pub fn column(&self, element: &str, property: &str) -> Option<&SpilledColumn> {
+        self.elements.get(element).and_then(|e| e.get(property))
+    }
+}
+
+impl SpilledColumn {
+    /// Number of values in the column.
+    pub fn len(&self) -> usize {
+        match *self {
+            SpilledColumn::InMemory(ref c) => c.len(),
+            SpilledColumn::Mapped(ref c) => c.len(),
+        }
+    }
+    /// Returns `true` if the column holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Value at `index`, `None` if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<Property> {
+        match *self {
+            SpilledColumn::InMemory(ref c) => c.get(index),
+            SpilledColumn::Mapped(ref c) => c.get(index),
+        }
+    }
+    /// Copy of the values in `range` as a `Column` in memory, the range must lie within the column.
+    ///
+    /// Lets the columnar API process a spilled column in chunks that fit into memory.
+    pub fn to_column(&self, range: Range<usize>) -> Column {
+        match *self {
+            SpilledColumn::InMemory(ref c) => c.slice(range),
+            SpilledColumn::Mapped(ref c) => c.to_column(range),
+        }
+    }
+}
+
+impl MappedColumn {
+    /// Type of the values.
+    pub fn scalar_type(&self) -> &ScalarType {
+        &self.scalar_type
+    }
+    /// Number of values in the column.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Returns `true` if the column holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Value at `index`, `None` if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<Property> {
+        if index >= self.len {
+            return None;
+        }
+        let bytes = &self.map[index * self.scalar_type.size_bytes()..];
+        Some(match self.scalar_type {
+            ScalarType::Char => Property::Char(bytes[0] as i8),
+            ScalarType::UChar => Property::UChar(bytes[0]),
+            ScalarType::Short => Property::Short(NativeEndian::read_i16(bytes)),
+            ScalarType::UShort => Property::UShort(NativeEndian::read_u16(bytes)),
+            ScalarType::Int => Property::Int(NativeEndian::read_i32(bytes)),
+            ScalarType::UInt => Property::UInt(NativeEndian::read_u32(bytes)),
+            ScalarType::Float => Property::Float(NativeEndian::read_f32(bytes)),
+            ScalarType::Double => Property::Double(NativeEndian::read_f64(bytes)),
+        })
+    }
+    /// Copy of the values in `range` as a `Column` in memory, the range must lie within the column.
+    pub fn to_column(&self, range: Range<usize>) -> Column {
+        assert!(range.end <= self.len, "Range {:?} exceeds the column of {} values.", range, self.len);
+        let mut column = Column::new(&PropertyType::Scalar(self.scalar_type.clone()));
+        for i in range {
+            column.push(self.get(i).unwrap()).expect("Column type doesn't match its scalar type.");
+        }
+        column
+    }
+}
+
+macro_rules! write_values(
+    ($column:expr, $out:expr; $($v:ident),*) => (
+        match *$column {
+            $(Column::$v(ref values) => for v in values {
+                try!($out.write_all(&v.to_ne_bytes()));
+            },)*
+            _ => unreachable!("List columns are never spilled."),
+        }
+    )
+);
+
+/// Appends the values of the scalar `column` to `out` in native byte order.
+fn write_native<W: Write>(out: &mut W, column: &Column) -> Result<()> {
+    write_values!(column, out; Char, UChar, Short, UShort, Int, UInt, Float, Double);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+
+    #[test]
+    fn spilled_matches_columnar() {
+        let dir = env::temp_dir().join("ply_rs_spill");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let p = ColumnarParser::new();
+        for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+            let (header, expected) = p.read_columnar(&mut File::open(path).unwrap()).unwrap();
+            // Each coordinate column of the 5 vertices takes 20 bytes.
+            for &(budget, spilled_keys) in &[(0, &["x", "y", "z"][..]), (40, &["z"][..]), (u64::max_value(), &[][..])] {
+                let (h, spilled) = p.read_columnar_spilled(&mut File::open(path).unwrap(), budget, &dir).unwrap();
+                assert_eq!(h, header);
+                for (name, columns) in &expected {
+                    for (key, column) in columns {
+                        let c = spilled.column(name, key).unwrap();
+                        assert_eq!(c.to_column(0..c.len()), *column);
+                        assert_eq!(c.get(c.len()), None);
+                        let mapped = match *c { SpilledColumn::Mapped(_) => true, SpilledColumn::InMemory(_) => false };
+                        assert_eq!(mapped, spilled_keys.contains(&&key[..]), "{}.{}", name, key);
+                    }
+                }
+                assert_eq!(fs::read_dir(&dir).unwrap().count(), spilled_keys.len());
+                drop(spilled);
+                assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+//! Reads element blocks at known offsets with positioned reads, requires the `pread` feature and a unix platform.
+
+use std::convert::TryFrom;
+use std::io;
+use std::io::{ BufRead, BufReader, Seek, SeekFrom, Result, ErrorKind };
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::{ Path, PathBuf };
+
+use chunks::{ Chunk, CHUNK_ELEMENT };
+use faces::VERTEX_ELEMENT;
+use ply::{ PropertyAccess, Header, ElementDef, Encoding, Error as PlyError };
+use util::scalar_value;
+use super::Parser;
+
+/// Reader fetching the elements of a file independently of each other, created by `Parser::open_indexed()`.
+///
+/// Element blocks are read with `pread`, which doesn't move a shared file position.
+/// All methods take `&self`, so several threads can fetch elements or entry ranges
+/// from the same reader at the same time without seek contention, e.g. from an NVMe drive.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use ply_rs::parser::Parser;
+/// # use ply_rs::ply::DefaultElement;
+/// let parser = Parser::<DefaultElement>::new();
+/// let reader = parser.open_indexed("scan.ply").unwrap();
+/// let faces = reader.read_element("face").unwrap();
+/// let some_vertices = reader.read_entries("vertex", 1000..2000).unwrap();
+/// ```
+pub struct IndexedReader<'a, E: PropertyAccess + 'a> {
+    parser: &'a Parser<E>,
+    file: File,
+    path: PathBuf,
+    header: Header,
+    /// Element definitions as found in the file, without derived properties.
+    elements: Vec<ElementDef>,
+    /// Byte range of each element block.
+    blocks: Vec<Range<u64>>,
+    /// Size of each entry, for binary elements without lists.
+    entry_sizes: Vec<Option<u64>>,
+}
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Opens the file at `path` and locates its element blocks, see `IndexedReader`.
+    ///
+    /// Binary elements without lists are located from the header alone.
+    /// Blocks of other elements are read once to find their end, ascii lines without being decoded.
+    ///
+    /// Errors are attributed to the file, see `Error::in_file()`.
+    pub fn open_indexed<'a, P: AsRef<Path>>(&'a self, path: P) -> Result<IndexedReader<'a, E>> {
+        let path = path.as_ref();
+        self.__open_indexed(path).map_err(|e| PlyError::in_file(e, path))
+    }
+    fn __open_indexed<'a>(&'a self, path: &Path) -> Result<IndexedReader<'a, E>> {
+        let file = try!(File::open(path));
+        let mut reader = BufReader::with_capacity(self.header_buffer_size(), &file);
+        let mut header = try!(self.read_header(&mut reader));
+        let elements: Vec<ElementDef> = header.elements.values().cloned().collect();
+        let mut offset = try!(reader.seek(SeekFrom::Current(0)));
+        let file_len = try!(file.metadata()).len();
+        let mut blocks = Vec::with_capacity(elements.len());
+        let mut entry_sizes = Vec::with_capacity(elements.len());
+        for element_def in &elements {
+            let sizes: Option<Vec<usize>> = element_def.properties.values().map(|p| p.data_type.fixed_record_size()).collect();
+            let entry_size = match (&header.encoding, sizes) {
+                (&Encoding::Ascii, _) | (_, None) => None,
+                (_, Some(sizes)) => Some(sizes.iter().sum::<usize>() as u64),
+            };
+            let end = match entry_size {
+                Some(size) => match element_def.count.checked_mul(size).and_then(|s| s.checked_add(offset)) {
+                    Some(end) => end,
+                    None => return Err(io::Error::new(ErrorKind::InvalidData, format!("Size of element `{}` overflows.", element_def.name))),
+                },
+                None => {
+                    try!(reader.seek(SeekFrom::Start(offset)));
+                    try!(self.skip_entries(&mut reader, element_def, &header.encoding));
+                    try!(reader.seek(SeekFrom::Current(0)))
+                },
+            };
+            // Blocks are allocated in full when read, a corrupt count must not get that far.
+            if end > file_len {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Element `{}` ends at byte {}, but the file has only {} bytes.", element_def.name, end, file_len)
+                ));
+            }
+            if usize::try_from(end - offset).is_err() {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("Element `{}` is too large to be read on this platform.", element_def.name)));
+            }
+            blocks.push(offset..end);
+            entry_sizes.push(entry_size);
+            offset = end;
+        }
+        drop(reader);
+        self.declare_derived_properties(&mut header);
+        Ok(IndexedReader {
+            parser: self,
+            file: file,
+            path: path.to_path_buf(),
+            header: header,
+            elements: elements,
+            blocks: blocks,
+            entry_sizes: entry_sizes,
+        })
+    }
+    /// Reads past all entries of `element_def`, ascii lines aren't decoded.
+    fn skip_entries<T: BufRead>(&self, reader: &mut T, element_def: &ElementDef, encoding: &Encoding) -> Result<()> {
+        let mut line = Vec::new();
+        for index in 0..element_def.count {
+            let skipped = match *encoding {
+                Encoding::Ascii => {
+                    line.clear();
+                    reader.read_until(b'\n', &mut line).and_then(|n| if n == 0 {
+                        Err(io::Error::new(ErrorKind::UnexpectedEof, "Unexpected end of file."))
+                    } else {
+                        Ok(())
+                    })
+                },
+                Encoding::BinaryBigEndian => self.read_big_endian_element(reader, element_def).map(|_| ()),
+                Encoding::BinaryLittleEndian => self.read_little_endian_element(reader, element_def).map(|_| ()),
+            };
+            if let Err(e) = skipped {
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    return Err(PlyError::UnexpectedEof { element: element_def.name.clone(), index: index }.into());
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, E: PropertyAccess> IndexedReader<'a, E> {
+    /// Header of the file, including derived properties.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Byte range of the block of `element` in the file.
+    pub fn block(&self, element: &str) -> Option<Range<u64>> {
+        self.position(element).map(|i| self.blocks[i].clone())
+    }
+    /// Reads all entries of `element`.
+    pub fn read_element(&self, element: &str) -> Result<Vec<E>> {
+        let i = try!(self.find(element));
+        self.read_block(&self.elements[i], self.blocks[i].clone())
+    }
+    /// Reads the entries of `element` with an index in `entries`.
+    ///
+    /// Only binary elements without lists can be read in parts, since the offset of their entries is known.
+    /// Read others as a whole with `read_element()`.
+    pub fn read_entries(&self, element: &str, entries: Range<u64>) -> Result<Vec<E>> {
+        let i = try!(self.find(element));
+        let element_def = &self.elements[i];
+        let size = match self.entry_sizes[i] {
+            Some(s) => s,
+            None => return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Entries of element `{}` vary in size, it can only be read as a whole.", element)
+            )),
+        };
+        if entries.start > entries.end || entries.end > element_def.count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Entries {:?} exceed the {} entries of element `{}`.", entries, element_def.count, element)
+            ));
+        }
+        let mut part = element_def.clone();
+        part.count = entries.end - entries.start;
+        let start = self.blocks[i].start + entries.start * size;
+        self.read_block(&part, start..start + part.count * size)
+    }
+    /// Reads the chunks of a file laid out by `chunks::sort_into_chunks()`.
+    pub fn chunks(&self) -> Result<Vec<Chunk>> {
+        let entries = try!(self.read_element(CHUNK_ELEMENT));
+        let mut chunks = Vec::with_capacity(entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            match Chunk::from_entry(entry) {
+                Some(c) => chunks.push(c),
+                None => return Err(io::Error::new(ErrorKind::InvalidData, format!("Entry {} of element `{}` is incomplete.", i, CHUNK_ELEMENT))),
+            }
+        }
+        Ok(chunks)
+    }
+    /// Reads the vertices within the box from `min` to `max`, bounds included,
+    /// of a file laid out by `chunks::sort_into_chunks()`.
+    ///
+    /// Only the vertex entries of chunks overlapping the box are read, adjacent chunks in one go.
+    /// The vertex element must be binary without lists, see `read_entries()`.
+    pub fn query_box(&self, min: [f64; 3], max: [f64; 3]) -> Result<Vec<E>> {
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        for chunk in try!(self.chunks()).iter().filter(|c| c.intersects(&min, &max)) {
+            match ranges.last_mut() {
+                Some(ref mut r) if r.end == chunk.first => r.end = chunk.entries().end,
+                _ => ranges.push(chunk.entries()),
+            }
+        }
+        let vertex_def = &self.elements[try!(self.find(VERTEX_ELEMENT))];
+        let mut coordinates = Vec::with_capacity(3);
+        for name in &["x", "y", "z"] {
+            match vertex_def.properties.get(*name) {
+                Some(p) => coordinates.push(p),
+                None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` has no property `{}`.", VERTEX_ELEMENT, name))),
+            }
+        }
+        let mut found = Vec::new();
+        for range in ranges {
+            for vertex in try!(self.read_entries(VERTEX_ELEMENT, range)) {
+                let inside = coordinates.iter().enumerate().all(|(i, p)| match scalar_value(&vertex, p) {
+                    Some(v) => min[i] <= v && v <= max[i],
+                    None => false,
+                });
+                if inside {
+                    found.push(vertex);
+                }
+            }
+        }
+        Ok(found)
+    }
+    fn read_block(&self, element_def: &ElementDef, block: Range<u64>) -> Result<Vec<E>> {
+        let len = match usize::try_from(block.end - block.start) {
+            Ok(len) => len,
+            Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, format!("Element `{}` is too large to be read on this platform.", element_def.name))),
+        };
+        let mut bytes = vec![0u8; len];
+        try!(self.file.read_exact_at(&mut bytes, block.start).map_err(|e| PlyError::in_file(e, &self.path)));
+        self.parser.read_payload_for_element(&mut &bytes[..], element_def, &self.header)
+            .map_err(|e| PlyError::in_file(e, &self.path))
+    }
+    fn position(&self, element: &str) -> Option<usize> {
+        self.elements.iter().position(|e| e.name == element)
+    }
+    fn find(&self, element: &str) -> Result<usize> {
+        match self.position(element) {
+            Some(i) => Ok(i),
+            None => Err(io::Error::new(ErrorKind::InvalidInput, format!("The file has no element `{}`.", element))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+    use std::thread;
+    use chunks::sort_into_chunks;
+    use ply::{ Ply, DefaultElement, ElementDef, PropertyDef, PropertyType, ScalarType, Property, Encoding, Addable };
+    use parser::Parser;
+    use writer::Writer;
+
+    #[test]
+    fn indexed_matches_sequential() {
+        // Threads need a reader that lives as long as they might.
+        let p: &'static Parser<DefaultElement> = Box::leak(Box::new(Parser::new()));
+        for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+            let expected = p.read_ply(&mut File::open(path).unwrap()).unwrap();
+            let reader = p.open_indexed(path).unwrap();
+            assert_eq!(*reader.header(), expected.header);
+            let reader = Arc::new(reader);
+            let threads: Vec<_> = ["vertex", "face"].iter().map(|&name| {
+                let reader = reader.clone();
+                thread::spawn(move || reader.read_element(name).unwrap())
+            }).collect();
+            let mut results = threads.into_iter().map(|t| t.join().unwrap());
+            let (vertices, faces) = (results.next().unwrap(), results.next().unwrap());
+            assert_eq!(vertices, expected.payload["vertex"]);
+            assert_eq!(faces, expected.payload["face"]);
+            assert_eq!(reader.block("face").unwrap().end, File::open(path).unwrap().metadata().unwrap().len());
+            assert!(reader.read_element("edge").is_err());
+            assert!(reader.read_entries("face", 0..1).is_err());
+            if path.ends_with("little_endian.ply") {
+                assert_eq!(reader.read_entries("vertex", 1..3).unwrap(), &expected.payload["vertex"][1..3]);
+                assert!(reader.read_entries("vertex", 4..6).is_err());
+            } else {
+                assert!(reader.read_entries("vertex", 1..3).is_err());
+            }
+        }
+        assert!(p.open_indexed("example_plys/does_not_exist.ply").is_err());
+    }
+    #[test]
+    fn query_box_reads_overlapping_chunks() {
+        let path = env::temp_dir().join("ply_rs_indexed_chunks.ply");
+        let mut ply = Ply::<DefaultElement>::new();
+        ply.header.encoding = Encoding::BinaryLittleEndian;
+        let mut v = ElementDef::new("vertex".to_string());
+        for name in &["x", "y", "z"] {
+            v.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(ScalarType::Double)));
+        }
+        ply.header.elements.add(v);
+        let vertices = (0..1000).map(|i| {
+            let mut e = DefaultElement::new();
+            e.insert("x".to_string(), Property::Double((i * 37 % 100) as f64));
+            e.insert("y".to_string(), Property::Double((i * 11 % 10) as f64));
+            e.insert("z".to_string(), Property::Double(i as f64 * 0.001));
+            e
+        }).collect();
+        ply.payload.insert("vertex".to_string(), vertices);
+        ply.make_consistent().unwrap();
+        let chunks = sort_into_chunks(&mut ply, 64).unwrap();
+        Writer::new().write_ply(&mut File::create(&path).unwrap(), &mut ply).unwrap();
+
+        let p = Parser::<DefaultElement>::new();
+        let reader = p.open_indexed(&path).unwrap();
+        assert_eq!(reader.chunks().unwrap(), chunks);
+        let (min, max) = ([10.0, 2.0, 0.0], [20.5, 4.0, 1.0]);
+        let found = reader.query_box(min, max).unwrap();
+        let expected: Vec<DefaultElement> = ply.payload["vertex"].iter().filter(|v| {
+            ["x", "y", "z"].iter().enumerate().all(|(i, k)| match v[*k] {
+                Property::Double(c) => min[i] <= c && c <= max[i],
+                _ => false,
+            })
+        }).cloned().collect();
+        assert!(!found.is_empty() && found.len() < 1000);
+        assert_eq!(found, expected);
+        assert!(reader.query_box([200.0; 3], [300.0; 3]).unwrap().is_empty());
+        fs::remove_file(&path).unwrap();
+
+        let reader = p.open_indexed("example_plys/house_2_ok_little_endian.ply").unwrap();
+        assert!(reader.query_box(min, max).is_err());
+    }
+    #[test]
+    fn oversized_count_err() {
+        let path = env::temp_dir().join("ply_rs_indexed_oversized.ply");
+        fs::write(&path, b"ply\nformat binary_little_endian 1.0\nelement vertex 4000000000000000\nproperty float x\nend_header\n\0\0\0\0").unwrap();
+        let e = Parser::<DefaultElement>::new().open_indexed(&path).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+}
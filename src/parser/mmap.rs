@@ -0,0 +1,58 @@
+//! Reads files through a memory map, requires the `mmap` feature.
+
+use std::fs::File;
+use std::io::Result;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use ply::{ Ply, PropertyAccess, Error as PlyError };
+use util::LocationTracker;
+use super::Parser;
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Like `read_ply()`, but maps the file at `path` into memory and decodes directly from the mapping.
+    ///
+    /// Saves the copies through the buffer of a `BufReader`, which pays off for large binary files.
+    ///
+    /// The file must not be modified or truncated while it is read,
+    /// other processes doing so can crash the program.
+    ///
+    /// Errors are attributed to the file, see `Error::in_file()`.
+    pub fn read_ply_mmap<P: AsRef<Path>>(&self, path: P) -> Result<Ply<E>> {
+        let path = path.as_ref();
+        self.__read_ply_mmap(path).map_err(|e| PlyError::in_file(e, path))
+    }
+    fn __read_ply_mmap(&self, path: &Path) -> Result<Ply<E>> {
+        let file = try!(File::open(path));
+        let map = try!(unsafe { Mmap::map(&file) });
+        let mut source: &[u8] = &map;
+        let mut location = LocationTracker::new();
+        let mut header = try!(self.__read_header(&mut source, &mut location));
+        let payload = try!(self.__read_payload(&mut source, &mut location, &header));
+        self.declare_derived_properties(&mut header);
+        let mut ply = Ply::new();
+        ply.header = header;
+        ply.payload = payload;
+        Ok(ply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use ply::DefaultElement;
+    use parser::Parser;
+
+    #[test]
+    fn mmap_matches_buffered() {
+        let p = Parser::<DefaultElement>::new();
+        for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+            let expected = p.read_ply(&mut File::open(path).unwrap()).unwrap();
+            let ply = p.read_ply_mmap(path).unwrap();
+            assert_eq!(ply.header, expected.header);
+            assert_eq!(ply.payload, expected.payload);
+        }
+        assert!(p.read_ply_mmap("example_plys/does_not_exist.ply").is_err());
+    }
+}
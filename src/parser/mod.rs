@@ -3,12 +3,14 @@
 use std::io;
 use std::io::{ Read, BufReader };
 use std::fmt::Debug;
+use std::hash::Hasher;
 use std::result;
 
 use std::io::{ BufRead, Result, ErrorKind };
 
 mod ply_grammar {
     use ply::{ PropertyDef, PropertyType, ScalarType, Encoding, Version, Comment, ObjInfo,ElementDef };
+    /// A single parsed header line.
     #[derive(Debug, PartialEq, Clone)]
     pub enum Line {
         MagicNumber,
@@ -23,20 +25,63 @@ mod ply_grammar {
 }
 
 use self::ply_grammar as grammar;
-use self::ply_grammar::Line;
+pub use self::ply_grammar::Line;
+
+mod stream;
+pub use self::stream::{ PlyReader, ElementEntries, SpannedEntries, EntrySpan };
+
+mod columnar;
+pub use self::columnar::ColumnarParser;
+#[cfg(feature = "mmap")]
+pub use self::columnar::{ SpilledPayload, SpilledColumn, MappedColumn };
+
+#[cfg(feature = "tokio")]
+mod async_reader;
+#[cfg(feature = "tokio")]
+pub use self::async_reader::ReadPly;
+
+#[cfg(feature = "mmap")]
+mod mmap;
+
+#[cfg(all(unix, feature = "pread"))]
+mod indexed;
+#[cfg(all(unix, feature = "pread"))]
+pub use self::indexed::IndexedReader;
+
+#[cfg(feature = "serde")]
+mod serde_reader;
 use util::LocationTracker;
 
 fn parse_ascii_rethrow<T, E: Debug>(location: &LocationTracker, line_str: &str, e: E, message: &str) -> Result<T> {
-    Err(io::Error::new(
-        ErrorKind::InvalidInput,
-        format!("Line {}: {}\n\tString: '{}'\n\tError: {:?}", location.line_index, message, line_str, e)
-    ))
+    parse_ascii_error(location, line_str, &format!("{}\n\tError: {:?}", message, e))
 }
 fn parse_ascii_error<T>(location: &LocationTracker, line_str: &str, message: &str) -> Result<T> {
-    Err(io::Error::new(
-        ErrorKind::InvalidInput,
-        format!("Line {}: {}\n\tString: '{}'", location.line_index, message, line_str)
-    ))
+    Err(PlyError::Parse {
+        line: location.line_index,
+        found: line_str.to_string(),
+        message: message.to_string(),
+    }.into())
+}
+/// Adds the position to an error of reading entry `index` of `element_def`.
+///
+/// Structured errors become `InvalidEntry`, others get the position in their message.
+fn entry_error(e: io::Error, element_def: &ElementDef, index: u64) -> io::Error {
+    let cause = match PlyError::from_io(&e) {
+        Some(&PlyError::UnexpectedEof { .. }) | Some(&PlyError::InvalidEntry { .. }) => return e,
+        Some(cause) => cause.clone(),
+        None => return io::Error::new(
+            e.kind(),
+            format!("Couldn't read entry {} of element `{}`.\n\tError: {}", index, element_def.name, e)
+        ),
+    };
+    PlyError::InvalidEntry { element: element_def.name.clone(), index: index, cause: Box::new(cause) }.into()
+}
+/// Like `entry_error()`, for ascii lines: Unstructured errors become `Parse` errors of the line.
+fn ascii_entry_error<T>(location: &LocationTracker, line_str: &str, e: io::Error, element_def: &ElementDef, index: u64) -> Result<T> {
+    if PlyError::from_io(&e).is_some() {
+        return Err(entry_error(e, element_def, index));
+    }
+    parse_ascii_rethrow(location, line_str, e, &format!("Couln't read entry {} of element `{}`.", index, element_def.name))
 }
 
 use std::marker::PhantomData;
@@ -104,14 +149,99 @@ use std::marker::PhantomData;
 /// ```
 ///
 pub struct Parser<E: PropertyAccess> {
+      version_policy: VersionPolicy,
+      decoders: KeyMap<Box<Fn(&ElementDef, &mut Read) -> Result<E> + Send + Sync>>,
+      max_list_len: u64,
+      derived: Vec<(String, PropertyDef, Box<Fn(&E) -> Property + Send + Sync>)>,
+      buffer_size: BufferSize,
+      verify_element_crcs: bool,
       phantom: PhantomData<E>,
 }
 
+/// Decides how a `Parser` reacts to the version declared in the `format` line.
+///
+/// The only published PLY version is 1.0.
+/// Files declaring other versions are read with the 1.0 rules,
+/// the policy only decides whether such a file is accepted at all.
+pub enum VersionPolicy {
+    /// Accept any version. This is the default.
+    AcceptAll,
+    /// Only accept version 1.0, fail on anything else.
+    Strict,
+    /// Let a hook decide.
+    ///
+    /// The hook must be `Send + Sync`, so a configured parser can be shared between threads.
+    /// Return `Ok(())` to accept the version, or `Err` with a message to reject it.
+    /// The hook is also the place to emit a warning or enable vendor specific handling for a known version.
+    Custom(Box<Fn(&Version) -> result::Result<(), String> + Send + Sync>),
+}
+
+impl VersionPolicy {
+    /// Checks `version` against the policy.
+    pub fn check(&self, version: &Version) -> result::Result<(), String> {
+        match *self {
+            VersionPolicy::AcceptAll => Ok(()),
+            VersionPolicy::Strict => if *version == (Version{ major: 1, minor: 0 }) {
+                Ok(())
+            } else {
+                Err(format!("Unsupported version {}, only 1.0 is accepted.", version))
+            },
+            VersionPolicy::Custom(ref hook) => hook(version),
+        }
+    }
+}
+
+/// Capacity of the buffer `read_ply()` and `Writer::write_ply()` put between the data and the source or target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferSize {
+    /// Always use this many bytes.
+    Fixed(usize),
+    /// Size the buffer after the payload size estimated from the header,
+    /// between `DEFAULT_BUFFER_SIZE` and `MAX_ADAPTIVE_BUFFER_SIZE`.
+    ///
+    /// Large buffers mainly pay off on network file systems, where every read has a high latency.
+    Adaptive,
+}
+
+/// Buffer size used unless configured otherwise, the same as for `std::io::BufReader`.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+/// Upper limit of `BufferSize::Adaptive`.
+pub const MAX_ADAPTIVE_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+impl BufferSize {
+    /// Number of bytes to buffer for a file with `header`.
+    pub fn capacity_for(&self, header: &Header) -> usize {
+        let size = match *self {
+            BufferSize::Fixed(size) => return size,
+            BufferSize::Adaptive => estimate_payload_size(header),
+        };
+        if size < DEFAULT_BUFFER_SIZE as u64 {
+            DEFAULT_BUFFER_SIZE
+        } else if size > MAX_ADAPTIVE_BUFFER_SIZE as u64 {
+            MAX_ADAPTIVE_BUFFER_SIZE
+        } else {
+            size as usize
+        }
+    }
+}
+
+/// Rough payload size: binary sizes or eight characters per ascii value, lists assumed to hold three values.
+fn estimate_payload_size(header: &Header) -> u64 {
+    let ascii = header.encoding == Encoding::Ascii;
+    let value_size = |t: &ScalarType| if ascii { 8 } else { t.size_bytes() as u64 };
+    header.elements.values().fold(0u64, |size, element_def| {
+        let entry_size = element_def.properties.values().map(|p| match p.data_type {
+            PropertyType::Scalar(ref t) => value_size(t),
+            PropertyType::List(ref i, ref t) => value_size(i) + 3 * value_size(t),
+        }).sum::<u64>();
+        size.saturating_add(element_def.count.saturating_mul(entry_size))
+    })
+}
 
 //use std::marker::PhantomData;
 //use std::io::{ Read, BufReader };
 use ply::Ply;
-use ply::{ Header, Payload, Encoding };
+use ply::{ Header, Payload, Encoding, Error as PlyError };
 
 impl<E: PropertyAccess> Parser<E> {
     /// Creates a new `Parser<E>`, where `E` is the type to store the element data in.
@@ -119,28 +249,216 @@ impl<E: PropertyAccess> Parser<E> {
     /// To get started quickly try `DefaultElement` from the `ply` module.
     pub fn new() -> Self {
         Parser {
+            version_policy: VersionPolicy::AcceptAll,
+            decoders: KeyMap::new(),
+            max_list_len: u64::max_value(),
+            derived: Vec::new(),
+            buffer_size: BufferSize::Fixed(DEFAULT_BUFFER_SIZE),
+            verify_element_crcs: false,
             phantom: PhantomData
         }
     }
+    /// Registers `decoder` to read every entry of the element `element_name`.
+    ///
+    /// The decoder replaces the generic property by property decoding for this element,
+    /// which allows hand optimized code for hot, known layouts while all other elements are read as usual.
+    ///
+    /// For binary payloads, the decoder reads exactly one entry from the given reader.
+    /// For ascii payloads, the reader contains a single line of the payload.
+    /// The decoder is responsible for the encoding, check the header before relying on a specific layout.
+    pub fn set_element_decoder<F>(&mut self, element_name: &str, decoder: F)
+        where F: Fn(&ElementDef, &mut Read) -> Result<E> + Send + Sync + 'static {
+        self.decoders.insert(element_name.to_string(), Box::new(decoder));
+    }
+    /// Registers a property of `element_name` that is computed from each entry right after it was decoded.
+    ///
+    /// Enriches the data in the same pass, e.g. a `range` computed from `x`, `y` and `z`,
+    /// or separate color channels unpacked from a packed value.
+    /// The value is stored with `set_property()`, after the properties found in the file.
+    /// Derived properties are computed in the order they were added, so they can build on each other.
+    ///
+    /// `read_ply()` adds `property_def` to the returned header, so the `Ply` can be written as is.
+    /// When reading header and payload separately, use `declare_derived_properties()` for this.
+    pub fn add_derived_property<F>(&mut self, element_name: &str, property_def: PropertyDef, compute: F)
+        where F: Fn(&E) -> Property + Send + Sync + 'static {
+        self.derived.push((element_name.to_string(), property_def, Box::new(compute)));
+    }
+    /// Adds the definitions of all derived properties to `header`, see `add_derived_property()`.
+    ///
+    /// Call it after the payload has been read, the header must still describe the file while reading.
+    pub fn declare_derived_properties(&self, header: &mut Header) {
+        for &(ref element_name, ref property_def, _) in &self.derived {
+            if let Some(element_def) = header.elements.get_mut(element_name) {
+                if !element_def.properties.contains_key(&property_def.name) {
+                    element_def.properties.add(property_def.clone());
+                }
+            }
+        }
+    }
+    /// Sets the maximal number of entries accepted for a list property, by default there is no limit.
+    ///
+    /// A corrupt length prefix in a binary file can announce billions of entries.
+    /// With a limit, such a list fails early with an error naming the element, property and entry,
+    /// instead of consuming memory until the end of the input.
+    pub fn set_max_list_len(&mut self, max_list_len: u64) {
+        self.max_list_len = max_list_len;
+    }
+    /// The limit set with `set_max_list_len()`.
+    pub fn max_list_len(&self) -> u64 {
+        self.max_list_len
+    }
+    /// Sets whether a `PlyReader` checks element blocks against the checksums recorded by `Writer::set_element_crcs()`,
+    /// disabled by default.
+    ///
+    /// The check runs once the last entry of an element was read, a mismatch is returned in its place
+    /// as `ply::Error::ChecksumMismatch`. Reading can go on with the next element.
+    /// If the last entry fails to decode, its decode error is returned and the block isn't checked.
+    /// Elements without a recorded checksum aren't checked.
+    pub fn set_verify_element_crcs(&mut self, verify: bool) {
+        self.verify_element_crcs = verify;
+    }
+    /// Sets the buffer size used by `read_ply()` and `read_ply_hashed()`, 8 KiB by default.
+    ///
+    /// With `BufferSize::Adaptive` the header is read through a default sized buffer,
+    /// the payload through one sized from the header.
+    pub fn set_buffer_size(&mut self, buffer_size: BufferSize) {
+        self.buffer_size = buffer_size;
+    }
+    /// Sets how versions other than 1.0 are handled, see `VersionPolicy`.
+    pub fn set_version_policy(&mut self, policy: VersionPolicy) {
+        self.version_policy = policy;
+    }
 
     /// Expects the complete content of a PLY file.
     ///
     /// A PLY file starts with "ply\n". `read_ply` reads until all elements have been read as
     /// defined in the header of the PLY file.
     pub fn read_ply<T: Read>(&self, source: &mut T) -> Result<Ply<E>> {
-        let mut source = BufReader::new(source);
+        let mut source = BufReader::with_capacity(self.header_buffer_size(), source);
         let mut location = LocationTracker::new();
-        let header = try!(self.__read_header(&mut source, &mut location));
+        let mut header = try!(self.__read_header(&mut source, &mut location));
+        let mut source = self.rebuffer(source, &header);
         let payload = try!(self.__read_payload(&mut source, &mut location, &header));
+        self.declare_derived_properties(&mut header);
+        let mut ply = Ply::new();
+        ply.header = header;
+        ply.payload = payload;
+        Ok(ply)
+    }
+    /// Like `read_ply()`, but feeds all header bytes into `header_hasher` and all payload bytes into `payload_hasher`.
+    ///
+    /// Lets ingestion systems compute content hashes (e.g. xxHash) while reading, without a second pass over the data.
+    /// Exactly the bytes consumed by the parser are hashed, in the order they appear in the source.
+    /// The hashers receive the data in chunks of varying size, so they should produce the same result for any chunking.
+    pub fn read_ply_hashed<T: Read, H: Hasher>(&self, source: &mut T, header_hasher: &mut H, payload_hasher: &mut H) -> Result<Ply<E>> {
+        let mut source = BufReader::with_capacity(self.header_buffer_size(), source);
+        let mut location = LocationTracker::new();
+        let mut header = {
+            let mut reader = HashingReader { inner: &mut source, hasher: header_hasher };
+            try!(self.__read_header(&mut reader, &mut location))
+        };
+        let mut source = self.rebuffer(source, &header);
+        let payload = {
+            let mut reader = HashingReader { inner: &mut source, hasher: payload_hasher };
+            try!(self.__read_payload(&mut reader, &mut location, &header))
+        };
+        self.declare_derived_properties(&mut header);
         let mut ply = Ply::new();
         ply.header = header;
         ply.payload = payload;
         Ok(ply)
     }
+    fn header_buffer_size(&self) -> usize {
+        match self.buffer_size {
+            BufferSize::Fixed(size) => size,
+            BufferSize::Adaptive => DEFAULT_BUFFER_SIZE,
+        }
+    }
+    /// Replaces the buffer used for the header by one sized for the payload, keeping the bytes already buffered.
+    ///
+    /// The buffer is only replaced if `BufferSize::Adaptive` asks for a different capacity.
+    fn rebuffer<T: Read>(&self, source: BufReader<T>, header: &Header) -> Rebuffered<T> {
+        let capacity = self.buffer_size.capacity_for(header);
+        if capacity == source.capacity() {
+            return Rebuffered::Kept(source);
+        }
+        let buffered = source.buffer().to_vec();
+        Rebuffered::Resized(BufReader::with_capacity(capacity, io::Cursor::new(buffered).chain(source.into_inner())))
+    }
+}
+
+/// Payload reader returned by `Parser::rebuffer()`.
+enum Rebuffered<T> {
+    /// The buffer used for the header.
+    Kept(BufReader<T>),
+    /// A buffer of a different size, reading the bytes left in the header's buffer first.
+    Resized(BufReader<io::Chain<io::Cursor<Vec<u8>>, T>>),
+}
+
+impl<T: Read> Read for Rebuffered<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match *self {
+            Rebuffered::Kept(ref mut r) => r.read(buf),
+            Rebuffered::Resized(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+impl<T: Read> BufRead for Rebuffered<T> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        match *self {
+            Rebuffered::Kept(ref mut r) => r.fill_buf(),
+            Rebuffered::Resized(ref mut r) => r.fill_buf(),
+        }
+    }
+    fn consume(&mut self, amt: usize) {
+        match *self {
+            Rebuffered::Kept(ref mut r) => r.consume(amt),
+            Rebuffered::Resized(ref mut r) => r.consume(amt),
+        }
+    }
+}
+
+/// `BufRead` adapter that feeds every consumed byte into a `Hasher`.
+struct HashingReader<'a, R, H: 'a> {
+    inner: R,
+    hasher: &'a mut H,
+}
+
+impl<'a, R: BufRead, H: Hasher> Read for HashingReader<'a, R, H> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = {
+            let available = try!(self.inner.fill_buf());
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, R: BufRead, H: Hasher> BufRead for HashingReader<'a, R, H> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        // The bytes are still buffered, as `consume` must follow a `fill_buf` returning at least `amt` bytes.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hasher.write(&buf[..amt]);
+        }
+        self.inner.consume(amt);
+    }
 }
 
 // use ply::{ Header, Encoding };
-use ply::{ PropertyAccess, Version, ObjInfo, Comment, ElementDef, KeyMap, Addable };
+use ply::{ PropertyAccess, Version, ObjInfo, Comment, ElementDef, PropertyDef, KeyMap, Addable };
+use std::ops::Range;
+use std::fs::File;
+use std::path::Path;
+
+/// Buffer size used by `scan_headers()`, big enough for typical headers without reading far into the payload.
+const HEADER_SCAN_BUFFER_SIZE: usize = 1024;
 /*
 use util::LocationTracker;
 use super::Parser;
@@ -155,6 +473,53 @@ use std::result;
 // ////////////////////////
 /// #Header
 // ////////////////////////
+
+/// Result of parsing a single header line, with the byte positions it was found at.
+///
+/// Intended for tooling such as syntax highlighters, which need to know where each part of a line is.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Spanned<T> {
+    /// The parsed value.
+    pub value: T,
+    /// Byte range of the line content, without trailing white space and line break.
+    pub span: Range<usize>,
+    /// Byte ranges of the individual tokens, starting with the keyword.
+    ///
+    /// Free text, as found in comments and object informations, is a single token.
+    pub tokens: Vec<Range<usize>>,
+}
+
+impl<T> Spanned<T> {
+    fn new(value: T, line: &str, trailing_text: bool) -> Self {
+        let content = line.trim_right();
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, c) in content.char_indices() {
+            let is_space = c == ' ' || c == '\t';
+            match start {
+                Some(s) if is_space && !(trailing_text && !tokens.is_empty()) => {
+                    tokens.push(s..i);
+                    start = None;
+                },
+                None if !is_space => start = Some(i),
+                _ => (),
+            }
+        }
+        if let Some(s) = start {
+            tokens.push(s..content.len());
+        }
+        Spanned {
+            value: value,
+            span: 0..content.len(),
+            tokens: tokens,
+        }
+    }
+}
+
+fn unexpected_line<T>(line: &str, expected: &str) -> Result<T> {
+    Err(PlyError::UnexpectedLine { expected: expected.to_string(), found: line.to_string() }.into())
+}
+
 impl<E: PropertyAccess> Parser<E> {
     /// Reads header until and inclusive `end_header`.
     ///
@@ -164,13 +529,86 @@ impl<E: PropertyAccess> Parser<E> {
         let mut line = LocationTracker::new();
         self.__read_header(reader, &mut line)
     }
+    /// Reads only the header of each source, for cataloging many files quickly.
+    ///
+    /// Each source is read through a small buffer, so hardly any payload bytes are touched.
+    /// A failing source does not stop the scan, its error is reported at its position in the result.
+    pub fn scan_headers<I, T>(&self, sources: I) -> Vec<Result<Header>>
+        where I: IntoIterator<Item = T>, T: Read {
+        sources.into_iter().map(|source| {
+            let mut reader = BufReader::with_capacity(HEADER_SCAN_BUFFER_SIZE, source);
+            self.read_header(&mut reader)
+        }).collect()
+    }
+    /// Opens each file in `paths` and reads its header, see `scan_headers()`.
+    ///
+    /// Errors are attributed to their file, see `Error::in_file()`.
+    pub fn scan_header_files<I, P>(&self, paths: I) -> Vec<Result<Header>>
+        where I: IntoIterator<Item = P>, P: AsRef<Path> {
+        paths.into_iter().map(|path| self.scan_header_file(path)).collect()
+    }
+    /// Like `scan_header_files()`, but reads the files in parallel on the `rayon` thread pool.
+    ///
+    /// Requires the `rayon` feature. The results are in the order of `paths`.
+    #[cfg(feature = "rayon")]
+    pub fn scan_header_files_parallel<P>(&self, paths: &[P]) -> Vec<Result<Header>>
+        where P: AsRef<Path> + Sync, E: Sync {
+        use rayon::prelude::*;
+        paths.par_iter().map(|path| self.scan_header_file(path)).collect()
+    }
+    fn scan_header_file<P: AsRef<Path>>(&self, path: P) -> Result<Header> {
+        let path = path.as_ref();
+        File::open(path).and_then(|file| {
+            let mut reader = BufReader::with_capacity(HEADER_SCAN_BUFFER_SIZE, file);
+            self.read_header(&mut reader)
+        }).map_err(|e| PlyError::in_file(e, path))
+    }
     pub fn read_header_line(&self, line: &str) -> Result<Line> {
         match self.__read_header_line(line) {
             Ok(l) => Ok(l),
-            Err(e) => Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                format!("Couldn't parse line.\n\tString: {}\n\tError: {:?}", line, e)
-            )),
+            Err(e) => {
+                let mut location = LocationTracker::new();
+                location.next_line();
+                parse_ascii_rethrow(&location, line, e, "Couldn't parse line.")
+            },
+        }
+    }
+
+    /// Parses a `format` line.
+    pub fn parse_format_line(&self, line: &str) -> Result<Spanned<(Encoding, Version)>> {
+        match try!(self.read_header_line(line)) {
+            Line::Format(f) => Ok(Spanned::new(f, line, false)),
+            _ => unexpected_line(line, "format"),
+        }
+    }
+    /// Parses a `comment` line.
+    pub fn parse_comment_line(&self, line: &str) -> Result<Spanned<Comment>> {
+        match try!(self.read_header_line(line)) {
+            Line::Comment(c) => Ok(Spanned::new(c, line, true)),
+            _ => unexpected_line(line, "comment"),
+        }
+    }
+    /// Parses an `obj_info` line.
+    pub fn parse_obj_info_line(&self, line: &str) -> Result<Spanned<ObjInfo>> {
+        match try!(self.read_header_line(line)) {
+            Line::ObjInfo(o) => Ok(Spanned::new(o, line, true)),
+            _ => unexpected_line(line, "obj_info"),
+        }
+    }
+    /// Parses an `element` line.
+    ///
+    /// The returned definition has no properties, they follow on separate lines.
+    pub fn parse_element_line(&self, line: &str) -> Result<Spanned<ElementDef>> {
+        match try!(self.read_header_line(line)) {
+            Line::Element(e) => Ok(Spanned::new(e, line, false)),
+            _ => unexpected_line(line, "element"),
+        }
+    }
+    /// Parses a `property` line.
+    pub fn parse_property_line(&self, line: &str) -> Result<Spanned<PropertyDef>> {
+        match try!(self.read_header_line(line)) {
+            Line::Property(p) => Ok(Spanned::new(p, line, false)),
+            _ => unexpected_line(line, "property"),
         }
     }
 
@@ -211,6 +649,9 @@ impl<E: PropertyAccess> Parser<E> {
                 Ok(Line::MagicNumber) => return parse_ascii_error(location, &line_str, "Unexpected 'ply' found."),
                 Ok(Line::Format(ref t)) => (
                     if header_form_ver.is_none() {
+                        if let Err(e) = self.version_policy.check(&t.1) {
+                            return parse_ascii_error(location, &line_str, &e);
+                        }
                         header_form_ver = Some(t.clone());
                     } else {
                         let f = header_form_ver.unwrap();
@@ -255,10 +696,7 @@ impl<E: PropertyAccess> Parser<E> {
             location.next_line();
         }
         if header_form_ver.is_none() {
-            return Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "No format line found."
-            ));
+            return Err(PlyError::MissingFormat.into());
         }
         let (encoding, version) = header_form_ver.unwrap();
         Ok(Header{
@@ -311,6 +749,16 @@ impl<E: PropertyAccess> Parser<E> {
         }
         Ok(payload)
     }
+    /// Appends the derived properties registered for the element, see `add_derived_property()`.
+    fn derive_properties(&self, mut element: E, element_def: &ElementDef) -> E {
+        for &(ref element_name, ref property_def, ref compute) in &self.derived {
+            if *element_name == element_def.name {
+                let value = compute(&element);
+                element.set_property(property_def.name.clone(), value);
+            }
+        }
+        element
+    }
 }
 
 
@@ -341,13 +789,15 @@ impl<E: PropertyAccess> Parser<E> {
     fn __read_ascii_payload_for_element<T: BufRead>(&self, reader: &mut T, location: &mut LocationTracker, element_def: &ElementDef) -> Result<Vec<E>> {
         let mut elems = Vec::<E>::new();
         let mut line_str = String::new();
-        for _ in 0..element_def.count {
+        for i in 0..element_def.count {
             line_str.clear();
-            try!(reader.read_line(&mut line_str));
+            if try!(reader.read_line(&mut line_str)) == 0 {
+                return Err(PlyError::UnexpectedEof { element: element_def.name.clone(), index: i }.into());
+            }
 
             let element = match self.read_ascii_element(&line_str, element_def) {
                 Ok(e) => e,
-                Err(e) => return parse_ascii_rethrow(location, &line_str, e, "Couln't read element line.")
+                Err(e) => return ascii_entry_error(location, &line_str, e, element_def, i)
             };
             elems.push(element);
             location.next_line();
@@ -358,6 +808,13 @@ impl<E: PropertyAccess> Parser<E> {
     ///
     /// Make sure all elements are parsed in the order they are defined in the header.
     pub fn read_ascii_element(&self, line: &str, element_def: &ElementDef) -> Result<E> {
+        let element = try!(self.__read_ascii_element(line, element_def));
+        Ok(self.derive_properties(element, element_def))
+    }
+    fn __read_ascii_element(&self, line: &str, element_def: &ElementDef) -> Result<E> {
+        if let Some(decoder) = self.decoders.get(&element_def.name) {
+            return decoder(element_def, &mut line.as_bytes());
+        }
         let elems = match grammar::data_line(line) {
             Ok(e) => e,
             Err(ref e) => return Err(io::Error::new(
@@ -368,13 +825,14 @@ impl<E: PropertyAccess> Parser<E> {
 
         let mut elem_it : Iter<String> = elems.iter();
         let mut vals = E::new();
-        for (k, p) in &element_def.properties {
-            let new_p : Property = try!(self.__read_ascii_property(&mut elem_it, &p.data_type));
-            vals.set_property(k.clone(), new_p);
+        for (i, (k, p)) in element_def.properties.iter().enumerate() {
+            let new_p : Property = try!(self.__read_ascii_property(&mut elem_it, p));
+            vals.set_by_index(i, k, new_p);
         }
         Ok(vals)
     }
-    fn __read_ascii_property(&self, elem_iter: &mut Iter<String>, data_type: &PropertyType) -> Result<Property> {
+    fn __read_ascii_property(&self, elem_iter: &mut Iter<String>, property_def: &PropertyDef) -> Result<Property> {
+        let data_type = &property_def.data_type;
         let s : &String = match elem_iter.next() {
             None => return Err(io::Error::new(
                 ErrorKind::InvalidInput,
@@ -395,7 +853,7 @@ impl<E: PropertyAccess> Parser<E> {
                 ScalarType::Double => Property::Double(try!(self.parse(s))),
             },
             PropertyType::List(_, ref scalar_type) => {
-                let count : usize = try!(self.parse(s));
+                let count = try!(self.check_list_len(try!(self.parse(s)), property_def));
                 match *scalar_type {
                     ScalarType::Char => Property::ListChar(try!(self.__read_ascii_list(elem_iter, count))),
                     ScalarType::UChar => Property::ListUChar(try!(self.__read_ascii_list(elem_iter, count))),
@@ -481,24 +939,37 @@ impl<E: PropertyAccess> Parser<E> {
 
     fn __read_binary_payload_for_element<T: Read, B: ByteOrder>(&self, reader: &mut T, location: &mut LocationTracker, element_def: &ElementDef) -> Result<Vec<E>> {
         let mut elems = Vec::<E>::new();
-        for _ in 0..element_def.count {
-            let element = try!(self.__read_binary_element::<T, B>(reader, element_def));
+        for i in 0..element_def.count {
+            let element = match self.__read_binary_element::<T, B>(reader, element_def) {
+                Ok(e) => e,
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Err(
+                    PlyError::UnexpectedEof { element: element_def.name.clone(), index: i }.into()
+                ),
+                Err(e) => return Err(entry_error(e, element_def, i)),
+            };
             elems.push(element);
             location.next_line();
         }
         Ok(elems)
     }
     fn __read_binary_element<T: Read, B: ByteOrder>(&self, reader: &mut T, element_def: &ElementDef) -> Result<E> {
+        let element = try!(self.__decode_binary_element::<T, B>(reader, element_def));
+        Ok(self.derive_properties(element, element_def))
+    }
+    fn __decode_binary_element<T: Read, B: ByteOrder>(&self, reader: &mut T, element_def: &ElementDef) -> Result<E> {
+        if let Some(decoder) = self.decoders.get(&element_def.name) {
+            return decoder(element_def, reader);
+        }
         let mut raw_element = E::new();
 
-        for (k, p) in &element_def.properties {
-            let property = try!(self.__read_binary_property::<T, B>(reader, &p.data_type));
-            raw_element.set_property(k.clone(), property);
+        for (i, (k, p)) in element_def.properties.iter().enumerate() {
+            let property = try!(self.__read_binary_property::<T, B>(reader, p));
+            raw_element.set_by_index(i, k, property);
         }
         Ok(raw_element)
     }
-    fn __read_binary_property<T: Read, B: ByteOrder>(&self, reader: &mut T, data_type: &PropertyType) -> Result<Property> {
-        let result = match *data_type {
+    fn __read_binary_property<T: Read, B: ByteOrder>(&self, reader: &mut T, property_def: &PropertyDef) -> Result<Property> {
+        let result = match property_def.data_type {
             PropertyType::Scalar(ref scalar_type) => match *scalar_type {
                 ScalarType::Char => Property::Char(try!(reader.read_i8())),
                 ScalarType::UChar => Property::UChar(try!(reader.read_u8())),
@@ -510,16 +981,20 @@ impl<E: PropertyAccess> Parser<E> {
                 ScalarType::Double => Property::Double(try!(reader.read_f64::<B>())),
             },
             PropertyType::List(ref index_type, ref property_type) => {
-                let count : usize = match *index_type {
-                    ScalarType::Char => try!(reader.read_i8()) as usize,
-                    ScalarType::UChar => try!(reader.read_u8()) as usize,
-                    ScalarType::Short => try!(reader.read_i16::<B>()) as usize,
-                    ScalarType::UShort => try!(reader.read_u16::<B>()) as usize,
-                    ScalarType::Int => try!(reader.read_i32::<B>()) as usize,
-                    ScalarType::UInt => try!(reader.read_u32::<B>()) as usize,
+                let count : i64 = match *index_type {
+                    ScalarType::Char => try!(reader.read_i8()) as i64,
+                    ScalarType::UChar => try!(reader.read_u8()) as i64,
+                    ScalarType::Short => try!(reader.read_i16::<B>()) as i64,
+                    ScalarType::UShort => try!(reader.read_u16::<B>()) as i64,
+                    ScalarType::Int => try!(reader.read_i32::<B>()) as i64,
+                    ScalarType::UInt => try!(reader.read_u32::<B>()) as i64,
                     ScalarType::Float => return Err(io::Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, float declared in ScalarType.")),
                     ScalarType::Double => return Err(io::Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, double declared in ScalarType.")),
                 };
+                if count < 0 {
+                    return Err(PlyError::NegativeListLength { property: property_def.name.clone(), len: count }.into());
+                }
+                let count = try!(self.check_list_len(count as u64, property_def));
                 match *property_type {
                     ScalarType::Char => Property::ListChar(try!(self.__read_binary_list(reader, &|r| r.read_i8(), count))),
                     ScalarType::UChar => Property::ListUChar(try!(self.__read_binary_list(reader, &|r| r.read_u8(), count))),
@@ -534,13 +1009,19 @@ impl<E: PropertyAccess> Parser<E> {
         };
         Ok(result)
     }
+    fn check_list_len(&self, len: u64, property_def: &PropertyDef) -> Result<usize> {
+        if len > self.max_list_len || len > usize::max_value() as u64 {
+            return Err(PlyError::ListTooLong { property: property_def.name.clone(), len: len, max: self.max_list_len }.into());
+        }
+        Ok(len as usize)
+    }
     fn __read_binary_list<T: Read, D: FromStr>(&self, reader: &mut T, read_from: &Fn(&mut T) -> Result<D>, count: usize) -> Result<Vec<D>>
         where <D as FromStr>::Err: error::Error + marker::Send + marker::Sync + 'static {
         let mut list = Vec::<D>::new();
         for i in 0..count {
             let value : D = match read_from(reader) {
                 Err(e) => return Err(io::Error::new(
-                    ErrorKind::InvalidInput,
+                    e.kind(),
                     format!("Couldn't find a list element at index {}.\n\tError: {:?}", i, e)
                 )),
                 Ok(x) => x
@@ -552,13 +1033,85 @@ impl<E: PropertyAccess> Parser<E> {
 }
 
 
+// ////////////////////////////////////////////////////////////////
+// # Fixed layout
+// ////////////////////////////////////////////////////////////////
+
+/// # Fixed layout
+impl<E: PropertyAccess> Parser<E> {
+    /// Reads all entries of an element made of exactly `N` float properties into arrays.
+    ///
+    /// Covers the common layouts like `x y z` or `x y z nx ny nz` without going through `E`,
+    /// the values keep the order of the properties in `element_def`.
+    /// Fails if the element has a different number of properties or any of them isn't a scalar `float`.
+    /// Element decoders and derived properties don't apply.
+    ///
+    /// The reader must be positioned at the start of the element's payload, any encoding is supported.
+    pub fn read_fixed<T: BufRead, const N: usize>(&self, reader: &mut T, element_def: &ElementDef, header: &Header) -> Result<Vec<[f32; N]>> {
+        if element_def.properties.len() != N {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` has {} properties, expected {}.", element_def.name, element_def.properties.len(), N)
+            ));
+        }
+        for property_def in element_def.properties.values() {
+            if property_def.data_type != PropertyType::Scalar(ScalarType::Float) {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Property `{}` of element `{}` is not a float.", property_def.name, element_def.name)
+                ));
+            }
+        }
+        let mut records = Vec::with_capacity(element_def.count.min(1 << 20) as usize);
+        let mut line = String::new();
+        for i in 0..element_def.count {
+            let mut record = [0.0; N];
+            let read = match header.encoding {
+                Encoding::Ascii => {
+                    line.clear();
+                    try!(reader.read_line(&mut line));
+                    read_fixed_ascii(&line, &mut record)
+                },
+                Encoding::BinaryBigEndian => reader.read_f32_into::<BigEndian>(&mut record),
+                Encoding::BinaryLittleEndian => reader.read_f32_into::<LittleEndian>(&mut record),
+            };
+            if let Err(e) = read {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("Couldn't read entry {} of element `{}`.\n\tError: {}", i, element_def.name, e)
+                ));
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+}
+
+/// Parses an ascii line holding exactly as many floats as `record`.
+fn read_fixed_ascii(line: &str, record: &mut [f32]) -> Result<()> {
+    let mut values = line.split_whitespace();
+    for v in record.iter_mut() {
+        *v = match values.next().map(|s| s.parse()) {
+            Some(Ok(f)) => f,
+            Some(Err(e)) => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Invalid float: {}", e))),
+            None => return Err(io::Error::new(ErrorKind::InvalidInput, "Line has too few values.")),
+        };
+    }
+    if values.next().is_some() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Line has too many values."));
+    }
+    Ok(())
+}
+
+
 
 #[cfg(test)]
 mod tests {
     use super::grammar as g;
     use super::Line;
-    use parser::Parser;
-    use ply::{ DefaultElement, PropertyDef, Version, Encoding, ScalarType, PropertyType, ElementDef, KeyMap, Addable };
+    use std::io::{ BufRead, BufReader, Read };
+    use parser::{ Parser, VersionPolicy, BufferSize, Rebuffered };
+    use ply::{ Header, DefaultElement, PropertyDef, Version, Encoding, ScalarType, PropertyType, ElementDef, KeyMap, Addable };
     macro_rules! assert_ok {
         ($e:expr) => (
             match $e {
@@ -628,6 +1181,72 @@ mod tests {
         assert_ok!(p.read_ply(&mut bytes));
     }
     #[test]
+    fn parser_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Parser<DefaultElement>>();
+    }
+    #[test]
+    fn rebuffer_only_when_resized() {
+        let mut header = Header::new();
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Double)));
+        header.elements.add(e);
+        let mut p = Parser::<DefaultElement>::new();
+        let kept = |p: &Parser<DefaultElement>, header: &Header| {
+            let source = BufReader::with_capacity(p.header_buffer_size(), &b"1 2 3"[..]);
+            match p.rebuffer(source, header) {
+                Rebuffered::Kept(_) => true,
+                Rebuffered::Resized(_) => false,
+            }
+        };
+        assert!(kept(&p, &header));
+        p.set_buffer_size(BufferSize::Adaptive);
+        assert!(kept(&p, &header));
+        header.elements["vertex"].count = 1 << 20;
+        assert!(!kept(&p, &header));
+        let mut source = BufReader::with_capacity(2, &b"1 2 3"[..]);
+        source.fill_buf().unwrap();
+        let mut rest = String::new();
+        p.rebuffer(source, &header).read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "1 2 3");
+    }
+    #[test]
+    fn parser_version_policy() {
+        let txt = "ply\nformat ascii 2.1\nend_header\n";
+        let mut p = Parser::<DefaultElement>::new();
+        assert_ok!(p.read_header(&mut txt.as_bytes()));
+
+        p.set_version_policy(VersionPolicy::Strict);
+        assert_err!(p.read_header(&mut txt.as_bytes()));
+        assert_ok!(p.read_header(&mut "ply\nformat ascii 1.0\nend_header\n".as_bytes()));
+
+        p.set_version_policy(VersionPolicy::Custom(Box::new(|v: &Version| {
+            if v.major == 2 { Ok(()) } else { Err("Only 2.x supported.".to_string()) }
+        })));
+        assert_ok!(p.read_header(&mut txt.as_bytes()));
+        assert_err!(p.read_header(&mut "ply\nformat ascii 1.0\nend_header\n".as_bytes()));
+    }
+    #[test]
+    fn parse_lines_spanned() {
+        let p = Parser::<DefaultElement>::new();
+        let e = assert_ok!(p.parse_element_line("element vertex  8 \r\n"));
+        assert_eq!(e.value.name, "vertex");
+        assert_eq!(e.value.count, 8);
+        assert_eq!(e.span, 0..17);
+        assert_eq!(e.tokens, vec![0..7, 8..14, 16..17]);
+
+        let pr = assert_ok!(p.parse_property_line("property list uchar int vertex_index\n"));
+        assert_eq!(pr.value, PropertyDef::new("vertex_index".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::Int)));
+        assert_eq!(pr.tokens.len(), 5);
+
+        let c = assert_ok!(p.parse_comment_line("comment made by  hand\n"));
+        assert_eq!(c.value, "made by  hand");
+        assert_eq!(c.tokens, vec![0..7, 8..21]);
+
+        assert_err!(p.parse_element_line("property float x"));
+        assert_err!(p.parse_format_line("format ascii"));
+    }
+    #[test]
     fn read_property_ok() {
         let p = Parser::<DefaultElement>::new();
         let txt = "0 1 2 3";
@@ -702,6 +1321,45 @@ mod tests {
         );
     }
     #[test]
+    fn element_huge_count_ok() {
+        let mut e = ElementDef::new("vertex".to_string());
+        e.count = 5_000_000_000;
+        assert_ok!(
+            g::element("element vertex 5000000000"),
+            e
+        );
+        let p = Parser::<DefaultElement>::new();
+        let txt = "ply\nformat binary_little_endian 1.0\nelement vertex 18446744073709551615\nproperty uchar x\nend_header\n";
+        let header = assert_ok!(p.read_header(&mut txt.as_bytes()));
+        assert_eq!(header.elements["vertex"].count, u64::max_value());
+    }
+    #[test]
+    fn number_overflow_err() {
+        assert_err!(g::element("element vertex 18446744073709551616"));
+        assert_err!(g::format("format ascii 65536.0"));
+        assert_err!(g::format("format ascii 1.256"));
+        let p = Parser::<DefaultElement>::new();
+        let txt = "ply\nformat ascii 1.0\nelement vertex 99999999999999999999\nend_header\n";
+        assert_err!(p.read_header(&mut txt.as_bytes()));
+    }
+    #[test]
+    fn max_list_len_err() {
+        let mut p = Parser::<DefaultElement>::new();
+        p.set_max_list_len(3);
+        let txt = b"ply\nformat binary_big_endian 1.0\nelement face 2\nproperty list uint uchar vertex_index\nend_header\n\
+            \x00\x00\x00\x03\x01\x02\x03\xff\xff\xff\xff";
+        let err = p.read_ply(&mut &txt[..]).unwrap_err().to_string();
+        assert!(err.contains("entry 1 of element `face`"), err);
+        assert!(err.contains("List `vertex_index` has 4294967295 entries"), err);
+        let txt = b"ply\nformat binary_big_endian 1.0\nelement face 1\nproperty list int uchar vertex_index\nend_header\n\xff\xff\xff\xff";
+        let err = p.read_ply(&mut &txt[..]).unwrap_err().to_string();
+        assert!(err.contains("negative length"), err);
+
+        p.set_max_list_len(3);
+        let txt = "ply\nformat ascii 1.0\nelement face 1\nproperty list uchar int vertex_index\nend_header\n4 1 2 3 4\n";
+        assert!(p.read_ply(&mut txt.as_bytes()).unwrap_err().to_string().contains("List `vertex_index` has 4 entries"));
+    }
+    #[test]
     fn element_err() {
         assert_err!(g::comment("element 8 vertex"));
     }
@@ -0,0 +1,113 @@
+//! Decodes entries straight into structs deriving `Deserialize`, requires the `serde` feature.
+
+use std::io;
+use std::io::{ BufRead, Result, ErrorKind };
+use std::slice::Iter;
+
+use byteorder::{ BigEndian, LittleEndian, ByteOrder };
+use serde::de::DeserializeOwned;
+
+use ply::{ PropertyAccess, ElementDef, Header, Encoding, Property, Error as PlyError };
+use serde_element::from_values;
+use super::{ Parser, grammar, entry_error };
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Reads all entries of `element_def` into structs, see the `serde_element` module for how fields are matched.
+    ///
+    /// Unlike `read_payload_for_element()` followed by `serde_element::from_elements()`,
+    /// no `E` is built per entry: the values of an entry are decoded into a reused buffer
+    /// and deserialized from there. Element decoders and derived properties don't apply.
+    ///
+    /// The reader must be positioned at the start of the element's payload, any encoding is supported.
+    pub fn read_deserialized_elements<T: DeserializeOwned, R: BufRead>(&self, reader: &mut R, element_def: &ElementDef, header: &Header) -> Result<Vec<T>> {
+        let mut entries = Vec::new();
+        let mut values = Vec::with_capacity(element_def.properties.len());
+        let mut line = String::new();
+        for i in 0..element_def.count {
+            values.clear();
+            let read = match header.encoding {
+                Encoding::Ascii => {
+                    line.clear();
+                    if try!(reader.read_line(&mut line)) == 0 {
+                        return Err(PlyError::UnexpectedEof { element: element_def.name.clone(), index: i }.into());
+                    }
+                    self.__decode_ascii_values(&line, element_def, &mut values)
+                },
+                Encoding::BinaryBigEndian => self.__decode_binary_values::<R, BigEndian>(reader, element_def, &mut values),
+                Encoding::BinaryLittleEndian => self.__decode_binary_values::<R, LittleEndian>(reader, element_def, &mut values),
+            };
+            let entry = read.and_then(|_| from_values(element_def, &values));
+            match entry {
+                Ok(e) => entries.push(e),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Err(
+                    PlyError::UnexpectedEof { element: element_def.name.clone(), index: i }.into()
+                ),
+                Err(e) => return Err(entry_error(e, element_def, i)),
+            }
+        }
+        Ok(entries)
+    }
+    fn __decode_ascii_values(&self, line: &str, element_def: &ElementDef, values: &mut Vec<Property>) -> Result<()> {
+        let elems = match grammar::data_line(line) {
+            Ok(e) => e,
+            Err(ref e) => return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Couldn't parse element line.\n\tString: '{}'\n\tError: {}", line, e)
+            )),
+        };
+        let mut elem_it: Iter<String> = elems.iter();
+        for p in element_def.properties.values() {
+            values.push(try!(self.__read_ascii_property(&mut elem_it, p)));
+        }
+        Ok(())
+    }
+    fn __decode_binary_values<R: BufRead, B: ByteOrder>(&self, reader: &mut R, element_def: &ElementDef, values: &mut Vec<Property>) -> Result<()> {
+        for p in element_def.properties.values() {
+            values.push(try!(self.__read_binary_property::<R, B>(reader, p)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ply::*;
+    use parser::Parser;
+    use writer::Writer;
+    use serde_element::{ to_element, Serialized };
+
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    struct Vertex {
+        x: f32,
+        indices: Vec<i32>,
+    }
+
+    #[test]
+    fn serde_roundtrip() {
+        let vertices: Vec<Vertex> = (0..5).map(|i| Vertex { x: i as f32 * 0.5, indices: vec![i, -i] }).collect();
+        let mut ply = Ply::<DefaultElement>::new();
+        ply.payload.insert("vertex".to_string(), vertices.iter().map(|v| to_element(v).unwrap()).collect());
+        ply.header = Header::infer_from_payload(&ply.payload).unwrap();
+        for encoding in &[Encoding::Ascii, Encoding::BinaryBigEndian, Encoding::BinaryLittleEndian] {
+            ply.header.encoding = *encoding;
+            let w = Writer::<DefaultElement>::new();
+            let mut expected = Vec::new();
+            w.write_ply(&mut expected, &mut ply).unwrap();
+            let mut buf = Vec::new();
+            w.write_header(&mut buf, &ply.header).unwrap();
+            w.write_payload_of_serialized(&mut buf, vertices.iter().map(Serialized), &ply.header.elements["vertex"], &ply.header).unwrap();
+            let p = Parser::<DefaultElement>::new();
+            if *encoding == Encoding::Ascii {
+                // The writer ends ascii lines with a space, records passed as serialized don't.
+                assert_eq!(p.read_ply(&mut &buf[..]).unwrap(), p.read_ply(&mut &expected[..]).unwrap());
+            } else {
+                assert_eq!(buf, expected);
+            }
+
+            let mut reader = &buf[..];
+            let header = p.read_header(&mut reader).unwrap();
+            let read: Vec<Vertex> = p.read_deserialized_elements(&mut reader, &header.elements["vertex"], &header).unwrap();
+            assert_eq!(read, vertices);
+        }
+    }
+}
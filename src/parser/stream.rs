@@ -0,0 +1,316 @@
+//! Reads the payload entry by entry instead of materializing it, see `Parser::read_ply_iter()`.
+
+use std::io;
+use std::io::{ Read, BufRead, BufReader, Result, ErrorKind };
+
+use ply::{ PropertyAccess, Header, ElementDef, Encoding, Error as PlyError };
+use util::{ LocationTracker, Crc32, split_crc_obj_info };
+use super::{ Parser, Rebuffered, entry_error };
+
+/// Reader handing out the elements of a PLY file one after the other, created by `Parser::read_ply_iter()`.
+///
+/// Only the entry currently decoded is held in memory,
+/// which makes files larger than the available memory processable.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// # use ply_rs::parser::Parser;
+/// # use ply_rs::ply::DefaultElement;
+/// let parser = Parser::<DefaultElement>::new();
+/// let mut f = std::fs::File::open("scan.ply").unwrap();
+/// let mut reader = parser.read_ply_iter(&mut f).unwrap();
+/// while let Some(entries) = reader.next_element().unwrap() {
+///     let name = entries.element_def().name.clone();
+///     let mut count = 0;
+///     for entry in entries {
+///         let _entry = entry.unwrap();
+///         count += 1;
+///     }
+///     println!("{}: {} entries", name, count);
+/// }
+/// ```
+pub struct PlyReader<'a, E: PropertyAccess + 'a> {
+    parser: &'a Parser<E>,
+    reader: Rebuffered<Box<Read + 'a>>,
+    header: Header,
+    /// Element definitions as found in the file, without derived properties.
+    elements: Vec<ElementDef>,
+    next_element: usize,
+    remaining: u64,
+    line: String,
+    failed: bool,
+    /// Byte offset of the next entry.
+    offset: u64,
+    /// Line number of the next entry, counting from 1.
+    line_number: u64,
+    /// Location of the last entry, if it failed to decode but could be skipped.
+    skipped: Option<EntrySpan>,
+    /// Checksum of the current element so far and the one recorded in the header, if it is verified.
+    crc: Option<(Crc32, u32)>,
+}
+
+/// Location of an entry in the file, see `ElementEntries::with_spans()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntrySpan {
+    /// Byte offset of the entry from the start of the file.
+    pub offset: u64,
+    /// Number of bytes the entry occupies, including the line break in ascii files.
+    pub len: u64,
+    /// Line number of the entry, counting from 1. Only set for ascii files.
+    pub line: Option<u64>,
+}
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Reads the header of `source` and returns a reader for its payload.
+    ///
+    /// Unlike `read_ply()`, entries are decoded only when requested from the returned `PlyReader`.
+    /// Decoders, derived properties and buffer sizes set on the parser apply as usual.
+    pub fn read_ply_iter<'a, T: Read + 'a>(&'a self, source: T) -> Result<PlyReader<'a, E>> {
+        let source: Box<Read + 'a> = Box::new(source);
+        let mut source = BufReader::with_capacity(self.header_buffer_size(), source);
+        let mut location = LocationTracker::new();
+        let (mut header, header_len) = {
+            let mut counted = Counted { inner: &mut source, count: 0, crc: None };
+            let header = try!(self.__read_header(&mut counted, &mut location));
+            (header, counted.count)
+        };
+        let reader = self.rebuffer(source, &header);
+        let elements = header.elements.values().cloned().collect();
+        self.declare_derived_properties(&mut header);
+        Ok(PlyReader {
+            parser: self,
+            reader: reader,
+            header: header,
+            elements: elements,
+            next_element: 0,
+            remaining: 0,
+            line: String::new(),
+            failed: false,
+            offset: header_len,
+            line_number: location.line_index,
+            skipped: None,
+            crc: None,
+        })
+    }
+}
+
+impl<'a, E: PropertyAccess> PlyReader<'a, E> {
+    /// Header of the file, including derived properties.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Moves on to the next element in header order, `None` once all elements have been read.
+    ///
+    /// Entries of the previous element that weren't consumed are read and dropped.
+    /// After an error, the position in the file is unknown and all further calls fail.
+    /// The exception is an ascii line that can't be decoded: it is skipped
+    /// and reading continues with the next line, see `SpannedEntries::skipped_entry()`.
+    pub fn next_element<'b>(&'b mut self) -> Result<Option<ElementEntries<'b, 'a, E>>> {
+        if self.failed {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "A previous error left the reader at an unknown position."));
+        }
+        if self.next_element > 0 {
+            let previous = self.next_element - 1;
+            while self.remaining > 0 {
+                try!(self.read_entry(previous));
+            }
+        }
+        if self.next_element >= self.elements.len() {
+            return Ok(None);
+        }
+        let element = self.next_element;
+        self.next_element += 1;
+        self.remaining = self.elements[element].count;
+        self.crc = None;
+        if self.parser.verify_element_crcs && self.remaining > 0 {
+            if let Some(expected) = try!(recorded_crc(&self.header, &self.elements[element].name)) {
+                self.crc = Some((Crc32::new(), expected));
+            }
+        }
+        Ok(Some(ElementEntries {
+            reader: self,
+            element: element,
+        }))
+    }
+    fn read_entry(&mut self, element: usize) -> Result<(EntrySpan, E)> {
+        let element_def = &self.elements[element];
+        let mut reader = Counted { inner: &mut self.reader, count: 0, crc: self.crc.as_mut().map(|c| &mut c.0) };
+        // A complete ascii line that fails to decode can be skipped, the next entry starts on the next line.
+        let (entry, line, skippable) = match self.header.encoding {
+            Encoding::Ascii => {
+                self.line.clear();
+                match reader.read_line(&mut self.line) {
+                    Ok(0) => (Err(io::Error::new(ErrorKind::UnexpectedEof, "Unexpected end of file.")), None, false),
+                    Ok(_) => (self.parser.read_ascii_element(&self.line, element_def), Some(self.line_number), true),
+                    Err(e) => (Err(e), None, false),
+                }
+            },
+            Encoding::BinaryBigEndian => (self.parser.read_big_endian_element(&mut reader, element_def), None, false),
+            Encoding::BinaryLittleEndian => (self.parser.read_little_endian_element(&mut reader, element_def), None, false),
+        };
+        let span = EntrySpan {
+            offset: self.offset,
+            len: reader.count,
+            line: line,
+        };
+        self.skipped = None;
+        match entry {
+            Ok(e) => {
+                self.offset += span.len;
+                self.line_number += 1;
+                self.remaining -= 1;
+                try!(self.check_crc(element));
+                Ok((span, e))
+            },
+            Err(e) if skippable => {
+                let index = element_def.count - self.remaining;
+                self.offset += span.len;
+                self.line_number += 1;
+                self.remaining -= 1;
+                self.skipped = Some(span);
+                Err(entry_error(e, element_def, index))
+            },
+            Err(e) => {
+                self.failed = true;
+                let index = element_def.count - self.remaining;
+                self.remaining = 0;
+                if e.kind() == ErrorKind::UnexpectedEof {
+                    return Err(PlyError::UnexpectedEof { element: element_def.name.clone(), index: index }.into());
+                }
+                Err(entry_error(e, element_def, index))
+            },
+        }
+    }
+    /// Compares the checksum of `element` with the recorded one, once its last entry was read.
+    fn check_crc(&mut self, element: usize) -> Result<()> {
+        if self.remaining > 0 {
+            return Ok(());
+        }
+        match self.crc.take() {
+            Some((ref crc, expected)) if crc.value() != expected => Err(PlyError::ChecksumMismatch {
+                element: self.elements[element].name.clone(),
+                expected: expected,
+                found: crc.value(),
+            }.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checksum recorded for `element` by `Writer::set_element_crcs()`, if any.
+fn recorded_crc(header: &Header, element: &str) -> Result<Option<u32>> {
+    for obj_info in &header.obj_infos {
+        match split_crc_obj_info(obj_info) {
+            Some((name, crc)) if name == element => return match u32::from_str_radix(crc, 16) {
+                Ok(crc) => Ok(Some(crc)),
+                Err(_) => Err(io::Error::new(ErrorKind::InvalidData, format!("Malformed checksum `{}` for element `{}`.", crc, element))),
+            },
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+/// Iterator over the entries of one element, see `PlyReader::next_element()`.
+pub struct ElementEntries<'b, 'a: 'b, E: PropertyAccess + 'a> {
+    reader: &'b mut PlyReader<'a, E>,
+    element: usize,
+}
+
+impl<'b, 'a, E: PropertyAccess> ElementEntries<'b, 'a, E> {
+    /// Definition of the element as found in the file, without derived properties.
+    pub fn element_def(&self) -> &ElementDef {
+        &self.reader.elements[self.element]
+    }
+    /// Turns the iterator into one that also yields where each entry is located in the file.
+    ///
+    /// Lets checks running later in a pipeline point back to the offending line or byte range.
+    pub fn with_spans(self) -> SpannedEntries<'b, 'a, E> {
+        SpannedEntries { entries: self }
+    }
+}
+
+impl<'b, 'a, E: PropertyAccess> Iterator for ElementEntries<'b, 'a, E> {
+    type Item = Result<E>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.remaining == 0 {
+            return None;
+        }
+        Some(self.reader.read_entry(self.element).map(|(_, e)| e))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.reader.remaining;
+        if remaining > usize::max_value() as u64 {
+            return (usize::max_value(), None);
+        }
+        (remaining as usize, Some(remaining as usize))
+    }
+}
+
+/// Iterator over the entries of one element and their locations, see `ElementEntries::with_spans()`.
+pub struct SpannedEntries<'b, 'a: 'b, E: PropertyAccess + 'a> {
+    entries: ElementEntries<'b, 'a, E>,
+}
+
+impl<'b, 'a, E: PropertyAccess> SpannedEntries<'b, 'a, E> {
+    /// Definition of the element as found in the file, without derived properties.
+    pub fn element_def(&self) -> &ElementDef {
+        self.entries.element_def()
+    }
+    /// Location and text of the entry that failed to decode in the last call to `next()`, if it was skipped.
+    ///
+    /// Only ascii lines are skipped, iteration then continues with the next line.
+    /// `None` after any other error, the reader is then at an unknown position.
+    pub fn skipped_entry(&self) -> Option<(EntrySpan, &str)> {
+        let reader = &self.entries.reader;
+        reader.skipped.map(|span| (span, &reader.line[..]))
+    }
+}
+
+impl<'b, 'a, E: PropertyAccess> Iterator for SpannedEntries<'b, 'a, E> {
+    type Item = Result<(EntrySpan, E)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.entries.reader.remaining == 0 {
+            return None;
+        }
+        Some(self.entries.reader.read_entry(self.entries.element))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+/// Adapter counting the bytes consumed from `inner`, also feeding them into `crc` if set.
+struct Counted<'c, R> {
+    inner: R,
+    count: u64,
+    crc: Option<&'c mut Crc32>,
+}
+
+impl<'c, R: BufRead> Read for Counted<'c, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = try!(self.inner.read(buf));
+        self.count += n as u64;
+        if let Some(ref mut crc) = self.crc {
+            crc.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl<'c, R: BufRead> BufRead for Counted<'c, R> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        if let Some(ref mut crc) = self.crc {
+            // The bytes are still buffered, as `consume` must follow a `fill_buf` returning at least `amt` bytes.
+            if let Ok(buf) = self.inner.fill_buf() {
+                crc.update(&buf[..amt]);
+            }
+        }
+        self.inner.consume(amt);
+        self.count += amt as u64;
+    }
+}
@@ -0,0 +1,227 @@
+//! Compact differences between two versions of a PLY with the same schema, for example to sync datasets.
+//!
+//! A patch is a standard PLY in the format of the delta frames of the `sequence` module:
+//! every element holds the changed and inserted entries, tagged with their index in the new version in `delta_index`.
+//! Where entries were inserted or removed, an object information line
+//! `patch_splice <element> <start> <removed> <inserted>` records the range,
+//! so unchanged entries after it don't need to be shipped.
+
+use std::io;
+use std::io::{ Result, ErrorKind };
+
+use ply::{ Ply, DefaultElement, ElementDef, PropertyDef, PropertyType, ScalarType, Property, Addable };
+use sequence::{ same_schema, apply_delta, DELTA_INDEX_PROPERTY };
+
+/// Prefix of the object information lines recording inserted and removed ranges.
+pub const SPLICE_OBJ_INFO: &'static str = "patch_splice";
+
+/// Computes the patch turning `old` into `new`.
+///
+/// Per element, the entries both versions start and end with are skipped.
+/// In the range between, entries are compared by index, changed entries only keep the properties
+/// that changed in any of them. If the range differs in length, the surplus of `new` is stored completely
+/// as inserted entries, the surplus of `old` is removed by `apply()`.
+/// A single inserted or removed range costs nothing beyond its own entries,
+/// with several of them the entries between are stored as changed.
+/// Elements without any change are left out.
+///
+/// Both must have the same schema, see `sequence::same_schema()`.
+pub fn diff(old: &Ply<DefaultElement>, new: &Ply<DefaultElement>) -> Result<Ply<DefaultElement>> {
+    if !same_schema(&old.header, &new.header) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "The files have different schemas."));
+    }
+    let mut patch = Ply::<DefaultElement>::new();
+    patch.header.encoding = new.header.encoding;
+    patch.header.version = new.header.version;
+    patch.header.comments = new.header.comments.clone();
+    patch.header.obj_infos = new.header.obj_infos.clone();
+    for (name, element_def) in &new.header.elements {
+        let empty = Vec::new();
+        let old_entries = old.payload.get(name).unwrap_or(&empty);
+        let entries = new.payload.get(name).unwrap_or(&empty);
+        if entries.len() > u32::max_value() as usize {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` has too many entries for a delta index.", name)));
+        }
+        let shorter = ::std::cmp::min(old_entries.len(), entries.len());
+        let prefix = (0..shorter).take_while(|&i| entries[i] == old_entries[i]).count();
+        let suffix = (0..shorter - prefix)
+            .take_while(|&i| entries[entries.len() - 1 - i] == old_entries[old_entries.len() - 1 - i])
+            .count();
+        let old_end = old_entries.len() - suffix;
+        let end = entries.len() - suffix;
+        // Compared by index from `prefix` up to `splice`, inserted or removed from there.
+        let splice = ::std::cmp::min(old_end, end);
+        let changed: Vec<usize> = (prefix..splice).filter(|&i| entries[i] != old_entries[i]).collect();
+        if changed.is_empty() && old_end == end {
+            continue;
+        }
+        if old_end != end {
+            patch.header.obj_infos.push(format!("{} {} {} {} {}", SPLICE_OBJ_INFO, name, splice, old_end - splice, end - splice));
+        }
+        let mut patch_def = ElementDef::new(name.clone());
+        patch_def.properties.add(PropertyDef::new(DELTA_INDEX_PROPERTY.to_string(), PropertyType::Scalar(ScalarType::UInt)));
+        for (k, p) in &element_def.properties {
+            if end > splice || changed.iter().any(|&i| entries[i].get(k) != old_entries[i].get(k)) {
+                patch_def.properties.add(p.clone());
+            }
+        }
+        let list = changed.into_iter().chain(splice..end).map(|i| {
+            let mut e = DefaultElement::new();
+            e.insert(DELTA_INDEX_PROPERTY.to_string(), Property::UInt(i as u32));
+            for k in patch_def.properties.keys().skip(1) {
+                if let Some(v) = entries[i].get(k) {
+                    e.insert(k.clone(), v.clone());
+                }
+            }
+            e
+        }).collect::<Vec<_>>();
+        patch_def.count = list.len() as u64;
+        patch.header.elements.add(patch_def);
+        patch.payload.insert(name.clone(), list);
+    }
+    Ok(patch)
+}
+
+/// Reconstructs the new version from `old` and a `patch` created by `diff()`.
+///
+/// Splices are applied in the order of the object information lines, before the changed entries.
+pub fn apply(old: &Ply<DefaultElement>, patch: &Ply<DefaultElement>) -> Result<Ply<DefaultElement>> {
+    let mut resized = old.clone();
+    let mut inserted = Vec::new();
+    for obj_info in &patch.header.obj_infos {
+        let splice = match parse_splice(obj_info) {
+            None => continue,
+            Some(Ok(s)) => s,
+            Some(Err(e)) => return Err(e),
+        };
+        let entries = match resized.payload.get_mut(&splice.element) {
+            Some(e) => e,
+            None => return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Patch splices element `{}` missing in the original.", splice.element)
+            )),
+        };
+        if splice.start > entries.len() || splice.removed > entries.len() - splice.start {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Object information `{}` exceeds the original entries.", obj_info)));
+        }
+        let end = splice.start + splice.removed;
+        entries.splice(splice.start..end, ::std::iter::repeat(DefaultElement::new()).take(splice.inserted));
+        resized.header.elements[&splice.element].count = entries.len() as u64;
+        inserted.push(splice);
+    }
+    let mut new = try!(apply_delta(&resized, patch));
+    new.header.obj_infos.retain(|o| parse_splice(o).is_none());
+    for splice in inserted {
+        let properties = new.header.elements[&splice.element].properties.len();
+        let entries = &new.payload[&splice.element][splice.start..splice.start + splice.inserted];
+        if entries.iter().any(|e| e.len() != properties) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Patch lacks values for inserted entries of element `{}`.", splice.element)));
+        }
+    }
+    Ok(new)
+}
+
+/// Range of an element replaced by a patch.
+struct Splice {
+    element: String,
+    start: usize,
+    removed: usize,
+    inserted: usize,
+}
+
+/// Parses a `patch_splice` line, `None` for other object informations.
+fn parse_splice(obj_info: &str) -> Option<Result<Splice>> {
+    let mut words = obj_info.split_whitespace();
+    if words.next() != Some(SPLICE_OBJ_INFO) {
+        return None;
+    }
+    let name = words.next();
+    let numbers: Vec<Option<usize>> = words.by_ref().take(3).map(|w| w.parse().ok()).collect();
+    let parsed = match (name, &numbers[..], words.next()) {
+        (Some(name), &[Some(start), Some(removed), Some(inserted)], None) => Ok(Splice {
+            element: name.to_string(),
+            start: start,
+            removed: removed,
+            inserted: inserted,
+        }),
+        _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("Invalid object information `{}`.", obj_info))),
+    };
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+    use writer::Writer;
+    use ply::*;
+    fn scan(n: usize) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Int)));
+        e.properties.add(PropertyDef::new("y".to_string(), PropertyType::Scalar(ScalarType::Int)));
+        ply.header.elements.add(e);
+        let list = (0..n).map(|i| {
+            let mut v = DefaultElement::new();
+            v.insert("x".to_string(), Property::Int(i as i32));
+            v.insert("y".to_string(), Property::Int(0));
+            v
+        }).collect();
+        ply.payload.insert("vertex".to_string(), list);
+        ply.make_consistent().unwrap();
+        ply
+    }
+    fn through_file(patch: &mut Ply<DefaultElement>) -> Ply<DefaultElement> {
+        let mut buf = Vec::new();
+        Writer::new().write_ply(&mut buf, patch).unwrap();
+        Parser::<DefaultElement>::new().read_ply(&mut &buf[..]).unwrap()
+    }
+    #[test]
+    fn patch_roundtrip() {
+        let old = scan(4);
+        let mut grown = scan(6);
+        grown.payload["vertex"][1].insert("y".to_string(), Property::Int(7));
+        let mut patch = diff(&old, &grown).unwrap();
+        assert_eq!(patch.header.elements["vertex"].count, 3);
+        assert_eq!(apply(&old, &through_file(&mut patch)).unwrap(), grown);
+
+        let shrunk = scan(2);
+        let mut patch = diff(&old, &shrunk).unwrap();
+        assert_eq!(patch.header.elements["vertex"].count, 0);
+        assert_eq!(apply(&old, &through_file(&mut patch)).unwrap(), shrunk);
+
+        assert!(diff(&old, &old).unwrap().payload.is_empty());
+    }
+    #[test]
+    fn patch_splices_mid_file() {
+        let old = scan(6);
+        let mut inserted = old.clone();
+        let mut v = DefaultElement::new();
+        v.insert("x".to_string(), Property::Int(-1));
+        v.insert("y".to_string(), Property::Int(-1));
+        inserted.payload["vertex"].insert(2, v);
+        inserted.make_consistent().unwrap();
+        let mut patch = diff(&old, &inserted).unwrap();
+        assert_eq!(patch.header.elements["vertex"].count, 1);
+        assert_eq!(patch.header.obj_infos, vec!["patch_splice vertex 2 0 1".to_string()]);
+        assert_eq!(apply(&old, &through_file(&mut patch)).unwrap(), inserted);
+
+        let mut removed = old.clone();
+        removed.payload["vertex"].drain(1..3);
+        removed.make_consistent().unwrap();
+        let mut patch = diff(&old, &removed).unwrap();
+        assert_eq!(patch.header.elements["vertex"].count, 0);
+        assert_eq!(patch.header.obj_infos, vec!["patch_splice vertex 1 2 0".to_string()]);
+        assert_eq!(apply(&old, &through_file(&mut patch)).unwrap(), removed);
+
+        patch.header.obj_infos = vec!["patch_splice vertex 5 2 0".to_string()];
+        assert!(apply(&old, &patch).is_err());
+    }
+    #[test]
+    fn incomplete_patch_err() {
+        let old = scan(1);
+        let mut patch = diff(&old, &scan(3)).unwrap();
+        patch.payload["vertex"].pop();
+        assert!(apply(&old, &patch).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Streams a PLY file through a chain of steps into a new one, holding only one entry at a time.
+//!
+//! The source is read with a `PlyReader`, the result written with a `PlyWriter`,
+//! so files of any size can be converted, e.g. read ascii, drop properties, crop, write binary.
+
+use std::io;
+use std::io::{ Read, Write, Result, ErrorKind };
+
+use parser::Parser;
+use writer::Writer;
+use ply::{ Header, PropertyAccess, Encoding };
+
+enum Step<E> {
+    DropProperty(String, String),
+    Filter(String, Box<Fn(&E) -> bool>),
+    Map(String, Box<Fn(&mut E)>),
+}
+
+/// Chain of steps applied to the entries of a PLY file on their way from a source to a sink.
+///
+/// Steps apply to the entries of one element, in the order they were added.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::pipeline::Pipeline;
+/// # use ply_rs::parser::Parser;
+/// # use ply_rs::writer::Writer;
+/// # use ply_rs::ply::{ DefaultElement, Encoding, PropertyAccess };
+/// let source = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty uchar intensity\nend_header\n\
+///               0.5 10\n3.5 20\n-1 30\n";
+/// let mut pipeline = Pipeline::<DefaultElement>::new();
+/// pipeline.drop_property("vertex", "intensity")
+///         .filter("vertex", |v| v.get_float(&"x".to_string()).map_or(false, |x| x >= 0.0 && x <= 1.0))
+///         .set_encoding(Encoding::BinaryLittleEndian);
+/// let (parser, writer) = (Parser::new(), Writer::new());
+/// let out = pipeline.run(&parser, &writer, || Ok(source.as_bytes()), Vec::new()).unwrap();
+/// let ply = parser.read_ply(&mut &out[..]).unwrap();
+/// assert_eq!(ply.header.elements["vertex"].count, 1);
+/// ```
+pub struct Pipeline<E: PropertyAccess> {
+    steps: Vec<Step<E>>,
+    encoding: Option<Encoding>,
+}
+
+impl<E: PropertyAccess> Pipeline<E> {
+    /// Creates a pipeline copying its source.
+    pub fn new() -> Self {
+        Pipeline {
+            steps: Vec::new(),
+            encoding: None,
+        }
+    }
+    /// Leaves `property` of `element` out of the output.
+    pub fn drop_property(&mut self, element: &str, property: &str) -> &mut Self {
+        self.steps.push(Step::DropProperty(element.to_string(), property.to_string()));
+        self
+    }
+    /// Only keeps the entries of `element` for which `keep` returns `true`.
+    ///
+    /// Since the header holds the number of entries, the source is read twice when filtering,
+    /// see `run()`. Vertex indices of faces are not adjusted when vertices are filtered.
+    pub fn filter<F>(&mut self, element: &str, keep: F) -> &mut Self
+        where F: Fn(&E) -> bool + 'static {
+        self.steps.push(Step::Filter(element.to_string(), Box::new(keep)));
+        self
+    }
+    /// Changes each entry of `element` with `map`, e.g. to transform coordinates.
+    ///
+    /// Only the properties declared in the header are written.
+    pub fn map<F>(&mut self, element: &str, map: F) -> &mut Self
+        where F: Fn(&mut E) + 'static {
+        self.steps.push(Step::Map(element.to_string(), Box::new(map)));
+        self
+    }
+    /// Writes with `encoding` instead of the encoding of the source.
+    pub fn set_encoding(&mut self, encoding: Encoding) -> &mut Self {
+        self.encoding = Some(encoding);
+        self
+    }
+    /// Reads the source returned by `open`, applies all steps and writes the result to `out`.
+    ///
+    /// Without filters, the source is opened once. Filters change the element counts written in the header,
+    /// so a first pass counts the entries kept and `open` is called again for the second pass,
+    /// which has to yield the same file.
+    ///
+    /// Returns `out` once everything was written.
+    pub fn run<R, O, W>(&self, parser: &Parser<E>, writer: &Writer<E>, mut open: O, out: W) -> Result<W>
+        where R: Read, O: FnMut() -> Result<R>, W: Write {
+        let mut reader = try!(parser.read_ply_iter(try!(open())));
+        let mut header = try!(self.output_header(reader.header()));
+        let filters = self.steps.iter().any(|s| match *s { Step::Filter(_, _) => true, _ => false });
+        if filters {
+            let source_header = reader.header().clone();
+            while let Some(entries) = try!(reader.next_element()) {
+                let name = entries.element_def().name.clone();
+                let mut count = 0;
+                for entry in entries {
+                    if self.apply(&name, try!(entry)).is_some() {
+                        count += 1;
+                    }
+                }
+                header.elements[&name].count = count;
+            }
+            reader = try!(parser.read_ply_iter(try!(open())));
+            if *reader.header() != source_header {
+                return Err(io::Error::new(ErrorKind::InvalidData, "The source changed between the two passes."));
+            }
+        }
+        let mut out = try!(writer.begin_ply(out, &header));
+        while let Some(entries) = try!(reader.next_element()) {
+            let name = entries.element_def().name.clone();
+            for entry in entries {
+                if let Some(entry) = self.apply(&name, try!(entry)) {
+                    try!(out.write_element(&name, &entry));
+                }
+            }
+        }
+        out.finish()
+    }
+
+    fn output_header(&self, source: &Header) -> Result<Header> {
+        let mut header = source.clone();
+        if let Some(encoding) = self.encoding {
+            header.encoding = encoding;
+        }
+        for step in &self.steps {
+            let element = match *step {
+                Step::DropProperty(ref e, _) | Step::Filter(ref e, _) | Step::Map(ref e, _) => e,
+            };
+            let element_def = match header.elements.get_mut(element) {
+                Some(d) => d,
+                None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("The source has no element `{}`.", element))),
+            };
+            if let Step::DropProperty(_, ref property) = *step {
+                if element_def.properties.remove(property).is_none() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Element `{}` of the source has no property `{}`.", element, property)
+                    ));
+                }
+            }
+        }
+        Ok(header)
+    }
+    /// Runs the steps for `element` on `entry`, `None` if a filter dropped it.
+    fn apply(&self, element: &str, mut entry: E) -> Option<E> {
+        for step in &self.steps {
+            match *step {
+                Step::Filter(ref e, ref keep) if e == element => if !keep(&entry) {
+                    return None;
+                },
+                Step::Map(ref e, ref map) if e == element => map(&mut entry),
+                _ => (),
+            }
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::{ DefaultElement, Property };
+
+    const SOURCE: &'static str = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty uchar intensity\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n0.5 10\n3.5 20\n-1 30\n3 0 1 2\n";
+
+    #[test]
+    fn map_without_second_pass() {
+        let mut pipeline = Pipeline::<DefaultElement>::new();
+        pipeline.map("vertex", |v| {
+            let x = v.get_float(&"x".to_string()).unwrap();
+            v.set_property("x".to_string(), Property::Float(x * 2.0));
+        });
+        let (parser, writer) = (Parser::new(), Writer::new());
+        let mut opened = 0;
+        let out = pipeline.run(&parser, &writer, || { opened += 1; Ok(SOURCE.as_bytes()) }, Vec::new()).unwrap();
+        assert_eq!(opened, 1);
+        let ply = parser.read_ply(&mut &out[..]).unwrap();
+        assert_eq!(ply.payload["vertex"][1]["x"], Property::Float(7.0));
+        assert_eq!(ply.payload["face"].len(), 1);
+    }
+    #[test]
+    fn filter_updates_counts() {
+        let mut pipeline = Pipeline::<DefaultElement>::new();
+        pipeline.filter("vertex", |v| v.get_uchar(&"intensity".to_string()) != Some(20))
+                .drop_property("vertex", "intensity")
+                .set_encoding(Encoding::BinaryBigEndian);
+        let (parser, writer) = (Parser::new(), Writer::new());
+        let out = pipeline.run(&parser, &writer, || Ok(SOURCE.as_bytes()), Vec::new()).unwrap();
+        let ply = parser.read_ply(&mut &out[..]).unwrap();
+        assert_eq!(ply.header.encoding, Encoding::BinaryBigEndian);
+        assert_eq!(ply.header.elements["vertex"].count, 2);
+        assert!(!ply.header.elements["vertex"].properties.contains_key("intensity"));
+        assert_eq!(ply.payload["vertex"][1]["x"], Property::Float(-1.0));
+    }
+    #[test]
+    fn unknown_names_err() {
+        let (parser, writer) = (Parser::<DefaultElement>::new(), Writer::new());
+        let mut pipeline = Pipeline::new();
+        pipeline.drop_property("vertex", "y");
+        assert!(pipeline.run(&parser, &writer, || Ok(SOURCE.as_bytes()), Vec::new()).is_err());
+        let mut pipeline = Pipeline::new();
+        pipeline.filter("point", |_| true);
+        assert!(pipeline.run(&parser, &writer, || Ok(SOURCE.as_bytes()), Vec::new()).is_err());
+    }
+}
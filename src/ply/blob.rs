@@ -0,0 +1,153 @@
+//! Embeds small binary blobs, like a preview image, in the comments of a header.
+//!
+//! A blob is stored base64 encoded over one or more comments of the form `blob <key> <chunk>`,
+//! other PLY readers simply ignore them.
+
+use super::{ Header, ConsistencyError };
+
+/// Largest blob accepted by `Header::embed_blob()`, in bytes.
+pub const MAX_BLOB_SIZE: usize = 64 * 1024;
+
+/// Number of base64 characters per comment line.
+const CHUNK_LEN: usize = 76;
+
+const BLOB_PREFIX: &'static str = "blob ";
+
+const ALPHABET: &'static [u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl Header {
+    /// Stores `data` under `key` in the comments, replacing a previous blob with the same key.
+    ///
+    /// The key must not be empty or contain white space, `data` must not exceed `MAX_BLOB_SIZE`.
+    pub fn embed_blob(&mut self, key: &str, data: &[u8]) -> Result<(), ConsistencyError> {
+        if key.is_empty() || key.chars().any(|c| c.is_whitespace()) {
+            return Err(ConsistencyError::new(&format!("Blob key `{}` must be non-empty and free of white space.", key)));
+        }
+        if data.len() > MAX_BLOB_SIZE {
+            return Err(ConsistencyError::new(&format!("Blob `{}` has {} bytes, at most {} are allowed.", key, data.len(), MAX_BLOB_SIZE)));
+        }
+        self.remove_blob(key);
+        let encoded = encode(data);
+        // An empty blob still gets a line, otherwise it could not be told apart from a missing one.
+        if encoded.is_empty() {
+            self.comments.push(format!("{}{} ", BLOB_PREFIX, key));
+        }
+        for chunk in encoded.as_bytes().chunks(CHUNK_LEN) {
+            self.comments.push(format!("{}{} {}", BLOB_PREFIX, key, String::from_utf8_lossy(chunk)));
+        }
+        Ok(())
+    }
+    /// Returns the blob stored under `key`, or `None` if there is none.
+    ///
+    /// Fails if the stored data is not valid base64.
+    pub fn extract_blob(&self, key: &str) -> Option<Result<Vec<u8>, ConsistencyError>> {
+        let mut encoded = String::new();
+        let mut found = false;
+        for chunk in self.comments.iter().filter_map(|c| blob_chunk(c, key)) {
+            found = true;
+            encoded.push_str(chunk);
+        }
+        if !found {
+            return None;
+        }
+        Some(decode(&encoded).ok_or_else(|| ConsistencyError::new(&format!("Blob `{}` is not valid base64.", key))))
+    }
+    /// Removes the blob stored under `key` from the comments.
+    pub fn remove_blob(&mut self, key: &str) {
+        self.comments.retain(|c| blob_chunk(c, key).is_none());
+    }
+}
+
+/// Base64 part of `comment` if it belongs to the blob `key`.
+fn blob_chunk<'a>(comment: &'a str, key: &str) -> Option<&'a str> {
+    if !comment.starts_with(BLOB_PREFIX) {
+        return None;
+    }
+    let rest = &comment[BLOB_PREFIX.len()..];
+    if !rest.starts_with(key) || !rest[key.len()..].starts_with(' ') {
+        return None;
+    }
+    Some(rest[key.len() + 1..].trim())
+}
+
+fn encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for group in data.chunks(3) {
+        let b = [group[0], *group.get(1).unwrap_or(&0), *group.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if text.len() % 4 != 0 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for (gi, group) in text.chunks(4).enumerate() {
+        let last = gi == text.len() / 4 - 1;
+        let padding = group.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &group[..4 - padding] {
+            let v = match ALPHABET.iter().position(|&a| a == c) {
+                Some(v) => v as u32,
+                None => return None,
+            };
+            n = n << 6 | v;
+        }
+        n <<= 6 * padding as u32;
+        let bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        out.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn base64_roundtrip() {
+        assert_eq!(encode(b"Man"), "TWFu");
+        assert_eq!(encode(b"Ma"), "TWE=");
+        assert_eq!(encode(b"M"), "TQ==");
+        for len in 0..10 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37) as u8).collect();
+            assert_eq!(decode(&encode(&data)).unwrap(), data);
+        }
+        assert!(decode("TQ=").is_none());
+        assert!(decode("T===").is_none());
+        assert!(decode("TQ==TWFu").is_none());
+        assert!(decode("T*Fu").is_none());
+    }
+    #[test]
+    fn embed_extract_blob() {
+        let mut h = Header::new();
+        h.comments.push("made by hand".to_string());
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        h.embed_blob("thumbnail", &data).unwrap();
+        h.embed_blob("thumb", b"").unwrap();
+        assert!(h.comments.len() > 3);
+        assert!(h.comments.iter().all(|c| c.len() <= CHUNK_LEN + 20));
+        assert_eq!(h.extract_blob("thumbnail").unwrap().unwrap(), data);
+        assert_eq!(h.extract_blob("thumb").unwrap().unwrap(), Vec::<u8>::new());
+        assert!(h.extract_blob("preview").is_none());
+        h.embed_blob("thumbnail", b"small").unwrap();
+        assert_eq!(h.extract_blob("thumbnail").unwrap().unwrap(), b"small".to_vec());
+        h.remove_blob("thumbnail");
+        h.remove_blob("thumb");
+        assert_eq!(h.comments, vec!["made by hand".to_string()]);
+        assert!(h.embed_blob("two words", b"x").is_err());
+        assert!(h.embed_blob("big", &vec![0; MAX_BLOB_SIZE + 1]).is_err());
+    }
+}
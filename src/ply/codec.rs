@@ -0,0 +1,351 @@
+//! Keeps rarely used columns compressed in memory, requires the `codecs` feature.
+
+use std::borrow::Cow;
+
+use byteorder::{ LittleEndian, ByteOrder };
+use lz4_flex;
+
+use super::{ KeyMap, Column, ColumnarPayload, ScalarType, ConsistencyError };
+
+/// Compression applied to a column, see `CompressedPayload::set_codec()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Stores each integer as its offset from the smallest value, with as few bits as the largest offset needs.
+    ///
+    /// Only for scalar integer columns, pays off for small value ranges like labels or intensities.
+    BitPack,
+    /// Groups the n-th bytes of all values together and compresses the result with LZ4.
+    ///
+    /// For all scalar columns, pays off for smoothly varying floating point values like coordinates or normals.
+    ShuffleLz4,
+}
+
+/// Column kept compressed with a `Codec`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressedColumn {
+    codec: Codec,
+    scalar_type: ScalarType,
+    len: usize,
+    /// Smallest value and bits per value, for `Codec::BitPack`.
+    base: i64,
+    bits: u32,
+    bytes: Vec<u8>,
+}
+
+impl CompressedColumn {
+    /// Compresses `column` with `codec`.
+    ///
+    /// Fails for list columns and for floating point columns with `Codec::BitPack`.
+    pub fn compress(column: &Column, codec: Codec) -> Result<Self, ConsistencyError> {
+        let scalar_type = match scalar_type_of(column) {
+            Some(t) => t,
+            None => return Err(ConsistencyError::new("Only scalar columns can be compressed.")),
+        };
+        let mut compressed = CompressedColumn {
+            codec: codec,
+            scalar_type: scalar_type,
+            len: column.len(),
+            base: 0,
+            bits: 0,
+            bytes: Vec::new(),
+        };
+        match codec {
+            Codec::BitPack => {
+                let values = match integers(column) {
+                    Some(v) => v,
+                    None => return Err(ConsistencyError::new("Bit packing is only available for integer columns.")),
+                };
+                compressed.base = values.iter().cloned().min().unwrap_or(0);
+                let range = values.iter().map(|&v| (v - compressed.base) as u64).max().unwrap_or(0);
+                compressed.bits = 64 - range.leading_zeros();
+                compressed.bytes = pack(&values, compressed.base, compressed.bits);
+            },
+            Codec::ShuffleLz4 => {
+                let width = compressed.scalar_type.size_bytes();
+                compressed.bytes = lz4_flex::compress_prepend_size(&shuffle(&le_bytes(column), width));
+            },
+        }
+        Ok(compressed)
+    }
+    /// Restores the column.
+    pub fn decompress(&self) -> Column {
+        match self.codec {
+            Codec::BitPack => from_integers(&self.scalar_type, unpack(&self.bytes, self.len, self.base, self.bits)),
+            Codec::ShuffleLz4 => {
+                let width = self.scalar_type.size_bytes();
+                let bytes = lz4_flex::decompress_size_prepended(&self.bytes).expect("Compressed column is corrupt.");
+                from_le_bytes(&self.scalar_type, &unshuffle(&bytes, width))
+            },
+        }
+    }
+    /// Codec the column is compressed with.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+    /// Number of values in the column.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Returns `true` if the column holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Number of bytes the compressed values take.
+    pub fn compressed_size(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+/// Column of a `CompressedPayload`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredColumn {
+    Plain(Column),
+    Compressed(CompressedColumn),
+}
+
+/// Columnar payload whose columns can be kept compressed, each with its own `Codec`.
+///
+/// Compressed columns are decompressed on every access, which trades CPU time for memory.
+/// Use it for attributes that are rarely touched.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::ply::{ Column, ColumnarElement, ColumnarPayload, CompressedPayload, Codec };
+/// let mut vertex = ColumnarElement::new();
+/// vertex.insert("label".to_string(), Column::Int((0..1000).map(|i| i % 7).collect()));
+/// let mut payload = ColumnarPayload::new();
+/// payload.insert("vertex".to_string(), vertex);
+///
+/// let mut payload = CompressedPayload::from(payload);
+/// payload.set_codec("vertex", "label", Some(Codec::BitPack)).unwrap();
+/// assert_eq!(payload.column("vertex", "label").unwrap().len(), 1000);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompressedPayload {
+    elements: KeyMap<KeyMap<StoredColumn>>,
+}
+
+impl CompressedPayload {
+    /// Creates an empty payload.
+    pub fn new() -> Self {
+        CompressedPayload { elements: KeyMap::new() }
+    }
+    /// Stored column of `property` in `element`, to inspect how it is kept.
+    pub fn stored_column(&self, element: &str, property: &str) -> Option<&StoredColumn> {
+        self.elements.get(element).and_then(|e| e.get(property))
+    }
+    /// Column of `property` in `element`, decompressed if needed.
+    pub fn column<'a>(&'a self, element: &str, property: &str) -> Option<Cow<'a, Column>> {
+        self.stored_column(element, property).map(|c| match *c {
+            StoredColumn::Plain(ref c) => Cow::Borrowed(c),
+            StoredColumn::Compressed(ref c) => Cow::Owned(c.decompress()),
+        })
+    }
+    /// Compresses the column of `property` in `element` with `codec`, or keeps it uncompressed with `None`.
+    ///
+    /// Fails without changing anything if there is no such column or the codec doesn't apply to it,
+    /// see `CompressedColumn::compress()`.
+    pub fn set_codec(&mut self, element: &str, property: &str, codec: Option<Codec>) -> Result<(), ConsistencyError> {
+        let stored = match self.elements.get_mut(element).and_then(|e| e.get_mut(property)) {
+            Some(c) => c,
+            None => return Err(ConsistencyError::new(&format!("Payload has no column `{}` in element `{}`.", property, element))),
+        };
+        let column = match *stored {
+            StoredColumn::Plain(ref c) => Cow::Borrowed(c),
+            StoredColumn::Compressed(ref c) if Some(c.codec) == codec => return Ok(()),
+            StoredColumn::Compressed(ref c) => Cow::Owned(c.decompress()),
+        };
+        let new = match codec {
+            Some(codec) => StoredColumn::Compressed(try!(CompressedColumn::compress(&column, codec))),
+            None => StoredColumn::Plain(column.into_owned()),
+        };
+        *stored = new;
+        Ok(())
+    }
+    /// Decompresses all columns into a `ColumnarPayload`.
+    pub fn into_columnar(self) -> ColumnarPayload {
+        self.elements.into_iter().map(|(name, columns)| {
+            let element: KeyMap<Column> = columns.into_iter().map(|(k, c)| {
+                let column = match c {
+                    StoredColumn::Plain(c) => c,
+                    StoredColumn::Compressed(c) => c.decompress(),
+                };
+                (k, column)
+            }).collect();
+            (name, element)
+        }).collect()
+    }
+}
+
+impl From<ColumnarPayload> for CompressedPayload {
+    fn from(payload: ColumnarPayload) -> Self {
+        CompressedPayload {
+            elements: payload.into_iter().map(|(name, columns)| {
+                let stored: KeyMap<StoredColumn> = columns.into_iter().map(|(k, c)| (k, StoredColumn::Plain(c))).collect();
+                (name, stored)
+            }).collect(),
+        }
+    }
+}
+
+fn scalar_type_of(column: &Column) -> Option<ScalarType> {
+    match *column {
+        Column::Char(_) => Some(ScalarType::Char),
+        Column::UChar(_) => Some(ScalarType::UChar),
+        Column::Short(_) => Some(ScalarType::Short),
+        Column::UShort(_) => Some(ScalarType::UShort),
+        Column::Int(_) => Some(ScalarType::Int),
+        Column::UInt(_) => Some(ScalarType::UInt),
+        Column::Float(_) => Some(ScalarType::Float),
+        Column::Double(_) => Some(ScalarType::Double),
+        _ => None,
+    }
+}
+
+fn integers(column: &Column) -> Option<Vec<i64>> {
+    match *column {
+        Column::Char(ref c) => Some(c.iter().map(|&v| v as i64).collect()),
+        Column::UChar(ref c) => Some(c.iter().map(|&v| v as i64).collect()),
+        Column::Short(ref c) => Some(c.iter().map(|&v| v as i64).collect()),
+        Column::UShort(ref c) => Some(c.iter().map(|&v| v as i64).collect()),
+        Column::Int(ref c) => Some(c.iter().map(|&v| v as i64).collect()),
+        Column::UInt(ref c) => Some(c.iter().map(|&v| v as i64).collect()),
+        _ => None,
+    }
+}
+
+fn from_integers(scalar_type: &ScalarType, values: Vec<i64>) -> Column {
+    match *scalar_type {
+        ScalarType::Char => Column::Char(values.into_iter().map(|v| v as i8).collect()),
+        ScalarType::UChar => Column::UChar(values.into_iter().map(|v| v as u8).collect()),
+        ScalarType::Short => Column::Short(values.into_iter().map(|v| v as i16).collect()),
+        ScalarType::UShort => Column::UShort(values.into_iter().map(|v| v as u16).collect()),
+        ScalarType::Int => Column::Int(values.into_iter().map(|v| v as i32).collect()),
+        ScalarType::UInt => Column::UInt(values.into_iter().map(|v| v as u32).collect()),
+        ScalarType::Float | ScalarType::Double => unreachable!("Floating point columns are never bit packed."),
+    }
+}
+
+/// Packs the offsets of `values` from `base` with `bits` bits each, least significant bit first.
+fn pack(values: &[i64], base: i64, bits: u32) -> Vec<u8> {
+    let mut bytes = vec![0u8; (values.len() * bits as usize + 7) / 8];
+    for (i, &v) in values.iter().enumerate() {
+        let offset = (v - base) as u64;
+        for b in 0..bits as usize {
+            if offset >> b & 1 == 1 {
+                let bit = i * bits as usize + b;
+                bytes[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+    bytes
+}
+
+fn unpack(bytes: &[u8], len: usize, base: i64, bits: u32) -> Vec<i64> {
+    (0..len).map(|i| {
+        let mut offset = 0u64;
+        for b in 0..bits as usize {
+            let bit = i * bits as usize + b;
+            offset |= ((bytes[bit / 8] >> (bit % 8) & 1) as u64) << b;
+        }
+        base + offset as i64
+    }).collect()
+}
+
+fn le_bytes(column: &Column) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match *column {
+        Column::Char(ref c) => bytes.extend(c.iter().map(|&v| v as u8)),
+        Column::UChar(ref c) => bytes.extend_from_slice(c),
+        Column::Short(ref c) => for v in c { bytes.extend_from_slice(&v.to_le_bytes()) },
+        Column::UShort(ref c) => for v in c { bytes.extend_from_slice(&v.to_le_bytes()) },
+        Column::Int(ref c) => for v in c { bytes.extend_from_slice(&v.to_le_bytes()) },
+        Column::UInt(ref c) => for v in c { bytes.extend_from_slice(&v.to_le_bytes()) },
+        Column::Float(ref c) => for v in c { bytes.extend_from_slice(&v.to_le_bytes()) },
+        Column::Double(ref c) => for v in c { bytes.extend_from_slice(&v.to_le_bytes()) },
+        _ => unreachable!("List columns are never shuffled."),
+    }
+    bytes
+}
+
+fn from_le_bytes(scalar_type: &ScalarType, bytes: &[u8]) -> Column {
+    match *scalar_type {
+        ScalarType::Char => Column::Char(bytes.iter().map(|&b| b as i8).collect()),
+        ScalarType::UChar => Column::UChar(bytes.to_vec()),
+        ScalarType::Short => Column::Short(bytes.chunks(2).map(LittleEndian::read_i16).collect()),
+        ScalarType::UShort => Column::UShort(bytes.chunks(2).map(LittleEndian::read_u16).collect()),
+        ScalarType::Int => Column::Int(bytes.chunks(4).map(LittleEndian::read_i32).collect()),
+        ScalarType::UInt => Column::UInt(bytes.chunks(4).map(LittleEndian::read_u32).collect()),
+        ScalarType::Float => Column::Float(bytes.chunks(4).map(LittleEndian::read_f32).collect()),
+        ScalarType::Double => Column::Double(bytes.chunks(8).map(LittleEndian::read_f64).collect()),
+    }
+}
+
+/// Puts the first byte of all values first, then the second bytes and so on.
+fn shuffle(bytes: &[u8], width: usize) -> Vec<u8> {
+    let n = bytes.len() / width;
+    let mut shuffled = vec![0u8; bytes.len()];
+    for i in 0..n {
+        for b in 0..width {
+            shuffled[b * n + i] = bytes[i * width + b];
+        }
+    }
+    shuffled
+}
+
+fn unshuffle(shuffled: &[u8], width: usize) -> Vec<u8> {
+    let n = shuffled.len() / width;
+    let mut bytes = vec![0u8; shuffled.len()];
+    for i in 0..n {
+        for b in 0..width {
+            bytes[i * width + b] = shuffled[b * n + i];
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ColumnarElement;
+
+    #[test]
+    fn roundtrip_and_size() {
+        let labels = Column::UShort((0..10000).map(|i| 1000 + (i * 7 % 100) as u16).collect());
+        let c = CompressedColumn::compress(&labels, Codec::BitPack).unwrap();
+        assert_eq!(c.compressed_size(), 10000 * 7 / 8);
+        assert_eq!(c.decompress(), labels);
+        let x = Column::Float((0..10000).map(|i| (i as f32 * 0.01).sin()).collect());
+        let c = CompressedColumn::compress(&x, Codec::ShuffleLz4).unwrap();
+        assert!(c.compressed_size() < 40000);
+        assert_eq!(c.decompress(), x);
+        let signed = Column::Char(vec![-128, 0, 127]);
+        assert_eq!(CompressedColumn::compress(&signed, Codec::BitPack).unwrap().decompress(), signed);
+        let empty = Column::Int(Vec::new());
+        assert_eq!(CompressedColumn::compress(&empty, Codec::BitPack).unwrap().decompress(), empty);
+        assert!(CompressedColumn::compress(&x, Codec::BitPack).is_err());
+        assert!(CompressedColumn::compress(&Column::ListInt(vec![vec![1]]), Codec::ShuffleLz4).is_err());
+    }
+    #[test]
+    fn payload_codecs() {
+        let mut vertex = ColumnarElement::new();
+        vertex.insert("x".to_string(), Column::Double(vec![0.5, 1.5, 2.5]));
+        vertex.insert("i".to_string(), Column::ListUChar(vec![vec![1], vec![], vec![2, 3]]));
+        let mut columnar = ColumnarPayload::new();
+        columnar.insert("vertex".to_string(), vertex);
+        let mut payload = CompressedPayload::from(columnar.clone());
+        payload.set_codec("vertex", "x", Some(Codec::ShuffleLz4)).unwrap();
+        match payload.stored_column("vertex", "x") {
+            Some(&StoredColumn::Compressed(ref c)) => assert_eq!(c.codec(), Codec::ShuffleLz4),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(*payload.column("vertex", "x").unwrap(), columnar["vertex"]["x"]);
+        assert!(payload.set_codec("vertex", "x", Some(Codec::BitPack)).is_err());
+        assert!(payload.set_codec("vertex", "i", Some(Codec::ShuffleLz4)).is_err());
+        assert!(payload.set_codec("face", "i", None).is_err());
+        assert_eq!(payload.clone().into_columnar(), columnar);
+        payload.set_codec("vertex", "x", None).unwrap();
+        assert_eq!(payload, CompressedPayload::from(columnar));
+    }
+}
@@ -0,0 +1,245 @@
+//! Extracts the values of one property as a typed vector.
+
+use super::{ Ply, DefaultElement, PropertyType, ScalarType, Property, ConsistencyError };
+use faces::VERTEX_ELEMENT;
+
+/// Properties holding the coordinates of a vertex.
+const POSITION_PROPERTIES: [&'static str; 3] = ["x", "y", "z"];
+
+/// Rust type a property can be extracted as, see `Ply::column()`.
+///
+/// Implemented for the scalar types listed in `ScalarType` and vectors of them for list properties.
+pub trait ColumnValue: Sized {
+    /// Type values are cast to before extraction.
+    fn data_type() -> PropertyType;
+    /// Unwraps a value of `data_type()`.
+    fn from_property(property: Property) -> Option<Self>;
+}
+
+macro_rules! column_value(
+    ($t:ty, $scalar_type:ident, $scalar:ident, $list:ident) => (
+        impl ColumnValue for $t {
+            fn data_type() -> PropertyType {
+                PropertyType::Scalar(ScalarType::$scalar_type)
+            }
+            fn from_property(property: Property) -> Option<Self> {
+                match property {
+                    Property::$scalar(v) => Some(v),
+                    _ => None,
+                }
+            }
+        }
+        impl ColumnValue for Vec<$t> {
+            fn data_type() -> PropertyType {
+                PropertyType::List(ScalarType::UInt, ScalarType::$scalar_type)
+            }
+            fn from_property(property: Property) -> Option<Self> {
+                match property {
+                    Property::$list(v) => Some(v),
+                    _ => None,
+                }
+            }
+        }
+    )
+);
+
+column_value!(i8, Char, Char, ListChar);
+column_value!(u8, UChar, UChar, ListUChar);
+column_value!(i16, Short, Short, ListShort);
+column_value!(u16, UShort, UShort, ListUShort);
+column_value!(i32, Int, Int, ListInt);
+column_value!(u32, UInt, UInt, ListUInt);
+column_value!(f32, Float, Float, ListFloat);
+column_value!(f64, Double, Double, ListDouble);
+
+impl Ply<DefaultElement> {
+    /// Returns the values of `property` in all entries of `element`, converted to `T`.
+    ///
+    /// Values are converted with `Property::cast()`, e.g. `uchar` colors can be extracted as `f32`,
+    /// but a `float` can't be extracted as an integer, nor a list as a scalar.
+    /// Fails if the element or property is missing or a value can't be converted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use ply_rs::ply::{ Ply, DefaultElement };
+    /// # let ply = Ply::<DefaultElement>::new();
+    /// let x = ply.column::<f32>("vertex", "x").unwrap();
+    /// let faces = ply.column::<Vec<u32>>("face", "vertex_indices").unwrap();
+    /// ```
+    pub fn column<T: ColumnValue>(&self, element: &str, property: &str) -> Result<Vec<T>, ConsistencyError> {
+        let entries = match self.payload.get(element) {
+            Some(e) => e,
+            None => return Err(ConsistencyError::new(&format!("Element `{}` not found.", element))),
+        };
+        let data_type = T::data_type();
+        let mut column = Vec::with_capacity(entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            let value = match entry.get(property) {
+                Some(v) => v,
+                None => return Err(ConsistencyError::new(&format!(
+                    "Entry {} of element `{}` has no property `{}`.", i, element, property))),
+            };
+            match value.cast(&data_type).and_then(T::from_property) {
+                Some(v) => column.push(v),
+                None => return Err(ConsistencyError::new(&format!(
+                    "Value {:?} of property `{}` in entry {} of element `{}` can't be converted to {}.",
+                    value, property, i, element, data_type))),
+            }
+        }
+        Ok(column)
+    }
+    /// Returns the `x`, `y` and `z` coordinates of all vertices in double precision.
+    ///
+    /// Works for any numeric type the coordinates are stored in, `double` coordinates are returned unchanged.
+    /// Fails if the `vertex` element or one of the coordinates is missing, see `column()`.
+    pub fn positions_f64(&self) -> Result<Vec<[f64; 3]>, ConsistencyError> {
+        let x = try!(self.column::<f64>(VERTEX_ELEMENT, POSITION_PROPERTIES[0]));
+        let y = try!(self.column::<f64>(VERTEX_ELEMENT, POSITION_PROPERTIES[1]));
+        let z = try!(self.column::<f64>(VERTEX_ELEMENT, POSITION_PROPERTIES[2]));
+        Ok(x.into_iter().zip(y).zip(z).map(|((x, y), z)| [x, y, z]).collect())
+    }
+    /// Axis aligned bounding box of the vertices, as minimum and maximum corner in double precision.
+    ///
+    /// `None` if there are no vertices, NaN coordinates are ignored.
+    pub fn bounding_box_f64(&self) -> Result<Option<([f64; 3], [f64; 3])>, ConsistencyError> {
+        let positions = try!(self.positions_f64());
+        if positions.is_empty() {
+            return Ok(None);
+        }
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for p in &positions {
+            for i in 0..3 {
+                min[i] = min[i].min(p[i]);
+                max[i] = max[i].max(p[i]);
+            }
+        }
+        Ok(Some((min, max)))
+    }
+    /// Applies the affine transformation `matrix` to all vertices, computed in double precision.
+    ///
+    /// `matrix` holds the rows of a 3x4 matrix, the last column is the translation.
+    /// Results are stored in the type declared for each coordinate in the header,
+    /// so `double` coordinates keep their precision while `float` coordinates are rounded once.
+    /// Fails without changing anything if a coordinate is missing or a result isn't representable in its type,
+    /// e.g. exceeds the range of `float`. Integer coordinates are rejected, as `Property::cast()` doesn't round.
+    pub fn transform_positions_f64(&mut self, matrix: &[[f64; 4]; 3]) -> Result<(), ConsistencyError> {
+        let positions = try!(self.positions_f64());
+        let mut data_types = Vec::with_capacity(3);
+        for name in &POSITION_PROPERTIES {
+            match self.header.elements.get(VERTEX_ELEMENT).and_then(|e| e.properties.get(*name)) {
+                Some(p) => data_types.push(p.data_type.clone()),
+                None => return Err(ConsistencyError::new(&format!("Property `{}` of `{}` is not declared.", name, VERTEX_ELEMENT))),
+            }
+        }
+        let mut transformed = Vec::with_capacity(positions.len());
+        for (i, p) in positions.iter().enumerate() {
+            let mut values = Vec::with_capacity(3);
+            for (row, data_type) in matrix.iter().zip(&data_types) {
+                let v = row[0] * p[0] + row[1] * p[1] + row[2] * p[2] + row[3];
+                match Property::Double(v).cast(data_type) {
+                    Some(value) => values.push(value),
+                    None => return Err(ConsistencyError::new(&format!(
+                        "Transformed coordinate {} of vertex {} can't be stored as {}.", v, i, data_type))),
+                }
+            }
+            transformed.push(values);
+        }
+        let vertices = self.payload.get_mut(VERTEX_ELEMENT).unwrap();
+        for (vertex, values) in vertices.iter_mut().zip(transformed) {
+            for (name, value) in POSITION_PROPERTIES.iter().zip(values) {
+                vertex.insert(name.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    #[test]
+    fn column_conversions() {
+        let mut p = Ply::<DefaultElement>::new();
+        let vertices = (0..3).map(|i| {
+            let mut v = DefaultElement::new();
+            v.insert("x".to_string(), Property::Float(i as f32 * 0.5));
+            v.insert("red".to_string(), Property::UChar(200 + i));
+            v.insert("n".to_string(), Property::ListShort(vec![-1, i as i16]));
+            v
+        }).collect();
+        p.payload.insert("vertex".to_string(), vertices);
+        assert_eq!(p.column::<f32>("vertex", "x").unwrap(), vec![0.0, 0.5, 1.0]);
+        assert_eq!(p.column::<f64>("vertex", "x").unwrap(), vec![0.0, 0.5, 1.0]);
+        assert_eq!(p.column::<i32>("vertex", "red").unwrap(), vec![200, 201, 202]);
+        assert_eq!(p.column::<f32>("vertex", "red").unwrap(), vec![200.0, 201.0, 202.0]);
+        assert_eq!(p.column::<Vec<i32>>("vertex", "n").unwrap()[2], vec![-1, 2]);
+        assert!(p.column::<i8>("vertex", "red").is_err());
+        assert!(p.column::<Vec<u16>>("vertex", "n").is_err());
+        assert!(p.column::<i32>("vertex", "x").is_err());
+        assert!(p.column::<f32>("vertex", "n").is_err());
+        assert!(p.column::<f32>("vertex", "y").is_err());
+        assert!(p.column::<f32>("face", "x").is_err());
+    }
+    fn vertex_ply(scalar_type: ScalarType, positions: &[[f64; 3]]) -> Ply<DefaultElement> {
+        let mut p = Ply::<DefaultElement>::new();
+        let mut e = ElementDef::new("vertex".to_string());
+        for n in &["x", "y", "z"] {
+            e.properties.add(PropertyDef::new(n.to_string(), PropertyType::Scalar(scalar_type.clone())));
+        }
+        p.header.elements.add(e);
+        let vertices = positions.iter().map(|pos| {
+            let mut v = DefaultElement::new();
+            for (n, c) in ["x", "y", "z"].iter().zip(pos) {
+                v.insert(n.to_string(), Property::Double(*c).cast(&PropertyType::Scalar(scalar_type.clone())).unwrap());
+            }
+            v
+        }).collect();
+        p.payload.insert("vertex".to_string(), vertices);
+        p.make_consistent().unwrap();
+        p
+    }
+    #[test]
+    fn positions_keep_double_precision() {
+        let survey = [[4512345.123456789, 5412345.987654321, 312.0001], [4512346.5, 5412340.25, -1.0]];
+        let mut p = vertex_ply(ScalarType::Double, &survey);
+        assert_eq!(p.positions_f64().unwrap(), survey.to_vec());
+        assert_eq!(p.bounding_box_f64().unwrap(), Some(([4512345.123456789, 5412340.25, -1.0], [4512346.5, 5412345.987654321, 312.0001])));
+
+        p.transform_positions_f64(&[[1.0, 0.0, 0.0, -4512345.0], [0.0, 1.0, 0.0, -5412345.0], [0.0, 0.0, 2.0, 0.0]]).unwrap();
+        let moved = p.positions_f64().unwrap();
+        assert_eq!(moved[0], [4512345.123456789 - 4512345.0, 5412345.987654321 - 5412345.0, 624.0002]);
+        assert_eq!(p.payload["vertex"][1]["x"], Property::Double(1.5));
+
+        let mut p = vertex_ply(ScalarType::Float, &[[1.0, 2.0, 3.0]]);
+        p.transform_positions_f64(&[[1.0, 0.0, 0.0, 0.25], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]).unwrap();
+        assert_eq!(p.payload["vertex"][0]["x"], Property::Float(1.25));
+        assert_eq!(vertex_ply(ScalarType::Float, &[]).bounding_box_f64().unwrap(), None);
+    }
+    #[test]
+    fn transform_fail_keeps_positions() {
+        let mut p = vertex_ply(ScalarType::Float, &[[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+        let before = p.clone();
+        assert!(p.transform_positions_f64(&[[1e300, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]).is_err());
+        assert_eq!(p, before);
+        p.transform_positions_f64(&[[2.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, -3.0]]).unwrap();
+        assert_eq!(p.positions_f64().unwrap(), vec![[2.0, 2.0, 0.0], [8.0, 5.0, 3.0]]);
+
+        let mut p = Ply::<DefaultElement>::new();
+        let mut e = ElementDef::new("vertex".to_string());
+        let mut v = DefaultElement::new();
+        for n in &["x", "y", "z"] {
+            e.properties.add(PropertyDef::new(n.to_string(), PropertyType::Scalar(ScalarType::Int)));
+            v.insert(n.to_string(), Property::Int(1));
+        }
+        p.header.elements.add(e);
+        p.payload.insert("vertex".to_string(), vec![v]);
+        assert_eq!(p.positions_f64().unwrap(), vec![[1.0, 1.0, 1.0]]);
+        assert!(p.transform_positions_f64(&[[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]).is_err());
+
+        let mut p = Ply::<DefaultElement>::new();
+        assert!(p.positions_f64().is_err());
+        assert!(p.transform_positions_f64(&[[1.0, 0.0, 0.0, 0.0]; 3]).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Payload stored as one vector per property instead of one map per entry.
+
+use std::ops::Range;
+use std::sync::Arc;
+use super::{ KeyMap, Property, PropertyType, ScalarType, ConsistencyError };
+
+/// Values of one property in all entries of an element.
+///
+/// The variant corresponds to the declared type, list properties keep one `Vec` per entry.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Column {
+    Char(Vec<i8>),
+    UChar(Vec<u8>),
+    Short(Vec<i16>),
+    UShort(Vec<u16>),
+    Int(Vec<i32>),
+    UInt(Vec<u32>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    ListChar(Vec<Vec<i8>>),
+    ListUChar(Vec<Vec<u8>>),
+    ListShort(Vec<Vec<i16>>),
+    ListUShort(Vec<Vec<u16>>),
+    ListInt(Vec<Vec<i32>>),
+    ListUInt(Vec<Vec<u32>>),
+    ListFloat(Vec<Vec<f32>>),
+    ListDouble(Vec<Vec<f64>>),
+}
+
+/// Columns of an element, in the order of the properties in the header.
+pub type ColumnarElement = KeyMap<Column>;
+
+/// Columnar alternative to `Payload`, see `parser::ColumnarParser`.
+pub type ColumnarPayload = KeyMap<ColumnarElement>;
+
+/// Columnar payload whose columns can be shared between threads and copies, edits copy a column on write.
+///
+/// Cloning is cheap, the clone shares all columns with the original. A column is copied once
+/// it is changed in one of them, e.g. a viewer thread keeps rendering the original data
+/// while a worker filters its clone, and only the columns touched by the worker take extra memory.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::ply::{ Column, ColumnarElement, ColumnarPayload, SharedPayload };
+/// let mut vertex = ColumnarElement::new();
+/// vertex.insert("x".to_string(), Column::Float(vec![0.5, 3.5, -1.0]));
+/// vertex.insert("y".to_string(), Column::Float(vec![1.0, 2.0, 3.0]));
+/// let mut payload = ColumnarPayload::new();
+/// payload.insert("vertex".to_string(), vertex);
+///
+/// let viewer = SharedPayload::from(payload);
+/// let mut worker = viewer.clone();
+/// worker.retain_entries("vertex", &[true, false, true]).unwrap();
+/// assert_eq!(worker.column("vertex", "x"), Some(&Column::Float(vec![0.5, -1.0])));
+/// assert_eq!(viewer.column("vertex", "x"), Some(&Column::Float(vec![0.5, 3.5, -1.0])));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SharedPayload {
+    elements: KeyMap<KeyMap<Arc<Column>>>,
+}
+
+impl SharedPayload {
+    /// Creates an empty payload.
+    pub fn new() -> Self {
+        SharedPayload { elements: KeyMap::new() }
+    }
+    /// Names of the elements, in the order they were added.
+    pub fn element_names(&self) -> Vec<&String> {
+        self.elements.keys().collect()
+    }
+    /// Column of `property` in `element`, if there is one.
+    pub fn column(&self, element: &str, property: &str) -> Option<&Column> {
+        self.elements.get(element).and_then(|e| e.get(property)).map(|c| &**c)
+    }
+    /// Column of `property` in `element` for editing, copied first if it is shared.
+    pub fn column_mut(&mut self, element: &str, property: &str) -> Option<&mut Column> {
+        self.elements.get_mut(element).and_then(|e| e.get_mut(property)).map(Arc::make_mut)
+    }
+    /// Replaces or adds the column of `property` in `element`, adding the element if needed.
+    pub fn set_column(&mut self, element: &str, property: &str, column: Column) {
+        if !self.elements.contains_key(element) {
+            self.elements.insert(element.to_string(), KeyMap::new());
+        }
+        self.elements[element].insert(property.to_string(), Arc::new(column));
+    }
+    /// Returns `true` if the column of `property` in `element` is the same, not copied, in both payloads.
+    pub fn shares_column(&self, other: &SharedPayload, element: &str, property: &str) -> bool {
+        let get = |p: &SharedPayload| p.elements.get(element).and_then(|e| e.get(property)).cloned();
+        match (get(self), get(other)) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a, &b),
+            _ => false,
+        }
+    }
+    /// Only keeps the entries of `element` whose flag in `keep` is `true`, copying the columns of the element if shared.
+    ///
+    /// Fails without changing anything if the element is unknown or `keep` differs in length from a column.
+    pub fn retain_entries(&mut self, element: &str, keep: &[bool]) -> Result<(), ConsistencyError> {
+        let columns = match self.elements.get_mut(element) {
+            Some(c) => c,
+            None => return Err(ConsistencyError::new(&format!("Payload has no element `{}`.", element))),
+        };
+        if let Some((name, _)) = columns.iter().find(|&(_, c)| c.len() != keep.len()) {
+            return Err(ConsistencyError::new(&format!(
+                "Column `{}` of element `{}` has {} entries, but {} flags were given.", name, element, columns[name].len(), keep.len()
+            )));
+        }
+        for (_, column) in columns.iter_mut() {
+            Arc::make_mut(column).retain_entries(keep);
+        }
+        Ok(())
+    }
+    /// Copies the columns into a `ColumnarPayload`, e.g. to write them.
+    pub fn to_columnar(&self) -> ColumnarPayload {
+        self.elements.iter().map(|(name, columns)| {
+            let element: ColumnarElement = columns.iter().map(|(k, c)| (k.clone(), (**c).clone())).collect();
+            (name.clone(), element)
+        }).collect()
+    }
+}
+
+impl From<ColumnarPayload> for SharedPayload {
+    fn from(payload: ColumnarPayload) -> Self {
+        SharedPayload {
+            elements: payload.into_iter().map(|(name, columns)| {
+                let shared: KeyMap<Arc<Column>> = columns.into_iter().map(|(k, c)| (k, Arc::new(c))).collect();
+                (name, shared)
+            }).collect(),
+        }
+    }
+}
+
+macro_rules! for_each_column(
+    ($m:ident!($($args:tt)*)) => (
+        $m!($($args)* Char, UChar, Short, UShort, Int, UInt, Float, Double,
+            ListChar, ListUChar, ListShort, ListUShort, ListInt, ListUInt, ListFloat, ListDouble)
+    )
+);
+
+macro_rules! column_len(
+    ($s:expr; $($v:ident),*) => (
+        match *$s { $(Column::$v(ref c) => c.len()),* }
+    )
+);
+
+macro_rules! column_push(
+    ($s:expr, $value:expr; $($v:ident),*) => (
+        match ($s, $value) {
+            $((&mut Column::$v(ref mut c), Property::$v(v)) => { c.push(v); None },)*
+            (_, v) => Some(v),
+        }
+    )
+);
+
+macro_rules! column_get(
+    ($s:expr, $index:expr; $($v:ident),*) => (
+        match *$s { $(Column::$v(ref c) => c.get($index).cloned().map(Property::$v)),* }
+    )
+);
+
+macro_rules! column_slice(
+    ($s:expr, $range:expr; $($v:ident),*) => (
+        match *$s { $(Column::$v(ref c) => Column::$v(c[$range].to_vec())),* }
+    )
+);
+
+macro_rules! column_retain(
+    ($s:expr, $keep:expr; $($v:ident),*) => (
+        match *$s { $(Column::$v(ref mut c) => {
+            let mut i = 0;
+            c.retain(|_| { i += 1; $keep[i - 1] });
+        }),* }
+    )
+);
+
+impl Column {
+    /// Empty column for values of `data_type`.
+    pub fn new(data_type: &PropertyType) -> Self {
+        match *data_type {
+            PropertyType::Scalar(ref scalar_type) => match *scalar_type {
+                ScalarType::Char => Column::Char(Vec::new()),
+                ScalarType::UChar => Column::UChar(Vec::new()),
+                ScalarType::Short => Column::Short(Vec::new()),
+                ScalarType::UShort => Column::UShort(Vec::new()),
+                ScalarType::Int => Column::Int(Vec::new()),
+                ScalarType::UInt => Column::UInt(Vec::new()),
+                ScalarType::Float => Column::Float(Vec::new()),
+                ScalarType::Double => Column::Double(Vec::new()),
+            },
+            PropertyType::List(_, ref scalar_type) => match *scalar_type {
+                ScalarType::Char => Column::ListChar(Vec::new()),
+                ScalarType::UChar => Column::ListUChar(Vec::new()),
+                ScalarType::Short => Column::ListShort(Vec::new()),
+                ScalarType::UShort => Column::ListUShort(Vec::new()),
+                ScalarType::Int => Column::ListInt(Vec::new()),
+                ScalarType::UInt => Column::ListUInt(Vec::new()),
+                ScalarType::Float => Column::ListFloat(Vec::new()),
+                ScalarType::Double => Column::ListDouble(Vec::new()),
+            },
+        }
+    }
+    /// Number of values in the column.
+    pub fn len(&self) -> usize {
+        for_each_column!(column_len!(self;))
+    }
+    /// Returns `true` if the column holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Value at `index`, `None` if it is out of bounds.
+    pub fn get(&self, index: usize) -> Option<Property> {
+        for_each_column!(column_get!(self, index;))
+    }
+    /// Copy of the values in `range`, which must lie within the column.
+    pub fn slice(&self, range: Range<usize>) -> Column {
+        for_each_column!(column_slice!(self, range;))
+    }
+    /// Only keeps the values whose flag in `keep` is `true`, `keep` must have the length of the column.
+    pub fn retain_entries(&mut self, keep: &[bool]) {
+        for_each_column!(column_retain!(self, keep;))
+    }
+    /// Appends `value`, which must be of the column's type.
+    pub fn push(&mut self, value: Property) -> Result<(), ConsistencyError> {
+        match for_each_column!(column_push!(self, value;)) {
+            None => Ok(()),
+            Some(v) => Err(ConsistencyError::new(&format!("Can't append {:?} to a column of another type.", v))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn push_checks_type() {
+        let mut c = Column::new(&PropertyType::List(ScalarType::UChar, ScalarType::Int));
+        assert!(c.is_empty());
+        c.push(Property::ListInt(vec![0, 1, 2])).unwrap();
+        assert!(c.push(Property::Int(3)).is_err());
+        assert_eq!(c, Column::ListInt(vec![vec![0, 1, 2]]));
+        assert_eq!(c.get(0), Some(Property::ListInt(vec![0, 1, 2])));
+        assert_eq!(c.get(1), None);
+        assert_eq!(Column::UChar(vec![1, 2, 3]).slice(1..3), Column::UChar(vec![2, 3]));
+    }
+    #[test]
+    fn shared_copy_on_write() {
+        let mut vertex = ColumnarElement::new();
+        vertex.insert("x".to_string(), Column::Float(vec![0.0, 1.0, 2.0]));
+        vertex.insert("i".to_string(), Column::ListUChar(vec![vec![1], vec![], vec![2, 3]]));
+        let mut payload = ColumnarPayload::new();
+        payload.insert("vertex".to_string(), vertex);
+        payload.insert("face".to_string(), ColumnarElement::new());
+        let original = SharedPayload::from(payload.clone());
+        let mut edited = original.clone();
+        assert!(edited.shares_column(&original, "vertex", "x"));
+        if let Some(&mut Column::Float(ref mut x)) = edited.column_mut("vertex", "x") {
+            x[0] = 5.0;
+        }
+        assert!(!edited.shares_column(&original, "vertex", "x"));
+        assert!(edited.shares_column(&original, "vertex", "i"));
+        assert_eq!(original.to_columnar(), payload);
+        edited.retain_entries("vertex", &[false, true, true]).unwrap();
+        assert_eq!(edited.column("vertex", "i"), Some(&Column::ListUChar(vec![vec![], vec![2, 3]])));
+        assert!(edited.retain_entries("vertex", &[true]).is_err());
+        assert!(edited.retain_entries("edge", &[]).is_err());
+        assert_eq!(edited.element_names(), vec!["vertex", "face"]);
+        assert_eq!(original.column("vertex", "x"), Some(&Column::Float(vec![0.0, 1.0, 2.0])));
+    }
+}
@@ -0,0 +1,285 @@
+//! Brings a `Ply` into the layout of a target header, e.g. before concatenating the payloads of many files.
+
+use std::convert::TryFrom;
+
+use super::{ Ply, Header, Payload, KeyMap, DefaultElement, ElementDef, PropertyType, ScalarType, Property, ConsistencyError, Addable };
+
+impl PropertyType {
+    /// Value used for a property that is missing in an entry:
+    /// `NaN` for floating point scalars, zero for integers, and an empty list for lists.
+    pub fn filler(&self) -> Property {
+        match *self {
+            PropertyType::Scalar(ref scalar_type) => match *scalar_type {
+                ScalarType::Char => Property::Char(0),
+                ScalarType::UChar => Property::UChar(0),
+                ScalarType::Short => Property::Short(0),
+                ScalarType::UShort => Property::UShort(0),
+                ScalarType::Int => Property::Int(0),
+                ScalarType::UInt => Property::UInt(0),
+                ScalarType::Float => Property::Float(::std::f32::NAN),
+                ScalarType::Double => Property::Double(::std::f64::NAN),
+            },
+            PropertyType::List(_, ref scalar_type) => match *scalar_type {
+                ScalarType::Char => Property::ListChar(Vec::new()),
+                ScalarType::UChar => Property::ListUChar(Vec::new()),
+                ScalarType::Short => Property::ListShort(Vec::new()),
+                ScalarType::UShort => Property::ListUShort(Vec::new()),
+                ScalarType::Int => Property::ListInt(Vec::new()),
+                ScalarType::UInt => Property::ListUInt(Vec::new()),
+                ScalarType::Float => Property::ListFloat(Vec::new()),
+                ScalarType::Double => Property::ListDouble(Vec::new()),
+            },
+        }
+    }
+}
+
+impl Ply<DefaultElement> {
+    /// Rearranges `self` to follow the elements and properties of `target`.
+    ///
+    /// Elements and properties are reordered as in `target`, encoding and version are taken over,
+    /// comments and object informations are kept.
+    /// Elements missing in `self` are added without entries,
+    /// properties missing in `self` are added with `PropertyType::filler()` values.
+    /// Properties of a different type are cast if every value is representable in the target type:
+    /// integers are range checked, integers converted to floating point must be exact,
+    /// `double` may be rounded to `float`. Scalars and lists can't be converted into each other.
+    ///
+    /// Fails without touching `self` if it has elements or properties not found in `target`,
+    /// or if a value can't be cast. Afterwards, files conformed to the same target share their binary layout.
+    pub fn conform_to(&mut self, target: &Header) -> Result<(), ConsistencyError> {
+        for (name, element_def) in &self.header.elements {
+            let target_def = match target.elements.get(name) {
+                Some(d) => d,
+                None => return Err(ConsistencyError::new(&format!("Element `{}` is not part of the target.", name))),
+            };
+            for (property_name, property_def) in &element_def.properties {
+                match target_def.properties.get(property_name) {
+                    Some(t) if compatible(&property_def.data_type, &t.data_type) => (),
+                    Some(t) => return Err(ConsistencyError::new(&format!(
+                        "Property `{}` of element `{}` can't be converted from {} to {}.",
+                        property_name, name, property_def.data_type, t.data_type
+                    ))),
+                    None => return Err(ConsistencyError::new(&format!(
+                        "Property `{}` of element `{}` is not part of the target.", property_name, name
+                    ))),
+                }
+            }
+        }
+        let mut payload = Payload::new();
+        for (name, target_def) in &target.elements {
+            let entries = match self.payload.get(name) {
+                Some(e) => e,
+                None => {
+                    payload.insert(name.clone(), Vec::new());
+                    continue;
+                },
+            };
+            let mut conformed = Vec::with_capacity(entries.len());
+            for (i, entry) in entries.iter().enumerate() {
+                let mut e = DefaultElement::new();
+                for (property_name, property_def) in &target_def.properties {
+                    let value = match entry.get(property_name) {
+                        Some(v) => match v.cast(&property_def.data_type) {
+                            Some(v) => v,
+                            None => return Err(ConsistencyError::new(&format!(
+                                "Value {:?} of property `{}` in entry {} of element `{}` can't be represented as {}.",
+                                v, property_name, i, name, property_def.data_type
+                            ))),
+                        },
+                        None => property_def.data_type.filler(),
+                    };
+                    e.insert(property_name.clone(), value);
+                }
+                conformed.push(e);
+            }
+            payload.insert(name.clone(), conformed);
+        }
+        let mut elements = KeyMap::<ElementDef>::new();
+        for (name, target_def) in &target.elements {
+            let mut element_def = target_def.clone();
+            element_def.count = payload[name].len() as u64;
+            elements.add(element_def);
+        }
+        self.header.elements = elements;
+        self.header.encoding = target.encoding;
+        self.header.version = target.version;
+        self.payload = payload;
+        Ok(())
+    }
+}
+
+/// True if values of type `from` can be cast to `to`, at least in principle.
+fn compatible(from: &PropertyType, to: &PropertyType) -> bool {
+    match (from, to) {
+        (&PropertyType::Scalar(_), &PropertyType::Scalar(_)) => true,
+        (&PropertyType::List(_, _), &PropertyType::List(_, _)) => true,
+        _ => false,
+    }
+}
+
+/// Number held by a scalar or a list entry, on the way to another type.
+#[derive(Clone, Copy)]
+enum Number {
+    Integer(i64),
+    Real(f64),
+}
+
+impl Property {
+    /// Converts the value to `to`, `None` if it isn't representable in that type.
+    ///
+    /// Integers are range checked, integers converted to floating point must be exact,
+    /// `double` may be rounded to `float`, but must not exceed its range.
+    /// Scalars and lists can't be converted into each other,
+    /// the length of a list must fit into the target index type.
+    pub fn cast(&self, to: &PropertyType) -> Option<Property> {
+        cast(self, to)
+    }
+}
+
+fn cast(value: &Property, to: &PropertyType) -> Option<Property> {
+    if let PropertyType::List(ref index_type, _) = *to {
+        if index_type.is_integer() && list_len(value) as f64 > index_type.max_value() {
+            return None;
+        }
+    }
+    match (value.clone(), to) {
+        (Property::ListChar(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Integer(v as i64)), t),
+        (Property::ListUChar(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Integer(v as i64)), t),
+        (Property::ListShort(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Integer(v as i64)), t),
+        (Property::ListUShort(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Integer(v as i64)), t),
+        (Property::ListInt(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Integer(v as i64)), t),
+        (Property::ListUInt(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Integer(v as i64)), t),
+        (Property::ListFloat(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(|v| Number::Real(v as f64)), t),
+        (Property::ListDouble(l), &PropertyType::List(_, ref t)) => cast_list(l.into_iter().map(Number::Real), t),
+        (Property::Char(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Integer(v as i64), t),
+        (Property::UChar(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Integer(v as i64), t),
+        (Property::Short(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Integer(v as i64), t),
+        (Property::UShort(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Integer(v as i64), t),
+        (Property::Int(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Integer(v as i64), t),
+        (Property::UInt(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Integer(v as i64), t),
+        (Property::Float(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Real(v as f64), t),
+        (Property::Double(v), &PropertyType::Scalar(ref t)) => cast_scalar(Number::Real(v), t),
+        _ => None,
+    }
+}
+
+/// Number of entries of a list, zero for scalars.
+fn list_len(value: &Property) -> usize {
+    match *value {
+        Property::ListChar(ref l) => l.len(),
+        Property::ListUChar(ref l) => l.len(),
+        Property::ListShort(ref l) => l.len(),
+        Property::ListUShort(ref l) => l.len(),
+        Property::ListInt(ref l) => l.len(),
+        Property::ListUInt(ref l) => l.len(),
+        Property::ListFloat(ref l) => l.len(),
+        Property::ListDouble(ref l) => l.len(),
+        _ => 0,
+    }
+}
+
+fn cast_scalar(n: Number, to: &ScalarType) -> Option<Property> {
+    match *to {
+        ScalarType::Char => integer(n).map(Property::Char),
+        ScalarType::UChar => integer(n).map(Property::UChar),
+        ScalarType::Short => integer(n).map(Property::Short),
+        ScalarType::UShort => integer(n).map(Property::UShort),
+        ScalarType::Int => integer(n).map(Property::Int),
+        ScalarType::UInt => integer(n).map(Property::UInt),
+        ScalarType::Float => float(n).map(Property::Float),
+        ScalarType::Double => double(n).map(Property::Double),
+    }
+}
+
+fn cast_list<I: Iterator<Item = Number>>(values: I, to: &ScalarType) -> Option<Property> {
+    match *to {
+        ScalarType::Char => values.map(integer).collect::<Option<_>>().map(Property::ListChar),
+        ScalarType::UChar => values.map(integer).collect::<Option<_>>().map(Property::ListUChar),
+        ScalarType::Short => values.map(integer).collect::<Option<_>>().map(Property::ListShort),
+        ScalarType::UShort => values.map(integer).collect::<Option<_>>().map(Property::ListUShort),
+        ScalarType::Int => values.map(integer).collect::<Option<_>>().map(Property::ListInt),
+        ScalarType::UInt => values.map(integer).collect::<Option<_>>().map(Property::ListUInt),
+        ScalarType::Float => values.map(float).collect::<Option<_>>().map(Property::ListFloat),
+        ScalarType::Double => values.map(double).collect::<Option<_>>().map(Property::ListDouble),
+    }
+}
+
+fn integer<T: TryFrom<i64>>(n: Number) -> Option<T> {
+    match n {
+        Number::Integer(v) => T::try_from(v).ok(),
+        Number::Real(_) => None,
+    }
+}
+
+fn float(n: Number) -> Option<f32> {
+    match n {
+        Number::Integer(v) if (v as f32) as i64 == v => Some(v as f32),
+        Number::Integer(_) => None,
+        Number::Real(v) if v.is_finite() && (v as f32).is_infinite() => None,
+        Number::Real(v) => Some(v as f32),
+    }
+}
+
+fn double(n: Number) -> Option<f64> {
+    match n {
+        Number::Integer(v) => Some(v as f64),
+        Number::Real(v) => Some(v),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    fn vertex_ply(properties: &[(&str, Property, ScalarType)]) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        let mut v = ElementDef::new("vertex".to_string());
+        let mut e = DefaultElement::new();
+        for &(name, ref value, ref scalar_type) in properties {
+            v.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(scalar_type.clone())));
+            e.insert(name.to_string(), value.clone());
+        }
+        ply.header.elements.add(v);
+        ply.payload.insert("vertex".to_string(), vec![e]);
+        ply.make_consistent().unwrap();
+        ply
+    }
+    #[test]
+    fn conform_reorders_and_casts() {
+        let target = vertex_ply(&[
+            ("x", Property::Float(0.0), ScalarType::Float),
+            ("y", Property::Float(0.0), ScalarType::Float),
+            ("red", Property::UChar(0), ScalarType::UChar),
+        ]).header;
+        let mut p = vertex_ply(&[("y", Property::Double(2.5), ScalarType::Double), ("red", Property::Int(7), ScalarType::Int), ("x", Property::Short(-1), ScalarType::Short)]);
+        p.conform_to(&target).unwrap();
+        assert_eq!(p.header.elements, target.elements);
+        let keys: Vec<&String> = p.payload["vertex"][0].keys().collect();
+        assert_eq!(keys, vec!["x", "y", "red"]);
+        assert_eq!(p.payload["vertex"][0]["x"], Property::Float(-1.0));
+        assert_eq!(p.payload["vertex"][0]["y"], Property::Float(2.5));
+        assert_eq!(p.payload["vertex"][0]["red"], Property::UChar(7));
+
+        let mut p = vertex_ply(&[("x", Property::Float(1.0), ScalarType::Float)]);
+        p.conform_to(&target).unwrap();
+        assert_eq!(p.payload["vertex"][0]["red"], Property::UChar(0));
+
+        let mut p = vertex_ply(&[("red", Property::Int(300), ScalarType::Int)]);
+        let before = p.clone();
+        assert!(p.conform_to(&target).is_err());
+        assert_eq!(p, before);
+        assert!(vertex_ply(&[("z", Property::Float(1.0), ScalarType::Float)]).conform_to(&target).is_err());
+        assert!(vertex_ply(&[("x", Property::Double(0.5), ScalarType::Double)]).conform_to(&target).is_ok());
+        assert!(vertex_ply(&[("x", Property::Double(1e300), ScalarType::Double)]).conform_to(&target).is_err());
+    }
+    #[test]
+    fn cast_checks_ranges() {
+        let uchar_list = PropertyType::List(ScalarType::UChar, ScalarType::Int);
+        assert!(Property::ListInt(vec![0; 255]).cast(&uchar_list).is_some());
+        assert_eq!(Property::ListInt(vec![0; 256]).cast(&uchar_list), None);
+        assert_eq!(Property::ListInt(vec![0; 256]).cast(&PropertyType::List(ScalarType::UShort, ScalarType::Int)), Some(Property::ListInt(vec![0; 256])));
+        let float = PropertyType::Scalar(ScalarType::Float);
+        assert_eq!(Property::Double(1e300).cast(&float), None);
+        assert_eq!(Property::Double(::std::f64::INFINITY).cast(&float), Some(Property::Float(::std::f32::INFINITY)));
+        assert!(Property::Double(::std::f64::NAN).cast(&float).is_some());
+    }
+}
@@ -3,7 +3,7 @@
 use std::fmt::{ Display, Formatter };
 use std::fmt;
 use std::error;
-use super::Ply;
+use super::{ Ply, Header, PropertyType };
 use super::PropertyAccess;
 
 /// Contains a description, why a given `Ply` object isn't consistent and could not be made consistent.
@@ -19,6 +19,10 @@ impl ConsistencyError {
             description: description.to_string(),
         }
     }
+    /// What went wrong, without the `ConsistencyError:` prefix of `Display`.
+    pub fn message(&self) -> &str {
+        &self.description
+    }
 }
 
 impl Display for ConsistencyError {
@@ -75,19 +79,31 @@ impl<E: PropertyAccess> Ply<E>{
             if ed.is_none() {
                 return Err(ConsistencyError::new(&format!("No decleration for element `{}` found.", pk)));
             }
-            ed.unwrap().count = pe.len();
+            ed.unwrap().count = pe.len() as u64;
         }
-        for ref oi in &self.header.obj_infos {
+        self.header.check()
+    }
+}
+
+impl Header {
+    /// Checks the invariants of a header that can't be fixed automatically.
+    ///
+    /// Every relevant string is checked to not contain line breaks,
+    /// identifiers are also checked to not contain white spaces,
+    /// and list indices must be of an integer type.
+    /// Performed by `Ply::make_consistent()` and before the `Writer` writes a header.
+    pub fn check(&self) -> Result<(), ConsistencyError> {
+        for ref oi in &self.obj_infos {
             if has_line_break(oi) {
                 return Err(ConsistencyError::new(&format!("Objection information `{}` should not contain any line breaks.", oi)));
             }
         }
-        for ref c in &self.header.comments {
+        for ref c in &self.comments {
             if has_line_break(&c) {
                return Err(ConsistencyError::new(&format!("Comment `{}` should not contain any line breaks.", c)));
             }
         }
-        for (_, ref e) in &self.header.elements {
+        for (_, ref e) in &self.elements {
             if has_line_break(&e.name) {
                 return Err(ConsistencyError::new(&format!("Name of element `{}` should not contain any line breaks.", e.name)));
             }
@@ -101,6 +117,11 @@ impl<E: PropertyAccess> Ply<E>{
                 if has_white_space(&p.name) {
                     return Err(ConsistencyError::new(&format!("Name of property `{}` of element `{}` should not contain any spaces.", p.name, e.name)));
                 }
+                if let PropertyType::List(ref index_type, _) = p.data_type {
+                    if !index_type.is_integer() {
+                        return Err(ConsistencyError::new(&format!("Index of list `{}` of element `{}` must be an integer type, not {}.", p.name, e.name, index_type)));
+                    }
+                }
             }
         }
         Ok(())
@@ -157,4 +178,13 @@ mod tests {
         let r = p.make_consistent();
         assert!(r.is_err());
     }
+    #[test]
+    fn consistent_float_index_fail() {
+        let mut p = P::new();
+        let mut e = ElementDef::new("ok".to_string());
+        e.properties.add(PropertyDef::new("list".to_string(), PropertyType::List(ScalarType::Float, ScalarType::Int)));
+        p.header.elements.add(e);
+        assert!(p.header.check().is_err());
+        assert!(p.make_consistent().is_err());
+    }
 }
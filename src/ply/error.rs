@@ -0,0 +1,180 @@
+//! Structured causes of failed reads and writes.
+
+use std::error;
+use std::fmt;
+use std::fmt::{ Display, Formatter };
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+use super::PropertyType;
+
+/// Why reading or writing a PLY failed.
+///
+/// The `Parser` and `Writer` return `io::Error`s, for the failures listed here they carry an `Error` inside,
+/// use `Error::from_io()` to get it back instead of inspecting the message.
+/// With the `serde` feature it can be serialized, e.g. to aggregate failures of many files in a report.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Error {
+    /// A line of the header or of an ascii payload couldn't be parsed.
+    Parse {
+        /// Line number, counting from 1. A line parsed on its own, e.g. by `Parser::read_header_line()`, is line 1.
+        line: u64,
+        /// The offending line.
+        found: String,
+        /// What went wrong.
+        message: String,
+    },
+    /// A header line was parsed fine, but is of another kind than required.
+    UnexpectedLine {
+        /// Kind of line expected, e.g. `element`.
+        expected: String,
+        /// The offending line.
+        found: String,
+    },
+    /// The header has no `format` line.
+    MissingFormat,
+    /// A list announces more entries than allowed by `Parser::set_max_list_len()`.
+    ListTooLong {
+        /// Name of the list property.
+        property: String,
+        /// Announced number of entries.
+        len: u64,
+        /// The configured maximum.
+        max: u64,
+    },
+    /// A list of a binary payload announces a negative number of entries.
+    NegativeListLength {
+        /// Name of the list property.
+        property: String,
+        /// The announced length.
+        len: i64,
+    },
+    /// An entry of the payload couldn't be read.
+    InvalidEntry {
+        /// Element being read.
+        element: String,
+        /// Index of the entry.
+        index: u64,
+        /// What went wrong.
+        cause: Box<Error>,
+    },
+    /// The source ended before all entries declared in the header were read.
+    UnexpectedEof {
+        /// Element being read.
+        element: String,
+        /// Index of the missing entry.
+        index: u64,
+    },
+    /// An element to write has no value of the declared type for a property.
+    TypeMismatch {
+        /// Name of the property.
+        property: String,
+        /// Type declared in the header.
+        expected: PropertyType,
+        /// Type of the value stored under the name instead, like `float` or `list of int`,
+        /// `None` if there is none.
+        found: Option<String>,
+    },
+    /// The bytes of an element block don't match the checksum recorded in the header,
+    /// see `Parser::set_verify_element_crcs()`.
+    ChecksumMismatch {
+        /// Name of the element.
+        element: String,
+        /// CRC-32 recorded in the header.
+        expected: u32,
+        /// CRC-32 of the bytes read.
+        found: u32,
+    },
+    /// Reading or writing the file at `path` failed, returned by the functions taking a path.
+    File {
+        /// Path of the file, as given.
+        path: String,
+        /// What went wrong.
+        cause: Box<Error>,
+    },
+    /// A failure without a structured cause, e.g. a file that doesn't exist.
+    Io {
+        /// Description of the failure.
+        message: String,
+    },
+}
+
+impl Error {
+    /// Returns the `Error` carried by `e`, if any.
+    pub fn from_io(e: &io::Error) -> Option<&Error> {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<Error>())
+    }
+    /// Attributes `e` to the file at `path`, keeping its kind.
+    ///
+    /// The returned error carries an `Error::File`, whose cause is the `Error` carried by `e`,
+    /// or an `Error::Io` with its message. Errors already attributed to a file are returned unchanged.
+    pub fn in_file<P: AsRef<Path>>(e: io::Error, path: P) -> io::Error {
+        let cause = match Error::from_io(&e) {
+            Some(&Error::File { .. }) => return e,
+            Some(cause) => cause.clone(),
+            None => Error::Io { message: e.to_string() },
+        };
+        let file = Error::File { path: path.as_ref().to_string_lossy().into_owned(), cause: Box::new(cause) };
+        io::Error::new(e.kind(), file)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::Parse { line, ref found, ref message } => write!(f, "Line {}: {}\n\tString: '{}'", line, message, found),
+            Error::UnexpectedLine { ref expected, ref found } => write!(f, "Expected {} line, but found '{}'.", expected, found),
+            Error::MissingFormat => f.write_str("No format line found."),
+            Error::ListTooLong { ref property, len, max } => write!(f, "List `{}` has {} entries, exceeding the maximum of {}.", property, len, max),
+            Error::NegativeListLength { ref property, len } => write!(f, "List `{}` has a negative length of {}.", property, len),
+            Error::InvalidEntry { ref element, index, ref cause } => write!(f, "Couldn't read entry {} of element `{}`.\n\tError: {}", index, element, cause),
+            Error::UnexpectedEof { ref element, index } => write!(f, "Unexpected end of file while reading entry {} of element `{}`.", index, element),
+            Error::TypeMismatch { ref property, ref expected, found: None } => write!(f, "No value of type {} available for property `{}`.", expected, property),
+            Error::TypeMismatch { ref property, ref expected, found: Some(ref found) } =>
+                write!(f, "Property `{}` is declared as {}, but holds a value of type {}.", property, expected, found),
+            Error::ChecksumMismatch { ref element, expected, found } =>
+                write!(f, "Element `{}` has the CRC-32 {:08x}, but {:08x} is recorded in the header.", element, found, expected),
+            Error::File { ref path, ref cause } => write!(f, "`{}`: {}", path, cause),
+            Error::Io { ref message } => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        let kind = match e {
+            Error::UnexpectedEof { .. } => ErrorKind::UnexpectedEof,
+            Error::ChecksumMismatch { .. } => ErrorKind::InvalidData,
+            _ => ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::io::ErrorKind;
+    #[test]
+    fn carried_by_io_error() {
+        let e: io::Error = Error::UnexpectedEof { element: "vertex".to_string(), index: 3 }.into();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(Error::from_io(&e), Some(&Error::UnexpectedEof { element: "vertex".to_string(), index: 3 }));
+        assert_eq!(Error::from_io(&io::Error::new(ErrorKind::Other, "other")), None);
+    }
+    #[test]
+    fn in_file() {
+        let e = Error::in_file(io::Error::new(ErrorKind::NotFound, "gone"), "scan.ply");
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        let expected = Error::File { path: "scan.ply".to_string(), cause: Box::new(Error::Io { message: "gone".to_string() }) };
+        assert_eq!(Error::from_io(&e), Some(&expected));
+        let e = Error::in_file(e, "other.ply");
+        assert_eq!(Error::from_io(&e), Some(&expected));
+        let e = Error::in_file(Error::MissingFormat.into(), "scan.ply");
+        assert_eq!(e.to_string(), "`scan.ply`: No format line found.");
+    }
+}
@@ -0,0 +1,148 @@
+//! Stable content hash of a `Ply`, independent of its encoding.
+
+use super::{ Ply, PropertyAccess, PropertyType, ScalarType };
+
+impl<E: PropertyAccess> Ply<E> {
+    /// Hashes the schema and all payload values into a 64 bit fingerprint.
+    ///
+    /// Covers element names and counts, property names and types, and the values in file order.
+    /// Encoding, version, comments and object informations are ignored,
+    /// so an ascii and a binary copy of the same data share their fingerprint.
+    ///
+    /// With `float_step` set, floating point values are rounded to multiples of it before hashing,
+    /// this tolerates the small differences left by an ascii roundtrip.
+    /// Values close to a rounding boundary can still end up on different sides, choose the step accordingly.
+    /// Without it, values are hashed bit by bit, only `-0.0` and all NaNs are unified.
+    ///
+    /// The result doesn't depend on the platform or the version of rust, it can be stored and compared later.
+    pub fn fingerprint(&self, float_step: Option<f64>) -> u64 {
+        let mut h = Fnv::new();
+        for (name, element_def) in &self.header.elements {
+            h.write_str(name);
+            h.write(&element_def.count.to_le_bytes());
+            for (key, property_def) in &element_def.properties {
+                h.write_str(key);
+                h.write_str(&property_def.data_type.to_string());
+            }
+        }
+        let f = |v: f64| quantize(v, float_step);
+        for (name, element_def) in &self.header.elements {
+            let entries = match self.payload.get(name) {
+                Some(e) => &e[..],
+                None => &[],
+            };
+            for entry in entries {
+                for (k, property_def) in &element_def.properties {
+                    match property_def.data_type {
+                        PropertyType::Scalar(ref scalar_type) => match *scalar_type {
+                            ScalarType::Char => h.write_scalar(entry.get_char(k).map(|v| v as i64)),
+                            ScalarType::UChar => h.write_scalar(entry.get_uchar(k).map(|v| v as i64)),
+                            ScalarType::Short => h.write_scalar(entry.get_short(k).map(|v| v as i64)),
+                            ScalarType::UShort => h.write_scalar(entry.get_ushort(k).map(|v| v as i64)),
+                            ScalarType::Int => h.write_scalar(entry.get_int(k).map(|v| v as i64)),
+                            ScalarType::UInt => h.write_scalar(entry.get_uint(k).map(|v| v as i64)),
+                            ScalarType::Float => h.write_scalar(entry.get_float(k).map(|v| f(v as f64))),
+                            ScalarType::Double => h.write_scalar(entry.get_double(k).map(&f)),
+                        },
+                        PropertyType::List(_, ref scalar_type) => match *scalar_type {
+                            ScalarType::Char => h.write_list(entry.get_list_char(k), |v| *v as i64),
+                            ScalarType::UChar => h.write_list(entry.get_list_uchar(k), |v| *v as i64),
+                            ScalarType::Short => h.write_list(entry.get_list_short(k), |v| *v as i64),
+                            ScalarType::UShort => h.write_list(entry.get_list_ushort(k), |v| *v as i64),
+                            ScalarType::Int => h.write_list(entry.get_list_int(k), |v| *v as i64),
+                            ScalarType::UInt => h.write_list(entry.get_list_uint(k), |v| *v as i64),
+                            ScalarType::Float => h.write_list(entry.get_list_float(k), |v| f(*v as f64)),
+                            ScalarType::Double => h.write_list(entry.get_list_double(k), |v| f(*v)),
+                        },
+                    }
+                }
+            }
+        }
+        h.0
+    }
+}
+
+/// Maps a float to an integer, equal for values that should hash the same.
+fn quantize(v: f64, float_step: Option<f64>) -> i64 {
+    if v.is_nan() {
+        return i64::min_value();
+    }
+    match float_step {
+        Some(step) if step > 0.0 => (v / step).round() as i64,
+        _ => if v == 0.0 { 0 } else { v.to_bits() as i64 },
+    }
+}
+
+/// 64 bit FNV-1a, fixed by its specification unlike `std::collections::hash_map::DefaultHasher`.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u64).to_le_bytes());
+        self.write(s.as_bytes());
+    }
+    /// Missing values hash differently from all present ones.
+    fn write_scalar(&mut self, value: Option<i64>) {
+        match value {
+            Some(v) => {
+                self.write(&[0]);
+                self.write(&v.to_le_bytes());
+            },
+            None => self.write(&[1]),
+        }
+    }
+    fn write_list<D, F: Fn(&D) -> i64>(&mut self, list: Option<&[D]>, to_i64: F) {
+        match list {
+            Some(l) => {
+                self.write(&[0]);
+                self.write(&(l.len() as u64).to_le_bytes());
+                for v in l {
+                    self.write(&to_i64(v).to_le_bytes());
+                }
+            },
+            None => self.write(&[1]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    fn ply(x: f32) -> Ply<DefaultElement> {
+        let mut p = Ply::<DefaultElement>::new();
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        e.properties.add(PropertyDef::new("i".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::Int)));
+        e.count = 1;
+        p.header.elements.add(e);
+        let mut v = DefaultElement::new();
+        v.insert("x".to_string(), Property::Float(x));
+        v.insert("i".to_string(), Property::ListInt(vec![1, 2]));
+        p.payload.insert("vertex".to_string(), vec![v]);
+        p
+    }
+    #[test]
+    fn fingerprint_stable() {
+        let a = ply(0.1);
+        let mut b = ply(0.1);
+        b.header.encoding = Encoding::BinaryLittleEndian;
+        b.header.comments.push("other tool".to_string());
+        assert_eq!(a.fingerprint(None), b.fingerprint(None));
+        assert_eq!(ply(0.0).fingerprint(None), ply(-0.0).fingerprint(None));
+        assert!(ply(0.1).fingerprint(None) != ply(0.1000001).fingerprint(None));
+        assert_eq!(ply(0.1).fingerprint(Some(1e-3)), ply(0.1000001).fingerprint(Some(1e-3)));
+        b.payload["vertex"][0].insert("i".to_string(), Property::ListInt(vec![1]));
+        assert!(a.fingerprint(None) != b.fingerprint(None));
+        b.payload["vertex"][0].remove("i");
+        assert!(a.fingerprint(None) != b.fingerprint(None));
+    }
+}
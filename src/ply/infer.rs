@@ -0,0 +1,137 @@
+//! Derives a `Header` from a payload built in code.
+
+use super::{ Header, Payload, DefaultElement, ElementDef, PropertyDef, PropertyType, ScalarType, Property, ConsistencyError, Addable };
+
+impl Header {
+    /// Builds the element and property definitions matching `payload`.
+    ///
+    /// Properties are declared in the order of the first entry of each element,
+    /// their types follow the `Property` variants found.
+    /// The index type of a list is the smallest unsigned type able to hold its longest instance.
+    /// All entries of an element must have the same properties with the same variants,
+    /// otherwise an error names the first entry that differs.
+    ///
+    /// Encoding, version, comments and object informations are those of `Header::new()`.
+    pub fn infer_from_payload(payload: &Payload<DefaultElement>) -> Result<Header, ConsistencyError> {
+        let mut header = Header::new();
+        for (name, entries) in payload {
+            let mut element_def = ElementDef::new(name.clone());
+            element_def.count = entries.len() as u64;
+            let first = match entries.first() {
+                Some(f) => f,
+                None => {
+                    header.elements.add(element_def);
+                    continue;
+                },
+            };
+            let mut max_lens = vec![0usize; first.len()];
+            for (i, entry) in entries.iter().enumerate() {
+                if entry.len() != first.len() {
+                    return Err(ConsistencyError::new(&format!(
+                        "Entry {} of element `{}` has {} properties, but entry 0 has {}.", i, name, entry.len(), first.len())));
+                }
+                for ((key, reference), max_len) in first.iter().zip(max_lens.iter_mut()) {
+                    let value = match entry.get(key) {
+                        Some(v) => v,
+                        None => return Err(ConsistencyError::new(&format!(
+                            "Entry {} of element `{}` lacks property `{}`.", i, name, key))),
+                    };
+                    let (scalar_type, len) = describe(value);
+                    let (reference_type, reference_len) = describe(reference);
+                    if scalar_type != reference_type || len.is_some() != reference_len.is_some() {
+                        return Err(ConsistencyError::new(&format!(
+                            "Property `{}` of entry {} of element `{}` is {:?}, but entry 0 has {:?}.", key, i, name, value, reference)));
+                    }
+                    if let Some(len) = len {
+                        if len > *max_len {
+                            *max_len = len;
+                        }
+                    }
+                }
+            }
+            for ((key, value), max_len) in first.iter().zip(max_lens) {
+                let data_type = match describe(value) {
+                    (scalar_type, None) => PropertyType::Scalar(scalar_type),
+                    (scalar_type, Some(_)) => {
+                        let index_type = if max_len <= u8::max_value() as usize {
+                            ScalarType::UChar
+                        } else if max_len <= u16::max_value() as usize {
+                            ScalarType::UShort
+                        } else if max_len as u64 <= u32::max_value() as u64 {
+                            ScalarType::UInt
+                        } else {
+                            return Err(ConsistencyError::new(&format!(
+                                "List `{}` of element `{}` has {} entries, more than a PLY index can hold.", key, name, max_len)));
+                        };
+                        PropertyType::List(index_type, scalar_type)
+                    },
+                };
+                element_def.properties.add(PropertyDef::new(key.clone(), data_type));
+            }
+            header.elements.add(element_def);
+        }
+        Ok(header)
+    }
+}
+
+/// Scalar type of `property` and its length if it is a list.
+fn describe(property: &Property) -> (ScalarType, Option<usize>) {
+    match *property {
+        Property::Char(_) => (ScalarType::Char, None),
+        Property::UChar(_) => (ScalarType::UChar, None),
+        Property::Short(_) => (ScalarType::Short, None),
+        Property::UShort(_) => (ScalarType::UShort, None),
+        Property::Int(_) => (ScalarType::Int, None),
+        Property::UInt(_) => (ScalarType::UInt, None),
+        Property::Float(_) => (ScalarType::Float, None),
+        Property::Double(_) => (ScalarType::Double, None),
+        Property::ListChar(ref l) => (ScalarType::Char, Some(l.len())),
+        Property::ListUChar(ref l) => (ScalarType::UChar, Some(l.len())),
+        Property::ListShort(ref l) => (ScalarType::Short, Some(l.len())),
+        Property::ListUShort(ref l) => (ScalarType::UShort, Some(l.len())),
+        Property::ListInt(ref l) => (ScalarType::Int, Some(l.len())),
+        Property::ListUInt(ref l) => (ScalarType::UInt, Some(l.len())),
+        Property::ListFloat(ref l) => (ScalarType::Float, Some(l.len())),
+        Property::ListDouble(ref l) => (ScalarType::Double, Some(l.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    fn entry(x: f32, indices: Vec<u32>) -> DefaultElement {
+        let mut e = DefaultElement::new();
+        e.insert("x".to_string(), Property::Float(x));
+        e.insert("indices".to_string(), Property::ListUInt(indices));
+        e
+    }
+    #[test]
+    fn infer_header() {
+        let mut payload = Payload::new();
+        payload.insert("node".to_string(), vec![entry(1.0, vec![1, 2]), entry(2.0, vec![0; 300])]);
+        payload.insert("empty".to_string(), Vec::new());
+        let h = Header::infer_from_payload(&payload).unwrap();
+        let node = &h.elements["node"];
+        assert_eq!(node.count, 2);
+        assert_eq!(node.properties["x"].data_type, PropertyType::Scalar(ScalarType::Float));
+        assert_eq!(node.properties["indices"].data_type, PropertyType::List(ScalarType::UShort, ScalarType::UInt));
+        assert_eq!(h.elements["empty"].count, 0);
+        let mut ply = Ply::<DefaultElement>::new();
+        ply.header = h;
+        ply.payload = payload;
+        assert!(ply.make_consistent().is_ok());
+    }
+    #[test]
+    fn infer_header_inhomogeneous_err() {
+        let mut payload = Payload::new();
+        let mut other = entry(1.0, vec![]);
+        other.insert("x".to_string(), Property::Double(1.0));
+        payload.insert("node".to_string(), vec![entry(1.0, vec![]), other]);
+        assert!(Header::infer_from_payload(&payload).is_err());
+        let mut missing = entry(1.0, vec![]);
+        missing.remove("x");
+        missing.insert("y".to_string(), Property::Float(1.0));
+        payload.insert("node".to_string(), vec![entry(1.0, vec![]), missing]);
+        assert!(Header::infer_from_payload(&payload).is_err());
+    }
+}
@@ -1,5 +1,20 @@
 //! Definitions used to model PLY files.
 
+mod blob;
+pub use self::blob::*;
+
+#[cfg(feature = "codecs")]
+mod codec;
+#[cfg(feature = "codecs")]
+pub use self::codec::*;
+
+mod column;
+pub use self::column::*;
+
+mod columnar;
+pub use self::columnar::*;
+
+mod conform;
 
 mod consistency;
 pub use self::consistency::*;
@@ -7,6 +22,13 @@ pub use self::consistency::*;
 mod default_element;
 pub use self::default_element::*;
 
+mod error;
+pub use self::error::*;
+
+mod fingerprint;
+
+mod infer;
+
 mod key_map;
 pub use self::key_map::*;
 
@@ -15,3 +37,10 @@ pub use self::ply_data_structure::*;
 
 mod property;
 pub use self::property::*;
+
+mod row_element;
+pub use self::row_element::*;
+
+#[macro_use]
+mod to_element_def;
+pub use self::to_element_def::*;
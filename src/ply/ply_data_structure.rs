@@ -3,6 +3,8 @@ use std::fmt;
 use super::PropertyType;
 use super::KeyMap;
 use super::PropertyAccess;
+use super::ConsistencyError;
+use faces::{ VERTEX_ELEMENT, FACE_ELEMENT };
 
 /// Models all necessary information to interact with a PLY file.
 ///
@@ -37,6 +39,50 @@ impl<E: PropertyAccess> Ply<E> {
             payload: Payload::new(),
         }
     }
+    /// Renames the element `old_name` to `new_name`.
+    ///
+    /// Updates the key and name of the element definition in the header as well as the key of the payload,
+    /// the position of the element in the file is kept.
+    ///
+    /// Fails without changing anything if there is no element `old_name` or if `new_name` is already in use.
+    ///
+    /// The helpers in `faces`, `merge` and `concat` find faces and the vertices they index by the names
+    /// `face` and `vertex`. As long as a `face` element exists, neither of the two can be renamed,
+    /// since the faces would no longer be linked to their vertices.
+    pub fn rename_element(&mut self, old_name: &str, new_name: &str) -> Result<(), ConsistencyError> {
+        if !self.header.elements.contains_key(old_name) {
+            return Err(ConsistencyError::new(&format!("No element `{}` found to rename.", old_name)));
+        }
+        if old_name == new_name {
+            return Ok(());
+        }
+        if (old_name == VERTEX_ELEMENT || old_name == FACE_ELEMENT) && self.header.elements.contains_key(FACE_ELEMENT) {
+            return Err(ConsistencyError::new(&format!("Can not rename `{}`, the faces index their vertices by the names `{}` and `{}`.", old_name, VERTEX_ELEMENT, FACE_ELEMENT)));
+        }
+        if self.header.elements.contains_key(new_name) || self.payload.contains_key(new_name) {
+            return Err(ConsistencyError::new(&format!("Can not rename `{}`, element `{}` already exists.", old_name, new_name)));
+        }
+        rename_key(&mut self.header.elements, old_name, new_name);
+        self.header.elements.get_mut(new_name).unwrap().name = new_name.to_string();
+        rename_key(&mut self.payload, old_name, new_name);
+        Ok(())
+    }
+}
+
+/// Replaces `old_key` with `new_key` while keeping the insertion order.
+fn rename_key<V>(map: &mut KeyMap<V>, old_key: &str, new_key: &str) {
+    if !map.contains_key(old_key) {
+        return;
+    }
+    let mut renamed = KeyMap::with_capacity(map.len());
+    while let Some((k, v)) = map.pop_front() {
+        if k == old_key {
+            renamed.insert(new_key.to_string(), v);
+        } else {
+            renamed.insert(k, v);
+        }
+    }
+    *map = renamed;
 }
 
 // Header Types
@@ -137,7 +183,9 @@ pub struct ElementDef {
     ///
     /// The `count` is used when reading since we need to know how many elements we should interprete as having this type.
     /// The `count` is also needed for writing, since it will be written to the header.
-    pub count: usize,
+    ///
+    /// It is a `u64` on all targets, so huge declared counts are not truncated on 32 bit platforms.
+    pub count: u64,
     /// An element is modeled by multiple properties, those are named values or lists.
     ///
     /// # Examples
@@ -161,8 +209,34 @@ impl ElementDef {
             properties: KeyMap::new(),
         }
     }
+    /// Moves the properties named in `order` to the front, in the given sequence.
+    ///
+    /// Names without a matching property are ignored.
+    /// All other properties follow in their previous order.
+    /// See `CONVENTIONAL_PROPERTY_ORDER` for the layout many legacy readers expect.
+    pub fn reorder_properties(&mut self, order: &[&str]) {
+        let mut reordered = KeyMap::with_capacity(self.properties.len());
+        for name in order {
+            if let Some(p) = self.properties.remove(*name) {
+                reordered.insert(name.to_string(), p);
+            }
+        }
+        while let Some((k, p)) = self.properties.pop_front() {
+            reordered.insert(k, p);
+        }
+        self.properties = reordered;
+    }
 }
 
+/// Property order that some legacy readers assume, regardless of the header:
+/// Position, normal, color, and texture coordinates.
+pub const CONVENTIONAL_PROPERTY_ORDER: [&'static str; 12] = [
+    "x", "y", "z",
+    "nx", "ny", "nz",
+    "red", "green", "blue", "alpha",
+    "u", "v",
+];
+
 /// Defines a property of an element.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct PropertyDef {
@@ -188,3 +262,57 @@ impl PropertyDef {
 
 /// The part after `end_header`, contains the main data.
 pub type Payload<E> = KeyMap<Vec<E>>;
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    type P = Ply<DefaultElement>;
+    #[test]
+    fn rename_element_keeps_order() {
+        let mut p = P::new();
+        p.header.elements.add(ElementDef::new("vertex".to_string()));
+        p.header.elements.add(ElementDef::new("face".to_string()));
+        p.header.elements.add(ElementDef::new("edge".to_string()));
+        p.make_consistent().unwrap();
+        p.rename_element("edge", "line").unwrap();
+        let names: Vec<&String> = p.header.elements.keys().collect();
+        assert_eq!(names, vec!["vertex", "face", "line"]);
+        assert_eq!(p.header.elements["line"].name, "line");
+        let names: Vec<&String> = p.payload.keys().collect();
+        assert_eq!(names, vec!["vertex", "face", "line"]);
+    }
+    #[test]
+    fn reorder_properties() {
+        let mut e = ElementDef::new("vertex".to_string());
+        for n in &["red", "quality", "z", "x", "y", "nx"] {
+            e.properties.add(PropertyDef::new(n.to_string(), PropertyType::Scalar(ScalarType::Float)));
+        }
+        e.reorder_properties(&CONVENTIONAL_PROPERTY_ORDER);
+        let names: Vec<&String> = e.properties.keys().collect();
+        assert_eq!(names, vec!["x", "y", "z", "nx", "red", "quality"]);
+    }
+    #[test]
+    fn rename_element_fail() {
+        let mut p = P::new();
+        p.header.elements.add(ElementDef::new("vertex".to_string()));
+        p.header.elements.add(ElementDef::new("face".to_string()));
+        assert!(p.rename_element("edge", "line").is_err());
+        assert!(p.rename_element("vertex", "face").is_err());
+        assert!(p.header.elements.contains_key("vertex"));
+    }
+    #[test]
+    fn rename_linked_element_fail() {
+        let mut p = P::new();
+        p.header.elements.add(ElementDef::new("vertex".to_string()));
+        p.header.elements.add(ElementDef::new("face".to_string()));
+        p.make_consistent().unwrap();
+        assert!(p.rename_element("vertex", "point").is_err());
+        assert!(p.rename_element("face", "polygon").is_err());
+        assert_eq!(p.header.elements.keys().collect::<Vec<_>>(), vec!["vertex", "face"]);
+
+        p.header.elements.remove("face");
+        p.payload.remove("face");
+        p.rename_element("vertex", "point").unwrap();
+        assert!(p.header.elements.contains_key("point"));
+    }
+}
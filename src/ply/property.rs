@@ -1,8 +1,13 @@
+use std::fmt::{ Display, Formatter };
+use std::fmt;
+use std::str::FromStr;
+
 
 /// Scalar type used to encode properties in the payload.
 ///
 /// For the translation to rust types, see individual documentation.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum ScalarType {
     /// Signed 8 bit integer, rust: `i8`.
     Char,
@@ -22,11 +27,91 @@ pub enum ScalarType {
     Double,
 }
 
+impl ScalarType {
+    /// Number of bytes in binary encoding.
+    pub fn size_bytes(&self) -> usize {
+        match *self {
+            ScalarType::Char | ScalarType::UChar => 1,
+            ScalarType::Short | ScalarType::UShort => 2,
+            ScalarType::Int | ScalarType::UInt | ScalarType::Float => 4,
+            ScalarType::Double => 8,
+        }
+    }
+    /// True for all integer types, that is all types valid as list index.
+    pub fn is_integer(&self) -> bool {
+        match *self {
+            ScalarType::Float | ScalarType::Double => false,
+            _ => true,
+        }
+    }
+    /// Largest finite value of the type.
+    pub fn max_value(&self) -> f64 {
+        match *self {
+            ScalarType::Char => i8::max_value() as f64,
+            ScalarType::UChar => u8::max_value() as f64,
+            ScalarType::Short => i16::max_value() as f64,
+            ScalarType::UShort => u16::max_value() as f64,
+            ScalarType::Int => i32::max_value() as f64,
+            ScalarType::UInt => u32::max_value() as f64,
+            ScalarType::Float => ::std::f32::MAX as f64,
+            ScalarType::Double => ::std::f64::MAX,
+        }
+    }
+    /// Classic name as used in the original PLY specification, e.g. `uchar`.
+    ///
+    /// This is what `Display` and the `Writer` use.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ScalarType::Char => "char",
+            ScalarType::UChar => "uchar",
+            ScalarType::Short => "short",
+            ScalarType::UShort => "ushort",
+            ScalarType::Int => "int",
+            ScalarType::UInt => "uint",
+            ScalarType::Float => "float",
+            ScalarType::Double => "double",
+        }
+    }
+    /// Name stating the size, e.g. `uint8`.
+    pub fn sized_name(&self) -> &'static str {
+        match *self {
+            ScalarType::Char => "int8",
+            ScalarType::UChar => "uint8",
+            ScalarType::Short => "int16",
+            ScalarType::UShort => "uint16",
+            ScalarType::Int => "int32",
+            ScalarType::UInt => "uint32",
+            ScalarType::Float => "float32",
+            ScalarType::Double => "float64",
+        }
+    }
+}
+
+impl Display for ScalarType {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ScalarType {
+    type Err = String;
+    /// Accepts classic and sized names.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let all = [ScalarType::Char, ScalarType::UChar, ScalarType::Short, ScalarType::UShort,
+            ScalarType::Int, ScalarType::UInt, ScalarType::Float, ScalarType::Double];
+        match all.iter().find(|t| t.name() == s || t.sized_name() == s) {
+            Some(t) => Ok(t.clone()),
+            None => Err(format!("Unknown scalar type `{}`.", s)),
+        }
+    }
+}
+
 /// Data type used to encode properties in the payload.
 ///
 /// There are two possible types: scalars and lists.
 /// Lists are a sequence of scalars with a leading integer value defining how many elements the list contains.
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub enum PropertyType {
     /// Simple, "one-number" type.
     Scalar(ScalarType),
@@ -42,6 +127,25 @@ pub enum PropertyType {
     List(ScalarType, ScalarType)
 }
 
+impl PropertyType {
+    /// Number of bytes in binary encoding, `None` for lists since their size varies.
+    pub fn fixed_record_size(&self) -> Option<usize> {
+        match *self {
+            PropertyType::Scalar(ref scalar_type) => Some(scalar_type.size_bytes()),
+            PropertyType::List(_, _) => None,
+        }
+    }
+}
+
+impl Display for PropertyType {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            PropertyType::Scalar(ref scalar_type) => f.write_str(scalar_type.name()),
+            PropertyType::List(ref index_type, ref scalar_type) => write!(f, "list {} {}", index_type, scalar_type),
+        }
+    }
+}
+
 /// Wrapper used to implement a dynamic type system as required by the PLY file format.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Property {
@@ -80,6 +184,14 @@ pub trait PropertyAccess {
         // By default, do nothing
         // Sombody might only want to write, no point in bothering him/her with setter implementations.
     }
+    /// Called by the `Parser` for every decoded value, `index` is the position of the property in its element definition.
+    ///
+    /// Forwards to `set_property()` by default.
+    /// Types with a fixed layout can override it to store values by position,
+    /// which avoids cloning and hashing the property name for each value.
+    fn set_by_index(&mut self, _index: usize, property_name: &String, property: Property) {
+        self.set_property(property_name.clone(), property);
+    }
     fn get_char(&self, _property_name: &String) -> Option<i8> {
         None
     }
@@ -129,3 +241,26 @@ pub trait PropertyAccess {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn scalar_type_names() {
+        assert_eq!("uint8".parse::<ScalarType>(), Ok(ScalarType::UChar));
+        assert_eq!("uchar".parse::<ScalarType>(), Ok(ScalarType::UChar));
+        assert_eq!("float64".parse::<ScalarType>(), Ok(ScalarType::Double));
+        assert!("int64".parse::<ScalarType>().is_err());
+        assert_eq!(ScalarType::Short.sized_name(), "int16");
+        let t = PropertyType::List(ScalarType::UChar, ScalarType::Int);
+        assert_eq!(t.to_string(), "list uchar int");
+        assert_eq!(t.fixed_record_size(), None);
+        assert_eq!(PropertyType::Scalar(ScalarType::Double).fixed_record_size(), Some(8));
+    }
+    #[test]
+    fn scalar_type_limits() {
+        assert_eq!(ScalarType::UShort.max_value(), 65535.0);
+        assert!(ScalarType::UInt.is_integer());
+        assert!(!ScalarType::Float.is_integer());
+    }
+}
@@ -0,0 +1,259 @@
+//! Compact element storage: one row of values per entry and one schema per element.
+
+use std::sync::Arc;
+use super::{ Ply, ElementDef, KeyMap, Property, PropertyAccess };
+
+/// Maps the property names of an element to their position in a `RowElement`.
+///
+/// Created once per element and shared by all its rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowSchema {
+    columns: KeyMap<usize>,
+}
+
+impl RowSchema {
+    /// Creates the schema of `element_def`, columns follow the order of its properties.
+    pub fn new(element_def: &ElementDef) -> Self {
+        let mut columns = KeyMap::with_capacity(element_def.properties.len());
+        for (i, k) in element_def.properties.keys().enumerate() {
+            columns.insert(k.clone(), i);
+        }
+        RowSchema {
+            columns: columns,
+        }
+    }
+    /// Position of the property `name`, `None` if the element has no such property.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.get(name).cloned()
+    }
+    /// Number of properties.
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+    /// True if the element has no properties.
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+}
+
+/// Entry holding its values in a plain vector, in the order of the element definition.
+///
+/// Compared to `DefaultElement`, no map, hashes or property names are stored per entry,
+/// which saves most of the per entry overhead for elements with many small values, like vertices.
+/// Names are resolved through a `RowSchema` shared by all entries of an element.
+///
+/// The parser fills rows by position through `PropertyAccess::set_by_index()`.
+/// As `PropertyAccess::new()` has no access to the element definition,
+/// the schema is attached after reading, see `Ply::attach_row_schemas()` and `attach_row_schema()`.
+/// Until then, only positional access with `values()` works, the getters by name return `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowElement {
+    schema: Option<Arc<RowSchema>>,
+    values: Vec<Property>,
+}
+
+impl RowElement {
+    /// Creates a row of `values` described by `schema`.
+    pub fn with_schema(schema: Arc<RowSchema>, values: Vec<Property>) -> Self {
+        RowElement {
+            schema: Some(schema),
+            values: values,
+        }
+    }
+    /// The shared schema, `None` if not attached yet.
+    pub fn schema(&self) -> Option<&Arc<RowSchema>> {
+        self.schema.as_ref()
+    }
+    /// Values in the order of the element definition.
+    pub fn values(&self) -> &[Property] {
+        &self.values
+    }
+    /// Value of the property `name`, `None` if it is missing or no schema is attached.
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.schema.as_ref().and_then(|s| s.column(name)).and_then(|i| self.values.get(i))
+    }
+}
+
+/// Attaches one schema, created from `element_def`, to all `rows`.
+pub fn attach_row_schema(rows: &mut [RowElement], element_def: &ElementDef) {
+    let schema = Arc::new(RowSchema::new(element_def));
+    for row in rows {
+        row.schema = Some(schema.clone());
+    }
+}
+
+impl Ply<RowElement> {
+    /// Attaches a schema to the rows of every element declared in the header, see `RowElement`.
+    pub fn attach_row_schemas(&mut self) {
+        for (k, element_def) in &self.header.elements {
+            if let Some(rows) = self.payload.get_mut(k) {
+                attach_row_schema(rows, element_def);
+            }
+        }
+    }
+}
+
+macro_rules! get(
+    ($e:expr) => (match $e {None => return None, Some(x) => x})
+);
+impl PropertyAccess for RowElement {
+    fn new() -> Self {
+        RowElement {
+            schema: None,
+            values: Vec::new(),
+        }
+    }
+    /// Only sets properties known to the attached schema, others are dropped.
+    fn set_property(&mut self, key: String, property: Property) {
+        if let Some(i) = self.schema.as_ref().and_then(|s| s.column(&key)) {
+            self.set_by_index(i, &key, property);
+        }
+    }
+    fn set_by_index(&mut self, index: usize, _property_name: &String, property: Property) {
+        if index < self.values.len() {
+            self.values[index] = property;
+        } else if index == self.values.len() {
+            self.values.push(property);
+        }
+    }
+    fn get_char(&self, key: &String) -> Option<i8> {
+        match *get!(self.get(key)) {
+            Property::Char(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_uchar(&self, key: &String) -> Option<u8> {
+        match *get!(self.get(key)) {
+            Property::UChar(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_short(&self, key: &String) -> Option<i16> {
+        match *get!(self.get(key)) {
+            Property::Short(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_ushort(&self, key: &String) -> Option<u16> {
+        match *get!(self.get(key)) {
+            Property::UShort(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_int(&self, key: &String) -> Option<i32> {
+        match *get!(self.get(key)) {
+            Property::Int(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_uint(&self, key: &String) -> Option<u32> {
+        match *get!(self.get(key)) {
+            Property::UInt(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_float(&self, key: &String) -> Option<f32> {
+        match *get!(self.get(key)) {
+            Property::Float(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_double(&self, key: &String) -> Option<f64> {
+        match *get!(self.get(key)) {
+            Property::Double(x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_char(&self, key: &String) -> Option<&[i8]> {
+        match *get!(self.get(key)) {
+            Property::ListChar(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_uchar(&self, key: &String) -> Option<&[u8]> {
+        match *get!(self.get(key)) {
+            Property::ListUChar(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_short(&self, key: &String) -> Option<&[i16]> {
+        match *get!(self.get(key)) {
+            Property::ListShort(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_ushort(&self, key: &String) -> Option<&[u16]> {
+        match *get!(self.get(key)) {
+            Property::ListUShort(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_int(&self, key: &String) -> Option<&[i32]> {
+        match *get!(self.get(key)) {
+            Property::ListInt(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_uint(&self, key: &String) -> Option<&[u32]> {
+        match *get!(self.get(key)) {
+            Property::ListUInt(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_float(&self, key: &String) -> Option<&[f32]> {
+        match *get!(self.get(key)) {
+            Property::ListFloat(ref x) => Some(x),
+            _ => None,
+        }
+    }
+    fn get_list_double(&self, key: &String) -> Option<&[f64]> {
+        match *get!(self.get(key)) {
+            Property::ListDouble(ref x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::{ DefaultElement, PropertyDef, PropertyType, ScalarType, Addable };
+    use parser::Parser;
+    use writer::Writer;
+
+    const PLY: &'static str = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\n\
+        property list uchar int n\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+        0 1 2 5 6\n2.5 -1 0\n3 0 1 1\n";
+
+    #[test]
+    fn rows_read_and_write_like_default_elements() {
+        let mut ply = Parser::<RowElement>::new().read_ply(&mut PLY.as_bytes()).unwrap();
+        assert_eq!(ply.payload["vertex"][1].values(), &[Property::Float(2.5), Property::Float(-1.0), Property::ListInt(vec![])][..]);
+        assert_eq!(ply.payload["vertex"][1].get_float(&"x".to_string()), None);
+
+        ply.attach_row_schemas();
+        let v = &ply.payload["vertex"][0];
+        assert_eq!(v.get_float(&"y".to_string()), Some(1.0));
+        assert_eq!(v.get_list_int(&"n".to_string()), Some(&[5, 6][..]));
+        assert_eq!(v.get("z"), None);
+        assert!(Arc::ptr_eq(v.schema().unwrap(), ply.payload["vertex"][1].schema().unwrap()));
+
+        let mut written = Vec::<u8>::new();
+        Writer::new().write_ply(&mut written, &mut ply).unwrap();
+        let mut default = Parser::<DefaultElement>::new().read_ply(&mut PLY.as_bytes()).unwrap();
+        let mut expected = Vec::<u8>::new();
+        Writer::new().write_ply(&mut expected, &mut default).unwrap();
+        assert_eq!(written, expected);
+    }
+    #[test]
+    fn set_property_by_schema() {
+        let mut element_def = ElementDef::new("vertex".to_string());
+        element_def.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Int)));
+        let schema = Arc::new(RowSchema::new(&element_def));
+        assert_eq!((schema.column("x"), schema.len()), (Some(0), 1));
+        let mut row = RowElement::with_schema(schema, vec![Property::Int(1)]);
+        row.set_property("x".to_string(), Property::Int(7));
+        row.set_property("y".to_string(), Property::Int(8));
+        assert_eq!(row.values(), &[Property::Int(7)][..]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Derives element definitions from rust types, so headers can't drift from the data written.
+
+use super::{ ElementDef, PropertyDef, Addable };
+
+/// Type whose fields correspond to the properties of an element.
+///
+/// Implement it with `impl_to_element_def!` to get the property types from the field types.
+pub trait ToElementDef {
+    /// Properties in the order they are written.
+    fn property_defs() -> Vec<PropertyDef>;
+    /// Element definition named `name` with `count` entries and the properties of `property_defs()`.
+    fn element_def(name: &str, count: u64) -> ElementDef {
+        let mut element_def = ElementDef::new(name.to_string());
+        element_def.count = count;
+        for property_def in Self::property_defs() {
+            element_def.properties.add(property_def);
+        }
+        element_def
+    }
+}
+
+/// Implements `ToElementDef` for a struct, listing its fields with their types.
+///
+/// The property types follow from `ColumnValue::data_type()`:
+/// scalars map to the corresponding `ScalarType`, `Vec`s of them to lists with a `uint` index.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate ply_rs;
+/// use ply_rs::ply::{ ToElementDef, PropertyType, ScalarType };
+///
+/// struct Face { vertex_indices: Vec<u32>, quality: f32 }
+/// impl_to_element_def!(Face { vertex_indices: Vec<u32>, quality: f32 });
+///
+/// fn main() {
+///     let face = Face::element_def("face", 12);
+///     assert_eq!(face.count, 12);
+///     assert_eq!(face.properties["quality"].data_type, PropertyType::Scalar(ScalarType::Float));
+/// }
+/// ```
+#[macro_export]
+macro_rules! impl_to_element_def {
+    ($t:ty { $($field:ident : $field_type:ty),* $(,)* }) => {
+        impl $crate::ply::ToElementDef for $t {
+            fn property_defs() -> Vec<$crate::ply::PropertyDef> {
+                vec![$(
+                    $crate::ply::PropertyDef::new(
+                        stringify!($field).to_string(),
+                        <$field_type as $crate::ply::ColumnValue>::data_type()
+                    )
+                ),*]
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::*;
+    #[allow(dead_code)]
+    struct Vertex {
+        x: f32,
+        red: u8,
+        n: Vec<i16>,
+    }
+    impl_to_element_def!(Vertex { x: f32, red: u8, n: Vec<i16> });
+    #[test]
+    fn element_def_from_type() {
+        let e = Vertex::element_def("vertex", 3);
+        assert_eq!(e.name, "vertex");
+        assert_eq!(e.count, 3);
+        let keys: Vec<&String> = e.properties.keys().collect();
+        assert_eq!(keys, vec!["x", "red", "n"]);
+        assert_eq!(e.properties["red"].data_type, PropertyType::Scalar(ScalarType::UChar));
+        assert_eq!(e.properties["n"].data_type, PropertyType::List(ScalarType::UInt, ScalarType::Short));
+    }
+}
@@ -0,0 +1,315 @@
+//! Matches structs against element definitions that differ in order, padding or extra properties.
+//!
+//! `match_struct()` compares the fields declared by a `StructLayout` with the properties of an element
+//! and produces a `LayoutMap`, a plan which bytes of a record go into which bytes of the struct.
+//! If struct and record are identical, the plan degenerates to a bulk copy as in the `pod` module,
+//! otherwise the fields are gathered from the records on read and scattered into them on write.
+
+use std::io;
+use std::io::{ Read, Write, Result, ErrorKind };
+use std::mem;
+use bytemuck;
+use bytemuck::Pod;
+
+use ply::{ ElementDef, Encoding, Header, PropertyType, ScalarType };
+use super::needs_swap;
+
+/// A `#[repr(C)]` struct describing where its fields are located.
+///
+/// Contrary to `PodElement`, the fields may come in any order and the struct may contain padding
+/// or fields not present in the file.
+pub trait StructLayout: Pod {
+    /// Name, type and byte offset of every field mapped to a property.
+    const FIELDS: &'static [(&'static str, ScalarType, usize)];
+}
+
+/// Plan to convert between records of an element and structs, see `match_struct()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutMap {
+    record_size: usize,
+    struct_size: usize,
+    copies: Vec<FieldCopy>,
+    unmapped: Vec<String>,
+}
+
+/// One scalar moved between record and struct.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FieldCopy {
+    record_offset: usize,
+    struct_offset: usize,
+    size: usize,
+}
+
+/// Verifies the fields of `T` against `element_def` and plans how to convert between them.
+///
+/// Every field must have a property of the same name and type, and must lie within `T`.
+/// Properties without a field are skipped when reading and written as zero.
+/// Bytes of `T` not covered by a field, like padding, are zero after reading.
+/// List properties are not supported, since they give records a variable size.
+pub fn match_struct<T: StructLayout>(element_def: &ElementDef) -> Result<LayoutMap> {
+    let mut record_size = 0;
+    let mut offsets = Vec::with_capacity(element_def.properties.len());
+    for property_def in element_def.properties.values() {
+        match property_def.data_type {
+            PropertyType::Scalar(ref scalar_type) => {
+                offsets.push((record_size, scalar_type));
+                record_size += scalar_type.size_bytes();
+            },
+            PropertyType::List(_, _) => return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Property `{}` of element `{}` is a list, records of fixed size are required.", property_def.name, element_def.name)
+            )),
+        }
+    }
+    let mut copies = Vec::with_capacity(T::FIELDS.len());
+    let mut mapped = vec![false; offsets.len()];
+    for &(name, ref scalar_type, struct_offset) in T::FIELDS {
+        let index = match element_def.properties.keys().position(|k| k == name) {
+            Some(i) => i,
+            None => return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` has no property `{}`.", element_def.name, name)
+            )),
+        };
+        let (record_offset, property_type) = offsets[index];
+        if property_type != scalar_type {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Property `{}` of element `{}` is {:?}, but the struct declares {:?}.", name, element_def.name, property_type, scalar_type)
+            ));
+        }
+        let size = scalar_type.size_bytes();
+        if struct_offset + size > mem::size_of::<T>() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Field `{}` at offset {} exceeds the struct of {} bytes.", name, struct_offset, mem::size_of::<T>())
+            ));
+        }
+        if mapped[index] {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Property `{}` is mapped twice.", name)));
+        }
+        mapped[index] = true;
+        copies.push(FieldCopy { record_offset: record_offset, struct_offset: struct_offset, size: size });
+    }
+    copies.sort_by_key(|c| c.struct_offset);
+    for pair in copies.windows(2) {
+        if pair[0].struct_offset + pair[0].size > pair[1].struct_offset {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Fields at offsets {} and {} of the struct overlap.", pair[0].struct_offset, pair[1].struct_offset)
+            ));
+        }
+    }
+    copies.sort_by_key(|c| c.record_offset);
+    let unmapped = element_def.properties.keys().zip(mapped.iter())
+        .filter(|&(_, &m)| !m)
+        .map(|(k, _)| k.clone())
+        .collect();
+    Ok(LayoutMap {
+        record_size: record_size,
+        struct_size: mem::size_of::<T>(),
+        copies: copies,
+        unmapped: unmapped,
+    })
+}
+
+impl LayoutMap {
+    /// True if records and structs are byte for byte identical, apart from the byte order.
+    pub fn is_exact(&self) -> bool {
+        self.record_size == self.struct_size && self.unmapped.is_empty()
+            && self.copies.iter().all(|c| c.record_offset == c.struct_offset)
+    }
+    /// Size of a record in the file.
+    pub fn record_size(&self) -> usize {
+        self.record_size
+    }
+    /// Properties of the element that have no field in the struct.
+    pub fn unmapped(&self) -> &[String] {
+        &self.unmapped
+    }
+    /// Reads the payload of `element_def` into a vector of `T`.
+    ///
+    /// `self` must have been created by `match_struct::<T>()` for `element_def`.
+    /// The reader must be positioned at the start of the element's payload.
+    /// Only binary encodings are supported.
+    pub fn read_elements<T: StructLayout, R: Read>(&self, reader: &mut R, element_def: &ElementDef, header: &Header) -> Result<Vec<T>> {
+        try!(self.check::<T>(element_def, header));
+        let swap = needs_swap(&header.encoding);
+        let exact = self.is_exact();
+        // Grow chunk by chunk with the data actually read, a bogus count must not allocate up front.
+        let mut elements = Vec::new();
+        let mut remaining = element_def.count as usize;
+        let mut buffer = if exact { Vec::new() } else { vec![0u8; self.record_size * CHUNK_SIZE] };
+        while remaining > 0 {
+            let start = elements.len();
+            let n = remaining.min(CHUNK_SIZE);
+            elements.resize(start + n, T::zeroed());
+            let chunk = &mut elements[start..];
+            remaining -= n;
+            if exact {
+                let bytes: &mut [u8] = bytemuck::cast_slice_mut(chunk);
+                try!(reader.read_exact(bytes));
+                if swap {
+                    for element in bytes.chunks_mut(self.struct_size) {
+                        self.swap_fields(element);
+                    }
+                }
+                continue;
+            }
+            let records = &mut buffer[..self.record_size * n];
+            try!(reader.read_exact(records));
+            for (element, record) in chunk.iter_mut().zip(records.chunks(self.record_size)) {
+                let target = bytemuck::bytes_of_mut(element);
+                for c in &self.copies {
+                    let field = &mut target[c.struct_offset..c.struct_offset + c.size];
+                    field.copy_from_slice(&record[c.record_offset..c.record_offset + c.size]);
+                    if swap {
+                        field.reverse();
+                    }
+                }
+            }
+        }
+        Ok(elements)
+    }
+    /// Writes `elements` as the payload of `element_def`.
+    ///
+    /// `self` must have been created by `match_struct::<T>()` for `element_def`.
+    /// The number of elements must match `element_def.count`, unmapped properties are written as zero.
+    /// Only binary encodings are supported.
+    ///
+    /// Returns number of bytes written.
+    pub fn write_elements<T: StructLayout, W: Write>(&self, out: &mut W, elements: &[T], element_def: &ElementDef, header: &Header) -> Result<usize> {
+        try!(self.check::<T>(element_def, header));
+        if elements.len() as u64 != element_def.count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` declares {} entries, but {} were supplied.", element_def.name, element_def.count, elements.len())
+            ));
+        }
+        let swap = needs_swap(&header.encoding);
+        if self.is_exact() && !swap {
+            let bytes: &[u8] = bytemuck::cast_slice(elements);
+            try!(out.write_all(bytes));
+            return Ok(bytes.len());
+        }
+        let mut buffer = vec![0u8; self.record_size * CHUNK_SIZE];
+        for chunk in elements.chunks(CHUNK_SIZE) {
+            let records = &mut buffer[..self.record_size * chunk.len()];
+            for (element, record) in chunk.iter().zip(records.chunks_mut(self.record_size)) {
+                let source = bytemuck::bytes_of(element);
+                for c in &self.copies {
+                    let field = &mut record[c.record_offset..c.record_offset + c.size];
+                    field.copy_from_slice(&source[c.struct_offset..c.struct_offset + c.size]);
+                    if swap {
+                        field.reverse();
+                    }
+                }
+            }
+            try!(out.write_all(records));
+        }
+        Ok(self.record_size * elements.len())
+    }
+    fn check<T: StructLayout>(&self, element_def: &ElementDef, header: &Header) -> Result<()> {
+        if mem::size_of::<T>() != self.struct_size {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "The layout map was created for a different struct."));
+        }
+        if element_def.count > usize::max_value() as u64 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` has too many entries for this platform.", element_def.name)));
+        }
+        match header.encoding {
+            Encoding::Ascii => Err(io::Error::new(ErrorKind::InvalidInput, "Plain old data elements require a binary encoding.")),
+            _ => Ok(()),
+        }
+    }
+    fn swap_fields(&self, element: &mut [u8]) {
+        for c in &self.copies {
+            element[c.struct_offset..c.struct_offset + c.size].reverse();
+        }
+    }
+}
+
+/// Number of records converted at once.
+const CHUNK_SIZE: usize = 4096;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::{ Pod, Zeroable };
+    use ply::{ PropertyDef, Addable };
+
+    /// Swapped order, padding after `flag`, no `intensity`.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Point {
+        y: f32,
+        x: f32,
+        flag: u8,
+        _pad: [u8; 3],
+    }
+    unsafe impl Zeroable for Point {}
+    unsafe impl Pod for Point {}
+    impl StructLayout for Point {
+        const FIELDS: &'static [(&'static str, ScalarType, usize)] = &[
+            ("y", ScalarType::Float, 0), ("x", ScalarType::Float, 4), ("flag", ScalarType::UChar, 8),
+        ];
+    }
+
+    fn header(encoding: Encoding) -> Header {
+        let mut e = ElementDef::new("point".to_string());
+        for &(name, ref t) in &[("x", ScalarType::Float), ("flag", ScalarType::UChar), ("intensity", ScalarType::UShort), ("y", ScalarType::Float)] {
+            e.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(t.clone())));
+        }
+        e.count = 2;
+        let mut h = Header::new();
+        h.encoding = encoding;
+        h.elements.add(e);
+        h
+    }
+
+    #[test]
+    fn gather_scatter_roundtrip() {
+        let h = header(Encoding::BinaryBigEndian);
+        let map = match_struct::<Point>(&h.elements["point"]).unwrap();
+        assert!(!map.is_exact());
+        assert_eq!(map.record_size(), 11);
+        assert_eq!(map.unmapped(), &["intensity".to_string()]);
+        let points = vec![Point { y: 2.0, x: 1.0, flag: 7, _pad: [0; 3] }, Point { y: -1.0, x: 0.5, flag: 0, _pad: [0; 3] }];
+        let mut buf = Vec::new();
+        assert_eq!(map.write_elements(&mut buf, &points, &h.elements["point"], &h).unwrap(), 22);
+        assert_eq!(&buf[..11], &[0x3f, 0x80, 0, 0, 7, 0, 0, 0x40, 0, 0, 0]);
+        let read: Vec<Point> = map.read_elements(&mut &buf[..], &h.elements["point"], &h).unwrap();
+        assert_eq!(read, points);
+    }
+    #[test]
+    fn mismatch_err() {
+        let mut h = header(Encoding::BinaryLittleEndian);
+        h.elements["point"].properties["y"].data_type = PropertyType::Scalar(ScalarType::Double);
+        assert!(match_struct::<Point>(&h.elements["point"]).is_err());
+        h.elements["point"].properties.remove("y");
+        assert!(match_struct::<Point>(&h.elements["point"]).is_err());
+    }
+    /// Same struct, but `flag` is declared inside `x`.
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Overlapping(Point);
+    unsafe impl Zeroable for Overlapping {}
+    unsafe impl Pod for Overlapping {}
+    impl StructLayout for Overlapping {
+        const FIELDS: &'static [(&'static str, ScalarType, usize)] = &[
+            ("y", ScalarType::Float, 0), ("x", ScalarType::Float, 4), ("flag", ScalarType::UChar, 6),
+        ];
+    }
+    #[test]
+    fn overlapping_fields_err() {
+        let h = header(Encoding::BinaryLittleEndian);
+        assert!(match_struct::<Overlapping>(&h.elements["point"]).is_err());
+    }
+    #[test]
+    fn huge_count_truncated_err() {
+        let mut h = header(Encoding::BinaryLittleEndian);
+        h.elements["point"].count = 1 << 40;
+        let map = match_struct::<Point>(&h.elements["point"]).unwrap();
+        let buf = vec![0u8; map.record_size() * 10];
+        assert!(map.read_elements::<Point, _>(&mut &buf[..], &h.elements["point"], &h).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Bulk reading and writing of `#[repr(C)]` structs with a fixed layout.
+//!
+//! Available with the `pod` feature.
+//! An element whose properties are all scalars has a fixed binary size,
+//! its payload is then nothing else than an array of structs.
+//! Types implementing `PodElement` are read and written as a whole with `bytemuck`,
+//! only swapping bytes when the byte order of the file differs from the native one.
+//! Structs that only almost match the header are handled by the `layout` module.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! #[repr(C)]
+//! #[derive(Clone, Copy)]
+//! struct Vertex { x: f32, y: f32, z: f32 }
+//! unsafe impl bytemuck::Zeroable for Vertex {}
+//! unsafe impl bytemuck::Pod for Vertex {}
+//! impl PodElement for Vertex {
+//!     const PROPERTIES: &'static [(&'static str, ScalarType)] = &[
+//!         ("x", ScalarType::Float), ("y", ScalarType::Float), ("z", ScalarType::Float),
+//!     ];
+//! }
+//! ```
+
+use std::io;
+use std::io::{ Read, Write, Result, ErrorKind };
+use std::mem;
+use bytemuck;
+use bytemuck::Pod;
+
+use ply::{ ElementDef, Encoding, Header, PropertyType, ScalarType, PropertyAccess };
+use parser::Parser;
+
+pub mod layout;
+
+/// A `#[repr(C)]` struct matching the layout of an element.
+pub trait PodElement: Pod {
+    /// Name and type of every field, in declaration order.
+    ///
+    /// They must match the properties of the element definition,
+    /// and the struct must not contain padding.
+    const PROPERTIES: &'static [(&'static str, ScalarType)];
+}
+
+/// Reads the payload of `element_def` into a vector of `T`.
+///
+/// The reader must be positioned at the start of the element's payload,
+/// for example right after `Parser::read_header()` for the first element.
+/// Only binary encodings are supported.
+pub fn read_pod_elements<T: PodElement, R: Read>(reader: &mut R, element_def: &ElementDef, header: &Header) -> Result<Vec<T>> {
+    try!(check_layout::<T>(element_def, &header.encoding));
+    if element_def.count > usize::max_value() as u64 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` has too many entries for this platform.", element_def.name)));
+    }
+    // Grow with the data actually read, a bogus count must not allocate up front.
+    let mut elements = Vec::new();
+    let mut remaining = element_def.count as usize;
+    while remaining > 0 {
+        let start = elements.len();
+        let n = remaining.min(READ_CHUNK_SIZE);
+        elements.resize(start + n, T::zeroed());
+        let bytes: &mut [u8] = bytemuck::cast_slice_mut(&mut elements[start..]);
+        try!(reader.read_exact(bytes));
+        if needs_swap(&header.encoding) {
+            swap_bytes::<T>(bytes);
+        }
+        remaining -= n;
+    }
+    Ok(elements)
+}
+
+/// Writes `elements` as the payload of `element_def`.
+///
+/// The number of elements must match `element_def.count`.
+/// Only binary encodings are supported.
+///
+/// Returns number of bytes written.
+pub fn write_pod_elements<T: PodElement, W: Write>(out: &mut W, elements: &[T], element_def: &ElementDef, header: &Header) -> Result<usize> {
+    try!(check_layout::<T>(element_def, &header.encoding));
+    if elements.len() as u64 != element_def.count {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Element `{}` declares {} entries, but {} were supplied.", element_def.name, element_def.count, elements.len())
+        ));
+    }
+    let bytes: &[u8] = bytemuck::cast_slice(elements);
+    if needs_swap(&header.encoding) {
+        let mut swapped = Vec::with_capacity(SWAP_CHUNK_SIZE * mem::size_of::<T>());
+        for chunk in bytes.chunks(SWAP_CHUNK_SIZE * mem::size_of::<T>()) {
+            swapped.clear();
+            swapped.extend_from_slice(chunk);
+            swap_bytes::<T>(&mut swapped);
+            try!(out.write_all(&swapped));
+        }
+    } else {
+        try!(out.write_all(bytes));
+    }
+    Ok(bytes.len())
+}
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Reads the payload of `element_def` as a whole into a vector of `V`, see `read_pod_elements()`.
+    ///
+    /// Use it for large elements with a fixed layout, while other elements are read with
+    /// `read_payload_for_element()` from the same reader. Element decoders and derived properties don't apply.
+    pub fn read_packed_elements<V: PodElement, T: Read>(&self, reader: &mut T, element_def: &ElementDef, header: &Header) -> Result<Vec<V>> {
+        read_pod_elements(reader, element_def, header)
+    }
+}
+
+/// Number of elements converted at once when swapping bytes for writing.
+const SWAP_CHUNK_SIZE: usize = 4096;
+/// Number of elements read at once, bounds the allocation ahead of the data.
+const READ_CHUNK_SIZE: usize = 1 << 16;
+
+/// Verifies that `T` describes exactly the properties of `element_def`.
+fn check_layout<T: PodElement>(element_def: &ElementDef, encoding: &Encoding) -> Result<()> {
+    if *encoding == Encoding::Ascii {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Plain old data elements require a binary encoding."));
+    }
+    if element_def.properties.len() != T::PROPERTIES.len() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Element `{}` has {} properties, but the struct has {} fields.", element_def.name, element_def.properties.len(), T::PROPERTIES.len())
+        ));
+    }
+    let mut size = 0;
+    for (property_def, &(ref name, ref scalar_type)) in element_def.properties.values().zip(T::PROPERTIES.iter()) {
+        let matches = property_def.name == *name && match property_def.data_type {
+            PropertyType::Scalar(ref t) => t == scalar_type,
+            PropertyType::List(_, _) => false,
+        };
+        if !matches {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Property `{}` of element `{}` is {:?}, but the struct declares `{}` as {:?}.",
+                    property_def.name, element_def.name, property_def.data_type, name, scalar_type)
+            ));
+        }
+        size += scalar_type.size_bytes();
+    }
+    if size != mem::size_of::<T>() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("The struct for element `{}` has {} bytes, but its fields only need {}, remove the padding.", element_def.name, mem::size_of::<T>(), size)
+        ));
+    }
+    Ok(())
+}
+
+fn needs_swap(encoding: &Encoding) -> bool {
+    match *encoding {
+        Encoding::BinaryBigEndian => cfg!(target_endian = "little"),
+        Encoding::BinaryLittleEndian => cfg!(target_endian = "big"),
+        Encoding::Ascii => false,
+    }
+}
+
+/// Reverses the bytes of every field, `bytes` holds a whole number of `T`.
+fn swap_bytes<T: PodElement>(bytes: &mut [u8]) {
+    for element in bytes.chunks_mut(mem::size_of::<T>()) {
+        let mut offset = 0;
+        for &(_, ref scalar_type) in T::PROPERTIES {
+            let size = scalar_type.size_bytes();
+            element[offset..offset + size].reverse();
+            offset += size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::{ Pod, Zeroable };
+    use ply::{ PropertyDef, Addable, DefaultElement };
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Vertex {
+        x: f32,
+        index: u16,
+        flags: [u8; 2],
+    }
+    unsafe impl Zeroable for Vertex {}
+    unsafe impl Pod for Vertex {}
+    impl PodElement for Vertex {
+        const PROPERTIES: &'static [(&'static str, ScalarType)] = &[
+            ("x", ScalarType::Float), ("index", ScalarType::UShort), ("a", ScalarType::UChar), ("b", ScalarType::UChar),
+        ];
+    }
+
+    fn header(encoding: Encoding) -> Header {
+        let mut e = ElementDef::new("vertex".to_string());
+        for &(name, ref t) in Vertex::PROPERTIES {
+            e.properties.add(PropertyDef::new(name.to_string(), PropertyType::Scalar(t.clone())));
+        }
+        e.count = 2;
+        let mut h = Header::new();
+        h.encoding = encoding;
+        h.elements.add(e);
+        h
+    }
+
+    #[test]
+    fn big_endian_roundtrip() {
+        let h = header(Encoding::BinaryBigEndian);
+        let v = vec![Vertex { x: 1.5, index: 258, flags: [1, 2] }, Vertex { x: -2.0, index: 3, flags: [0, 255] }];
+        let mut buf = Vec::new();
+        assert_eq!(write_pod_elements(&mut buf, &v, &h.elements["vertex"], &h).unwrap(), 16);
+        assert_eq!(&buf[..8], &[0x3f, 0xc0, 0, 0, 1, 2, 1, 2]);
+        let read: Vec<Vertex> = read_pod_elements(&mut &buf[..], &h.elements["vertex"], &h).unwrap();
+        assert_eq!(read, v);
+    }
+    #[test]
+    fn parser_reads_packed() {
+        let h = header(Encoding::BinaryLittleEndian);
+        let v = vec![Vertex { x: 0.25, index: 7, flags: [3, 4] }; 2];
+        let mut buf = Vec::new();
+        write_pod_elements(&mut buf, &v, &h.elements["vertex"], &h).unwrap();
+        let p = Parser::<DefaultElement>::new();
+        let read: Vec<Vertex> = p.read_packed_elements(&mut &buf[..], &h.elements["vertex"], &h).unwrap();
+        assert_eq!(read, v);
+    }
+    #[test]
+    fn huge_count_truncated_err() {
+        let mut h = header(Encoding::BinaryLittleEndian);
+        h.elements["vertex"].count = 1 << 40;
+        let buf = vec![0u8; 16 * 10];
+        assert!(read_pod_elements::<Vertex, _>(&mut &buf[..], &h.elements["vertex"], &h).is_err());
+    }
+    #[test]
+    fn layout_mismatch_err() {
+        let mut h = header(Encoding::BinaryLittleEndian);
+        let v = vec![Vertex { x: 1.5, index: 258, flags: [1, 2] }; 2];
+        h.elements["vertex"].properties["index"].data_type = PropertyType::Scalar(ScalarType::Short);
+        assert!(write_pod_elements(&mut Vec::new(), &v, &h.elements["vertex"], &h).is_err());
+        let h = header(Encoding::BinaryLittleEndian);
+        assert!(write_pod_elements(&mut Vec::new(), &v, &h.elements["vertex"], &h).is_ok());
+        assert!(write_pod_elements(&mut Vec::new(), &v[..1], &h.elements["vertex"], &h).is_err());
+        assert!(write_pod_elements(&mut Vec::new(), &v, &h.elements["vertex"], &header(Encoding::Ascii)).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Copies binary payloads byte by byte, shared by `transcode` and `concat`.
+
+use std::io;
+use std::io::{ Read, BufRead, Write, Result, ErrorKind };
+
+use ply::{ ElementDef, PropertyType, ScalarType, Encoding };
+
+/// Number of fixed size entries swapped at once.
+const SWAP_CHUNK_SIZE: usize = 4096;
+/// Upper bound of the bytes of a list held in memory at once.
+const LIST_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Copies the entries of an element without lists chunk by chunk, byte-swapped if `swap` is set.
+pub fn pass_fixed<R: Read, W: Write>(reader: &mut R, writer: &mut W, element_def: &ElementDef, sizes: &[usize], swap: bool, buffer: &mut Vec<u8>) -> Result<u64> {
+    let record_size: usize = sizes.iter().sum();
+    let mut left = element_def.count;
+    while left > 0 {
+        let n = if left < SWAP_CHUNK_SIZE as u64 { left as usize } else { SWAP_CHUNK_SIZE };
+        buffer.resize(n * record_size, 0);
+        try!(read_entries(reader, buffer, element_def));
+        if swap {
+            for record in buffer.chunks_mut(record_size) {
+                let mut offset = 0;
+                for &size in sizes {
+                    record[offset..offset + size].reverse();
+                    offset += size;
+                }
+            }
+        }
+        try!(writer.write_all(buffer));
+        left -= n as u64;
+    }
+    Ok(element_def.count.saturating_mul(record_size as u64))
+}
+
+/// Copies the entries of an element with lists one by one, byte-swapped if `swap` is set.
+///
+/// The list lengths decide how much to read, lists longer than `max_list_len` are rejected.
+/// Long lists are copied in chunks of at most `LIST_CHUNK_BYTES`.
+/// With `shift` set, the offset is added to all values of the named integer list.
+pub fn pass_entries<R: Read, W: Write>(reader: &mut R, writer: &mut W, element_def: &ElementDef, big_endian: bool, swap: bool, shift: Option<(&str, i64)>, max_list_len: u64, buffer: &mut Vec<u8>) -> Result<u64> {
+    let mut written = 0;
+    for _ in 0..element_def.count {
+        for (_, property_def) in &element_def.properties {
+            let (len, scalar_type) = match property_def.data_type {
+                PropertyType::Scalar(ref scalar_type) => (1, scalar_type),
+                PropertyType::List(ref index_type, ref scalar_type) => {
+                    let index_size = index_type.size_bytes();
+                    buffer.resize(index_size, 0);
+                    try!(read_entries(reader, buffer, element_def));
+                    let len = try!(list_len(buffer, index_type, big_endian, element_def));
+                    if len as u64 > max_list_len {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("List `{}` has {} entries, exceeding the maximum of {}.", property_def.name, len, max_list_len)
+                        ));
+                    }
+                    if swap {
+                        buffer.reverse();
+                    }
+                    try!(writer.write_all(buffer));
+                    written += index_size as u64;
+                    (len, scalar_type)
+                },
+            };
+            let size = scalar_type.size_bytes();
+            let shifted = match (shift, &property_def.data_type) {
+                (Some((name, offset)), &PropertyType::List(_, _)) if name == property_def.name => Some(offset),
+                _ => None,
+            };
+            let mut left = len;
+            while left > 0 {
+                let n = left.min(LIST_CHUNK_BYTES / size);
+                buffer.resize(n * size, 0);
+                try!(read_entries(reader, buffer, element_def));
+                if let Some(offset) = shifted {
+                    for value in buffer.chunks_mut(size) {
+                        try!(shift_integer(value, scalar_type, offset, big_endian, element_def));
+                    }
+                }
+                if swap {
+                    for value in buffer.chunks_mut(size) {
+                        value.reverse();
+                    }
+                }
+                try!(writer.write_all(buffer));
+                written += buffer.len() as u64;
+                left -= n;
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Copies the payload of one element unchanged.
+pub fn pass_element<R: BufRead, W: Write>(reader: &mut R, writer: &mut W, element_def: &ElementDef, encoding: &Encoding, max_list_len: u64, buffer: &mut Vec<u8>) -> Result<u64> {
+    let big_endian = match *encoding {
+        Encoding::BinaryBigEndian => true,
+        Encoding::BinaryLittleEndian => false,
+        Encoding::Ascii => {
+            let mut written = 0;
+            for _ in 0..element_def.count {
+                buffer.clear();
+                if try!(reader.read_until(b'\n', buffer)) == 0 {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        format!("Couldn't read payload of element `{}`: unexpected end of file", element_def.name)
+                    ));
+                }
+                try!(writer.write_all(buffer));
+                written += buffer.len() as u64;
+            }
+            return Ok(written);
+        },
+    };
+    let sizes: Option<Vec<usize>> = element_def.properties.values().map(|p| p.data_type.fixed_record_size()).collect();
+    match sizes {
+        Some(sizes) => pass_fixed(reader, writer, element_def, &sizes, false, buffer),
+        None => pass_entries(reader, writer, element_def, big_endian, false, None, max_list_len, buffer),
+    }
+}
+
+fn read_entries<R: Read>(reader: &mut R, buffer: &mut [u8], element_def: &ElementDef) -> Result<()> {
+    reader.read_exact(buffer).map_err(|e| io::Error::new(
+        e.kind(),
+        format!("Couldn't read payload of element `{}`: {}", element_def.name, e)
+    ))
+}
+
+/// Decodes a list length stored in its original byte order.
+fn list_len(bytes: &[u8], index_type: &ScalarType, big_endian: bool, element_def: &ElementDef) -> Result<usize> {
+    match decode_integer(bytes, index_type, big_endian) {
+        None => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Element `{}` has a list with a non-integer index type.", element_def.name)
+        )),
+        Some(len) if len < 0 => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Element `{}` has a list of negative length.", element_def.name)
+        )),
+        Some(len) => Ok(len as usize),
+    }
+}
+
+/// Decodes an integer stored in its original byte order, `None` for floating point types.
+fn decode_integer(bytes: &[u8], scalar_type: &ScalarType, big_endian: bool) -> Option<i64> {
+    let mut unsigned = 0u64;
+    for i in 0..bytes.len() {
+        let b = if big_endian { bytes[i] } else { bytes[bytes.len() - 1 - i] };
+        unsigned = unsigned << 8 | b as u64;
+    }
+    let unused_bits = 64 - bytes.len() * 8;
+    match *scalar_type {
+        ScalarType::Char | ScalarType::Short | ScalarType::Int => Some(((unsigned << unused_bits) as i64) >> unused_bits),
+        ScalarType::UChar | ScalarType::UShort | ScalarType::UInt => Some(unsigned as i64),
+        ScalarType::Float | ScalarType::Double => None,
+    }
+}
+
+/// Stores `value` in `bytes` in the given byte order, truncated to their length.
+fn encode_integer(value: i64, bytes: &mut [u8], big_endian: bool) {
+    let len = bytes.len();
+    for i in 0..len {
+        let b = (value >> (8 * i)) as u8;
+        if big_endian {
+            bytes[len - 1 - i] = b;
+        } else {
+            bytes[i] = b;
+        }
+    }
+}
+
+/// Adds `offset` to an integer stored in its original byte order, failing if the result doesn't fit its type.
+fn shift_integer(bytes: &mut [u8], scalar_type: &ScalarType, offset: i64, big_endian: bool, element_def: &ElementDef) -> Result<()> {
+    let shifted = match decode_integer(bytes, scalar_type, big_endian) {
+        Some(v) => v + offset,
+        None => return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Element `{}` has vertex indices of non-integer type {}.", element_def.name, scalar_type)
+        )),
+    };
+    let min = match *scalar_type {
+        ScalarType::UChar | ScalarType::UShort | ScalarType::UInt => 0,
+        _ => -(scalar_type.max_value() as i64) - 1,
+    };
+    if shifted < min || shifted > scalar_type.max_value() as i64 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Shifted vertex index {} of element `{}` doesn't fit into {}.", shifted, element_def.name, scalar_type)
+        ));
+    }
+    encode_integer(shifted, bytes, big_endian);
+    Ok(())
+}
@@ -0,0 +1,265 @@
+//! Best-effort recovery of data from damaged PLY files.
+//!
+//! The functions in this module never give up on the first error,
+//! they return whatever could be decoded together with a description of what went wrong.
+
+use std::io;
+
+use parser::{ Parser, Line };
+use ply::{ PropertyAccess, DefaultElement, Header, ElementDef, Encoding, Addable };
+
+/// Entries decoded from a payload region by `parse_payload_fragment()`.
+#[derive(Debug)]
+pub struct Fragment<E: PropertyAccess> {
+    /// All complete entries found, in order.
+    pub elements: Vec<E>,
+    /// Number of bytes covered by `elements`, decoding can resume after them.
+    pub consumed: usize,
+    /// Why decoding stopped before the end of the region, `None` if all bytes were used.
+    pub error: Option<io::Error>,
+}
+
+/// Decodes as many entries of `element_def` as possible from `bytes`, a payload region cut out of a file.
+///
+/// The region has to start at an entry boundary, the schema and encoding are supplied by the caller,
+/// for example taken from an intact file of the same source.
+/// Decoding stops at the first entry that can't be decoded, a truncated last entry is dropped.
+/// `element_def.count` is ignored, the region may contain more or fewer entries.
+///
+/// Binary payloads carry no redundancy, a wrong schema or offset mostly yields garbage values instead of an error.
+/// Check the values for plausibility before relying on them.
+pub fn parse_payload_fragment<E: PropertyAccess>(bytes: &[u8], element_def: &ElementDef, encoding: Encoding) -> Fragment<E> {
+    let parser = Parser::<E>::new();
+    let mut fragment = Fragment {
+        elements: Vec::new(),
+        consumed: 0,
+        error: None,
+    };
+    match encoding {
+        Encoding::Ascii => {
+            while fragment.consumed < bytes.len() {
+                let rest = &bytes[fragment.consumed..];
+                let line_len = match rest.iter().position(|&b| b == b'\n') {
+                    Some(p) => p + 1,
+                    None => rest.len(),
+                };
+                let line = match ::std::str::from_utf8(&rest[..line_len]) {
+                    Ok(l) => l,
+                    Err(e) => {
+                        fragment.error = Some(io::Error::new(io::ErrorKind::InvalidData, e));
+                        break;
+                    },
+                };
+                if line.trim().is_empty() {
+                    fragment.consumed += line_len;
+                    continue;
+                }
+                match parser.read_ascii_element(line, element_def) {
+                    Ok(e) => fragment.elements.push(e),
+                    Err(e) => {
+                        fragment.error = Some(e);
+                        break;
+                    },
+                }
+                fragment.consumed += line_len;
+            }
+        },
+        Encoding::BinaryBigEndian | Encoding::BinaryLittleEndian => {
+            while fragment.consumed < bytes.len() {
+                let mut rest = &bytes[fragment.consumed..];
+                let before = rest.len();
+                let element = if encoding == Encoding::BinaryBigEndian {
+                    parser.read_big_endian_element(&mut rest, element_def)
+                } else {
+                    parser.read_little_endian_element(&mut rest, element_def)
+                };
+                match element {
+                    Ok(e) => fragment.elements.push(e),
+                    Err(e) => {
+                        fragment.error = Some(e);
+                        break;
+                    },
+                }
+                fragment.consumed += before - rest.len();
+            }
+        },
+    }
+    fragment
+}
+
+/// Result of `repair_header()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepairedHeader {
+    /// Best-effort header, check `report` before trusting it.
+    pub header: Header,
+    /// Offset in the input where the payload starts.
+    pub payload_start: usize,
+    /// Human readable description of every repair performed, empty if the header was intact.
+    pub report: Vec<String>,
+}
+
+/// Reconstructs the header of a damaged PLY file from its raw `bytes`.
+///
+/// Handles the common damage of files that were cut off or edited by hand:
+///
+/// - Lines that can't be parsed but start with a header keyword are dropped, like a truncated last property.
+/// - A missing `end_header` is tolerated, the payload starts at the first line that doesn't look like header.
+/// - A missing `format` line defaults to ascii 1.0.
+/// - The count of the last element is re-derived from the size of the payload,
+///   the counts of all other elements are trusted.
+///
+/// Fails only if the input doesn't start with the magic number `ply`.
+pub fn repair_header(bytes: &[u8]) -> io::Result<RepairedHeader> {
+    let parser = Parser::<DefaultElement>::new();
+    let mut report = Vec::new();
+    let mut format = None;
+    let mut header = Header::new();
+    let mut pos = 0;
+    let mut line_index = 0;
+    let mut ended = false;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        let line_len = match rest.iter().position(|&b| b == b'\n') {
+            Some(p) => p + 1,
+            None => rest.len(),
+        };
+        let line = String::from_utf8_lossy(&rest[..line_len]).into_owned();
+        line_index += 1;
+        if line_index == 1 {
+            if line.trim_right() != "ply" {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Magic number `ply` not found, this is not a PLY file."));
+            }
+            pos += line_len;
+            continue;
+        }
+        match parser.read_header_line(&line) {
+            Ok(Line::EndHeader) => {
+                pos += line_len;
+                ended = true;
+                break;
+            },
+            Ok(Line::MagicNumber) => report.push(format!("Dropped repeated magic number on line {}.", line_index)),
+            Ok(Line::Format(f)) => if format.is_none() {
+                format = Some(f);
+            } else {
+                report.push(format!("Dropped additional format line {}.", line_index));
+            },
+            Ok(Line::Comment(c)) => header.comments.push(c),
+            Ok(Line::ObjInfo(o)) => header.obj_infos.push(o),
+            Ok(Line::Element(e)) => header.elements.add(e),
+            Ok(Line::Property(p)) => match header.elements.iter_mut().last() {
+                Some((_, e)) => e.properties.add(p),
+                None => report.push(format!("Dropped property `{}` without element on line {}.", p.name, line_index)),
+            },
+            Err(_) => {
+                let keyword = line.split_whitespace().next().unwrap_or("");
+                if HEADER_KEYWORDS.contains(&keyword) {
+                    report.push(format!("Dropped damaged line {}: `{}`.", line_index, line.trim()));
+                } else {
+                    break;
+                }
+            },
+        }
+        pos += line_len;
+    }
+    if !ended {
+        report.push(format!("No `end_header` found, assuming the payload starts at byte {}.", pos));
+    }
+    match format {
+        Some((encoding, version)) => {
+            header.encoding = encoding;
+            header.version = version;
+        },
+        None => report.push("No format line found, assuming ascii 1.0.".to_string()),
+    }
+    repair_last_count(&parser, &mut header, &bytes[pos..], &mut report);
+    Ok(RepairedHeader {
+        header: header,
+        payload_start: pos,
+        report: report,
+    })
+}
+
+const HEADER_KEYWORDS: [&'static str; 6] = ["format", "comment", "obj_info", "element", "property", "end_header"];
+
+/// Skips all but the last element and counts the entries left in `payload` for it.
+fn repair_last_count(parser: &Parser<DefaultElement>, header: &mut Header, payload: &[u8], report: &mut Vec<String>) {
+    let mut reader = payload;
+    let last = match header.elements.keys().last() {
+        Some(k) => k.clone(),
+        None => return,
+    };
+    for (name, element_def) in &header.elements {
+        if *name == last {
+            break;
+        }
+        if let Err(e) = parser.read_payload_for_element(&mut reader, element_def, header) {
+            report.push(format!("Couldn't read element `{}`, counts left unverified: {}", name, e));
+            return;
+        }
+    }
+    let fragment: Fragment<DefaultElement> = parse_payload_fragment(reader, &header.elements[&last], header.encoding);
+    let element_def = header.elements.get_mut(&last).unwrap();
+    let count = fragment.elements.len() as u64;
+    if count != element_def.count {
+        report.push(format!("Element `{}` declares {} entries, but the payload holds {}.", last, element_def.count, count));
+        element_def.count = count;
+    }
+    if fragment.error.is_some() {
+        report.push(format!("Ignored {} bytes after the last complete entry of `{}`.", reader.len() - fragment.consumed, last));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    fn vertex_def() -> ElementDef {
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Short)));
+        e.properties.add(PropertyDef::new("i".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::UChar)));
+        e
+    }
+    #[test]
+    fn fragment_binary_truncated() {
+        let bytes = [0, 1, 2, 7, 8, 0, 2, 0, 9, 3, 1];
+        let f = parse_payload_fragment::<DefaultElement>(&bytes, &vertex_def(), Encoding::BinaryBigEndian);
+        assert_eq!(f.elements.len(), 2);
+        assert_eq!(f.consumed, 8);
+        assert_eq!(f.elements[0]["x"], Property::Short(1));
+        assert_eq!(f.elements[1]["i"], Property::ListUChar(vec![]));
+        assert!(f.error.is_some());
+    }
+    #[test]
+    fn fragment_ascii_stops_at_garbage() {
+        let bytes = b"1 2 3 4\n\n-5 0\n7 @@\n8 0\n";
+        let f = parse_payload_fragment::<DefaultElement>(bytes, &vertex_def(), Encoding::Ascii);
+        assert_eq!(f.elements.len(), 2);
+        assert_eq!(f.consumed, 14);
+        assert_eq!(f.elements[1]["x"], Property::Short(-5));
+        assert!(f.error.is_some());
+        let f = parse_payload_fragment::<DefaultElement>(b"1 1 3\n", &vertex_def(), Encoding::Ascii);
+        assert_eq!(f.consumed, 6);
+        assert!(f.error.is_none());
+    }
+    #[test]
+    fn repair_truncated_header() {
+        let bytes = b"ply\nformat ascii 1.0\nelement vertex 9\nproperty short x\nproperty list uchar uchar i\nproperty flo\n1 0\n2 1 5\n3 2 6";
+        let r = repair_header(bytes).unwrap();
+        assert_eq!(r.header.elements["vertex"].count, 2);
+        assert_eq!(r.header.elements["vertex"].properties.len(), 2);
+        assert_eq!(&bytes[r.payload_start..r.payload_start + 4], b"1 0\n");
+        assert_eq!(r.report.len(), 4);
+        assert!(repair_header(b"plx\n").is_err());
+    }
+    #[test]
+    fn repair_binary_count() {
+        let bytes = b"ply\nformat binary_big_endian 1.0\nelement a 1\nproperty uchar v\nelement b 1\nproperty short v\nend_header\n\x07\x00\x01\x00\x02";
+        let r = repair_header(bytes).unwrap();
+        assert_eq!(r.header.elements["a"].count, 1);
+        assert_eq!(r.header.elements["b"].count, 2);
+        assert_eq!(r.report.len(), 1);
+        let intact = b"ply\nformat ascii 1.0\nelement a 1\nproperty uchar v\nend_header\n7\n";
+        assert!(repair_header(intact).unwrap().report.is_empty());
+    }
+}
@@ -0,0 +1,182 @@
+//! Removes sensitive elements, properties, and header texts from a `Ply` before publishing it.
+//!
+//! Patterns are globs: `*` matches any sequence of characters, `?` matches a single character.
+
+use ply::{ Ply, DefaultElement };
+
+/// Describes what `redact()` removes.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::redact::RedactionProfile;
+/// let mut profile = RedactionProfile::new();
+/// profile.remove_property("vertex", "intensity")
+///        .remove_property("*", "user_*")
+///        .remove_element("camera")
+///        .scrub_comments(true);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedactionProfile {
+    elements: Vec<String>,
+    properties: Vec<(String, String)>,
+    comments: bool,
+    obj_infos: bool,
+}
+
+impl RedactionProfile {
+    /// Creates a profile that removes nothing.
+    pub fn new() -> Self {
+        RedactionProfile {
+            elements: Vec::new(),
+            properties: Vec::new(),
+            comments: false,
+            obj_infos: false,
+        }
+    }
+    /// Removes all elements whose name matches `element_pattern`.
+    pub fn remove_element(&mut self, element_pattern: &str) -> &mut Self {
+        self.elements.push(element_pattern.to_string());
+        self
+    }
+    /// Removes all properties matching `property_pattern` of elements matching `element_pattern`.
+    pub fn remove_property(&mut self, element_pattern: &str, property_pattern: &str) -> &mut Self {
+        self.properties.push((element_pattern.to_string(), property_pattern.to_string()));
+        self
+    }
+    /// Whether all comments are removed.
+    pub fn scrub_comments(&mut self, scrub: bool) -> &mut Self {
+        self.comments = scrub;
+        self
+    }
+    /// Whether all object informations are removed.
+    pub fn scrub_obj_infos(&mut self, scrub: bool) -> &mut Self {
+        self.obj_infos = scrub;
+        self
+    }
+    /// Checks if the element `element` is removed entirely.
+    pub fn removes_element(&self, element: &str) -> bool {
+        self.elements.iter().any(|p| glob_match(p, element))
+    }
+    /// Checks if the property `property` of `element` is removed.
+    pub fn removes_property(&self, element: &str, property: &str) -> bool {
+        self.removes_element(element) ||
+            self.properties.iter().any(|&(ref e, ref p)| glob_match(e, element) && glob_match(p, property))
+    }
+}
+
+/// Returns a copy of `ply` with everything removed that `profile` asks for.
+///
+/// Element counts are left untouched, also for elements that lose all their properties.
+pub fn redact(ply: &Ply<DefaultElement>, profile: &RedactionProfile) -> Ply<DefaultElement> {
+    let mut redacted = Ply::new();
+    redacted.header.encoding = ply.header.encoding;
+    redacted.header.version = ply.header.version;
+    if !profile.comments {
+        redacted.header.comments = ply.header.comments.clone();
+    }
+    if !profile.obj_infos {
+        redacted.header.obj_infos = ply.header.obj_infos.clone();
+    }
+    for (name, element_def) in &ply.header.elements {
+        if profile.removes_element(name) {
+            continue;
+        }
+        let mut element_def = element_def.clone();
+        let removed: Vec<String> = element_def.properties.keys()
+            .filter(|p| profile.removes_property(name, p))
+            .cloned()
+            .collect();
+        for p in &removed {
+            element_def.properties.remove(p);
+        }
+        if let Some(list) = ply.payload.get(name) {
+            let list = list.iter().map(|e| {
+                let mut e = e.clone();
+                for p in &removed {
+                    e.remove(p);
+                }
+                e
+            }).collect();
+            redacted.payload.insert(name.clone(), list);
+        }
+        redacted.header.elements.insert(name.clone(), element_def);
+    }
+    redacted
+}
+
+/// Matches `text` against a glob `pattern` supporting `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` in the pattern and the text position it was tried at.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((bp, bt)) = backtrack {
+            backtrack = Some((bp, bt + 1));
+            p = bp + 1;
+            t = bt + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    #[test]
+    fn glob() {
+        assert!(glob_match("vertex", "vertex"));
+        assert!(glob_match("*", "vertex"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("user_*", "user_id"));
+        assert!(glob_match("v?rt*x", "vertex"));
+        assert!(glob_match("*_*_*", "a_b_c"));
+        assert!(!glob_match("user_*", "username"));
+        assert!(!glob_match("vertex", "vertices"));
+        assert!(!glob_match("?", ""));
+    }
+    #[test]
+    fn redact_properties_and_comments() {
+        let mut ply = Ply::<DefaultElement>::new();
+        ply.header.comments.push("scanned at patient home".to_string());
+        ply.header.obj_infos.push("scanner 42".to_string());
+        let mut v = ElementDef::new("vertex".to_string());
+        for n in &["x", "intensity", "user_id"] {
+            v.properties.add(PropertyDef::new(n.to_string(), PropertyType::Scalar(ScalarType::Int)));
+        }
+        ply.header.elements.add(v);
+        ply.header.elements.add(ElementDef::new("camera".to_string()));
+        let mut e = DefaultElement::new();
+        e.insert("x".to_string(), Property::Int(1));
+        e.insert("intensity".to_string(), Property::Int(2));
+        e.insert("user_id".to_string(), Property::Int(3));
+        ply.payload.insert("vertex".to_string(), vec![e]);
+        ply.make_consistent().unwrap();
+
+        let mut profile = RedactionProfile::new();
+        profile.remove_property("vert*", "intensity")
+            .remove_property("*", "user_*")
+            .remove_element("camera")
+            .scrub_comments(true);
+        let mut r = redact(&ply, &profile);
+        assert!(r.header.comments.is_empty());
+        assert_eq!(r.header.obj_infos, ply.header.obj_infos);
+        assert!(!r.header.elements.contains_key("camera"));
+        let names: Vec<&String> = r.header.elements["vertex"].properties.keys().collect();
+        assert_eq!(names, vec!["x"]);
+        let names: Vec<&String> = r.payload["vertex"][0].keys().collect();
+        assert_eq!(names, vec!["x"]);
+        assert!(r.make_consistent().is_ok());
+    }
+}
@@ -0,0 +1,396 @@
+//! Time-ordered series of PLY frames sharing one schema, as captured for dynamic point clouds.
+//!
+//! Frames are complete PLY files, either concatenated in a single stream or stored one per file.
+//! All frames must declare the same elements and properties, only the element counts may differ.
+//! To save space, frames can be stored as deltas to a keyframe, see `encode_delta()`.
+
+use std::io;
+use std::io::{ BufRead, BufReader, Write, Result, ErrorKind };
+use std::fs::File;
+use std::path::Path;
+
+use parser::Parser;
+use writer::Writer;
+use ply::{ Ply, Header, PropertyAccess, DefaultElement, ElementDef, PropertyDef, PropertyType, ScalarType, Property, Addable, Error as PlyError };
+
+/// Checks whether two headers describe the same schema, ignoring element counts, comments and object informations.
+pub fn same_schema(a: &Header, b: &Header) -> bool {
+    a.encoding == b.encoding && a.elements.len() == b.elements.len() &&
+        a.elements.values().zip(b.elements.values()).all(|(ea, eb)| ea.name == eb.name && ea.properties == eb.properties)
+}
+
+/// Reads the frames concatenated in a stream, one after another.
+///
+/// Iterating yields the frames in order, until the stream ends.
+/// A frame whose schema differs from the first one yields an error.
+pub struct SequenceReader<R: BufRead, E: PropertyAccess> {
+    reader: R,
+    parser: Parser<E>,
+    schema: Option<Header>,
+    frame_index: u64,
+    failed: bool,
+}
+
+impl<R: BufRead, E: PropertyAccess> SequenceReader<R, E> {
+    /// Reads from `reader` with a default `Parser`.
+    pub fn new(reader: R) -> Self {
+        Self::with_parser(reader, Parser::new())
+    }
+    /// Reads from `reader` with a configured `parser`.
+    pub fn with_parser(reader: R, parser: Parser<E>) -> Self {
+        SequenceReader {
+            reader: reader,
+            parser: parser,
+            schema: None,
+            frame_index: 0,
+            failed: false,
+        }
+    }
+    fn read_frame(&mut self) -> Result<Ply<E>> {
+        let header = try!(self.parser.read_header(&mut self.reader));
+        try!(check_schema(&mut self.schema, &header, self.frame_index));
+        let payload = try!(self.parser.read_payload(&mut self.reader, &header));
+        self.frame_index += 1;
+        Ok(Ply { header: header, payload: payload })
+    }
+}
+
+impl<R: BufRead, E: PropertyAccess> Iterator for SequenceReader<R, E> {
+    type Item = Result<Ply<E>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let at_end = match self.reader.fill_buf() {
+            Ok(buf) => buf.is_empty(),
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            },
+        };
+        if at_end {
+            return None;
+        }
+        let frame = self.read_frame();
+        // After an error the position in the stream is unknown, don't try to continue.
+        self.failed = frame.is_err();
+        Some(frame)
+    }
+}
+
+/// Reads a sequence stored as one file per frame, in the order of `paths`.
+///
+/// Iterating yields the frames one at a time, like `SequenceReader`,
+/// so only the current frame is held in memory.
+pub fn read_sequence_files<'a, E, I, P>(parser: &'a Parser<E>, paths: I) -> SequenceFiles<'a, E, I::IntoIter>
+    where E: PropertyAccess, I: IntoIterator<Item = P>, P: AsRef<Path> {
+    SequenceFiles {
+        paths: paths.into_iter(),
+        parser: parser,
+        schema: None,
+        frame_index: 0,
+    }
+}
+
+/// Iterator over the frames of a sequence stored one per file, see `read_sequence_files()`.
+///
+/// A file that can't be read or whose schema differs from the first frame yields an error
+/// attributed to the file, see `ply::Error::in_file()`. Iteration then continues with the next file.
+pub struct SequenceFiles<'a, E: PropertyAccess + 'a, I> {
+    paths: I,
+    parser: &'a Parser<E>,
+    schema: Option<Header>,
+    frame_index: u64,
+}
+
+impl<'a, E: PropertyAccess, I: Iterator<Item = P>, P: AsRef<Path>> SequenceFiles<'a, E, I> {
+    fn read_frame(&mut self, path: P) -> Result<Ply<E>> {
+        let path = path.as_ref();
+        self.read_frame_file(path).map_err(|e| PlyError::in_file(e, path))
+    }
+    fn read_frame_file(&mut self, path: &Path) -> Result<Ply<E>> {
+        let mut reader = BufReader::new(try!(File::open(path)));
+        let header = try!(self.parser.read_header(&mut reader));
+        try!(check_schema(&mut self.schema, &header, self.frame_index));
+        let payload = try!(self.parser.read_payload(&mut reader, &header));
+        Ok(Ply { header: header, payload: payload })
+    }
+}
+
+impl<'a, E: PropertyAccess, I: Iterator<Item = P>, P: AsRef<Path>> Iterator for SequenceFiles<'a, E, I> {
+    type Item = Result<Ply<E>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let path = match self.paths.next() {
+            Some(p) => p,
+            None => return None,
+        };
+        let frame = self.read_frame(path);
+        self.frame_index += 1;
+        Some(frame)
+    }
+}
+
+/// Writes a sequence as one file per frame.
+///
+/// Every frame is made consistent and must have the schema of the first one.
+pub struct SequenceFileWriter<E: PropertyAccess> {
+    writer: Writer<E>,
+    schema: Option<Header>,
+    frame_index: u64,
+}
+
+impl<E: PropertyAccess> SequenceFileWriter<E> {
+    /// Writes with a default `Writer`.
+    pub fn new() -> Self {
+        Self::with_writer(Writer::new())
+    }
+    /// Writes with a configured `writer`, see `Writer::write_ply_file()` for how files are replaced.
+    pub fn with_writer(writer: Writer<E>) -> Self {
+        SequenceFileWriter {
+            writer: writer,
+            schema: None,
+            frame_index: 0,
+        }
+    }
+    /// Writes `frame` as the next frame of the sequence to the file at `path`.
+    ///
+    /// Returns number of bytes written.
+    pub fn write_frame<P: AsRef<Path>>(&mut self, path: P, frame: &mut Ply<E>) -> Result<usize> {
+        if let Err(e) = frame.make_consistent() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Frame {} isn't consistent: {:?}", self.frame_index, e)));
+        }
+        try!(check_schema(&mut self.schema, &frame.header, self.frame_index));
+        let written = try!(self.writer.write_ply_file(path, frame));
+        self.frame_index += 1;
+        Ok(written)
+    }
+}
+
+/// Writes frames one after another into a single stream.
+///
+/// Every frame is made consistent and must have the schema of the first one.
+pub struct SequenceWriter<W: Write, E: PropertyAccess> {
+    out: W,
+    writer: Writer<E>,
+    schema: Option<Header>,
+    frame_index: u64,
+}
+
+impl<W: Write, E: PropertyAccess> SequenceWriter<W, E> {
+    /// Writes to `out` with a default `Writer`.
+    pub fn new(out: W) -> Self {
+        Self::with_writer(out, Writer::new())
+    }
+    /// Writes to `out` with a configured `writer`.
+    pub fn with_writer(out: W, writer: Writer<E>) -> Self {
+        SequenceWriter {
+            out: out,
+            writer: writer,
+            schema: None,
+            frame_index: 0,
+        }
+    }
+    /// Appends `frame` to the sequence.
+    ///
+    /// Returns number of bytes written.
+    pub fn write_frame(&mut self, frame: &mut Ply<E>) -> Result<usize> {
+        if let Err(e) = frame.make_consistent() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Frame {} isn't consistent: {:?}", self.frame_index, e)));
+        }
+        try!(check_schema(&mut self.schema, &frame.header, self.frame_index));
+        let written = try!(self.writer.write_ply_unchecked(&mut self.out, frame));
+        self.frame_index += 1;
+        Ok(written)
+    }
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Name of the property holding the entry index in a delta frame, see `encode_delta()`.
+pub const DELTA_INDEX_PROPERTY: &'static str = "delta_index";
+
+/// Encodes `frame` relative to `keyframe`, keeping only what changed.
+///
+/// Every element of the delta frame gets the additional property `delta_index` (`uint`)
+/// and only holds the entries that differ from the keyframe.
+/// Of these, only the properties changing in at least one entry are kept.
+/// Elements without any change are left out. The result is a standard PLY, readable by any tool.
+///
+/// Both frames must have the same schema and the same element counts.
+pub fn encode_delta(keyframe: &Ply<DefaultElement>, frame: &Ply<DefaultElement>) -> Result<Ply<DefaultElement>> {
+    if !same_schema(&keyframe.header, &frame.header) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Frame and keyframe have different schemas."));
+    }
+    let mut delta = Ply::<DefaultElement>::new();
+    delta.header.encoding = frame.header.encoding;
+    delta.header.version = frame.header.version;
+    delta.header.comments = frame.header.comments.clone();
+    delta.header.obj_infos = frame.header.obj_infos.clone();
+    for (name, element_def) in &frame.header.elements {
+        let empty = Vec::new();
+        let key_entries = keyframe.payload.get(name).unwrap_or(&empty);
+        let entries = frame.payload.get(name).unwrap_or(&empty);
+        if key_entries.len() != entries.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` has {} entries in the frame, but {} in the keyframe.", name, entries.len(), key_entries.len())
+            ));
+        }
+        if entries.len() > u32::max_value() as usize {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` has too many entries for a delta index.", name)));
+        }
+        let changed: Vec<usize> = (0..entries.len()).filter(|&i| entries[i] != key_entries[i]).collect();
+        if changed.is_empty() {
+            continue;
+        }
+        let mut delta_def = ElementDef::new(name.clone());
+        delta_def.properties.add(PropertyDef::new(DELTA_INDEX_PROPERTY.to_string(), PropertyType::Scalar(ScalarType::UInt)));
+        for (k, p) in &element_def.properties {
+            if changed.iter().any(|&i| entries[i].get(k) != key_entries[i].get(k)) {
+                delta_def.properties.add(p.clone());
+            }
+        }
+        let list = changed.iter().map(|&i| {
+            let mut e = DefaultElement::new();
+            e.insert(DELTA_INDEX_PROPERTY.to_string(), Property::UInt(i as u32));
+            for k in delta_def.properties.keys().skip(1) {
+                if let Some(v) = entries[i].get(k) {
+                    e.insert(k.clone(), v.clone());
+                }
+            }
+            e
+        }).collect::<Vec<_>>();
+        delta_def.count = list.len() as u64;
+        delta.header.elements.add(delta_def);
+        delta.payload.insert(name.clone(), list);
+    }
+    Ok(delta)
+}
+
+/// Reconstructs the full frame from `keyframe` and a `delta` created by `encode_delta()`.
+pub fn apply_delta(keyframe: &Ply<DefaultElement>, delta: &Ply<DefaultElement>) -> Result<Ply<DefaultElement>> {
+    let mut frame = keyframe.clone();
+    frame.header.comments = delta.header.comments.clone();
+    frame.header.obj_infos = delta.header.obj_infos.clone();
+    for (name, entries) in &delta.payload {
+        let target = match frame.payload.get_mut(name) {
+            Some(t) => t,
+            None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Delta changes element `{}` missing in the keyframe.", name))),
+        };
+        for entry in entries {
+            let index = match entry.get(DELTA_INDEX_PROPERTY) {
+                Some(&Property::UInt(i)) if (i as usize) < target.len() => i as usize,
+                _ => return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Delta entry of element `{}` lacks a valid `{}`.", name, DELTA_INDEX_PROPERTY)
+                )),
+            };
+            for (k, v) in entry {
+                if k != DELTA_INDEX_PROPERTY {
+                    target[index].insert(k.clone(), v.clone());
+                }
+            }
+        }
+    }
+    Ok(frame)
+}
+
+/// Remembers the first schema seen and compares all later ones with it.
+fn check_schema(schema: &mut Option<Header>, header: &Header, frame_index: u64) -> Result<()> {
+    match *schema {
+        None => {
+            *schema = Some(header.clone());
+            Ok(())
+        },
+        Some(ref s) if same_schema(s, header) => Ok(()),
+        Some(_) => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Frame {} has a different schema than the first frame.", frame_index)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use ply::*;
+    fn frame(n: usize, property: &str) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new(property.to_string(), PropertyType::Scalar(ScalarType::Int)));
+        ply.header.elements.add(e);
+        let list = (0..n).map(|i| {
+            let mut v = DefaultElement::new();
+            v.insert(property.to_string(), Property::Int(i as i32));
+            v
+        }).collect();
+        ply.payload.insert("vertex".to_string(), list);
+        ply
+    }
+    #[test]
+    fn concatenated_roundtrip() {
+        let mut w = SequenceWriter::new(Vec::new());
+        let mut frames = vec![frame(2, "x"), frame(0, "x"), frame(3, "x")];
+        for f in frames.iter_mut() {
+            w.write_frame(f).unwrap();
+        }
+        assert!(w.write_frame(&mut frame(1, "y")).is_err());
+        let buf = w.into_inner();
+        let read: Vec<Ply<DefaultElement>> = SequenceReader::new(&buf[..]).map(|f| f.unwrap()).collect();
+        assert_eq!(read, frames);
+    }
+    #[test]
+    fn schema_change_err() {
+        let mut buf = Vec::new();
+        let w = Writer::new();
+        w.write_ply(&mut buf, &mut frame(1, "x")).unwrap();
+        w.write_ply(&mut buf, &mut frame(1, "y")).unwrap();
+        let mut r = SequenceReader::<_, DefaultElement>::new(&buf[..]);
+        assert!(r.next().unwrap().is_ok());
+        assert!(r.next().unwrap().is_err());
+        assert!(r.next().is_none());
+    }
+    #[test]
+    fn file_sequence_roundtrip() {
+        let dir = ::std::env::temp_dir();
+        let paths: Vec<_> = (0..3).map(|i| dir.join(format!("ply_rs_sequence_{}_{}.ply", ::std::process::id(), i))).collect();
+        let mut frames = vec![frame(2, "x"), frame(0, "x"), frame(3, "x")];
+        let mut w = SequenceFileWriter::new();
+        for (path, f) in paths.iter().zip(frames.iter_mut()) {
+            w.write_frame(path, f).unwrap();
+        }
+        assert!(w.write_frame(&paths[0], &mut frame(1, "y")).is_err());
+        let p = Parser::<DefaultElement>::new();
+        let mut r = read_sequence_files(&p, &paths);
+        assert_eq!(r.next().unwrap().unwrap(), frames[0]);
+        Writer::new().write_ply_file(&paths[1], &mut frame(1, "y")).unwrap();
+        assert!(r.next().unwrap().is_err());
+        assert_eq!(r.next().unwrap().unwrap(), frames[2]);
+        assert!(r.next().is_none());
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+    #[test]
+    fn delta_roundtrip() {
+        let key = frame(4, "x");
+        let mut f = frame(4, "x");
+        f.payload["vertex"][2].insert("x".to_string(), Property::Int(-1));
+        let mut d = encode_delta(&key, &f).unwrap();
+        assert_eq!(d.header.elements["vertex"].count, 1);
+        assert_eq!(d.payload["vertex"][0][DELTA_INDEX_PROPERTY], Property::UInt(2));
+        assert!(d.make_consistent().is_ok());
+        let buf = {
+            let mut buf = Vec::new();
+            Writer::new().write_ply(&mut buf, &mut d).unwrap();
+            buf
+        };
+        let d = Parser::<DefaultElement>::new().read_ply(&mut &buf[..]).unwrap();
+        assert_eq!(apply_delta(&key, &d).unwrap(), f);
+        assert!(encode_delta(&key, &key).unwrap().payload.is_empty());
+        assert!(encode_delta(&key, &frame(3, "x")).is_err());
+    }
+}
@@ -0,0 +1,486 @@
+//! Converts between `DefaultElement` and structs deriving serde's `Serialize` and `Deserialize`.
+//!
+//! Available with the `serde` feature.
+//! Struct fields are matched with properties by name.
+//! Scalars map to the rust types listed in `ScalarType`, lists to sequences like `Vec` or arrays.
+//! Fields of type `Option` may be missing, properties without a field are ignored when deserializing.
+//!
+//! To write structs, convert them with `to_element()` and derive the header with `Header::infer_from_payload()`.
+//! Empty sequences become `ListInt` since their type can't be told, declare such properties yourself.
+//!
+//! Large payloads don't need a `DefaultElement` per entry:
+//! `Parser::read_deserialized_elements()` decodes entries straight into structs,
+//! and wrapping structs in `Serialized` writes them with `Writer::write_payload_of_serialized()`.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Vertex { x: f32, y: f32, z: f32 }
+//!
+//! let ply = Parser::<DefaultElement>::new().read_ply(&mut f)?;
+//! let vertices: Vec<Vertex> = from_elements(&ply.payload["vertex"])?;
+//! ```
+
+use std::io;
+use std::io::ErrorKind;
+
+use serde::de::{ DeserializeOwned, Deserializer, IntoDeserializer, Visitor };
+use serde::de::value::{ Error, MapDeserializer, SeqDeserializer };
+use serde::ser;
+use serde::ser::{ Serialize, Serializer, Impossible };
+
+use ply::{ DefaultElement, Property, PropertyAccess, ElementDef, Encoding };
+use writer::{ Writer, SerializeElement };
+
+/// Builds a `T` from the properties of `element`.
+pub fn from_element<T: DeserializeOwned>(element: &DefaultElement) -> io::Result<T> {
+    let map = MapDeserializer::new(element.iter().map(|(k, v)| (k.as_str(), PropertyDeserializer(v))));
+    T::deserialize(map).map_err(to_io_error)
+}
+
+/// Builds a `T` from `values`, the decoded properties of `element_def` in order.
+pub fn from_values<T: DeserializeOwned>(element_def: &ElementDef, values: &[Property]) -> io::Result<T> {
+    let map = MapDeserializer::new(element_def.properties.keys().zip(values).map(|(k, v)| (k.as_str(), PropertyDeserializer(v))));
+    T::deserialize(map).map_err(to_io_error)
+}
+
+/// Builds a `T` from each entry in `elements`, see `from_element()`.
+pub fn from_elements<T: DeserializeOwned>(elements: &[DefaultElement]) -> io::Result<Vec<T>> {
+    elements.iter().enumerate().map(|(i, e)| from_element(e).map_err(|err| io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("Couldn't deserialize entry {}: {}", i, err)
+    ))).collect()
+}
+
+/// Turns the fields of the struct `value` into properties.
+///
+/// Fields set to `None` are left out.
+/// Only types with a PLY counterpart are supported, 64 bit integers, booleans and strings are rejected.
+pub fn to_element<T: Serialize>(value: &T) -> io::Result<DefaultElement> {
+    value.serialize(ElementSerializer(DefaultElement::new())).map_err(to_io_error)
+}
+
+/// Encodes a struct deriving `Serialize` for `Writer::write_payload_of_serialized()`.
+///
+/// The fields are encoded in the order of the element definition's properties, matched by name,
+/// and must have the declared type. Precision settings of the writer don't apply.
+///
+/// ```rust,ignore
+/// writer.write_payload_of_serialized(&mut out, vertices.iter().map(Serialized), &element_def, &header)?;
+/// ```
+pub struct Serialized<'a, T: 'a>(pub &'a T);
+
+impl<'a, T: Serialize> SerializeElement for Serialized<'a, T> {
+    fn serialize_element(&self, element_def: &ElementDef, encoding: &Encoding, out: &mut Vec<u8>) -> io::Result<()> {
+        let fields = Fields(Vec::with_capacity(element_def.properties.len()));
+        let fields = try!(self.0.serialize(ElementSerializer(fields)).map_err(to_io_error));
+        let writer = Writer::<Fields>::new();
+        match *encoding {
+            Encoding::Ascii => {
+                let start = out.len();
+                try!(writer.write_ascii_element(out, &fields, element_def));
+                // The line break is added by the caller.
+                while out.len() > start && (out[out.len() - 1] as char).is_whitespace() {
+                    out.pop();
+                }
+            },
+            Encoding::BinaryBigEndian => { try!(writer.write_big_endian_element(out, &fields, element_def)); },
+            Encoding::BinaryLittleEndian => { try!(writer.write_little_endian_element(out, &fields, element_def)); },
+        }
+        Ok(())
+    }
+}
+
+fn to_io_error(e: Error) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, e.to_string())
+}
+
+// ////////////////////////////////////////////////////////////////
+// # Deserialization
+// ////////////////////////////////////////////////////////////////
+
+/// Hands a single property to a visitor.
+struct PropertyDeserializer<'a>(&'a Property);
+
+impl<'de, 'a> IntoDeserializer<'de, Error> for PropertyDeserializer<'a> {
+    type Deserializer = Self;
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de, 'a> Deserializer<'de> for PropertyDeserializer<'a> {
+    type Error = Error;
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        match *self.0 {
+            Property::Char(v) => visitor.visit_i8(v),
+            Property::UChar(v) => visitor.visit_u8(v),
+            Property::Short(v) => visitor.visit_i16(v),
+            Property::UShort(v) => visitor.visit_u16(v),
+            Property::Int(v) => visitor.visit_i32(v),
+            Property::UInt(v) => visitor.visit_u32(v),
+            Property::Float(v) => visitor.visit_f32(v),
+            Property::Double(v) => visitor.visit_f64(v),
+            Property::ListChar(ref l) => visit_list(l, visitor),
+            Property::ListUChar(ref l) => visit_list(l, visitor),
+            Property::ListShort(ref l) => visit_list(l, visitor),
+            Property::ListUShort(ref l) => visit_list(l, visitor),
+            Property::ListInt(ref l) => visit_list(l, visitor),
+            Property::ListUInt(ref l) => visit_list(l, visitor),
+            Property::ListFloat(ref l) => visit_list(l, visitor),
+            Property::ListDouble(ref l) => visit_list(l, visitor),
+        }
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+fn visit_list<'de, D, V>(list: &[D], visitor: V) -> Result<V::Value, Error>
+    where D: Copy + IntoDeserializer<'de, Error>, V: Visitor<'de> {
+    let mut seq = SeqDeserializer::new(list.iter().cloned());
+    let value = try!(visitor.visit_seq(&mut seq));
+    try!(seq.end());
+    Ok(value)
+}
+
+// ////////////////////////////////////////////////////////////////
+// # Serialization
+// ////////////////////////////////////////////////////////////////
+
+fn unsupported<T>(what: &str) -> Result<T, Error> {
+    Err(ser::Error::custom(format!("{} can't be stored in a PLY property.", what)))
+}
+
+/// Receives the fields of a serialized struct.
+trait FieldSink {
+    fn put(&mut self, key: &'static str, property: Property);
+}
+
+impl FieldSink for DefaultElement {
+    fn put(&mut self, key: &'static str, property: Property) {
+        self.insert(key.to_string(), property);
+    }
+}
+
+/// Fields of a serialized struct in declaration order, looked up by a linear search.
+struct Fields(Vec<(&'static str, Property)>);
+
+impl Fields {
+    fn get(&self, key: &str) -> Option<&Property> {
+        self.0.iter().find(|f| f.0 == key).map(|f| &f.1)
+    }
+}
+
+impl FieldSink for Fields {
+    fn put(&mut self, key: &'static str, property: Property) {
+        self.0.push((key, property));
+    }
+}
+
+macro_rules! field(
+    ($fields:expr, $key:expr, $variant:ident) => (match $fields.get($key) { Some(&Property::$variant(x)) => Some(x), _ => None });
+    ($fields:expr, $key:expr, ref $variant:ident) => (match $fields.get($key) { Some(&Property::$variant(ref x)) => Some(x), _ => None })
+);
+
+impl PropertyAccess for Fields {
+    fn new() -> Self {
+        Fields(Vec::new())
+    }
+    fn get_char(&self, key: &String) -> Option<i8> { field!(self, key, Char) }
+    fn get_uchar(&self, key: &String) -> Option<u8> { field!(self, key, UChar) }
+    fn get_short(&self, key: &String) -> Option<i16> { field!(self, key, Short) }
+    fn get_ushort(&self, key: &String) -> Option<u16> { field!(self, key, UShort) }
+    fn get_int(&self, key: &String) -> Option<i32> { field!(self, key, Int) }
+    fn get_uint(&self, key: &String) -> Option<u32> { field!(self, key, UInt) }
+    fn get_float(&self, key: &String) -> Option<f32> { field!(self, key, Float) }
+    fn get_double(&self, key: &String) -> Option<f64> { field!(self, key, Double) }
+    fn get_list_char(&self, key: &String) -> Option<&[i8]> { field!(self, key, ref ListChar) }
+    fn get_list_uchar(&self, key: &String) -> Option<&[u8]> { field!(self, key, ref ListUChar) }
+    fn get_list_short(&self, key: &String) -> Option<&[i16]> { field!(self, key, ref ListShort) }
+    fn get_list_ushort(&self, key: &String) -> Option<&[u16]> { field!(self, key, ref ListUShort) }
+    fn get_list_int(&self, key: &String) -> Option<&[i32]> { field!(self, key, ref ListInt) }
+    fn get_list_uint(&self, key: &String) -> Option<&[u32]> { field!(self, key, ref ListUInt) }
+    fn get_list_float(&self, key: &String) -> Option<&[f32]> { field!(self, key, ref ListFloat) }
+    fn get_list_double(&self, key: &String) -> Option<&[f64]> { field!(self, key, ref ListDouble) }
+}
+
+/// Serializes a struct into the fields of `S`, which starts out empty.
+struct ElementSerializer<S>(S);
+
+/// Collects the fields of a struct.
+struct StructSerializer<S>(S);
+
+impl<S: FieldSink> ser::SerializeStruct for StructSerializer<S> {
+    type Ok = S;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), Error> {
+        match value.serialize(PropertySerializer) {
+            Ok(Some(p)) => {
+                self.0.put(key, p);
+                Ok(())
+            },
+            Ok(None) => Ok(()),
+            Err(e) => Err(ser::Error::custom(format!("Field `{}`: {}", key, e))),
+        }
+    }
+    fn end(self) -> Result<S, Error> {
+        Ok(self.0)
+    }
+}
+
+impl<S: FieldSink> Serializer for ElementSerializer<S> {
+    type Ok = S;
+    type Error = Error;
+    type SerializeSeq = Impossible<S, Error>;
+    type SerializeTuple = Impossible<S, Error>;
+    type SerializeTupleStruct = Impossible<S, Error>;
+    type SerializeTupleVariant = Impossible<S, Error>;
+    type SerializeMap = Impossible<S, Error>;
+    type SerializeStruct = StructSerializer<S>;
+    type SerializeStructVariant = Impossible<S, Error>;
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<StructSerializer<S>, Error> {
+        Ok(StructSerializer(self.0))
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<S, Error> {
+        value.serialize(self)
+    }
+    fn serialize_bool(self, _v: bool) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_i8(self, _v: i8) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_i16(self, _v: i16) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_i32(self, _v: i32) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_i64(self, _v: i64) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_u8(self, _v: u8) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_u16(self, _v: u16) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_u32(self, _v: u32) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_u64(self, _v: u64) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_f32(self, _v: f32) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_f64(self, _v: f64) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_char(self, _v: char) -> Result<S, Error> { unsupported("A scalar") }
+    fn serialize_str(self, _v: &str) -> Result<S, Error> { unsupported("A string") }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<S, Error> { unsupported("A byte array") }
+    fn serialize_none(self) -> Result<S, Error> { unsupported("`None`") }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<S, Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<S, Error> { unsupported("A unit") }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<S, Error> { unsupported("A unit struct") }
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<S, Error> {
+        unsupported("An enum")
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T)
+        -> Result<S, Error> {
+        unsupported("An enum")
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> { unsupported("A sequence") }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> { unsupported("A tuple") }
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeTupleStruct, Error> {
+        unsupported("A tuple struct")
+    }
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleVariant, Error> {
+        unsupported("An enum")
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> { unsupported("A map") }
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+        -> Result<Self::SerializeStructVariant, Error> {
+        unsupported("An enum")
+    }
+}
+
+/// Serializes a field into a property, `None` for fields left out.
+struct PropertySerializer;
+
+/// Collects the values of a sequence, which must all be scalars of the same type.
+struct ListSerializer(Vec<Property>);
+
+impl ListSerializer {
+    fn push<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match try!(value.serialize(PropertySerializer)) {
+            Some(p) => {
+                self.0.push(p);
+                Ok(())
+            },
+            None => unsupported("`None` in a sequence"),
+        }
+    }
+    fn end(self) -> Result<Option<Property>, Error> {
+        macro_rules! collect_list {
+            ($scalar:ident, $list:ident) => {{
+                let mut list = Vec::with_capacity(self.0.len());
+                for p in self.0 {
+                    match p {
+                        Property::$scalar(v) => list.push(v),
+                        _ => return unsupported("A sequence of mixed or nested types"),
+                    }
+                }
+                Property::$list(list)
+            }}
+        }
+        let list = match self.0.first() {
+            None => Property::ListInt(Vec::new()),
+            Some(&Property::Char(_)) => collect_list!(Char, ListChar),
+            Some(&Property::UChar(_)) => collect_list!(UChar, ListUChar),
+            Some(&Property::Short(_)) => collect_list!(Short, ListShort),
+            Some(&Property::UShort(_)) => collect_list!(UShort, ListUShort),
+            Some(&Property::Int(_)) => collect_list!(Int, ListInt),
+            Some(&Property::UInt(_)) => collect_list!(UInt, ListUInt),
+            Some(&Property::Float(_)) => collect_list!(Float, ListFloat),
+            Some(&Property::Double(_)) => collect_list!(Double, ListDouble),
+            Some(_) => return unsupported("A nested sequence"),
+        };
+        Ok(Some(list))
+    }
+}
+
+impl ser::SerializeSeq for ListSerializer {
+    type Ok = Option<Property>;
+    type Error = Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Option<Property>, Error> {
+        ListSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTuple for ListSerializer {
+    type Ok = Option<Property>;
+    type Error = Error;
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Option<Property>, Error> {
+        ListSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for ListSerializer {
+    type Ok = Option<Property>;
+    type Error = Error;
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.push(value)
+    }
+    fn end(self) -> Result<Option<Property>, Error> {
+        ListSerializer::end(self)
+    }
+}
+
+impl Serializer for PropertySerializer {
+    type Ok = Option<Property>;
+    type Error = Error;
+    type SerializeSeq = ListSerializer;
+    type SerializeTuple = ListSerializer;
+    type SerializeTupleStruct = ListSerializer;
+    type SerializeTupleVariant = Impossible<Option<Property>, Error>;
+    type SerializeMap = Impossible<Option<Property>, Error>;
+    type SerializeStruct = Impossible<Option<Property>, Error>;
+    type SerializeStructVariant = Impossible<Option<Property>, Error>;
+
+    fn serialize_i8(self, v: i8) -> Result<Option<Property>, Error> { Ok(Some(Property::Char(v))) }
+    fn serialize_u8(self, v: u8) -> Result<Option<Property>, Error> { Ok(Some(Property::UChar(v))) }
+    fn serialize_i16(self, v: i16) -> Result<Option<Property>, Error> { Ok(Some(Property::Short(v))) }
+    fn serialize_u16(self, v: u16) -> Result<Option<Property>, Error> { Ok(Some(Property::UShort(v))) }
+    fn serialize_i32(self, v: i32) -> Result<Option<Property>, Error> { Ok(Some(Property::Int(v))) }
+    fn serialize_u32(self, v: u32) -> Result<Option<Property>, Error> { Ok(Some(Property::UInt(v))) }
+    fn serialize_f32(self, v: f32) -> Result<Option<Property>, Error> { Ok(Some(Property::Float(v))) }
+    fn serialize_f64(self, v: f64) -> Result<Option<Property>, Error> { Ok(Some(Property::Double(v))) }
+    fn serialize_i64(self, _v: i64) -> Result<Option<Property>, Error> { unsupported("A 64 bit integer") }
+    fn serialize_u64(self, _v: u64) -> Result<Option<Property>, Error> { unsupported("A 64 bit integer") }
+    fn serialize_bool(self, _v: bool) -> Result<Option<Property>, Error> { unsupported("A boolean") }
+    fn serialize_char(self, _v: char) -> Result<Option<Property>, Error> { unsupported("A character") }
+    fn serialize_str(self, _v: &str) -> Result<Option<Property>, Error> { unsupported("A string") }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Option<Property>, Error> { Ok(Some(Property::ListUChar(v.to_vec()))) }
+    fn serialize_none(self) -> Result<Option<Property>, Error> { Ok(None) }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Option<Property>, Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Option<Property>, Error> { Ok(None) }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Option<Property>, Error> { Ok(None) }
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, _variant: &'static str) -> Result<Option<Property>, Error> {
+        unsupported("An enum")
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _name: &'static str, value: &T) -> Result<Option<Property>, Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _name: &'static str, _index: u32, _variant: &'static str, _value: &T)
+        -> Result<Option<Property>, Error> {
+        unsupported("An enum")
+    }
+    fn serialize_seq(self, len: Option<usize>) -> Result<ListSerializer, Error> {
+        Ok(ListSerializer(Vec::with_capacity(len.unwrap_or(0))))
+    }
+    fn serialize_tuple(self, len: usize) -> Result<ListSerializer, Error> {
+        Ok(ListSerializer(Vec::with_capacity(len)))
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<ListSerializer, Error> {
+        Ok(ListSerializer(Vec::with_capacity(len)))
+    }
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleVariant, Error> {
+        unsupported("An enum")
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> { unsupported("A map") }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, Error> {
+        unsupported("A nested struct")
+    }
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+        -> Result<Self::SerializeStructVariant, Error> {
+        unsupported("An enum")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::{ Header, Payload };
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Vertex {
+        x: f32,
+        y: f64,
+        red: u8,
+        normal: Option<[f32; 3]>,
+    }
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Face {
+        vertex_indices: Vec<i32>,
+    }
+    #[test]
+    fn roundtrip() {
+        let v = Vertex { x: 1.5, y: -2.0, red: 255, normal: Some([0.0, 0.0, 1.0]) };
+        let e = to_element(&v).unwrap();
+        assert_eq!(e["normal"], Property::ListFloat(vec![0.0, 0.0, 1.0]));
+        assert_eq!(e["red"], Property::UChar(255));
+        assert_eq!(from_element::<Vertex>(&e).unwrap(), v);
+
+        let v = Vertex { normal: None, ..v };
+        let e = to_element(&v).unwrap();
+        assert!(!e.contains_key("normal"));
+        let vertices = from_elements::<Vertex>(&[e.clone(), e]).unwrap();
+        assert_eq!(vertices.len(), 2);
+        assert_eq!(vertices[1], v);
+        let f = Face { vertex_indices: vec![0, 1, 2] };
+        let mut payload = Payload::new();
+        payload.insert("face".to_string(), vec![to_element(&f).unwrap()]);
+        assert!(Header::infer_from_payload(&payload).is_ok());
+        assert_eq!(from_elements::<Face>(&payload["face"]).unwrap(), vec![f]);
+    }
+    #[test]
+    fn type_mismatch_err() {
+        let mut e = DefaultElement::new();
+        e.insert("vertex_indices".to_string(), Property::ListFloat(vec![0.5]));
+        assert!(from_element::<Face>(&e).is_err());
+        assert!(from_element::<Vertex>(&e).is_err());
+        assert!(to_element(&1u64).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Randomizes the order of the entries of an element without loading the whole file, e.g. for training pipelines.
+//!
+//! While streaming the source, every entry is appended to a randomly chosen shard,
+//! kept in a temporary file next to the shard's destination.
+//! Each shard is then loaded, shuffled and written as a PLY file of its own.
+//! Only one shard is held in memory at a time, more shards lower the memory needed.
+
+use std::io;
+use std::io::{ Read, Seek, SeekFrom, BufReader, BufWriter, Result, ErrorKind };
+use std::path::Path;
+
+use parser::Parser;
+use writer::Writer;
+use ply::{ Ply, KeyMap, PropertyAccess, Addable };
+use util::{ create_temp_file, TempFiles };
+
+/// Shuffles the entries of `element` read from `source` into one PLY file per path in `paths`.
+///
+/// Every entry ends up at a random position of a random shard, the same `seed` yields the same shards.
+/// The shards get the header of the source restricted to `element`, other elements are skipped.
+///
+/// Returns the number of entries written to each shard.
+pub fn shuffle_to_shards<E, R, P>(parser: &Parser<E>, writer: &Writer<E>, source: R, element: &str, seed: u64, paths: &[P]) -> Result<Vec<u64>>
+    where E: PropertyAccess, R: Read, P: AsRef<Path> {
+    if paths.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "At least one shard is needed."));
+    }
+    let mut rng = SplitMix64(seed);
+    let mut reader = try!(parser.read_ply_iter(source));
+    let mut shard_header = reader.header().clone();
+    let element_def = match shard_header.elements.get(element) {
+        Some(d) => d.clone(),
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("The source has no element `{}`.", element))),
+    };
+    shard_header.elements = KeyMap::new();
+    shard_header.elements.add(element_def.clone());
+
+    let mut scratch = TempFiles(Vec::with_capacity(paths.len()));
+    let mut shards = Vec::with_capacity(paths.len());
+    for path in paths {
+        let (tmp_path, file) = try!(create_temp_file(path.as_ref()));
+        scratch.0.push(tmp_path);
+        shards.push((BufWriter::new(file), 0u64));
+    }
+    while let Some(entries) = try!(reader.next_element()) {
+        if entries.element_def().name != element {
+            continue;
+        }
+        for entry in entries {
+            let shard = &mut shards[rng.below(paths.len() as u64) as usize];
+            try!(writer.write_little_endian_element(&mut shard.0, &try!(entry), &element_def));
+            shard.1 += 1;
+        }
+    }
+
+    // Shards only hold what was just written, decoders and derived properties of `parser` must not apply again.
+    let shard_parser = Parser::<E>::new();
+    let mut counts = Vec::with_capacity(paths.len());
+    for ((out, count), path) in shards.into_iter().zip(paths) {
+        let mut file = try!(out.into_inner().map_err(|e| e.into_error()));
+        try!(file.seek(SeekFrom::Start(0)));
+        let mut input = BufReader::new(file);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            entries.push(try!(shard_parser.read_little_endian_element(&mut input, &element_def)));
+        }
+        for i in (1..entries.len()).rev() {
+            let j = rng.below(i as u64 + 1) as usize;
+            entries.swap(i, j);
+        }
+        let mut ply = Ply::new();
+        ply.header = shard_header.clone();
+        ply.header.elements[element].count = count;
+        ply.payload.insert(element.to_string(), entries);
+        try!(writer.write_ply_file(path, &mut ply));
+        counts.push(count);
+    }
+    Ok(counts)
+}
+
+/// Small deterministic random number generator (SplitMix64), good enough for shuffling.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    /// Uniformly distributed in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        ((self.next() as u128 * n as u128) >> 64) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use ply::{ DefaultElement, Property };
+
+    fn source() -> String {
+        let mut s = "ply\nformat ascii 1.0\nelement camera 1\nproperty float f\nelement vertex 100\nproperty int id\nend_header\n1.5\n".to_string();
+        for i in 0..100 {
+            s.push_str(&format!("{}\n", i));
+        }
+        s
+    }
+
+    fn shard_ids(dir: &Path, seed: u64) -> Vec<Vec<i32>> {
+        let paths: Vec<PathBuf> = (0..3).map(|i| dir.join(format!("shard_{}.ply", i))).collect();
+        let (p, w) = (Parser::<DefaultElement>::new(), Writer::new());
+        let counts = shuffle_to_shards(&p, &w, source().as_bytes(), "vertex", seed, &paths).unwrap();
+        assert_eq!(counts.iter().sum::<u64>(), 100);
+        paths.iter().zip(counts).map(|(path, count)| {
+            let ply = p.read_ply(&mut fs::File::open(path).unwrap()).unwrap();
+            assert_eq!(ply.header.elements.keys().collect::<Vec<_>>(), vec!["vertex"]);
+            assert_eq!(ply.payload["vertex"].len() as u64, count);
+            ply.payload["vertex"].iter().map(|v| match v["id"] {
+                Property::Int(id) => id,
+                ref p => panic!("Unexpected {:?}", p),
+            }).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn shuffle_is_permutation() {
+        let dir = env::temp_dir().join("ply_rs_shuffle");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let shards = shard_ids(&dir, 7);
+        assert_eq!(shard_ids(&dir, 7), shards);
+        let mut ids: Vec<i32> = shards.iter().flat_map(|s| s.iter().cloned()).collect();
+        assert!(ids != (0..100).collect::<Vec<_>>());
+        ids.sort();
+        assert_eq!(ids, (0..100).collect::<Vec<_>>());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn unknown_element_err() {
+        let (p, w) = (Parser::<DefaultElement>::new(), Writer::new());
+        let path = env::temp_dir().join("ply_rs_shuffle_none.ply");
+        assert!(shuffle_to_shards(&p, &w, source().as_bytes(), "face", 1, &[&path]).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,345 @@
+//! Summary statistics of the scalar properties of an element, e.g. for quality checks of scans.
+//!
+//! Values are collected entry by entry: `from_elements()` summarizes a payload in memory,
+//! `from_stream()` the entries of a `PlyReader`, which also works for files larger than the available memory.
+//! For checking rules while streaming, see `validate::Validator::screen()`.
+//!
+//! `histogram()` and `normalize()` work on a single property of a payload in memory,
+//! e.g. to inspect and rescale lidar intensities, whose range differs between datasets.
+
+use std::f64;
+use std::io;
+
+use parser::ElementEntries;
+use util::scalar_value;
+use ply::{ ElementDef, PropertyDef, PropertyAccess, PropertyType, ScalarType, Property, KeyMap, ConsistencyError };
+
+/// Statistics of the values of a scalar property.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PropertyStats {
+    /// Number of values, NaNs excluded.
+    pub count: u64,
+    /// Number of NaN values, they are left out of all other fields.
+    pub nan_count: u64,
+    /// Smallest value, `f64::INFINITY` if there is none.
+    pub min: f64,
+    /// Largest value, `f64::NEG_INFINITY` if there is none.
+    pub max: f64,
+    /// Sum of all values.
+    pub sum: f64,
+}
+
+impl PropertyStats {
+    fn new() -> Self {
+        PropertyStats {
+            count: 0,
+            nan_count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+        }
+    }
+    fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            self.nan_count += 1;
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value;
+    }
+    /// Mean of the values, `None` if there are none.
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum / self.count as f64)
+        }
+    }
+}
+
+/// Statistics of the scalar properties of an element, in the order of its definition.
+pub type ElementStats = KeyMap<PropertyStats>;
+
+/// Collects statistics entry by entry, for use in a pass over the data that does more than that.
+///
+/// List properties are left out, as are entries lacking a value of the declared type.
+pub struct StatsCollector {
+    properties: Vec<PropertyDef>,
+    stats: Vec<PropertyStats>,
+}
+
+impl StatsCollector {
+    /// Creates a collector for the scalar properties of `element_def`.
+    pub fn new(element_def: &ElementDef) -> Self {
+        let properties: Vec<PropertyDef> = element_def.properties.values()
+            .filter(|p| match p.data_type { PropertyType::Scalar(_) => true, PropertyType::List(_, _) => false })
+            .cloned()
+            .collect();
+        let stats = properties.iter().map(|_| PropertyStats::new()).collect();
+        StatsCollector {
+            properties: properties,
+            stats: stats,
+        }
+    }
+    /// Adds the values of `entry`.
+    pub fn add<E: PropertyAccess>(&mut self, entry: &E) {
+        for (property_def, stats) in self.properties.iter().zip(self.stats.iter_mut()) {
+            if let Some(value) = scalar_value(entry, property_def) {
+                stats.add(value);
+            }
+        }
+    }
+    /// Returns the statistics of all entries added.
+    pub fn finish(self) -> ElementStats {
+        let mut element_stats = ElementStats::new();
+        for (property_def, stats) in self.properties.into_iter().zip(self.stats.into_iter()) {
+            element_stats.insert(property_def.name, stats);
+        }
+        element_stats
+    }
+}
+
+/// Computes the statistics of `entries`, described by `element_def`.
+pub fn from_elements<E: PropertyAccess>(element_def: &ElementDef, entries: &[E]) -> ElementStats {
+    let mut collector = StatsCollector::new(element_def);
+    for entry in entries {
+        collector.add(entry);
+    }
+    collector.finish()
+}
+
+/// Computes the statistics of the entries read from a `PlyReader`, holding only one entry at a time.
+///
+/// Fails with the first entry that can't be read.
+pub fn from_stream<E: PropertyAccess>(entries: ElementEntries<E>) -> io::Result<ElementStats> {
+    let mut collector = StatsCollector::new(entries.element_def());
+    for entry in entries {
+        collector.add(&try!(entry));
+    }
+    Ok(collector.finish())
+}
+
+/// Axis aligned bounding box of the `x`, `y` and `z` properties, as minimum and maximum corner.
+///
+/// `None` if one of them is missing or has no values.
+pub fn bounding_box(stats: &ElementStats) -> Option<([f64; 3], [f64; 3])> {
+    let mut min = [0.0; 3];
+    let mut max = [0.0; 3];
+    for (i, name) in ["x", "y", "z"].iter().enumerate() {
+        match stats.get(*name) {
+            Some(s) if s.count > 0 => {
+                min[i] = s.min;
+                max[i] = s.max;
+            },
+            _ => return None,
+        }
+    }
+    Some((min, max))
+}
+
+/// Number of values in equally wide bins between the smallest and the largest value of a property.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Lower bound of the first bin, `f64::INFINITY` if there are no values.
+    pub min: f64,
+    /// Upper bound of the last bin, which is the only bin including its upper bound.
+    pub max: f64,
+    /// Number of values per bin, NaNs are left out.
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    /// Width of each bin.
+    pub fn bin_width(&self) -> f64 {
+        (self.max - self.min) / self.counts.len() as f64
+    }
+}
+
+/// Counts the values of the scalar `property` of `entries` in `bins` bins, described by `element_def`.
+///
+/// Fails if `bins` is zero or the property is missing or a list.
+pub fn histogram<E: PropertyAccess>(element_def: &ElementDef, entries: &[E], property: &str, bins: usize) -> Result<Histogram, ConsistencyError> {
+    if bins == 0 {
+        return Err(ConsistencyError::new("A histogram needs at least one bin."));
+    }
+    let values = try!(scalar_values(element_def, entries, property));
+    let mut stats = PropertyStats::new();
+    for &v in &values {
+        stats.add(v);
+    }
+    let mut counts = vec![0; bins];
+    for v in values.into_iter().filter(|v| !v.is_nan()) {
+        let bin = if stats.max > stats.min {
+            ((v - stats.min) / (stats.max - stats.min) * bins as f64) as usize
+        } else {
+            0
+        };
+        counts[bin.min(bins - 1)] += 1;
+    }
+    Ok(Histogram {
+        min: stats.min,
+        max: stats.max,
+        counts: counts,
+    })
+}
+
+/// How `normalize()` determines the range of values mapped onto the full output range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    /// From the smallest to the largest value.
+    MinMax,
+    /// Between two percentiles, in the range 0 to 100. Values outside are clipped.
+    ///
+    /// Unlike `MinMax`, a few outliers don't compress the range of all other values.
+    PercentileClip(f64, f64),
+}
+
+/// Rescales the scalar `property` of `entries`, described by `element_def`, and writes the values back.
+///
+/// The range selected by `normalization` is mapped onto 0 to 1 for `float` and `double`,
+/// and onto 0 to the largest value of the type for integers, e.g. 0 to 65535 for `ushort` intensities.
+/// The type of the property is kept, NaNs and entries lacking a value are left unchanged. If all values are equal, they become 0.
+/// Fails without changing anything if the property is missing or a list, or if the percentiles are invalid.
+pub fn normalize<E: PropertyAccess>(element_def: &ElementDef, entries: &mut [E], property: &str, normalization: Normalization) -> Result<(), ConsistencyError> {
+    let values = try!(scalar_values(element_def, entries, property));
+    let mut sorted: Vec<f64> = values.iter().cloned().filter(|v| !v.is_nan()).collect();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let (low, high) = match normalization {
+        Normalization::MinMax => (percentile(&sorted, 0.0), percentile(&sorted, 100.0)),
+        Normalization::PercentileClip(lower, upper) => {
+            if !(0.0 <= lower && lower <= upper && upper <= 100.0) {
+                return Err(ConsistencyError::new(&format!("Invalid percentiles {} and {}, expected 0 <= lower <= upper <= 100.", lower, upper)));
+            }
+            (percentile(&sorted, lower), percentile(&sorted, upper))
+        },
+    };
+    let scalar_type = match element_def.properties[property].data_type {
+        PropertyType::Scalar(ref t) => t.clone(),
+        PropertyType::List(_, _) => unreachable!(),
+    };
+    let scale = if scalar_type.is_integer() { scalar_type.max_value() } else { 1.0 };
+    for (entry, v) in entries.iter_mut().zip(values) {
+        if v.is_nan() {
+            continue;
+        }
+        let normalized = if high > low { (v.max(low).min(high) - low) / (high - low) } else { 0.0 };
+        entry.set_property(property.to_string(), scaled_property(normalized * scale, &scalar_type));
+    }
+    Ok(())
+}
+
+/// Values of the scalar `property` in all `entries`, NaN where an entry lacks a value of the declared type.
+fn scalar_values<E: PropertyAccess>(element_def: &ElementDef, entries: &[E], property: &str) -> Result<Vec<f64>, ConsistencyError> {
+    let property_def = match element_def.properties.get(property) {
+        Some(p) => p,
+        None => return Err(ConsistencyError::new(&format!("Element `{}` has no property `{}`.", element_def.name, property))),
+    };
+    if let PropertyType::List(_, _) = property_def.data_type {
+        return Err(ConsistencyError::new(&format!("Property `{}` of `{}` is a list, expected a scalar.", property, element_def.name)));
+    }
+    Ok(entries.iter().map(|e| scalar_value(e, property_def).unwrap_or(f64::NAN)).collect())
+}
+
+/// Linearly interpolated percentile `p` of `sorted`, NaN if it is empty.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let position = p / 100.0 * (sorted.len() - 1) as f64;
+    let i = position.floor() as usize;
+    match sorted.get(i + 1) {
+        Some(next) => sorted[i] + (next - sorted[i]) * (position - i as f64),
+        None => sorted[i],
+    }
+}
+
+/// `value`, within the range of `scalar_type`, as a property of that type, rounded for integers.
+fn scaled_property(value: f64, scalar_type: &ScalarType) -> Property {
+    match *scalar_type {
+        ScalarType::Char => Property::Char(value.round() as i8),
+        ScalarType::UChar => Property::UChar(value.round() as u8),
+        ScalarType::Short => Property::Short(value.round() as i16),
+        ScalarType::UShort => Property::UShort(value.round() as u16),
+        ScalarType::Int => Property::Int(value.round() as i32),
+        ScalarType::UInt => Property::UInt(value.round() as u32),
+        ScalarType::Float => Property::Float(value as f32),
+        ScalarType::Double => Property::Double(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+    use ply::{ DefaultElement, Property, Addable };
+
+    const PLY: &'static str = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+        property uchar intensity\nproperty list uchar int n\nend_header\n\
+        0 1 2 10 0\n-1 4 1.5 20 1 7\n3 2 1 30 0\n";
+
+    #[test]
+    fn stream_matches_memory() {
+        let p = Parser::<DefaultElement>::new();
+        let ply = p.read_ply(&mut PLY.as_bytes()).unwrap();
+        let in_memory = from_elements(&ply.header.elements["vertex"], &ply.payload["vertex"]);
+        let mut reader = p.read_ply_iter(PLY.as_bytes()).unwrap();
+        let streamed = from_stream(reader.next_element().unwrap().unwrap()).unwrap();
+        assert_eq!(streamed, in_memory);
+        assert_eq!(streamed.keys().cloned().collect::<Vec<_>>(), vec!["x", "y", "z", "intensity"]);
+        assert_eq!(streamed["z"].count, 3);
+        assert_eq!(streamed["intensity"].mean(), Some(20.0));
+        assert_eq!(bounding_box(&streamed), Some(([-1.0, 1.0, 1.0], [3.0, 4.0, 2.0])));
+    }
+    #[test]
+    fn nan_and_empty() {
+        let mut element_def = ElementDef::new("vertex".to_string());
+        element_def.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        let entries: Vec<DefaultElement> = [1.0, f32::NAN, 3.0].iter().map(|&x| {
+            let mut e = DefaultElement::new();
+            e.insert("x".to_string(), Property::Float(x));
+            e
+        }).collect();
+        let stats = from_elements(&element_def, &entries);
+        assert_eq!((stats["x"].count, stats["x"].nan_count, stats["x"].mean()), (2, 1, Some(2.0)));
+        let mut normalized = entries.clone();
+        normalize(&element_def, &mut normalized, "x", Normalization::MinMax).unwrap();
+        assert_eq!(normalized[2]["x"], Property::Float(1.0));
+        assert!(normalized[1].get_float(&"x".to_string()).unwrap().is_nan());
+        assert_eq!(histogram(&element_def, &entries, "x", 2).unwrap().counts, vec![1, 1]);
+        let stats = from_elements::<DefaultElement>(&element_def, &[]);
+        assert_eq!(stats["x"].mean(), None);
+        assert_eq!(bounding_box(&stats), None);
+    }
+    #[test]
+    fn histogram_bins() {
+        let ply = Parser::<DefaultElement>::new().read_ply(&mut PLY.as_bytes()).unwrap();
+        let element_def = &ply.header.elements["vertex"];
+        let h = histogram(element_def, &ply.payload["vertex"], "intensity", 4).unwrap();
+        assert_eq!((h.min, h.max, h.bin_width()), (10.0, 30.0, 5.0));
+        assert_eq!(h.counts, vec![1, 0, 1, 1]);
+        assert_eq!(histogram(element_def, &ply.payload["vertex"], "x", 1).unwrap().counts, vec![3]);
+        assert!(histogram(element_def, &ply.payload["vertex"], "intensity", 0).is_err());
+        assert!(histogram(element_def, &ply.payload["vertex"], "n", 2).is_err());
+        assert!(histogram(element_def, &ply.payload["vertex"], "w", 2).is_err());
+    }
+    #[test]
+    fn normalize_writes_back() {
+        let mut ply = Parser::<DefaultElement>::new().read_ply(&mut PLY.as_bytes()).unwrap();
+        let element_def = ply.header.elements["vertex"].clone();
+        let vertices = ply.payload.get_mut("vertex").unwrap();
+        normalize(&element_def, vertices, "intensity", Normalization::MinMax).unwrap();
+        let intensities: Vec<Property> = vertices.iter().map(|v| v["intensity"].clone()).collect();
+        assert_eq!(intensities, vec![Property::UChar(0), Property::UChar(128), Property::UChar(255)]);
+
+        normalize(&element_def, vertices, "z", Normalization::PercentileClip(0.0, 50.0)).unwrap();
+        let z: Vec<Property> = vertices.iter().map(|v| v["z"].clone()).collect();
+        assert_eq!(z, vec![Property::Float(1.0), Property::Float(1.0), Property::Float(0.0)]);
+
+        let before = vertices.clone();
+        assert!(normalize(&element_def, vertices, "x", Normalization::PercentileClip(60.0, 50.0)).is_err());
+        assert!(normalize(&element_def, vertices, "n", Normalization::MinMax).is_err());
+        assert_eq!(*vertices, before);
+    }
+}
@@ -0,0 +1,243 @@
+//! Works on raw payload bytes, without building elements where possible.
+
+use std::io;
+use std::io::{ Read, BufRead, Write, Result, ErrorKind };
+
+use parser::Parser;
+use raw_payload::{ pass_fixed, pass_entries, pass_element };
+use writer::Writer;
+use util::split_crc_obj_info;
+use ply::{ Ply, Header, PropertyAccess, DefaultElement, Encoding };
+
+/// Converts a binary payload from big to little endian or vice versa.
+///
+/// Reads the payload described by `header` from `reader`, which must be positioned right after `end_header`,
+/// and writes it with every value byte-swapped to `writer`.
+/// No elements are constructed, elements without lists are swapped in large chunks.
+/// The header itself is not written, write it with the opposite encoding before, e.g. with `Writer::write_header()`.
+///
+/// Pass a buffered `reader` and `writer` for good performance.
+/// Fails for ascii payloads.
+/// Lists are streamed in bounded chunks, use `Parser::swap_endianness()` to also limit their length.
+///
+/// Returns number of bytes written.
+pub fn swap_endianness<R: Read, W: Write>(reader: &mut R, writer: &mut W, header: &Header) -> Result<u64> {
+    Parser::<DefaultElement>::new().swap_endianness(reader, writer, header)
+}
+
+impl<E: PropertyAccess> Parser<E> {
+    /// Same as `transcode::swap_endianness()`, but fails on lists longer than `max_list_len()`.
+    pub fn swap_endianness<R: Read, W: Write>(&self, reader: &mut R, writer: &mut W, header: &Header) -> Result<u64> {
+        swap_payload(reader, writer, header, self.max_list_len())
+    }
+}
+
+fn swap_payload<R: Read, W: Write>(reader: &mut R, writer: &mut W, header: &Header, max_list_len: u64) -> Result<u64> {
+    let big_endian = match header.encoding {
+        Encoding::BinaryBigEndian => true,
+        Encoding::BinaryLittleEndian => false,
+        Encoding::Ascii => return Err(io::Error::new(ErrorKind::InvalidInput, "Only binary payloads can be byte-swapped.")),
+    };
+    let mut written = 0;
+    let mut buffer = Vec::new();
+    for (_, element_def) in &header.elements {
+        let sizes: Option<Vec<usize>> = element_def.properties.values().map(|p| p.data_type.fixed_record_size()).collect();
+        written += try!(match sizes {
+            Some(sizes) => pass_fixed(reader, writer, element_def, &sizes, true, &mut buffer),
+            None => pass_entries(reader, writer, element_def, big_endian, true, None, max_list_len, &mut buffer),
+        });
+    }
+    try!(writer.flush());
+    Ok(written)
+}
+
+/// Writes `ply` to `out`, copying the payload of unchanged elements from `source` instead of encoding it again.
+///
+/// Speeds up saving when only some elements of a large file were modified.
+/// `source` must be the file `ply` was read from, positioned at its start.
+/// Elements named in `changed` are encoded from the payload of `ply`,
+/// all other elements are copied byte by byte and must have the same definition, count included, in both headers.
+/// Encoding, and the order of the copied elements, must be those of the source,
+/// elements may be dropped or added as long as added ones are listed in `changed`.
+///
+/// `ply` is written as is, make it consistent beforehand.
+/// Only checksums recorded by `Writer::set_element_crcs()` for changed elements are dropped, as they no longer hold.
+///
+/// Returns number of bytes written.
+pub fn rewrite_changed<R, W, E>(source: &mut R, out: &mut W, ply: &Ply<E>, changed: &[&str]) -> Result<u64>
+    where R: BufRead, W: Write, E: PropertyAccess {
+    let parser = Parser::<E>::new();
+    let source_header = try!(parser.read_header(source));
+    if source_header.encoding != ply.header.encoding {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Encoding differs from the source, all elements would change."));
+    }
+    let w = Writer::<E>::new();
+    let mut header = ply.header.clone();
+    header.obj_infos.retain(|o| match split_crc_obj_info(o) {
+        Some((element, _)) => !changed.contains(&element),
+        None => true,
+    });
+    let mut written = try!(w.write_header(out, &header)) as u64;
+    let mut source_elements = source_header.elements.iter();
+    let mut buffer = Vec::new();
+    for (name, element_def) in &ply.header.elements {
+        if changed.contains(&name.as_ref()) {
+            let entries = match ply.payload.get(name) {
+                Some(e) => &e[..],
+                None => &[],
+            };
+            written += try!(w.write_payload_for_element(out, entries, element_def, &ply.header)) as u64;
+            continue;
+        }
+        if source_header.elements.get(name) != Some(element_def) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` is not marked as changed, but its definition differs from the source.", name)
+            ));
+        }
+        // Skip source elements until we arrive at the one to copy.
+        loop {
+            let (source_name, source_def) = match source_elements.next() {
+                Some(e) => e,
+                None => return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Element `{}` is out of the order of the source.", name)
+                )),
+            };
+            if source_name == name {
+                written += try!(pass_element(source, out, source_def, &source_header.encoding, parser.max_list_len(), &mut buffer));
+                break;
+            }
+            try!(pass_element(source, &mut io::sink(), source_def, &source_header.encoding, parser.max_list_len(), &mut buffer));
+        }
+    }
+    try!(out.flush());
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    fn ply() -> Ply<DefaultElement> {
+        let mut p = Ply::<DefaultElement>::new();
+        p.header.encoding = Encoding::BinaryBigEndian;
+        let mut v = ElementDef::new("vertex".to_string());
+        v.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        v.properties.add(PropertyDef::new("c".to_string(), PropertyType::Scalar(ScalarType::UChar)));
+        p.header.elements.add(v);
+        let mut f = ElementDef::new("face".to_string());
+        f.properties.add(PropertyDef::new("i".to_string(), PropertyType::List(ScalarType::Short, ScalarType::Int)));
+        f.properties.add(PropertyDef::new("d".to_string(), PropertyType::Scalar(ScalarType::Double)));
+        p.header.elements.add(f);
+        let vertices = (0..5000).map(|i| {
+            let mut e = DefaultElement::new();
+            e.insert("x".to_string(), Property::Float(i as f32 * 0.5));
+            e.insert("c".to_string(), Property::UChar(i as u8));
+            e
+        }).collect();
+        p.payload.insert("vertex".to_string(), vertices);
+        let mut face = DefaultElement::new();
+        face.insert("i".to_string(), Property::ListInt(vec![1, -2, 300]));
+        face.insert("d".to_string(), Property::Double(-1.25));
+        p.payload.insert("face".to_string(), vec![face.clone(), face]);
+        p
+    }
+    fn payload(p: &mut Ply<DefaultElement>) -> Vec<u8> {
+        let w = Writer::new();
+        let mut buf = Vec::new();
+        w.write_ply(&mut buf, p).unwrap();
+        let header_len = w.write_header(&mut Vec::new(), &p.header).unwrap();
+        buf.split_off(header_len)
+    }
+    #[test]
+    fn swap_roundtrip() {
+        let mut p = ply();
+        let big = payload(&mut p);
+        let header = p.header.clone();
+        p.header.encoding = Encoding::BinaryLittleEndian;
+        let little = payload(&mut p);
+        let mut swapped = Vec::new();
+        assert_eq!(swap_endianness(&mut &big[..], &mut swapped, &header).unwrap(), big.len() as u64);
+        assert_eq!(swapped, little);
+        let mut back = Vec::new();
+        swap_endianness(&mut &little[..], &mut back, &p.header).unwrap();
+        assert_eq!(back, big);
+        assert!(swap_endianness(&mut &big[..big.len() - 1], &mut Vec::new(), &header).is_err());
+        p.header.encoding = Encoding::Ascii;
+        assert!(swap_endianness(&mut &big[..], &mut Vec::new(), &p.header).is_err());
+    }
+    #[test]
+    fn swap_long_list() {
+        let mut p = Ply::<DefaultElement>::new();
+        p.header.encoding = Encoding::BinaryBigEndian;
+        let mut e = ElementDef::new("l".to_string());
+        e.properties.add(PropertyDef::new("v".to_string(), PropertyType::List(ScalarType::UInt, ScalarType::Int)));
+        p.header.elements.add(e);
+        let mut entry = DefaultElement::new();
+        entry.insert("v".to_string(), Property::ListInt((0..20000).collect()));
+        p.payload.insert("l".to_string(), vec![entry]);
+        let big = payload(&mut p);
+        let header = p.header.clone();
+        p.header.encoding = Encoding::BinaryLittleEndian;
+        let little = payload(&mut p);
+        let mut swapped = Vec::new();
+        swap_endianness(&mut &big[..], &mut swapped, &header).unwrap();
+        assert_eq!(swapped, little);
+
+        let mut parser = Parser::<DefaultElement>::new();
+        parser.set_max_list_len(10000);
+        assert!(parser.swap_endianness(&mut &big[..], &mut Vec::new(), &header).is_err());
+        let mut huge = big[..4].to_vec();
+        huge[..4].copy_from_slice(&[0xff; 4]);
+        assert!(swap_endianness(&mut &huge[..], &mut Vec::new(), &header).is_err());
+    }
+    #[test]
+    fn rewrite_copies_unchanged() {
+        for encoding in &[Encoding::Ascii, Encoding::BinaryLittleEndian] {
+            let mut p = ply();
+            p.header.encoding = *encoding;
+            let mut source = Vec::new();
+            Writer::new().write_ply(&mut source, &mut p).unwrap();
+            p.payload.get_mut("face").unwrap()[1].insert("d".to_string(), Property::Double(7.5));
+            let mut out = Vec::new();
+            let written = rewrite_changed(&mut &source[..], &mut out, &p, &["face"]).unwrap();
+            assert_eq!(written, out.len() as u64);
+            let mut expected = Vec::new();
+            Writer::new().write_ply(&mut expected, &mut p).unwrap();
+            assert_eq!(out, expected);
+            let faces_only = {
+                let mut f = p.clone();
+                f.header.elements.remove("vertex");
+                f.payload.remove("vertex");
+                f
+            };
+            assert!(rewrite_changed(&mut &source[..], &mut Vec::new(), &faces_only, &[]).is_ok());
+            assert!(rewrite_changed(&mut &source[..], &mut Vec::new(), &p, &[]).is_ok());
+            p.header.elements.get_mut("vertex").unwrap().count += 1;
+            assert!(rewrite_changed(&mut &source[..], &mut Vec::new(), &p, &["face"]).is_err());
+        }
+    }
+    #[test]
+    fn rewrite_drops_changed_crcs() {
+        let mut w = Writer::new();
+        w.set_element_crcs(true);
+        let mut source = Vec::new();
+        w.write_ply(&mut source, &mut ply()).unwrap();
+        let mut parser = Parser::<DefaultElement>::new();
+        parser.set_verify_element_crcs(true);
+        let mut p = parser.read_ply(&mut &source[..]).unwrap();
+        p.payload.get_mut("face").unwrap()[1].insert("d".to_string(), Property::Double(7.5));
+        let mut out = Vec::new();
+        rewrite_changed(&mut &source[..], &mut out, &p, &["face"]).unwrap();
+        let mut reader = parser.read_ply_iter(&out[..]).unwrap();
+        let crcs: Vec<_> = reader.header().obj_infos.iter().filter(|o| o.starts_with("crc32 ")).cloned().collect();
+        assert_eq!(crcs.len(), 1);
+        assert!(crcs[0].starts_with("crc32 vertex "));
+        while let Some(entries) = reader.next_element().unwrap() {
+            for entry in entries {
+                entry.unwrap();
+            }
+        }
+    }
+}
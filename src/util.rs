@@ -1,7 +1,7 @@
 
 #[derive(Debug, Clone, Copy)]
 pub struct LocationTracker {
-    pub line_index: usize
+    pub line_index: u64
 }
 impl LocationTracker {
     pub fn new() -> Self {
@@ -13,3 +13,182 @@ impl LocationTracker {
         self.line_index += 1;
     }
 }
+
+use std::fs;
+use std::fs::{ File, OpenOptions };
+use std::io;
+use std::io::{ Result, ErrorKind };
+use std::path::{ Path, PathBuf };
+use std::process;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use ply::{ PropertyAccess, PropertyDef, PropertyType, ScalarType };
+
+/// Distinguishes the temporary files of concurrent writes within one process.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes the file at `path` with `write` through a temporary file in the same directory,
+/// which is renamed over `path` once `write` succeeded.
+///
+/// The temporary file gets a name no other write uses and the permissions of the file it replaces.
+/// It is removed if anything fails, `path` is left untouched then.
+pub fn write_file_atomically<T, F>(path: &Path, write: F) -> Result<T>
+    where F: FnOnce(&mut File) -> Result<T> {
+    let (tmp_path, mut file) = try!(create_temp_file(path));
+    let result = copy_permissions(path, &file)
+        .and_then(|_| write(&mut file))
+        .and_then(|value| file.sync_all().map(|_| value));
+    drop(file);
+    let result = result.and_then(|value| fs::rename(&tmp_path, path).map(|_| value));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Creates a new file next to `path` under a name no other call uses, for reading and writing.
+///
+/// Returns its path, the caller is responsible for removing it.
+pub fn create_temp_file(path: &Path) -> Result<(PathBuf, File)> {
+    let name = match path.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("`{}` doesn't name a file.", path.display()))),
+    };
+    loop {
+        let tmp_name = format!(".{}.{}-{}.tmp", name, process::id(), TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed));
+        let tmp_path = path.with_file_name(tmp_name);
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(ref e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Removes the files at the paths it holds once dropped, also when an error left them behind.
+#[derive(Debug)]
+pub struct TempFiles(pub Vec<PathBuf>);
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Writes the file at `path` with `write` directly, creating or truncating it.
+///
+/// If `write` fails, the partial output is removed.
+/// Anything but a regular file, e.g. a device or named pipe, is neither synced nor removed.
+pub fn write_file_in_place<T, F>(path: &Path, write: F) -> Result<T>
+    where F: FnOnce(&mut File) -> Result<T> {
+    let special = fs::metadata(path).map(|m| !m.is_file()).unwrap_or(false);
+    let mut file = try!(File::create(path));
+    let result = write(&mut file).and_then(|value| {
+        if !special {
+            try!(file.sync_all());
+        }
+        Ok(value)
+    });
+    drop(file);
+    if result.is_err() && !special {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// Gives `file` the permissions of the regular file at `path`, if there is one.
+fn copy_permissions(path: &Path, file: &File) -> Result<()> {
+    match fs::metadata(path) {
+        Ok(ref m) if m.is_file() => file.set_permissions(m.permissions()),
+        _ => Ok(()),
+    }
+}
+
+/// Key of the `obj_info` lines holding the checksum of an element block: `obj_info crc32 <element> <hex>`.
+pub const CRC_OBJ_INFO: &'static str = "crc32";
+
+/// Element name and checksum text of an `obj_info` line with the key `CRC_OBJ_INFO`, `None` for other lines.
+pub fn split_crc_obj_info(obj_info: &str) -> Option<(&str, &str)> {
+    let mut parts = obj_info.split_whitespace();
+    if parts.next() != Some(CRC_OBJ_INFO) {
+        return None;
+    }
+    Some((parts.next().unwrap_or(""), parts.next().unwrap_or("")))
+}
+
+/// CRC-32 as used by zlib and PNG (IEEE polynomial, reflected), computed incrementally.
+///
+/// As a `Write` target it hashes everything written to it and discards the bytes.
+pub struct Crc32 {
+    table: [u32; 256],
+    value: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        Crc32 {
+            table: table,
+            value: 0xFFFF_FFFF,
+        }
+    }
+    pub fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.value = self.table[((self.value ^ *b as u32) & 0xFF) as usize] ^ (self.value >> 8);
+        }
+    }
+    /// Checksum of all bytes seen so far.
+    pub fn value(&self) -> u32 {
+        self.value ^ 0xFFFF_FFFF
+    }
+}
+
+impl io::Write for Crc32 {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Value of the scalar property `property_def` of `entry` as `f64`, `None` for lists and missing values.
+pub fn scalar_value<E: PropertyAccess>(entry: &E, property_def: &PropertyDef) -> Option<f64> {
+    let k = &property_def.name;
+    match property_def.data_type {
+        PropertyType::Scalar(ScalarType::Char) => entry.get_char(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::UChar) => entry.get_uchar(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::Short) => entry.get_short(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::UShort) => entry.get_ushort(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::Int) => entry.get_int(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::UInt) => entry.get_uint(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::Float) => entry.get_float(k).map(|v| v as f64),
+        PropertyType::Scalar(ScalarType::Double) => entry.get_double(k),
+        PropertyType::List(_, _) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        assert_eq!(crc.value(), 0);
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.value(), 0xCBF4_3926);
+        assert_eq!(split_crc_obj_info("crc32 vertex 0a1b2c3d"), Some(("vertex", "0a1b2c3d")));
+        assert_eq!(split_crc_obj_info("crc32s vertex 0"), None);
+    }
+}
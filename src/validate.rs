@@ -0,0 +1,405 @@
+//! Checks a `Ply` against user defined rules, for example to enforce the contract of a dataset in CI.
+//!
+//! Besides the registered rules, the header checks of `Header::check()` and `faces::validate_indices()` are run.
+//! Ingestion jobs can set broken entries aside instead of rejecting the whole file,
+//! either from a `Ply` in memory with `Validator::quarantine()`,
+//! or while streaming with `Validator::screen()`, which also catches lines that fail to decode.
+
+use std::fmt;
+use std::fmt::{ Display, Formatter };
+use std::io;
+use std::io::Write;
+
+use faces;
+use parser::{ SpannedEntries, EntrySpan };
+use ply::{ Ply, PropertyAccess, DefaultElement, PropertyDef, PropertyType, ScalarType, Property, Addable };
+use writer::Writer;
+
+/// Name of the property recording the original position of a quarantined entry, see `Validator::quarantine()`.
+pub const SOURCE_INDEX_PROPERTY: &'static str = "source_index";
+
+/// A rule broken by a `Ply`, see `Validator::validate()`.
+///
+/// With the `serde` feature it can be serialized into a machine readable report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Violation {
+    /// Name of the broken rule, built-in checks are named `header` and `face_indices`.
+    pub rule: String,
+    /// Element the violation was found in, `None` for violations concerning the whole file.
+    pub element: Option<String>,
+    /// Index of the offending entry within its element.
+    pub index: Option<usize>,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        match (&self.element, self.index) {
+            (&Some(ref e), Some(i)) => write!(f, "{}: entry {} of element `{}`: {}", self.rule, i, e, self.message),
+            (&Some(ref e), None) => write!(f, "{}: element `{}`: {}", self.rule, e, self.message),
+            _ => write!(f, "{}: {}", self.rule, self.message),
+        }
+    }
+}
+
+/// Collection of rules checked by `validate()`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::validate::Validator;
+/// # use ply_rs::ply::{ Ply, DefaultElement, PropertyAccess };
+/// let mut validator = Validator::<DefaultElement>::new();
+/// validator.add_rule("intensity_range", "vertex", |v| match v.get_float(&"intensity".to_string()) {
+///     Some(i) if i >= 0.0 && i <= 1.0 => Ok(()),
+///     Some(i) => Err(format!("intensity {} is not in 0..=1", i)),
+///     None => Err("intensity is missing".to_string()),
+/// });
+/// validator.add_rule("triangles", "face", |f| match f.get_list_int(&"vertex_indices".to_string()) {
+///     Some(l) if l.len() == 3 => Ok(()),
+///     _ => Err("face is not a triangle".to_string()),
+/// });
+/// let ply = Ply::<DefaultElement>::new();
+/// for v in validator.validate(&ply) {
+///     println!("{}", v);
+/// }
+/// ```
+pub struct Validator<E: PropertyAccess> {
+    rules: Vec<(String, String, Box<Fn(&E) -> Result<(), String>>)>,
+    max_violations: usize,
+}
+
+impl<E: PropertyAccess> Validator<E> {
+    /// Creates a validator running only the built-in checks.
+    pub fn new() -> Self {
+        Validator {
+            rules: Vec::new(),
+            max_violations: usize::max_value(),
+        }
+    }
+    /// Registers `check` under the name `rule` for every entry of `element`.
+    ///
+    /// The check returns a description of the problem if the entry breaks the rule.
+    pub fn add_rule<F>(&mut self, rule: &str, element: &str, check: F) -> &mut Self
+        where F: Fn(&E) -> Result<(), String> + 'static {
+        self.rules.push((rule.to_string(), element.to_string(), Box::new(check)));
+        self
+    }
+    /// Stops validating after `max` violations, to keep the report of a broken file short.
+    pub fn set_max_violations(&mut self, max: usize) -> &mut Self {
+        self.max_violations = max;
+        self
+    }
+    /// Runs the built-in checks and all rules against `ply`.
+    ///
+    /// Returns all violations found, in the order of the elements in the header and of their entries, empty if `ply` is valid.
+    /// Elements only found in the payload come last.
+    pub fn validate(&self, ply: &Ply<E>) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        if let Err(e) = ply.header.check() {
+            violations.push(Violation {
+                rule: "header".to_string(),
+                element: None,
+                index: None,
+                message: e.message().to_string(),
+            });
+        }
+        if let Err(e) = faces::validate_indices(ply) {
+            violations.push(Violation {
+                rule: "face_indices".to_string(),
+                element: Some(faces::FACE_ELEMENT.to_string()),
+                index: None,
+                message: e.message().to_string(),
+            });
+        }
+        let declared = ply.header.elements.keys().filter_map(|name| ply.payload.get(name).map(|entries| (name, entries)));
+        let undeclared = ply.payload.iter().filter(|&(name, _)| !ply.header.elements.contains_key(name));
+        for (name, entries) in declared.chain(undeclared) {
+            for (i, entry) in entries.iter().enumerate() {
+                for &(ref rule, ref element, ref check) in &self.rules {
+                    if element != name {
+                        continue;
+                    }
+                    if violations.len() >= self.max_violations {
+                        return violations;
+                    }
+                    if let Err(message) = check(entry) {
+                        violations.push(Violation {
+                            rule: rule.clone(),
+                            element: Some(name.clone()),
+                            index: Some(i),
+                            message: message,
+                        });
+                    }
+                }
+            }
+        }
+        violations.truncate(self.max_violations);
+        violations
+    }
+    /// Reads the entries of one element, passing clean ones on to `clean` and writing the others to `quarantine`.
+    ///
+    /// An entry is quarantined if it breaks a rule or, in ascii files, if its line can't be decoded.
+    /// Each row records where the entry was found, see `QuarantineWriter`.
+    /// Other read errors leave the reader at an unknown position and are returned.
+    /// Built-in checks and `set_max_violations()` don't apply.
+    ///
+    /// Returns number of entries quarantined.
+    pub fn screen<W, F>(&self, mut entries: SpannedEntries<E>, quarantine: &mut QuarantineWriter<W>, mut clean: F) -> io::Result<u64>
+        where W: Write, F: FnMut(EntrySpan, E) -> io::Result<()> {
+        let element_def = entries.element_def().clone();
+        let writer = Writer::<E>::new();
+        let mut index = 0;
+        let mut quarantined = 0;
+        while let Some(next) = entries.next() {
+            match next {
+                Ok((span, entry)) => {
+                    let reasons: Vec<String> = self.rules.iter()
+                        .filter(|&&(_, ref element, _)| *element == element_def.name)
+                        .filter_map(|&(ref rule, _, ref check)| check(&entry).err().map(|m| format!("{}: {}", rule, m)))
+                        .collect();
+                    if reasons.is_empty() {
+                        try!(clean(span, entry));
+                    } else {
+                        let mut record = Vec::new();
+                        try!(writer.write_ascii_element(&mut record, &entry, &element_def));
+                        let record = String::from_utf8_lossy(&record);
+                        try!(quarantine.write_row(&element_def.name, index, &span, &reasons.join("; "), record.trim_right()));
+                        quarantined += 1;
+                    }
+                },
+                Err(e) => match entries.skipped_entry() {
+                    Some((span, line)) => {
+                        let reason = format!("decoding: {}", e).split_whitespace().collect::<Vec<_>>().join(" ");
+                        try!(quarantine.write_row(&element_def.name, index, &span, &reason, line.trim_right()));
+                        quarantined += 1;
+                    },
+                    None => return Err(e),
+                },
+            }
+            index += 1;
+        }
+        Ok(quarantined)
+    }
+}
+
+/// Writes the entries set aside by `Validator::screen()` as CSV.
+///
+/// The columns are `element,index,offset,len,line,reason,record`:
+/// the entry's position within its element, its byte range in the file, its line number (empty for binary files),
+/// the broken rules or the decoding error, and the entry as an ascii PLY line, or the line as found if it couldn't be decoded.
+/// The column names are written with the first row.
+pub struct QuarantineWriter<W: Write> {
+    out: W,
+    rows: u64,
+}
+
+impl<W: Write> QuarantineWriter<W> {
+    /// Writes the rows to `out`.
+    pub fn new(out: W) -> Self {
+        QuarantineWriter {
+            out: out,
+            rows: 0,
+        }
+    }
+    /// Number of entries written so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+    fn write_row(&mut self, element: &str, index: u64, span: &EntrySpan, reason: &str, record: &str) -> io::Result<()> {
+        if self.rows == 0 {
+            try!(self.out.write_all(b"element,index,offset,len,line,reason,record\n"));
+        }
+        let line = span.line.map(|l| l.to_string()).unwrap_or_default();
+        try!(write!(self.out, "{},{},{},{},{},{},{}\n", csv_field(element), index, span.offset, span.len, line, csv_field(reason), csv_field(record)));
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+/// Quotes `s` for CSV if it contains a separator, quote or line break.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+impl Validator<DefaultElement> {
+    /// Moves all entries breaking a rule out of `ply` and returns them as a separate `Ply`.
+    ///
+    /// Meant for ingestion jobs, which continue with the clean entries and keep the quarantined ones for inspection.
+    /// The quarantine has the schema of `ply`, with the additional property `source_index`
+    /// holding the position of each entry in the original element. It is a `uint`,
+    /// or a `double` for elements with more entries than a `uint` can count.
+    /// See `screen()` to quarantine entries while streaming, with their location in the file.
+    /// Its comments list the violations, so it can be written as a regular PLY file.
+    /// Built-in checks and `set_max_violations()` don't apply, every entry is checked against the rules.
+    ///
+    /// Removing entries shifts the indices of those that follow,
+    /// faces referencing removed vertices have to be dealt with separately.
+    pub fn quarantine(&self, ply: &mut Ply<DefaultElement>) -> Ply<DefaultElement> {
+        let mut quarantine = Ply::<DefaultElement>::new();
+        quarantine.header.encoding = ply.header.encoding;
+        quarantine.header.version = ply.header.version;
+        for (name, element_def) in &ply.header.elements {
+            let mut element_def = element_def.clone();
+            element_def.count = 0;
+            let index_type = if wide_index(ply.payload.get(name).map_or(0, |e| e.len())) { ScalarType::Double } else { ScalarType::UInt };
+            element_def.properties.add(PropertyDef::new(SOURCE_INDEX_PROPERTY.to_string(), PropertyType::Scalar(index_type)));
+            quarantine.header.elements.add(element_def);
+        }
+        for (name, entries) in ply.payload.iter_mut() {
+            let wide = wide_index(entries.len());
+            let mut kept = Vec::with_capacity(entries.len());
+            let mut bad = Vec::new();
+            for (i, mut entry) in entries.drain(..).enumerate() {
+                let mut broken = false;
+                for &(ref rule, ref element, ref check) in &self.rules {
+                    if element != name {
+                        continue;
+                    }
+                    if let Err(message) = check(&entry) {
+                        quarantine.header.comments.push(Violation {
+                            rule: rule.clone(),
+                            element: Some(name.clone()),
+                            index: Some(i),
+                            message: message.replace('\n', " ").replace('\r', " "),
+                        }.to_string());
+                        broken = true;
+                    }
+                }
+                if broken {
+                    let index = if wide { Property::Double(i as f64) } else { Property::UInt(i as u32) };
+                    entry.insert(SOURCE_INDEX_PROPERTY.to_string(), index);
+                    bad.push(entry);
+                } else {
+                    kept.push(entry);
+                }
+            }
+            *entries = kept;
+            if let Some(element_def) = ply.header.elements.get_mut(name) {
+                element_def.count = entries.len() as u64;
+            }
+            if let Some(element_def) = quarantine.header.elements.get_mut(name) {
+                element_def.count = bad.len() as u64;
+            }
+            quarantine.payload.insert(name.clone(), bad);
+        }
+        quarantine
+    }
+}
+
+/// Whether an element with `len` entries needs a `double` to hold its indices.
+fn wide_index(len: usize) -> bool {
+    len as u64 > u32::max_value() as u64 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Parser;
+    use ply::*;
+    fn ply() -> Ply<DefaultElement> {
+        let mut p = Ply::<DefaultElement>::new();
+        let mut v = ElementDef::new("vertex".to_string());
+        v.properties.add(PropertyDef::new("intensity".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        p.header.elements.add(v);
+        let vertices = [0.5, 1.5, -1.0].iter().map(|&i| {
+            let mut e = DefaultElement::new();
+            e.insert("intensity".to_string(), Property::Float(i));
+            e
+        }).collect();
+        p.payload.insert("vertex".to_string(), vertices);
+        p.make_consistent().unwrap();
+        p
+    }
+    fn validator() -> Validator<DefaultElement> {
+        let mut v = Validator::<DefaultElement>::new();
+        v.add_rule("intensity_range", "vertex", |e| match e.get_float(&"intensity".to_string()) {
+            Some(i) if i >= 0.0 && i <= 1.0 => Ok(()),
+            i => Err(format!("intensity {:?} is not in 0..=1", i)),
+        });
+        v
+    }
+    #[test]
+    fn located_violations() {
+        let violations = validator().validate(&ply());
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].index, Some(1));
+        assert_eq!(violations[1].index, Some(2));
+        assert_eq!(violations[1].to_string(), "intensity_range: entry 2 of element `vertex`: intensity Some(-1.0) is not in 0..=1");
+        assert_eq!(validator().set_max_violations(1).validate(&ply()).len(), 1);
+        assert!(Validator::new().validate(&ply()).is_empty());
+    }
+    #[test]
+    fn violations_in_header_order() {
+        let mut p = ply();
+        let mut sample = ElementDef::new("sample".to_string());
+        sample.properties.add(PropertyDef::new("intensity".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        p.header.elements.add(sample);
+        let vertices = p.payload.remove("vertex").unwrap();
+        p.payload.insert("sample".to_string(), vertices.clone());
+        p.payload.insert("vertex".to_string(), vertices);
+        p.make_consistent().unwrap();
+        let mut v = validator();
+        v.add_rule("sample_intensity", "sample", |_| Err("bad".to_string()));
+        let elements: Vec<_> = v.validate(&p).into_iter().map(|v| v.element.unwrap()).collect();
+        assert_eq!(elements, vec!["vertex", "vertex", "sample", "sample", "sample"]);
+    }
+    #[test]
+    fn builtin_violations() {
+        let mut p = ply();
+        p.header.comments.push("two\nlines".to_string());
+        let violations = Validator::new().validate(&p);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule, "header");
+        assert_eq!(violations[0].element, None);
+    }
+    #[test]
+    fn quarantine_bad_entries() {
+        let mut p = ply();
+        let mut q = validator().quarantine(&mut p);
+        assert_eq!(p.payload["vertex"].len(), 1);
+        assert_eq!(p.header.elements["vertex"].count, 1);
+        assert_eq!(q.payload["vertex"].len(), 2);
+        assert_eq!(q.payload["vertex"][1][SOURCE_INDEX_PROPERTY], Property::UInt(2));
+        assert_eq!(q.header.comments.len(), 2);
+        assert!(q.make_consistent().is_ok());
+        assert!(validator().validate(&p).is_empty());
+    }
+    #[test]
+    fn screen_stream() {
+        let txt = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float intensity\nend_header\n0.5\nx\n-1\n1\n";
+        let p = Parser::<DefaultElement>::new();
+        let mut reader = p.read_ply_iter(txt.as_bytes()).unwrap();
+        let mut quarantine = QuarantineWriter::new(Vec::new());
+        let mut clean = Vec::new();
+        {
+            let entries = reader.next_element().unwrap().unwrap().with_spans();
+            let n = validator().screen(entries, &mut quarantine, |span, e| { clean.push((span.line, e)); Ok(()) }).unwrap();
+            assert_eq!(n, 2);
+        }
+        assert!(reader.next_element().unwrap().is_none());
+        assert_eq!(clean.iter().map(|c| c.0).collect::<Vec<_>>(), vec![Some(6), Some(9)]);
+        let csv = String::from_utf8(quarantine.into_inner()).unwrap();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], "element,index,offset,len,line,reason,record");
+        assert!(rows[1].starts_with("vertex,1,78,2,7,\"decoding: Couldn't read entry 1 of element `vertex`."), "{}", rows[1]);
+        assert!(rows[1].ends_with(",x"), "{}", rows[1]);
+        assert_eq!(rows[2], "vertex,2,80,3,8,intensity_range: intensity Some(-1.0) is not in 0..=1,-1");
+    }
+    #[test]
+    fn csv_quoting() {
+        assert_eq!(csv_field("a b"), "a b");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+}
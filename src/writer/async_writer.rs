@@ -0,0 +1,365 @@
+//! Asynchronous writing to a `tokio::io::AsyncWrite`, requires the `tokio` feature.
+
+use std::future::Future;
+use std::io;
+use std::io::{ ErrorKind, Result };
+use std::pin::Pin;
+use std::borrow::Borrow;
+use std::slice;
+use std::task::{ Context, Poll };
+
+use linked_hash_map;
+use tokio::io::AsyncWrite;
+
+use ply::{ Ply, PropertyAccess, ElementDef, Header, Encoding };
+use super::{ Writer, check_declared, element_list_of, check_entry_count };
+
+/// Elements are encoded until the buffer holds at least this many bytes, then the buffer is handed to the target.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+impl<E: PropertyAccess> Writer<E> {
+    /// Asynchronous variant of `write_ply()`, performs a consistency check.
+    ///
+    /// The returned future resolves to the number of bytes written.
+    pub fn write_ply_async<'a, W: AsyncWrite + Unpin>(&'a self, out: &'a mut W, ply: &'a mut Ply<E>) -> WritePly<'a, E, W> {
+        let error = self.prepare_ply(ply).err();
+        let mut future = self.write_ply_unchecked_async(out, ply);
+        future.error = error;
+        future
+    }
+    /// Asynchronous variant of `write_ply_unchecked()`, performs no consistency check.
+    ///
+    /// The returned future resolves to the number of bytes written.
+    pub fn write_ply_unchecked_async<'a, W: AsyncWrite + Unpin>(&'a self, out: &'a mut W, ply: &'a Ply<E>) -> WritePly<'a, E, W> {
+        self.reset_progress();
+        WritePly {
+            writer: self,
+            out: out,
+            ply: ply,
+            element_defs: ply.header.elements.iter(),
+            current: None,
+            buf: Vec::new(),
+            pos: 0,
+            header_written: false,
+            encoding_done: false,
+            written: 0,
+            error: None,
+        }
+    }
+    /// Asynchronous variant of `write_payload_of_element_iter()`.
+    ///
+    /// Entries are taken from `elements` only as fast as the target accepts the encoded data.
+    /// The number of entries must match `element_def.count`, otherwise the future fails.
+    /// The returned future resolves to the number of bytes written.
+    pub fn write_payload_of_element_iter_async<'a, W, I>(&'a self, out: &'a mut W, elements: I, element_def: &'a ElementDef, header: &Header) -> WriteElements<'a, E, W, I::IntoIter>
+        where W: AsyncWrite + Unpin, I: IntoIterator, I::Item: Borrow<E> {
+        WriteElements {
+            writer: self,
+            out: out,
+            elements: elements.into_iter(),
+            element_def: element_def,
+            encoding: header.encoding,
+            count: 0,
+            buf: Vec::new(),
+            pos: 0,
+            encoding_done: false,
+            written: 0,
+        }
+    }
+}
+
+/// Future returned by `Writer::write_ply_async()` and `Writer::write_ply_unchecked_async()`.
+///
+/// The PLY is streamed: Elements are encoded chunk by chunk
+/// and encoding only continues once the target accepted the previous chunk.
+/// Memory use stays bounded and backpressure of the target is respected.
+pub struct WritePly<'a, E: PropertyAccess + 'a, W: 'a> {
+    writer: &'a Writer<E>,
+    out: &'a mut W,
+    ply: &'a Ply<E>,
+    element_defs: linked_hash_map::Iter<'a, String, ElementDef>,
+    current: Option<(&'a ElementDef, slice::Iter<'a, E>)>,
+    buf: Vec<u8>,
+    pos: usize,
+    header_written: bool,
+    encoding_done: bool,
+    written: usize,
+    error: Option<io::Error>,
+}
+
+impl<'a, E: PropertyAccess, W: AsyncWrite + Unpin> WritePly<'a, E, W> {
+    fn fill_buffer(&mut self) -> Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        if !self.header_written {
+            try!(check_declared(&self.ply.payload, &self.ply.header));
+            let header = try!(self.writer.output_header(self.ply));
+            try!(self.writer.write_header(&mut self.buf, &header));
+            self.header_written = true;
+        }
+        while self.buf.len() < CHUNK_SIZE {
+            if let Some((element_def, ref mut iter)) = self.current {
+                if let Some(element) = iter.next() {
+                    try!(self.writer.write_element(&mut self.buf, element, element_def, &self.ply.header.encoding));
+                    continue;
+                }
+            }
+            match self.element_defs.next() {
+                Some((k, element_def)) => {
+                    let list = try!(element_list_of(&self.ply.payload, k));
+                    try!(check_entry_count(list.len(), element_def));
+                    self.current = Some((element_def, list.iter()));
+                },
+                None => {
+                    self.encoding_done = true;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, E: PropertyAccess, W: AsyncWrite + Unpin> Future for WritePly<'a, E, W> {
+    type Output = Result<usize>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        if let Some(e) = this.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        loop {
+            match poll_drain(&mut *this.out, cx, &this.buf, &mut this.pos, &mut this.written) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            if this.encoding_done {
+                return poll_finish(&mut *this.out, cx, this.written);
+            }
+            if let Err(e) = this.fill_buffer() {
+                return Poll::Ready(Err(e));
+            }
+        }
+    }
+}
+
+/// Future returned by `Writer::write_payload_of_element_iter_async()`.
+///
+/// Like `WritePly`, entries are encoded chunk by chunk and only pulled from the iterator
+/// once the target accepted the previous chunk.
+pub struct WriteElements<'a, E: PropertyAccess + 'a, W: 'a, I> {
+    writer: &'a Writer<E>,
+    out: &'a mut W,
+    elements: I,
+    element_def: &'a ElementDef,
+    encoding: Encoding,
+    count: u64,
+    buf: Vec<u8>,
+    pos: usize,
+    encoding_done: bool,
+    written: usize,
+}
+
+impl<'a, E: PropertyAccess, W, I> Unpin for WriteElements<'a, E, W, I> {}
+
+impl<'a, E: PropertyAccess, W: AsyncWrite + Unpin, I: Iterator> WriteElements<'a, E, W, I> where I::Item: Borrow<E> {
+    fn fill_buffer(&mut self) -> Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        while self.buf.len() < CHUNK_SIZE {
+            let element = match self.elements.next() {
+                Some(e) => e,
+                None => {
+                    self.encoding_done = true;
+                    break;
+                }
+            };
+            self.count += 1;
+            if self.count > self.element_def.count {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Element `{}` declares {} entries, but more were supplied.", self.element_def.name, self.element_def.count)
+                ));
+            }
+            try!(self.writer.write_element(&mut self.buf, element.borrow(), self.element_def, &self.encoding));
+        }
+        if self.encoding_done && self.count < self.element_def.count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` declares {} entries, but only {} were supplied.", self.element_def.name, self.element_def.count, self.count)
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<'a, E: PropertyAccess, W: AsyncWrite + Unpin, I: Iterator> Future for WriteElements<'a, E, W, I> where I::Item: Borrow<E> {
+    type Output = Result<usize>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match poll_drain(&mut *this.out, cx, &this.buf, &mut this.pos, &mut this.written) {
+                Poll::Ready(Ok(())) => (),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+            if this.encoding_done {
+                return poll_finish(&mut *this.out, cx, this.written);
+            }
+            if let Err(e) = this.fill_buffer() {
+                return Poll::Ready(Err(e));
+            }
+        }
+    }
+}
+
+/// Hands `buf[*pos..]` to `out` until it took everything.
+fn poll_drain<W: AsyncWrite + Unpin>(out: &mut W, cx: &mut Context, buf: &[u8], pos: &mut usize, written: &mut usize) -> Poll<Result<()>> {
+    while *pos < buf.len() {
+        match Pin::new(&mut *out).poll_write(cx, &buf[*pos..]) {
+            Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::Error::new(ErrorKind::WriteZero, "Target accepted no more data."))),
+            Poll::Ready(Ok(n)) => {
+                *pos += n;
+                *written += n;
+            },
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+    Poll::Ready(Ok(()))
+}
+
+/// Flushes `out`, then resolves to `written`.
+fn poll_finish<W: AsyncWrite + Unpin>(out: &mut W, cx: &mut Context, written: usize) -> Poll<Result<usize>> {
+    match Pin::new(out).poll_flush(cx) {
+        Poll::Ready(Ok(())) => Poll::Ready(Ok(written)),
+        Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::ptr;
+    use std::task::{ Context, Poll, RawWaker, RawWakerVTable, Waker };
+    use ply::*;
+    use writer::Writer;
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker { noop_raw_waker() }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(ptr::null(), &VTABLE)
+    }
+
+    fn create_ply(encoding: Encoding) -> Ply<DefaultElement> {
+        let mut ply = Ply::<DefaultElement>::new();
+        ply.header.encoding = encoding;
+        let mut e = ElementDef::new("point".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        e.properties.add(PropertyDef::new("i".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::Int)));
+        ply.header.elements.add(e);
+        let mut list = Vec::new();
+        for i in 0..10000 {
+            let mut pe = DefaultElement::new();
+            pe.insert("x".to_string(), Property::Float(i as f32 * 0.5));
+            pe.insert("i".to_string(), Property::ListInt(vec![i, -i]));
+            list.push(pe);
+        }
+        ply.payload.insert("point".to_string(), list);
+        ply.make_consistent().unwrap();
+        ply
+    }
+
+    fn poll_once<F: Future + Unpin>(mut future: F) -> F::Output {
+        let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+        let mut cx = Context::from_waker(&waker);
+        match Pin::new(&mut future).poll(&mut cx) {
+            Poll::Ready(r) => r,
+            Poll::Pending => panic!("Writing to a Vec should never be pending."),
+        }
+    }
+
+    #[test]
+    fn async_matches_sync() {
+        let w = Writer::<DefaultElement>::new();
+        for encoding in &[Encoding::Ascii, Encoding::BinaryBigEndian, Encoding::BinaryLittleEndian] {
+            let mut ply = create_ply(*encoding);
+            let mut expected = Vec::<u8>::new();
+            w.write_ply(&mut expected, &mut ply).unwrap();
+
+            let mut actual = Vec::<u8>::new();
+            let written = poll_once(w.write_ply_async(&mut actual, &mut ply)).unwrap();
+            assert_eq!(written, expected.len());
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn async_matches_sync_with_crcs() {
+        let mut w = Writer::<DefaultElement>::new();
+        w.set_element_crcs(true);
+        for encoding in &[Encoding::Ascii, Encoding::BinaryBigEndian, Encoding::BinaryLittleEndian] {
+            let mut ply = create_ply(*encoding);
+            let mut expected = Vec::<u8>::new();
+            w.write_ply(&mut expected, &mut ply).unwrap();
+            assert!(String::from_utf8_lossy(&expected).contains("obj_info crc32 point "));
+
+            let mut actual = Vec::<u8>::new();
+            let written = poll_once(w.write_ply_async(&mut actual, &mut ply)).unwrap();
+            assert_eq!(written, expected.len());
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn async_drops_stale_crcs() {
+        let w = Writer::<DefaultElement>::new();
+        let mut ply = create_ply(Encoding::Ascii);
+        ply.header.obj_infos.push("crc32 point 00000000".to_string());
+        let mut actual = Vec::<u8>::new();
+        poll_once(w.write_ply_async(&mut actual, &mut ply)).unwrap();
+        assert!(!String::from_utf8_lossy(&actual).contains("crc32"));
+    }
+
+    #[test]
+    fn async_header_order_and_counts() {
+        let w = Writer::<DefaultElement>::new();
+        let mut ply = create_ply(Encoding::Ascii);
+        let mut e = ElementDef::new("empty".to_string());
+        e.properties.add(PropertyDef::new("a".to_string(), PropertyType::Scalar(ScalarType::Int)));
+        ply.header.elements.add(e);
+        let points = ply.payload.remove("point").unwrap();
+        ply.payload.insert("empty".to_string(), Vec::new());
+        ply.payload.insert("point".to_string(), points);
+        ply.make_consistent().unwrap();
+        let mut expected = Vec::<u8>::new();
+        w.write_ply_unchecked(&mut expected, &ply).unwrap();
+        let mut actual = Vec::<u8>::new();
+        poll_once(w.write_ply_unchecked_async(&mut actual, &ply)).unwrap();
+        assert_eq!(actual, expected);
+
+        ply.header.elements.get_mut("point").unwrap().count += 1;
+        assert!(poll_once(w.write_ply_unchecked_async(&mut Vec::<u8>::new(), &ply)).is_err());
+        ply.payload.remove("empty");
+        assert!(poll_once(w.write_ply_unchecked_async(&mut Vec::<u8>::new(), &ply)).is_err());
+    }
+
+    #[test]
+    fn async_element_iter() {
+        let w = Writer::<DefaultElement>::new();
+        let ply = create_ply(Encoding::BinaryLittleEndian);
+        let element_def = &ply.header.elements["point"];
+        let mut expected = Vec::<u8>::new();
+        w.write_payload_of_element(&mut expected, &ply.payload["point"], element_def, &ply.header).unwrap();
+        let mut actual = Vec::<u8>::new();
+        let written = poll_once(w.write_payload_of_element_iter_async(&mut actual, ply.payload["point"].iter(), element_def, &ply.header)).unwrap();
+        assert_eq!(written, expected.len());
+        assert_eq!(actual, expected);
+
+        let too_few = ply.payload["point"][..10].iter();
+        assert!(poll_once(w.write_payload_of_element_iter_async(&mut Vec::<u8>::new(), too_few, element_def, &ply.header)).is_err());
+    }
+}
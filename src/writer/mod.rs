@@ -3,6 +3,17 @@
 use std::marker::PhantomData;
 use ply::PropertyAccess;
 
+#[cfg(feature = "tokio")]
+mod async_writer;
+#[cfg(feature = "tokio")]
+pub use self::async_writer::*;
+
+mod stream;
+pub use self::stream::PlyWriter;
+
+mod typed;
+pub use self::typed::{ TypedElements, write_typed };
+
 /// Writes a `Ply` to a `Write` trait.
 ///
 /// The simplest function to start with is `write_ply()`.
@@ -39,19 +50,77 @@ use ply::PropertyAccess;
 pub struct Writer<E: PropertyAccess> {
     /// Should be fairly efficient, se `as_bytes()` in https://doc.rust-lang.org/src/collections/string.rs.html#1001
     new_line: String,
+    property_order: Vec<String>,
+    precisions: Vec<(String, String, Precision)>,
+    buffer_size: BufferSize,
+    atomic_file_writes: bool,
+    element_crcs: bool,
+    progress: Option<ProgressReporter>,
     phantom: PhantomData<E>,
 }
 
+/// Snapshot handed to the progress callback of a `Writer`, see `Writer::set_progress_callback()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteProgress {
+    /// Number of elements written so far.
+    pub elements: u64,
+    /// Number of bytes written so far, including the header.
+    pub bytes: u64,
+}
+
+/// Lossy precision reduction of a floating point property, see `Writer::set_precision()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Precision {
+    /// Declares and stores `double` values as `float`.
+    pub downcast: bool,
+    /// Rounds values to this many significant decimal digits.
+    pub significant_digits: Option<u32>,
+}
+
+/// Encodes an entry of an element directly, without going through `PropertyAccess`.
+///
+/// Implement it for records whose layout is known up front, for example a `#[repr(C)]` vertex struct,
+/// and write them with `Writer::write_payload_of_serialized()`.
+pub trait SerializeElement {
+    /// Appends the encoded entry to `out`.
+    ///
+    /// Binary encodings expect the properties of `element_def` in order and in the byte order given by `encoding`.
+    /// For `Encoding::Ascii` append the values of a single line without the line break.
+    fn serialize_element(&self, element_def: &ElementDef, encoding: &Encoding, out: &mut Vec<u8>) -> Result<()>;
+}
+
+impl<'a, S: SerializeElement + ?Sized> SerializeElement for &'a S {
+    fn serialize_element(&self, element_def: &ElementDef, encoding: &Encoding, out: &mut Vec<u8>) -> Result<()> {
+        (**self).serialize_element(element_def, encoding, out)
+    }
+}
+
+struct ProgressReporter {
+    every_elements: u64,
+    every_bytes: u64,
+    callback: Box<Fn(&WriteProgress) -> bool + Send + Sync>,
+    elements: AtomicU64,
+    bytes: AtomicU64,
+    next_elements: AtomicU64,
+    next_bytes: AtomicU64,
+}
+
 /*
 use std::marker::PhantomData;
 use writer::Writer;
 use ply::PropertyAccess;
 // */
 
+use std::borrow::Cow;
 use std::io;
-use std::io::{ Write, Result, ErrorKind };
+use std::io::{ Write, BufWriter, Result, ErrorKind };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::fs::File;
+use std::path::Path;
+use parser::BufferSize;
+use util::{ write_file_atomically, write_file_in_place, Crc32, CRC_OBJ_INFO, split_crc_obj_info };
 
-use ply::Ply;
+use ply::{ Ply, Error as PlyError };
 
 // ////////////////////////////
 // General
@@ -61,9 +130,88 @@ impl<E: PropertyAccess> Writer<E> {
     pub fn new() -> Self {
         Writer {
             new_line: "\n".to_string(),
+            property_order: Vec::new(),
+            precisions: Vec::new(),
+            buffer_size: BufferSize::Fixed(0),
+            atomic_file_writes: true,
+            element_crcs: false,
+            progress: None,
             phantom: PhantomData,
         }
     }
+    /// Sets the order in which `write_ply()` arranges the properties of each element.
+    ///
+    /// Properties named in `order` come first, in the given sequence, the others follow in their original order.
+    /// Use `CONVENTIONAL_PROPERTY_ORDER` for readers that assume a fixed vertex layout.
+    /// The header of the written `Ply` is updated accordingly.
+    ///
+    /// `write_ply_unchecked()` and the lower level methods write properties as ordered in the header,
+    /// use `ElementDef::reorder_properties()` to prepare the header for them.
+    pub fn set_property_order(&mut self, order: &[&str]) {
+        self.property_order = order.iter().map(|s| s.to_string()).collect();
+    }
+    /// Reduces the precision of the floating point property `property` of `element` on write.
+    ///
+    /// Meant for smaller copies of high precision data, for example for visualization.
+    /// With `downcast`, `write_ply()` declares a `double` property as `float` in the header of the written `Ply`.
+    /// Rounding to significant digits mainly shrinks ascii files and makes binary ones compress better.
+    ///
+    /// `write_ply_unchecked()` and the lower level methods take the types from the header,
+    /// a property declared as `float` is then filled from `double` values if necessary.
+    /// Properties of integer type are left alone.
+    pub fn set_precision(&mut self, element: &str, property: &str, precision: Precision) {
+        self.precisions.retain(|&(ref e, ref p, _)| e != element || p != property);
+        self.precisions.push((element.to_string(), property.to_string(), precision));
+    }
+    /// Sets the size of the buffer `write_ply()` and `write_ply_unchecked()` put in front of the target.
+    ///
+    /// By default nothing is buffered, wrap the target in a `BufWriter` yourself or set a size here.
+    /// `BufferSize::Adaptive` sizes the buffer after the payload size estimated from the header.
+    pub fn set_buffer_size(&mut self, buffer_size: BufferSize) {
+        self.buffer_size = buffer_size;
+    }
+    /// Sets whether `write_ply_file()` writes to a temporary file first, enabled by default.
+    ///
+    /// The temporary file is placed next to the target under a unique name, takes over the permissions
+    /// of the file it replaces and is renamed over it once everything was written,
+    /// so readers never see a truncated file and an existing file is only replaced by a complete one.
+    /// Disable it where renaming isn't possible, e.g. when writing to a device or named pipe.
+    pub fn set_atomic_file_writes(&mut self, atomic: bool) {
+        self.atomic_file_writes = atomic;
+    }
+    /// Sets whether `write_ply()` and `write_ply_async()` record a CRC-32 of each element block in the header, disabled by default.
+    ///
+    /// Each checksum covers the encoded entries of one element and is stored as `obj_info crc32 <element> <hex>`,
+    /// replacing such lines already present. A reader can then detect a corrupted element
+    /// as soon as it has been transferred, see `Parser::set_verify_element_crcs()`.
+    /// The payload is encoded twice, once for the checksums and once for writing.
+    ///
+    /// When disabled, such lines already present are dropped, as they may not match the payload written.
+    /// A `PlyWriter` writes the header before any entry is known, it never records checksums and drops existing ones.
+    pub fn set_element_crcs(&mut self, enabled: bool) {
+        self.element_crcs = enabled;
+    }
+    /// Registers `callback` to be informed about the progress of a write.
+    ///
+    /// The callback is invoked whenever another `every_elements` elements or `every_bytes` bytes have been written,
+    /// a value of zero disables the respective trigger.
+    /// Returning `false` from the callback cancels the write, which then fails with an error.
+    ///
+    /// Counting starts over with each call to `write_ply()` or `write_ply_unchecked()`.
+    /// The callback must be `Send + Sync` so the writer can be shared between threads,
+    /// but writes running concurrently on the same writer share one count.
+    pub fn set_progress_callback<F>(&mut self, every_elements: u64, every_bytes: u64, callback: F)
+        where F: Fn(&WriteProgress) -> bool + Send + Sync + 'static {
+        self.progress = Some(ProgressReporter {
+            every_elements: every_elements,
+            every_bytes: every_bytes,
+            callback: Box::new(callback),
+            elements: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            next_elements: AtomicU64::new(every_elements),
+            next_bytes: AtomicU64::new(every_bytes),
+        });
+    }
     /// Writes an entire PLY file modeled by `ply` to `out`, performs consistency chekc.
     ///
     /// `ply` must be mutable since a consistency check is performed.
@@ -71,25 +219,210 @@ impl<E: PropertyAccess> Writer<E> {
     ///
     /// Returns number of bytes written.
     pub fn write_ply<T: Write>(&self, out: &mut T, ply: &mut Ply<E>) -> Result<usize> {
-        match ply.make_consistent() {
-            Ok(()) => (),
-            Err(e) => return Err(io::Error::new(ErrorKind::InvalidInput, format!("The given ply isn't consistent: {:?}", e))),
-        };
+        try!(self.prepare_ply(ply));
         self.write_ply_unchecked(out, ply)
     }
+    /// Writes `ply` to the file at `path`, performs consistency check like `write_ply()`.
+    ///
+    /// If the write fails or is cancelled by the progress callback, the partial output is removed.
+    /// See `set_atomic_file_writes()` for how an existing file is replaced.
+    /// Errors are attributed to the file, see `Error::in_file()`.
+    ///
+    /// Returns number of bytes written.
+    pub fn write_ply_file<P: AsRef<Path>>(&self, path: P, ply: &mut Ply<E>) -> Result<usize> {
+        try!(self.prepare_ply(ply));
+        let write = |file: &mut File| {
+            let mut out = BufWriter::new(file);
+            let written = try!(self.write_ply_unchecked(&mut out, ply));
+            try!(out.flush());
+            Ok(written)
+        };
+        let path = path.as_ref();
+        let result = if self.atomic_file_writes {
+            write_file_atomically(path, write)
+        } else {
+            write_file_in_place(path, write)
+        };
+        result.map_err(|e| PlyError::in_file(e, path))
+    }
     /// Writes an entire PLY file modeled by `ply` to `out`, performes no consistency check.
     ///
-    /// Like `write_ply` but doesn't check the input for inconsistency.
+    /// Like `write_ply` but doesn't make the input consistent.
     /// The user is responsible to provide a consistent `Ply`,
-    /// if not, behaviour is undefined and might result
-    /// in a corrupted output.
+    /// only the checks of `write_header()` and `write_payload()` are performed,
+    /// anything beyond might result in a corrupted output.
     pub fn write_ply_unchecked<T: Write>(&self, out: &mut T, ply: &Ply<E>) -> Result<usize> {
+        let capacity = self.buffer_size.capacity_for(&ply.header);
+        if capacity == 0 {
+            return self.__write_ply(out, ply);
+        }
+        let mut out = BufWriter::with_capacity(capacity, out);
+        let written = try!(self.__write_ply(&mut out, ply));
+        try!(out.flush());
+        Ok(written)
+    }
+    fn __write_ply<T: Write>(&self, out: &mut T, ply: &Ply<E>) -> Result<usize> {
+        self.reset_progress();
+        let header = try!(self.output_header(ply));
         let mut written = 0;
-        written += try!(self.write_header(out, &ply.header));
-        written += try!(self.write_payload(out, &ply.payload, &ply.header));
+        written += try!(self.write_header(out, &header));
+        try!(self.report_progress(0, written));
+        written += try!(self.write_payload(out, &ply.payload, &header));
         out.flush().unwrap();
         Ok(written)
     }
+    /// Header written for `ply`: with fresh checksums if enabled by `set_element_crcs()`, otherwise without any.
+    fn output_header<'a>(&self, ply: &'a Ply<E>) -> Result<Cow<'a, Header>> {
+        if self.element_crcs {
+            self.header_with_crcs(ply).map(Cow::Owned)
+        } else {
+            Ok(without_crcs(&ply.header))
+        }
+    }
+    /// Copy of the header of `ply` recording the checksum of each element block, see `set_element_crcs()`.
+    fn header_with_crcs(&self, ply: &Ply<E>) -> Result<Header> {
+        try!(check_declared(&ply.payload, &ply.header));
+        let mut header = without_crcs(&ply.header).into_owned();
+        for (k, element_def) in &ply.header.elements {
+            let mut crc = Crc32::new();
+            for element in try!(element_list_of(&ply.payload, k)) {
+                try!(match header.encoding {
+                    Encoding::Ascii => self.write_ascii_element(&mut crc, element, element_def),
+                    Encoding::BinaryBigEndian => self.write_big_endian_element(&mut crc, element, element_def),
+                    Encoding::BinaryLittleEndian => self.write_little_endian_element(&mut crc, element, element_def),
+                });
+            }
+            header.obj_infos.push(format!("{} {} {:08x}", CRC_OBJ_INFO, k, crc.value()));
+        }
+        Ok(header)
+    }
+    /// Computes how many bytes `write_ply_unchecked()` would write for `ply`, without writing anything.
+    ///
+    /// For binary encodings the size is computed from the element definitions and list lengths.
+    /// For ascii, every value has to be formatted, so this costs about as much as the write itself.
+    ///
+    /// The result is exact for both, as long as `ply` is consistent.
+    /// Checksum lines, see `set_element_crcs()`, are counted like `write_ply()` writes them.
+    pub fn estimate_size(&self, ply: &Ply<E>) -> Result<u64> {
+        try!(check_declared(&ply.payload, &ply.header));
+        let mut header = without_crcs(&ply.header);
+        if self.element_crcs {
+            // Checksums are written as fixed width hex, a placeholder has the same length.
+            for (k, _) in &ply.header.elements {
+                header.to_mut().obj_infos.push(format!("{} {} {:08x}", CRC_OBJ_INFO, k, 0));
+            }
+        }
+        let mut size = try!(self.write_header(&mut io::sink(), &header)) as u64;
+        for (k, element_def) in &ply.header.elements {
+            for element in try!(element_list_of(&ply.payload, k)) {
+                let element_size = match ply.header.encoding {
+                    Encoding::Ascii => try!(self.write_ascii_element(&mut io::sink(), element, element_def)) as u64,
+                    Encoding::BinaryBigEndian | Encoding::BinaryLittleEndian => try!(self.binary_element_size(element, element_def)),
+                };
+                size = try!(checked_add(size, element_size));
+            }
+        }
+        Ok(size)
+    }
+    /// Consistency check and property ordering performed by the checked write methods.
+    fn prepare_ply(&self, ply: &mut Ply<E>) -> Result<()> {
+        match ply.make_consistent() {
+            Ok(()) => (),
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidInput, format!("The given ply isn't consistent: {:?}", e))),
+        };
+        if !self.property_order.is_empty() {
+            let order: Vec<&str> = self.property_order.iter().map(|s| s.as_ref()).collect();
+            for (_, e) in ply.header.elements.iter_mut() {
+                e.reorder_properties(&order);
+            }
+        }
+        for &(ref element, ref property, ref precision) in &self.precisions {
+            if !precision.downcast {
+                continue;
+            }
+            let property_def = match ply.header.elements.get_mut(element).and_then(|e| e.properties.get_mut(property)) {
+                Some(p) => p,
+                None => continue,
+            };
+            property_def.data_type = match property_def.data_type {
+                PropertyType::Scalar(ScalarType::Double) => PropertyType::Scalar(ScalarType::Float),
+                PropertyType::List(ref index_type, ScalarType::Double) => PropertyType::List(index_type.clone(), ScalarType::Float),
+                ref t => t.clone(),
+            };
+        }
+        Ok(())
+    }
+    /// Precision set for a floating point property, `None` if it is written as is.
+    fn precision_of(&self, element_def: &ElementDef, property_def: &PropertyDef) -> Option<Precision> {
+        if self.precisions.is_empty() {
+            return None;
+        }
+        let scalar_type = match property_def.data_type {
+            PropertyType::Scalar(ref t) | PropertyType::List(_, ref t) => t,
+        };
+        if scalar_type.is_integer() {
+            return None;
+        }
+        self.precisions.iter()
+            .find(|&&(ref e, ref p, _)| *e == element_def.name && *p == property_def.name)
+            .map(|&(_, _, precision)| precision)
+    }
+    /// Values of a floating point property, rounded as requested by `precision`.
+    fn lossy_values(&self, element: &E, property_def: &PropertyDef, precision: &Precision) -> Result<Vec<f64>> {
+        let k = &property_def.name;
+        let values = match property_def.data_type {
+            PropertyType::Scalar(_) => element.get_double(k)
+                .or_else(|| element.get_float(k).map(|v| v as f64))
+                .map(|v| vec![v]),
+            PropertyType::List(_, _) => element.get_list_double(k)
+                .map(|l| l.to_vec())
+                .or_else(|| element.get_list_float(k).map(|l| l.iter().map(|&v| v as f64).collect())),
+        };
+        let mut values = match values {
+            Some(v) => v,
+            None => return Err(type_mismatch(element, k, property_def)),
+        };
+        if let Some(digits) = precision.significant_digits {
+            for v in &mut values {
+                *v = round_significant(*v, digits);
+            }
+        }
+        Ok(values)
+    }
+    fn reset_progress(&self) {
+        if let Some(ref p) = self.progress {
+            p.elements.store(0, Ordering::Relaxed);
+            p.bytes.store(0, Ordering::Relaxed);
+            p.next_elements.store(p.every_elements, Ordering::Relaxed);
+            p.next_bytes.store(p.every_bytes, Ordering::Relaxed);
+        }
+    }
+    fn report_progress(&self, elements: u64, bytes: usize) -> Result<()> {
+        let p = match self.progress {
+            None => return Ok(()),
+            Some(ref p) => p,
+        };
+        let current = WriteProgress {
+            elements: p.elements.fetch_add(elements, Ordering::Relaxed) + elements,
+            bytes: p.bytes.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64,
+        };
+        let element_due = p.every_elements != 0 && current.elements >= p.next_elements.load(Ordering::Relaxed);
+        let bytes_due = p.every_bytes != 0 && current.bytes >= p.next_bytes.load(Ordering::Relaxed);
+        if !element_due && !bytes_due {
+            return Ok(());
+        }
+        if p.every_elements != 0 {
+            p.next_elements.store((current.elements / p.every_elements + 1) * p.every_elements, Ordering::Relaxed);
+        }
+        if p.every_bytes != 0 {
+            p.next_bytes.store((current.bytes / p.every_bytes + 1) * p.every_bytes, Ordering::Relaxed);
+        }
+        if (p.callback)(&current) {
+            Ok(())
+        } else {
+            Err(io::Error::new(ErrorKind::Other, "Write cancelled by progress callback."))
+        }
+    }
     fn write_new_line<T: Write>(&self, out: &mut T) -> Result<usize> {
         out.write(self.new_line.as_bytes())
     }
@@ -103,6 +436,7 @@ use super::general;
 use ply::PropertyAccess;
 // */
 use ply::{ Header, Encoding, Version, Comment, ObjInfo, ElementDef, PropertyDef, PropertyType, ScalarType };
+use parser::Line;
 
 // ////////////////////////
 /// # Header
@@ -190,12 +524,45 @@ impl<E: PropertyAccess> Writer<E> {
         written += try!(self.write_new_line(out));
         Ok(written)
     }
+    /// Writes a single header line as returned by `Parser::read_header_line()`, including the line break.
+    ///
+    /// An element line is written without its properties.
+    pub fn write_line<T: Write>(&self, out: &mut T, line: &Line) -> Result<usize> {
+        match *line {
+            Line::MagicNumber => self.write_line_magic_number(out),
+            Line::Format((ref encoding, ref version)) => self.write_line_format(out, encoding, version),
+            Line::Comment(ref c) => self.write_line_comment(out, c),
+            Line::ObjInfo(ref o) => self.write_line_obj_info(out, o),
+            Line::Element(ref e) => self.write_line_element_definition(out, e),
+            Line::Property(ref p) => self.write_line_property_definition(out, p),
+            Line::EndHeader => self.write_line_end_header(out),
+        }
+    }
+    /// Renders a single header line to a string, without the line break.
+    ///
+    /// This is the inverse of `Parser::read_header_line()`.
+    pub fn line_to_string(&self, line: &Line) -> Result<String> {
+        let mut buf = Vec::new();
+        try!(self.write_line(&mut buf, line));
+        let len = buf.len() - self.new_line.len();
+        buf.truncate(len);
+        match String::from_utf8(buf) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(io::Error::new(ErrorKind::InvalidData, e)),
+        }
+    }
     /// Convenience method to write all header elements.
     ///
     /// It starts with writing the magic number "ply\n" and ends with "end_header".
+    /// Together with `write_payload()` or `write_payload_for_element()`,
+    /// this allows to interleave own I/O between the header and the element blocks.
     ///
-    /// Make sure the header is consistent with the payload.
+    /// The header is validated with `Header::check()` before anything is written.
+    /// Make sure the element counts match the payload written afterwards.
     pub fn write_header<T: Write>(&self, out: &mut T, header: &Header) -> Result<usize> {
+        if let Err(e) = header.check() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("The given header isn't consistent: {:?}", e)));
+        }
         let mut written = 0;
         written += try!(self.write_line_magic_number(out));
         written += try!(self.write_line_format(out, &header.encoding, &header.version));
@@ -237,16 +604,7 @@ impl<E: PropertyAccess> Writer<E> {
         }
     }
     fn write_scalar_type<T: Write>(&self, out: &mut T, scalar_type: &ScalarType) -> Result<usize> {
-        match *scalar_type {
-            ScalarType::Char => out.write("char".as_bytes()),
-            ScalarType::UChar => out.write("uchar".as_bytes()),
-            ScalarType::Short => out.write("short".as_bytes()),
-            ScalarType::UShort => out.write("ushort".as_bytes()),
-            ScalarType::Int => out.write("int".as_bytes()),
-            ScalarType::UInt => out.write("uint".as_bytes()),
-            ScalarType::Float => out.write("float".as_bytes()),
-            ScalarType::Double => out.write("double".as_bytes()),
-        }
+        out.write(scalar_type.name().as_bytes())
     }
 }
 /*
@@ -255,6 +613,7 @@ use std::io::{ Write, Result };
 use ply::{ Header, PropertyAccess, Encoding, ElementDef };
 // */
 use ply::Payload;
+use std::borrow::Borrow;
 
 // ////////////////////////
 /// # Payload
@@ -262,34 +621,137 @@ use ply::Payload;
 impl<E: PropertyAccess> Writer<E> {
     /// Writes the payload of a `ply` (`ply.playload`).
     ///
-    /// Make sure the Header is consistent with the payload.
+    /// Elements are written in the order of the header.
+    /// Fails before writing an element if the payload lacks it, or if its number of entries differs from the header,
+    /// and before writing anything if the payload contains an element not declared in the header.
     pub fn write_payload<T: Write>(&self, out: &mut T, payload: &Payload<E>, header: &Header) -> Result<usize> {
+        try!(check_declared(payload, header));
         let mut written = 0;
-        let element_defs = &header.elements;
-        for (k, element_list) in payload {
-            let element_def = &element_defs[k];
-            written += try!(self.write_payload_of_element(out, element_list, element_def, header));
+        for (k, element_def) in &header.elements {
+            let element_list = try!(element_list_of(payload, k));
+            written += try!(self.write_payload_for_element(out, element_list, element_def, header));
+        }
+        Ok(written)
+    }
+    /// Writes all entries of a single element.
+    ///
+    /// Element blocks must be written in the order of the header, directly after it or after the previous block.
+    /// Fails before writing anything if the number of entries differs from `element_def.count`.
+    pub fn write_payload_for_element<T: Write>(&self, out: &mut T, element_list: &[E], element_def: &ElementDef, header: &Header) -> Result<usize> {
+        try!(check_entry_count(element_list.len(), element_def));
+        let mut written = 0;
+        for element in element_list {
+            written += try!(self.write_element(out, element, element_def, &header.encoding));
         }
         Ok(written)
     }
     /// Write all elments as stored in the `element_list`.
     ///
+    /// Unlike `write_payload_for_element()`, the number of entries is not checked.
     /// Make sure the header and the element definition is consistent with the payload.
     pub fn write_payload_of_element<T: Write>(&self, out: &mut T, element_list: &Vec<E>, element_def: &ElementDef, header: &Header) -> Result<usize> {
         let mut written = 0;
-        match header.encoding {
-            Encoding::Ascii => for element in element_list {
-                written += try!(self.write_ascii_element(out, element, &element_def));
-            },
-            Encoding::BinaryBigEndian => for element in element_list {
-                written += try!(self.write_big_endian_element(out, element, &element_def));
-            },
-            Encoding::BinaryLittleEndian => for element in element_list {
-                written += try!(self.write_little_endian_element(out, element, &element_def));
+        for element in element_list {
+            written += try!(self.write_element(out, element, element_def, &header.encoding));
+        }
+        Ok(written)
+    }
+    /// Writes all elements produced by `elements`, for example an `mpsc::Receiver<E>`.
+    ///
+    /// Lets producer threads generate elements while the writer encodes them,
+    /// the elements never need to be collected in memory.
+    /// Since the header is already written, the number of elements must match `element_def.count`,
+    /// otherwise an error is returned.
+    ///
+    /// Make sure the header and the element definition is consistent with the payload.
+    pub fn write_payload_of_element_iter<T, I>(&self, out: &mut T, elements: I, element_def: &ElementDef, header: &Header) -> Result<usize>
+        where T: Write, I: IntoIterator, I::Item: Borrow<E> {
+        let mut written = 0;
+        let mut count = 0u64;
+        for element in elements {
+            count += 1;
+            if count > element_def.count {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Element `{}` declares {} entries, but more were supplied.", element_def.name, element_def.count)
+                ));
             }
+            written += try!(self.write_element(out, element.borrow(), element_def, &header.encoding));
+        }
+        if count < element_def.count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` declares {} entries, but only {} were supplied.", element_def.name, element_def.count, count)
+            ));
         }
         Ok(written)
     }
+    /// Writes all records produced by `elements` using their `SerializeElement` implementation.
+    ///
+    /// The encoded length of every record is checked against `element_def`:
+    /// it must match exactly if the element only has scalar properties,
+    /// otherwise it must at least hold all scalars and list lengths.
+    /// Ascii records must not contain line breaks, the writer terminates each line.
+    /// As with `write_payload_of_element_iter()`, the number of records must match `element_def.count`.
+    ///
+    /// Make sure the header and the element definition is consistent with the payload.
+    pub fn write_payload_of_serialized<T, I>(&self, out: &mut T, elements: I, element_def: &ElementDef, header: &Header) -> Result<usize>
+        where T: Write, I: IntoIterator, I::Item: SerializeElement {
+        let (min_size, exact) = try!(minimal_binary_size(element_def));
+        let mut buffer = Vec::new();
+        let mut written = 0;
+        let mut count = 0u64;
+        for element in elements {
+            count += 1;
+            if count > element_def.count {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Element `{}` declares {} entries, but more were supplied.", element_def.name, element_def.count)
+                ));
+            }
+            buffer.clear();
+            try!(element.serialize_element(element_def, &header.encoding, &mut buffer));
+            let len = buffer.len() as u64;
+            match header.encoding {
+                Encoding::Ascii => {
+                    if buffer.iter().any(|&b| b == b'\n' || b == b'\r') {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Entry {} of element `{}` contains a line break.", count - 1, element_def.name)
+                        ));
+                    }
+                    buffer.extend_from_slice(self.new_line.as_bytes());
+                },
+                _ => if len < min_size || (exact && len != min_size) {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Entry {} of element `{}` was encoded with {} bytes, but the element definition requires {}{}.",
+                            count - 1, element_def.name, len, if exact { "" } else { "at least " }, min_size)
+                    ));
+                },
+            }
+            try!(out.write_all(&buffer));
+            written += buffer.len();
+            try!(self.report_progress(1, buffer.len()));
+        }
+        if count < element_def.count {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` declares {} entries, but only {} were supplied.", element_def.name, element_def.count, count)
+            ));
+        }
+        Ok(written)
+    }
+    /// Encoding dispatch for a single element, also reports progress.
+    fn write_element<T: Write>(&self, out: &mut T, element: &E, element_def: &ElementDef, encoding: &Encoding) -> Result<usize> {
+        let written = match *encoding {
+            Encoding::Ascii => try!(self.write_ascii_element(out, element, element_def)),
+            Encoding::BinaryBigEndian => try!(self.write_big_endian_element(out, element, element_def)),
+            Encoding::BinaryLittleEndian => try!(self.write_little_endian_element(out, element, element_def)),
+        };
+        try!(self.report_progress(1, written));
+        Ok(written)
+    }
 }
 /*
 use std::io::{ Write, Result, ErrorKind };
@@ -299,7 +761,7 @@ use super::Writer;
 use std::fmt::Display;
 
 macro_rules! get_prop(
-    ($e:expr) => (match $e {None => return Err(io::Error::new(ErrorKind::InvalidInput, "No property available for given key.")), Some(x) => x})
+    ($el:ident . $get:ident ( $k:expr ), $property_def:expr) => (match $el.$get($k) {None => return Err(type_mismatch($el, $k, &$property_def)), Some(x) => x})
 );
 
 /// # Ascii
@@ -310,7 +772,7 @@ impl<E: PropertyAccess> Writer<E> {
         let mut written = 0;
         let mut p_iter = element_def.properties.iter();
         let (_k, prop_type) = p_iter.next().unwrap();
-        written += try!(self.write_ascii_property(out, element, &prop_type));
+        written += try!(self.write_ascii_property(out, element, element_def, &prop_type));
         loop {
             written += try!(out.write(" ".as_bytes()));
             let n = p_iter.next();
@@ -318,37 +780,52 @@ impl<E: PropertyAccess> Writer<E> {
                 break;
             }
             let (_name, prop_type) = n.unwrap();
-            written += try!(self.write_ascii_property(out, element, prop_type));
+            written += try!(self.write_ascii_property(out, element, element_def, prop_type));
         }
         written += try!(self.write_new_line(out));
         Ok(written)
     }
-    fn write_ascii_property<T: Write>(&self, out: &mut T, element: &E, prop_type: &PropertyDef) -> Result<usize> {
+    fn write_ascii_property<T: Write>(&self, out: &mut T, element: &E, element_def: &ElementDef, prop_type: &PropertyDef) -> Result<usize> {
+        if let Some(precision) = self.precision_of(element_def, prop_type) {
+            return self.write_ascii_lossy(out, element, prop_type, &precision);
+        }
         let k = &prop_type.name;
         let result = match prop_type.data_type {
             PropertyType::Scalar(ref scalar_type) => match *scalar_type {
-                ScalarType::Char => self.write_ascii_scalar(out, get_prop!(element.get_char(k))),
-                ScalarType::UChar => self.write_ascii_scalar(out, get_prop!(element.get_uchar(k))),
-                ScalarType::Short => self.write_ascii_scalar(out, get_prop!(element.get_short(k))),
-                ScalarType::UShort => self.write_ascii_scalar(out, get_prop!(element.get_ushort(k))),
-                ScalarType::Int => self.write_ascii_scalar(out, get_prop!(element.get_int(k))),
-                ScalarType::UInt => self.write_ascii_scalar(out, get_prop!(element.get_uint(k))),
-                ScalarType::Float => self.write_ascii_scalar(out, get_prop!(element.get_float(k))),
-                ScalarType::Double => self.write_ascii_scalar(out, get_prop!(element.get_double(k))),
+                ScalarType::Char => self.write_ascii_scalar(out, get_prop!(element.get_char(k), prop_type)),
+                ScalarType::UChar => self.write_ascii_scalar(out, get_prop!(element.get_uchar(k), prop_type)),
+                ScalarType::Short => self.write_ascii_scalar(out, get_prop!(element.get_short(k), prop_type)),
+                ScalarType::UShort => self.write_ascii_scalar(out, get_prop!(element.get_ushort(k), prop_type)),
+                ScalarType::Int => self.write_ascii_scalar(out, get_prop!(element.get_int(k), prop_type)),
+                ScalarType::UInt => self.write_ascii_scalar(out, get_prop!(element.get_uint(k), prop_type)),
+                ScalarType::Float => self.write_ascii_scalar(out, get_prop!(element.get_float(k), prop_type)),
+                ScalarType::Double => self.write_ascii_scalar(out, get_prop!(element.get_double(k), prop_type)),
             },
             PropertyType::List(_, ref scalar_type) => match *scalar_type {
-                ScalarType::Char => self.write_ascii_list(get_prop!(element.get_list_char(k)), out),
-                ScalarType::UChar => self.write_ascii_list(get_prop!(element.get_list_uchar(k)), out),
-                ScalarType::Short => self.write_ascii_list(get_prop!(element.get_list_short(k)), out),
-                ScalarType::UShort => self.write_ascii_list(get_prop!(element.get_list_ushort(k)), out),
-                ScalarType::Int => self.write_ascii_list(get_prop!(element.get_list_int(k)), out),
-                ScalarType::UInt => self.write_ascii_list(get_prop!(element.get_list_uint(k)), out),
-                ScalarType::Float => self.write_ascii_list(get_prop!(element.get_list_float(k)), out),
-                ScalarType::Double => self.write_ascii_list(get_prop!(element.get_list_double(k)), out),
+                ScalarType::Char => self.write_ascii_list(get_prop!(element.get_list_char(k), prop_type), out),
+                ScalarType::UChar => self.write_ascii_list(get_prop!(element.get_list_uchar(k), prop_type), out),
+                ScalarType::Short => self.write_ascii_list(get_prop!(element.get_list_short(k), prop_type), out),
+                ScalarType::UShort => self.write_ascii_list(get_prop!(element.get_list_ushort(k), prop_type), out),
+                ScalarType::Int => self.write_ascii_list(get_prop!(element.get_list_int(k), prop_type), out),
+                ScalarType::UInt => self.write_ascii_list(get_prop!(element.get_list_uint(k), prop_type), out),
+                ScalarType::Float => self.write_ascii_list(get_prop!(element.get_list_float(k), prop_type), out),
+                ScalarType::Double => self.write_ascii_list(get_prop!(element.get_list_double(k), prop_type), out),
             }
         };
         result
     }
+    fn write_ascii_lossy<T: Write>(&self, out: &mut T, element: &E, prop_type: &PropertyDef, precision: &Precision) -> Result<usize> {
+        let values = try!(self.lossy_values(element, prop_type, precision));
+        match prop_type.data_type {
+            PropertyType::Scalar(ScalarType::Float) => self.write_ascii_scalar(out, values[0] as f32),
+            PropertyType::Scalar(_) => self.write_ascii_scalar(out, values[0]),
+            PropertyType::List(_, ScalarType::Float) => {
+                let values: Vec<f32> = values.iter().map(|&v| v as f32).collect();
+                self.write_ascii_list(&values, out)
+            },
+            PropertyType::List(_, _) => self.write_ascii_list(&values, out),
+        }
+    }
     fn write_ascii_scalar<T: Write, V: ToString>(&self, out: &mut T, value: V) -> Result<usize> {
         out.write(value.to_string().as_bytes())
     }
@@ -374,7 +851,7 @@ use byteorder::{ BigEndian, LittleEndian, WriteBytesExt, ByteOrder };
 
 /*
 macro_rules! get_prop(
-    ($e:expr) => (match $e {None => return Err(io::Error::new(ErrorKind::InvalidInput, "No property available for given key.")), Some(x) => x})
+    ($el:ident . $get:ident ( $k:expr ), $property_def:expr) => (match $el.$get($k) {None => return Err(type_mismatch($el, $k, &$property_def)), Some(x) => x})
 );
 // */
 
@@ -392,52 +869,240 @@ impl<E: PropertyAccess> Writer<E> {
     fn __write_binary_element<T: Write, B: ByteOrder>(&self, out: &mut T, element: &E, element_def: &ElementDef) -> Result<usize> {
         let mut written = 0;
         for (k, property_def) in &element_def.properties {
+            if let Some(precision) = self.precision_of(element_def, property_def) {
+                written += try!(self.write_binary_lossy::<T, B>(out, element, property_def, &precision));
+                continue;
+            }
             match property_def.data_type {
                 PropertyType::Scalar(ref scalar_type) => {
                     written += match *scalar_type {
-                        ScalarType::Char => {try!(out.write_i8(get_prop!(element.get_char(k)))); 1},
-                        ScalarType::UChar => {try!(out.write_u8(get_prop!(element.get_uchar(k)))); 1},
-                        ScalarType::Short => {try!(out.write_i16::<B>(get_prop!(element.get_short(k)))); 2},
-                        ScalarType::UShort => {try!(out.write_u16::<B>(get_prop!(element.get_ushort(k)))); 2},
-                        ScalarType::Int => {try!(out.write_i32::<B>(get_prop!(element.get_int(k)))); 4},
-                        ScalarType::UInt => {try!(out.write_u32::<B>(get_prop!(element.get_uint(k)))); 4},
-                        ScalarType::Float => {try!(out.write_f32::<B>(get_prop!(element.get_float(k)))); 4},
-                        ScalarType::Double => {try!(out.write_f64::<B>(get_prop!(element.get_double(k)))); 8},
+                        ScalarType::Char => {try!(out.write_i8(get_prop!(element.get_char(k), property_def))); 1},
+                        ScalarType::UChar => {try!(out.write_u8(get_prop!(element.get_uchar(k), property_def))); 1},
+                        ScalarType::Short => {try!(out.write_i16::<B>(get_prop!(element.get_short(k), property_def))); 2},
+                        ScalarType::UShort => {try!(out.write_u16::<B>(get_prop!(element.get_ushort(k), property_def))); 2},
+                        ScalarType::Int => {try!(out.write_i32::<B>(get_prop!(element.get_int(k), property_def))); 4},
+                        ScalarType::UInt => {try!(out.write_u32::<B>(get_prop!(element.get_uint(k), property_def))); 4},
+                        ScalarType::Float => {try!(out.write_f32::<B>(get_prop!(element.get_float(k), property_def))); 4},
+                        ScalarType::Double => {try!(out.write_f64::<B>(get_prop!(element.get_double(k), property_def))); 8},
                     };
                 },
                 PropertyType::List(ref index_type, ref scalar_type) => {
-                    let vec_len = element_def.count;
-                    written += match *index_type {
-                        ScalarType::Char => {try!(out.write_i8(vec_len as i8)); 1},
-                        ScalarType::UChar => {try!(out.write_u8(vec_len as u8)); 1},
-                        ScalarType::Short => {try!(out.write_i16::<B>(vec_len as i16)); 2},
-                        ScalarType::UShort => {try!(out.write_u16::<B>(vec_len as u16)); 2},
-                        ScalarType::Int => {try!(out.write_i32::<B>(vec_len as i32)); 4},
-                        ScalarType::UInt => {try!(out.write_u32::<B>(vec_len as u32)); 4},
-                        ScalarType::Float => return Err(io::Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, float declared in PropertyType.")),
-                        ScalarType::Double => return Err(io::Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, double declared in PropertyType.")),
-                    };
-
                     written += match *scalar_type {
-                        ScalarType::Char => try!(self.write_binary_list::<T, i8, B>(get_prop!(element.get_list_char(k)), out, &|o, x| {try!(o.write_i8(*x)); Ok(1)} )),
-                        ScalarType::UChar => try!(self.write_binary_list::<T, u8, B>(get_prop!(element.get_list_uchar(k)), out, &|o, x| {try!(o.write_u8(*x)); Ok(1)} )),
-                        ScalarType::Short => try!(self.write_binary_list::<T, i16, B>(get_prop!(element.get_list_short(k)), out, &|o, x| {try!(o.write_i16::<B>(*x)); Ok(2)} )),
-                        ScalarType::UShort => try!(self.write_binary_list::<T, u16, B>(get_prop!(element.get_list_ushort(k)), out, &|o, x| {try!(o.write_u16::<B>(*x)); Ok(2)} )),
-                        ScalarType::Int => try!(self.write_binary_list::<T, i32, B>(get_prop!(element.get_list_int(k)), out, &|o, x| {try!(o.write_i32::<B>(*x)); Ok(4)} )),
-                        ScalarType::UInt => try!(self.write_binary_list::<T, u32, B>(get_prop!(element.get_list_uint(k)), out, &|o, x| {try!(o.write_u32::<B>(*x)); Ok(4)} )),
-                        ScalarType::Float => try!(self.write_binary_list::<T, f32, B>(get_prop!(element.get_list_float(k)), out, &|o, x| {try!(o.write_f32::<B>(*x)); Ok(4)} )),
-                        ScalarType::Double => try!(self.write_binary_list::<T, f64, B>(get_prop!(element.get_list_double(k)), out, &|o, x| {try!(o.write_f64::<B>(*x)); Ok(8)} )),
+                        ScalarType::Char => try!(self.write_binary_list::<T, i8, B>(get_prop!(element.get_list_char(k), property_def), index_type, out, &|o, x| {try!(o.write_i8(*x)); Ok(1)} )),
+                        ScalarType::UChar => try!(self.write_binary_list::<T, u8, B>(get_prop!(element.get_list_uchar(k), property_def), index_type, out, &|o, x| {try!(o.write_u8(*x)); Ok(1)} )),
+                        ScalarType::Short => try!(self.write_binary_list::<T, i16, B>(get_prop!(element.get_list_short(k), property_def), index_type, out, &|o, x| {try!(o.write_i16::<B>(*x)); Ok(2)} )),
+                        ScalarType::UShort => try!(self.write_binary_list::<T, u16, B>(get_prop!(element.get_list_ushort(k), property_def), index_type, out, &|o, x| {try!(o.write_u16::<B>(*x)); Ok(2)} )),
+                        ScalarType::Int => try!(self.write_binary_list::<T, i32, B>(get_prop!(element.get_list_int(k), property_def), index_type, out, &|o, x| {try!(o.write_i32::<B>(*x)); Ok(4)} )),
+                        ScalarType::UInt => try!(self.write_binary_list::<T, u32, B>(get_prop!(element.get_list_uint(k), property_def), index_type, out, &|o, x| {try!(o.write_u32::<B>(*x)); Ok(4)} )),
+                        ScalarType::Float => try!(self.write_binary_list::<T, f32, B>(get_prop!(element.get_list_float(k), property_def), index_type, out, &|o, x| {try!(o.write_f32::<B>(*x)); Ok(4)} )),
+                        ScalarType::Double => try!(self.write_binary_list::<T, f64, B>(get_prop!(element.get_list_double(k), property_def), index_type, out, &|o, x| {try!(o.write_f64::<B>(*x)); Ok(8)} )),
                     }
                 }
             }
         };
         Ok(written)
     }
-    fn write_binary_list<T: Write, D, B: ByteOrder>(&self, list: &[D], out: &mut T, out_val: &Fn(&mut T, &D) -> Result<usize>) -> Result<usize> {
-        let mut written = 0;
+    fn write_binary_lossy<T: Write, B: ByteOrder>(&self, out: &mut T, element: &E, property_def: &PropertyDef, precision: &Precision) -> Result<usize> {
+        let values = try!(self.lossy_values(element, property_def, precision));
+        match property_def.data_type {
+            PropertyType::Scalar(ScalarType::Float) => {try!(out.write_f32::<B>(values[0] as f32)); Ok(4)},
+            PropertyType::Scalar(_) => {try!(out.write_f64::<B>(values[0])); Ok(8)},
+            PropertyType::List(ref index_type, ScalarType::Float) => self.write_binary_list::<T, f64, B>(&values, index_type, out, &|o, x| {try!(o.write_f32::<B>(*x as f32)); Ok(4)}),
+            PropertyType::List(ref index_type, _) => self.write_binary_list::<T, f64, B>(&values, index_type, out, &|o, x| {try!(o.write_f64::<B>(*x)); Ok(8)}),
+        }
+    }
+    fn write_binary_list<T: Write, D, B: ByteOrder>(&self, list: &[D], index_type: &ScalarType, out: &mut T, out_val: &Fn(&mut T, &D) -> Result<usize>) -> Result<usize> {
+        let mut written = try!(self.write_binary_list_len::<T, B>(out, list.len(), index_type));
         for v in list {
             written += try!(out_val(out, v));
         }
         Ok(written)
     }
+    fn binary_element_size(&self, element: &E, element_def: &ElementDef) -> Result<u64> {
+        let mut size = 0;
+        for (k, property_def) in &element_def.properties {
+            let property_size = match property_def.data_type {
+                PropertyType::Scalar(ref scalar_type) => scalar_type.size_bytes() as u64,
+                PropertyType::List(ref index_type, ref scalar_type) => {
+                    let len = match *scalar_type {
+                        ScalarType::Char => get_prop!(element.get_list_char(k), property_def).len(),
+                        ScalarType::UChar => get_prop!(element.get_list_uchar(k), property_def).len(),
+                        ScalarType::Short => get_prop!(element.get_list_short(k), property_def).len(),
+                        ScalarType::UShort => get_prop!(element.get_list_ushort(k), property_def).len(),
+                        ScalarType::Int => get_prop!(element.get_list_int(k), property_def).len(),
+                        ScalarType::UInt => get_prop!(element.get_list_uint(k), property_def).len(),
+                        ScalarType::Float => get_prop!(element.get_list_float(k), property_def).len(),
+                        ScalarType::Double => get_prop!(element.get_list_double(k), property_def).len(),
+                    };
+                    try!(list_size(index_type, len as u64, scalar_type))
+                }
+            };
+            size = try!(checked_add(size, property_size));
+        }
+        Ok(size)
+    }
+    fn write_binary_list_len<T: Write, B: ByteOrder>(&self, out: &mut T, vec_len: usize, index_type: &ScalarType) -> Result<usize> {
+        if index_type.is_integer() && vec_len as f64 > index_type.max_value() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("List of {} entries exceeds the range of its index type {:?}.", vec_len, index_type)
+            ));
+        }
+        let written = match *index_type {
+            ScalarType::Char => {try!(out.write_i8(vec_len as i8)); 1},
+            ScalarType::UChar => {try!(out.write_u8(vec_len as u8)); 1},
+            ScalarType::Short => {try!(out.write_i16::<B>(vec_len as i16)); 2},
+            ScalarType::UShort => {try!(out.write_u16::<B>(vec_len as u16)); 2},
+            ScalarType::Int => {try!(out.write_i32::<B>(vec_len as i32)); 4},
+            ScalarType::UInt => {try!(out.write_u32::<B>(vec_len as u32)); 4},
+            ScalarType::Float => return Err(io::Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, float declared in PropertyType.")),
+            ScalarType::Double => return Err(io::Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, double declared in PropertyType.")),
+        };
+        Ok(written)
+    }
+}
+
+/// Error for a missing property value, names the type found under the property's name if any.
+fn type_mismatch<E: PropertyAccess>(element: &E, name: &String, property_def: &PropertyDef) -> io::Error {
+    let scalar = if element.get_char(name).is_some() { Some(ScalarType::Char) }
+        else if element.get_uchar(name).is_some() { Some(ScalarType::UChar) }
+        else if element.get_short(name).is_some() { Some(ScalarType::Short) }
+        else if element.get_ushort(name).is_some() { Some(ScalarType::UShort) }
+        else if element.get_int(name).is_some() { Some(ScalarType::Int) }
+        else if element.get_uint(name).is_some() { Some(ScalarType::UInt) }
+        else if element.get_float(name).is_some() { Some(ScalarType::Float) }
+        else if element.get_double(name).is_some() { Some(ScalarType::Double) }
+        else { None };
+    let list = if element.get_list_char(name).is_some() { Some(ScalarType::Char) }
+        else if element.get_list_uchar(name).is_some() { Some(ScalarType::UChar) }
+        else if element.get_list_short(name).is_some() { Some(ScalarType::Short) }
+        else if element.get_list_ushort(name).is_some() { Some(ScalarType::UShort) }
+        else if element.get_list_int(name).is_some() { Some(ScalarType::Int) }
+        else if element.get_list_uint(name).is_some() { Some(ScalarType::UInt) }
+        else if element.get_list_float(name).is_some() { Some(ScalarType::Float) }
+        else if element.get_list_double(name).is_some() { Some(ScalarType::Double) }
+        else { None };
+    let found = match (scalar, list) {
+        (Some(t), _) => Some(t.name().to_string()),
+        (None, Some(t)) => Some(format!("list of {}", t.name())),
+        (None, None) => None,
+    };
+    PlyError::TypeMismatch { property: name.clone(), expected: property_def.data_type.clone(), found: found }.into()
+}
+
+/// Rounds `value` to `digits` significant decimal digits.
+fn round_significant(value: f64, digits: u32) -> f64 {
+    if value == 0.0 || !value.is_finite() || digits == 0 {
+        return value;
+    }
+    let exponent = digits as i32 - 1 - value.abs().log10().floor() as i32;
+    // Scale by an exact power of ten, so e.g. 0.123 comes out as the double closest to 0.123.
+    let rounded = if exponent >= 0 {
+        let scale = 10f64.powi(exponent);
+        (value * scale).round() / scale
+    } else {
+        let scale = 10f64.powi(-exponent);
+        (value / scale).round() * scale
+    };
+    if rounded.is_finite() { rounded } else { value }
+}
+
+/// Smallest binary size of an entry of `element_def`, that is with empty lists,
+/// and whether all entries have exactly this size.
+fn minimal_binary_size(element_def: &ElementDef) -> Result<(u64, bool)> {
+    let mut size = 0;
+    let mut exact = true;
+    for (_, property_def) in &element_def.properties {
+        let property_size = match property_def.data_type {
+            PropertyType::Scalar(ref scalar_type) => scalar_type.size_bytes() as u64,
+            PropertyType::List(ref index_type, _) => {
+                exact = false;
+                index_type.size_bytes() as u64
+            },
+        };
+        size = try!(checked_add(size, property_size));
+    }
+    Ok((size, exact))
+}
+
+/// Size of a binary list with `len` entries, including the length prefix.
+fn list_size(index_type: &ScalarType, len: u64, scalar_type: &ScalarType) -> Result<u64> {
+    let content = match len.checked_mul(scalar_type.size_bytes() as u64) {
+        Some(c) => c,
+        None => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Size of list with {} entries overflows.", len))),
+    };
+    checked_add(index_type.size_bytes() as u64, content)
+}
+
+/// `header` without the checksums recorded by `Writer::set_element_crcs()`.
+///
+/// They only hold for the payload they were computed from, a rewritten payload could make them stale.
+fn without_crcs<'a>(header: &'a Header) -> Cow<'a, Header> {
+    if !header.obj_infos.iter().any(|o| split_crc_obj_info(o).is_some()) {
+        return Cow::Borrowed(header);
+    }
+    let mut header = header.clone();
+    header.obj_infos.retain(|o| split_crc_obj_info(o).is_none());
+    Cow::Owned(header)
+}
+
+/// Fails if `payload` holds an element the header doesn't declare.
+fn check_declared<E>(payload: &Payload<E>, header: &Header) -> Result<()> {
+    for k in payload.keys() {
+        if !header.elements.contains_key(k) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` is not declared in the header.", k)));
+        }
+    }
+    Ok(())
+}
+/// Entries of the element `name`, fails if they are missing.
+fn element_list_of<'a, E>(payload: &'a Payload<E>, name: &str) -> Result<&'a Vec<E>> {
+    match payload.get(name) {
+        Some(l) => Ok(l),
+        None => Err(io::Error::new(ErrorKind::InvalidInput, format!("Payload for element `{}` is missing.", name))),
+    }
+}
+/// Fails if `len` differs from the number of entries declared by `element_def`.
+fn check_entry_count(len: usize, element_def: &ElementDef) -> Result<()> {
+    if len as u64 != element_def.count {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Element `{}` declares {} entries, but {} were supplied.", element_def.name, element_def.count, len)
+        ));
+    }
+    Ok(())
+}
+fn checked_add(a: u64, b: u64) -> Result<u64> {
+    match a.checked_add(b) {
+        Some(s) => Ok(s),
+        None => Err(io::Error::new(ErrorKind::InvalidInput, "Output size overflows a 64 bit integer.")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ Writer, list_size, checked_add, round_significant };
+    use ply::{ ScalarType, DefaultElement };
+    #[test]
+    fn writer_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Writer<DefaultElement>>();
+    }
+    #[test]
+    fn round_significant_ok() {
+        assert_eq!(round_significant(0.123456, 3), 0.123);
+        assert_eq!(round_significant(-98765.4, 2), -99000.0);
+        assert!((round_significant(1.5e-300, 1) - 2e-300).abs() < 1e-310);
+        assert_eq!(round_significant(0.0, 3), 0.0);
+    }
+    #[test]
+    fn list_size_ok() {
+        assert_eq!(list_size(&ScalarType::UChar, 3, &ScalarType::Double).unwrap(), 25);
+    }
+    #[test]
+    fn size_overflow_err() {
+        assert!(list_size(&ScalarType::UInt, u64::max_value() / 2, &ScalarType::Int).is_err());
+        assert!(list_size(&ScalarType::UInt, u64::max_value() / 4, &ScalarType::Int).is_err());
+        assert!(checked_add(u64::max_value(), 1).is_err());
+    }
 }
@@ -0,0 +1,117 @@
+//! Writes the payload entry by entry instead of from a complete `Ply`, see `Writer::begin_ply()`.
+
+use std::io;
+use std::io::{ Write, Result, ErrorKind };
+
+use ply::{ PropertyAccess, Header, ElementDef, Encoding };
+use super::{ Writer, without_crcs };
+
+/// Writer accepting the entries of a PLY file one after the other, created by `Writer::begin_ply()`.
+///
+/// Entries must be written in header order, exactly as many as declared for each element.
+///
+/// # Examples
+///
+/// ```rust
+/// # use ply_rs::ply::{ Header, ElementDef, PropertyDef, PropertyType, ScalarType, DefaultElement, Property, Addable };
+/// # use ply_rs::writer::Writer;
+/// let mut header = Header::new();
+/// let mut vertex = ElementDef::new("vertex".to_string());
+/// vertex.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+/// vertex.count = 1000;
+/// header.elements.add(vertex);
+///
+/// let writer = Writer::new();
+/// let mut out = writer.begin_ply(Vec::<u8>::new(), &header).unwrap();
+/// for i in 0..1000 {
+///     let mut v = DefaultElement::new();
+///     v.insert("x".to_string(), Property::Float(i as f32));
+///     out.write_element("vertex", &v).unwrap();
+/// }
+/// let bytes = out.finish().unwrap();
+/// ```
+pub struct PlyWriter<'a, W: Write, E: PropertyAccess + 'a> {
+    writer: &'a Writer<E>,
+    out: W,
+    encoding: Encoding,
+    elements: Vec<ElementDef>,
+    current: usize,
+    entries: u64,
+    written: usize,
+}
+
+impl<E: PropertyAccess> Writer<E> {
+    /// Checks and writes `header`, then returns a writer for the payload.
+    ///
+    /// Unlike `write_ply()`, no complete `Ply` is needed, entries are passed to `PlyWriter::write_element()`.
+    /// The header is written as given, `set_property_order()` and precision downcasts don't apply to it.
+    /// Only checksums recorded by `set_element_crcs()` are dropped, they may not match the entries to come.
+    /// Progress is reported as usual.
+    pub fn begin_ply<'a, W: Write>(&'a self, mut out: W, header: &Header) -> Result<PlyWriter<'a, W, E>> {
+        if let Err(e) = header.check() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("The given header isn't consistent: {:?}", e)));
+        }
+        self.reset_progress();
+        let written = try!(self.write_header(&mut out, &without_crcs(header)));
+        try!(self.report_progress(0, written));
+        Ok(PlyWriter {
+            writer: self,
+            out: out,
+            encoding: header.encoding,
+            elements: header.elements.values().cloned().collect(),
+            current: 0,
+            entries: 0,
+            written: written,
+        })
+    }
+}
+
+impl<'a, W: Write, E: PropertyAccess> PlyWriter<'a, W, E> {
+    /// Writes the next entry, which must belong to `element_name`.
+    ///
+    /// Once all declared entries of an element are written, the writer moves on to the next element.
+    /// Returns number of bytes written.
+    pub fn write_element(&mut self, element_name: &str, element: &E) -> Result<usize> {
+        self.skip_complete();
+        let element_def = match self.elements.get(self.current) {
+            Some(d) => d,
+            None => return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Entry of element `{}` exceeds the counts declared in the header.", element_name)
+            )),
+        };
+        if element_def.name != element_name {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected entry {} of element `{}`, but got one of `{}`.", self.entries, element_def.name, element_name)
+            ));
+        }
+        let written = try!(self.writer.write_element(&mut self.out, element, element_def, &self.encoding));
+        self.entries += 1;
+        self.written += written;
+        Ok(written)
+    }
+    /// Number of bytes written so far, header included.
+    pub fn bytes_written(&self) -> usize {
+        self.written
+    }
+    /// Verifies that all declared entries were written, flushes and returns the output.
+    pub fn finish(mut self) -> Result<W> {
+        self.skip_complete();
+        if let Some(element_def) = self.elements.get(self.current) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Element `{}` declares {} entries, but only {} were written.", element_def.name, element_def.count, self.entries)
+            ));
+        }
+        try!(self.out.flush());
+        Ok(self.out)
+    }
+    /// Moves past all elements whose declared entries are written.
+    fn skip_complete(&mut self) {
+        while self.current < self.elements.len() && self.entries == self.elements[self.current].count {
+            self.current += 1;
+            self.entries = 0;
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Writes collections of typed entries without assembling a `Ply`, see `write_typed()`.
+
+use std::io;
+use std::io::{ Write, Result, ErrorKind };
+
+use ply::{ PropertyAccess, ToElementDef, Header, ElementDef, Encoding, DefaultElement, Addable, Error as PlyError };
+use super::Writer;
+
+/// Entries of one element, as passed to `write_typed()`.
+///
+/// Implemented for slices and vectors of types implementing both `ToElementDef` and `PropertyAccess`.
+pub trait TypedElements {
+    /// Definition of the element named `name`, with the number of entries as count.
+    fn element_def(&self, name: &str) -> ElementDef;
+    /// Writes all entries in `encoding`, returns the number of bytes written.
+    fn write_entries(&self, out: &mut Write, element_def: &ElementDef, encoding: &Encoding) -> Result<usize>;
+}
+
+impl<T: ToElementDef + PropertyAccess> TypedElements for [T] {
+    fn element_def(&self, name: &str) -> ElementDef {
+        T::element_def(name, self.len() as u64)
+    }
+    fn write_entries(&self, mut out: &mut Write, element_def: &ElementDef, encoding: &Encoding) -> Result<usize> {
+        let writer = Writer::<T>::new();
+        let mut written = 0;
+        for (i, entry) in self.iter().enumerate() {
+            written += match writer.write_element(&mut out, entry, element_def, encoding) {
+                Ok(w) => w,
+                Err(e) => return Err(match PlyError::from_io(&e) {
+                    Some(_) => e,
+                    None => io::Error::new(
+                        e.kind(),
+                        format!("Couldn't write entry {} of element `{}`.\n\tError: {}", i, element_def.name, e)
+                    ),
+                }),
+            };
+        }
+        Ok(written)
+    }
+}
+
+impl<T: ToElementDef + PropertyAccess> TypedElements for Vec<T> {
+    fn element_def(&self, name: &str) -> ElementDef {
+        self[..].element_def(name)
+    }
+    fn write_entries(&self, out: &mut Write, element_def: &ElementDef, encoding: &Encoding) -> Result<usize> {
+        self[..].write_entries(out, element_def, encoding)
+    }
+}
+
+/// Writes a complete PLY file from typed entries, in the order given.
+///
+/// The header is built from the `ToElementDef` implementations and the number of entries,
+/// so it always matches the data. Returns number of bytes written.
+///
+/// # Examples
+///
+/// ```rust
+/// #[macro_use]
+/// extern crate ply_rs;
+/// use ply_rs::ply::{ PropertyAccess, Encoding };
+/// use ply_rs::writer::write_typed;
+///
+/// struct Vertex { x: f32, y: f32 }
+/// impl_to_element_def!(Vertex { x: f32, y: f32 });
+/// impl PropertyAccess for Vertex {
+///     fn new() -> Self { Vertex { x: 0.0, y: 0.0 } }
+///     fn get_float(&self, key: &String) -> Option<f32> {
+///         match key.as_ref() { "x" => Some(self.x), "y" => Some(self.y), _ => None }
+///     }
+/// }
+///
+/// fn main() {
+///     let vertices = vec![Vertex { x: 0.0, y: 0.0 }, Vertex { x: 1.0, y: 0.0 }];
+///     let mut buf = Vec::<u8>::new();
+///     write_typed(&mut buf, Encoding::Ascii, &[("vertex", &vertices)]).unwrap();
+/// }
+/// ```
+pub fn write_typed<W: Write>(out: &mut W, encoding: Encoding, elements: &[(&str, &TypedElements)]) -> Result<usize> {
+    let mut header = Header::new();
+    header.encoding = encoding;
+    for &(name, entries) in elements {
+        if header.elements.contains_key(name) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Element `{}` is given twice.", name)));
+        }
+        header.elements.add(entries.element_def(name));
+    }
+    if let Err(e) = header.check() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("The resulting header isn't consistent: {:?}", e)));
+    }
+    let mut written = try!(Writer::<DefaultElement>::new().write_header(out, &header));
+    for &(name, entries) in elements {
+        written += try!(entries.write_entries(out, &header.elements[name], &encoding));
+    }
+    Ok(written)
+}
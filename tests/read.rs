@@ -65,6 +65,324 @@ fn read_all_atomic_types_ok() {
     let ply = read_file("example_plys/all_atomic_types_ok_ascii.ply");
     println!("Created ply: {:?}", ply);
 }
+#[test]
+fn scan_headers() {
+    let paths = vec![
+        "example_plys/house_ok_ascii.ply",
+        "example_plys/does_not_exist.ply",
+        "example_plys/house_2_ok_little_endian.ply",
+    ];
+    let p = parser::Parser::<ply::DefaultElement>::new();
+    let headers = p.scan_header_files(&paths);
+    assert_eq!(headers.len(), 3);
+    assert_eq!(headers[0].as_ref().unwrap().elements["face"].count, 3);
+    match ply::Error::from_io(headers[1].as_ref().unwrap_err()) {
+        Some(&ply::Error::File { ref path, .. }) => assert_eq!(path, "example_plys/does_not_exist.ply"),
+        e => panic!("Expected the error to name the file, got {:?}", e),
+    }
+    assert_eq!(headers[2].as_ref().unwrap().encoding, ply::Encoding::BinaryLittleEndian);
+
+    let sources = vec!["ply\nformat ascii 1.0\nend_header\n".as_bytes(), "ply\nend_header\n".as_bytes()];
+    let headers = p.scan_headers(sources);
+    assert!(headers[0].is_ok());
+    assert!(headers[1].is_err());
+}
+#[cfg(feature = "rayon")]
+#[test]
+fn scan_headers_parallel() {
+    let paths = vec![
+        "example_plys/house_ok_ascii.ply",
+        "example_plys/does_not_exist.ply",
+        "example_plys/house_2_ok_little_endian.ply",
+    ];
+    let p = parser::Parser::<ply::DefaultElement>::new();
+    let sequential = p.scan_header_files(&paths);
+    let parallel = p.scan_header_files_parallel(&paths);
+    assert_eq!(parallel.len(), 3);
+    assert_eq!(parallel[0].as_ref().unwrap(), sequential[0].as_ref().unwrap());
+    assert!(parallel[1].is_err());
+    assert_eq!(parallel[2].as_ref().unwrap(), sequential[2].as_ref().unwrap());
+}
+#[test]
+fn read_hashed() {
+    use std::hash::Hasher;
+    use std::collections::hash_map::DefaultHasher;
+    use std::io::Read;
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        let split = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        let mut expected_header = DefaultHasher::new();
+        expected_header.write(&bytes[..split]);
+        let mut expected_payload = DefaultHasher::new();
+        expected_payload.write(&bytes[split..]);
+
+        let p = parser::Parser::<ply::DefaultElement>::new();
+        let mut header_hasher = DefaultHasher::new();
+        let mut payload_hasher = DefaultHasher::new();
+        let ply = p.read_ply_hashed(&mut std::fs::File::open(path).unwrap(), &mut header_hasher, &mut payload_hasher).unwrap();
+        assert_eq!(ply, read_file(path));
+        assert_eq!(header_hasher.finish(), expected_header.finish());
+        assert_eq!(payload_hasher.finish(), expected_payload.finish());
+    }
+}
+#[test]
+fn read_with_element_decoder() {
+    use std::io::{ Read, BufRead, BufReader };
+    fn read_f32(r: &mut Read) -> std::io::Result<f32> {
+        let mut b = [0u8; 4];
+        try!(r.read_exact(&mut b));
+        Ok(f32::from_bits(u32::from_le_bytes(b)))
+    }
+    let mut p = parser::Parser::<ply::DefaultElement>::new();
+    p.set_element_decoder("vertex", |_def, r: &mut Read| {
+        let mut e = ply::DefaultElement::new();
+        // swap x and y to tell decoded elements apart
+        let x = try!(read_f32(r));
+        e.insert("y".to_string(), ply::Property::Float(x));
+        e.insert("x".to_string(), ply::Property::Float(try!(read_f32(r))));
+        e.insert("z".to_string(), ply::Property::Float(try!(read_f32(r))));
+        Ok(e)
+    });
+    let path = "example_plys/house_2_ok_little_endian.ply";
+    let decoded = p.read_ply(&mut std::fs::File::open(path).unwrap()).unwrap();
+    let expected = read_file(path);
+    assert_eq!(decoded.payload["face"], expected.payload["face"]);
+    for (d, e) in decoded.payload["vertex"].iter().zip(expected.payload["vertex"].iter()) {
+        assert_eq!(d["x"], e["y"]);
+        assert_eq!(d["y"], e["x"]);
+        assert_eq!(d["z"], e["z"]);
+    }
+
+    let mut p = parser::Parser::<ply::DefaultElement>::new();
+    p.set_element_decoder("face", |_def, r: &mut Read| {
+        let mut line = String::new();
+        try!(BufReader::new(r).read_line(&mut line));
+        let mut e = ply::DefaultElement::new();
+        e.insert("n".to_string(), ply::Property::UInt(line.split_whitespace().count() as u32));
+        Ok(e)
+    });
+    let decoded = p.read_ply(&mut std::fs::File::open("example_plys/house_2_ok_ascii.ply").unwrap()).unwrap();
+    assert_eq!(decoded.payload["face"][0]["n"], ply::Property::UInt(4));
+}
+#[test]
+fn read_with_derived_property() {
+    let mut p = parser::Parser::<ply::DefaultElement>::new();
+    let range = ply::PropertyDef::new("range".to_string(), ply::PropertyType::Scalar(ply::ScalarType::Float));
+    p.add_derived_property("vertex", range, |e| {
+        let c = |k: &str| match e[k] { ply::Property::Float(v) => v, _ => 0.0 };
+        ply::Property::Float((c("x") * c("x") + c("y") * c("y") + c("z") * c("z")).sqrt())
+    });
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let mut ply = p.read_ply(&mut std::fs::File::open(path).unwrap()).unwrap();
+        assert!(ply.header.elements["vertex"].properties.contains_key("range"));
+        assert_eq!(ply.payload["vertex"][0]["range"], ply::Property::Float(2f32.sqrt()));
+        assert!(!ply.payload["face"][0].contains_key("range"));
+        assert!(ply.make_consistent().is_ok());
+    }
+}
+#[test]
+fn read_with_buffer_sizes() {
+    let path = "example_plys/house_2_ok_little_endian.ply";
+    let expected = read_file(path);
+    for size in &[parser::BufferSize::Fixed(1), parser::BufferSize::Fixed(100), parser::BufferSize::Adaptive] {
+        let mut p = parser::Parser::<ply::DefaultElement>::new();
+        p.set_buffer_size(*size);
+        assert_eq!(p.read_ply(&mut std::fs::File::open(path).unwrap()).unwrap(), expected);
+    }
+    let mut header = expected.header.clone();
+    assert_eq!(parser::BufferSize::Adaptive.capacity_for(&header), parser::DEFAULT_BUFFER_SIZE);
+    header.elements.get_mut("vertex").unwrap().count = 1 << 40;
+    assert_eq!(parser::BufferSize::Adaptive.capacity_for(&header), parser::MAX_ADAPTIVE_BUFFER_SIZE);
+}
+#[test]
+fn read_fixed_floats() {
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let expected = read_file(path);
+        let p = parser::Parser::<ply::DefaultElement>::new();
+        let mut f = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        let header = p.read_header(&mut f).unwrap();
+        let vertex_def = &header.elements["vertex"];
+        assert!(p.read_fixed::<_, 2>(&mut f, vertex_def, &header).is_err());
+        let vertices = p.read_fixed::<_, 3>(&mut f, vertex_def, &header).unwrap();
+        assert_eq!(vertices.len(), 5);
+        for (v, e) in vertices.iter().zip(&expected.payload["vertex"]) {
+            assert_eq!(ply::Property::Float(v[0]), e["x"]);
+            assert_eq!(ply::Property::Float(v[2]), e["z"]);
+        }
+        assert!(p.read_fixed::<_, 1>(&mut f, &header.elements["face"], &header).is_err());
+    }
+}
+#[test]
+fn read_iter() {
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let expected = read_file(path);
+        let p = parser::Parser::<ply::DefaultElement>::new();
+        let mut f = std::fs::File::open(path).unwrap();
+        let mut reader = p.read_ply_iter(&mut f).unwrap();
+        assert_eq!(reader.header(), &expected.header);
+        let mut payload = ply::Payload::new();
+        while let Some(entries) = reader.next_element().unwrap() {
+            let name = entries.element_def().name.clone();
+            assert_eq!(entries.size_hint().0, expected.payload[&name].len());
+            payload.insert(name, entries.collect::<Result<Vec<_>, _>>().unwrap());
+        }
+        assert_eq!(payload, expected.payload);
+        assert!(reader.next_element().unwrap().is_none());
+
+        // Unread entries are skipped.
+        let mut f = std::fs::File::open(path).unwrap();
+        let mut reader = p.read_ply_iter(&mut f).unwrap();
+        reader.next_element().unwrap().unwrap().next();
+        let faces = reader.next_element().unwrap().unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(faces, expected.payload["face"]);
+    }
+}
+#[test]
+fn read_iter_spans() {
+    let p = parser::Parser::<ply::DefaultElement>::new();
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let ascii = path.ends_with("ascii.ply");
+        let bytes = std::fs::read(path).unwrap();
+        let mut reader = p.read_ply_iter(bytes.as_slice()).unwrap();
+        let mut end = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() as u64 + 11;
+        while let Some(entries) = reader.next_element().unwrap() {
+            let element_def = entries.element_def().clone();
+            for entry in entries.with_spans() {
+                let (span, e) = entry.unwrap();
+                assert_eq!(span.offset, end);
+                assert_eq!(span.line.is_some(), ascii);
+                end = span.offset + span.len;
+                let mut raw = &bytes[span.offset as usize..end as usize];
+                let expected = if ascii {
+                    p.read_ascii_element(std::str::from_utf8(raw).unwrap(), &element_def).unwrap()
+                } else {
+                    p.read_little_endian_element(&mut raw, &element_def).unwrap()
+                };
+                assert_eq!(e, expected);
+            }
+        }
+        assert_eq!(end, bytes.len() as u64);
+    }
+    let data = b"ply\nformat ascii 1.0\nelement v 2\nproperty int a\nend_header\n1\n2\n";
+    let mut reader = p.read_ply_iter(&data[..]).unwrap();
+    let lines: Vec<_> = reader.next_element().unwrap().unwrap().with_spans().map(|e| e.unwrap().0.line).collect();
+    assert_eq!(lines, vec![Some(6), Some(7)]);
+}
+#[test]
+fn read_iter_element_crcs() {
+    let mut p = parser::Parser::<ply::DefaultElement>::new();
+    p.set_verify_element_crcs(true);
+    let mut w = writer::Writer::new();
+    w.set_element_crcs(true);
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let mut ply = read_file(path);
+        let mut bytes = Vec::new();
+        w.write_ply(&mut bytes, &mut ply).unwrap();
+        {
+            let mut reader = p.read_ply_iter(bytes.as_slice()).unwrap();
+            assert_eq!(reader.header().obj_infos.iter().filter(|o| o.starts_with("crc32 ")).count(), 2);
+            while let Some(entries) = reader.next_element().unwrap() {
+                assert!(entries.map(|e| e.unwrap()).count() > 0);
+            }
+        }
+        // Corrupt the last entry of the face element, vertices remain intact.
+        let last = bytes.len() - 2;
+        bytes[last] = if bytes[last] == b'1' { b'2' } else { b'1' };
+        let mut reader = p.read_ply_iter(bytes.as_slice()).unwrap();
+        assert!(reader.next_element().unwrap().unwrap().all(|e| e.is_ok()));
+        let e = reader.next_element().unwrap().unwrap().last().unwrap().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        match ply::Error::from_io(&e) {
+            Some(&ply::Error::ChecksumMismatch { ref element, expected, found }) => {
+                assert_eq!(element, "face");
+                assert!(expected != found);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(reader.next_element().unwrap().is_none());
+        drop(reader);
+        if path.ends_with("ascii.ply") {
+            // A last entry that fails to decode reports the decode error, not the checksum.
+            bytes[last] = b'x';
+            let mut reader = p.read_ply_iter(bytes.as_slice()).unwrap();
+            assert!(reader.next_element().unwrap().unwrap().all(|e| e.is_ok()));
+            let e = reader.next_element().unwrap().unwrap().last().unwrap().unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(e.to_string().contains("Couldn't read entry 2 of element `face`"));
+        }
+    }
+    // Checksums read from a file are dropped when writing without them, the payload may have changed.
+    let mut ply = p.read_ply(&mut std::fs::File::open("example_plys/house_2_ok_ascii.ply").unwrap()).unwrap();
+    ply.header.obj_infos.push("crc32 face 00000000".to_string());
+    let mut bytes = Vec::new();
+    writer::Writer::new().write_ply(&mut bytes, &mut ply).unwrap();
+    let mut reader = p.read_ply_iter(bytes.as_slice()).unwrap();
+    assert!(reader.header().obj_infos.is_empty());
+    while let Some(mut entries) = reader.next_element().unwrap() {
+        assert!(entries.all(|e| e.is_ok()));
+    }
+    let mut bytes = Vec::new();
+    let w = writer::Writer::new();
+    let mut out = w.begin_ply(&mut bytes, &ply.header).unwrap();
+    for (name, entries) in &ply.payload {
+        for entry in entries {
+            out.write_element(name, entry).unwrap();
+        }
+    }
+    out.finish().unwrap();
+    assert!(p.read_ply(&mut bytes.as_slice()).unwrap().header.obj_infos.is_empty());
+}
+#[test]
+fn read_structured_errors() {
+    let p = parser::Parser::<ply::DefaultElement>::new();
+    let err = |txt: &str| p.read_ply(&mut txt.as_bytes()).unwrap_err();
+    let e = err("ply\nelement vertex 1\nproperty int x\nend_header\n1\n");
+    assert_eq!(ply::Error::from_io(&e), Some(&ply::Error::MissingFormat));
+    let e = err("ply\nformat ascii 1.0\nelement vertex 1\nproperti int x\nend_header\n1\n");
+    match ply::Error::from_io(&e) {
+        Some(&ply::Error::Parse { line, ref found, .. }) => {
+            assert_eq!(line, 4);
+            assert_eq!(found, "properti int x\n");
+        },
+        other => panic!("unexpected {:?}", other),
+    }
+    for txt in &["ply\nformat ascii 1.0\nelement vertex 3\nproperty int x\nend_header\n1\n2\n",
+                 "ply\nformat binary_little_endian 1.0\nelement vertex 3\nproperty int x\nend_header\n\x01\0\0\0\x02\0\0\0\x03"] {
+        let e = err(txt);
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert_eq!(ply::Error::from_io(&e), Some(&ply::Error::UnexpectedEof { element: "vertex".to_string(), index: 2 }));
+        let e = parser::ColumnarParser::new().read_columnar(&mut txt.as_bytes()).unwrap_err();
+        assert_eq!(ply::Error::from_io(&e), Some(&ply::Error::UnexpectedEof { element: "vertex".to_string(), index: 2 }));
+    }
+    let e = p.parse_element_line("comment x").unwrap_err();
+    assert_eq!(ply::Error::from_io(&e), Some(&ply::Error::UnexpectedLine { expected: "element".to_string(), found: "comment x".to_string() }));
+    let mut p = parser::Parser::<ply::DefaultElement>::new();
+    p.set_max_list_len(3);
+    let txt = "ply\nformat binary_little_endian 1.0\nelement face 1\nproperty list uchar int vertex_index\nend_header\n\x04";
+    let e = p.read_ply(&mut txt.as_bytes()).unwrap_err();
+    let cause = ply::Error::ListTooLong { property: "vertex_index".to_string(), len: 4, max: 3 };
+    assert_eq!(ply::Error::from_io(&e), Some(&ply::Error::InvalidEntry { element: "face".to_string(), index: 0, cause: Box::new(cause) }));
+}
+#[test]
+fn read_columnar() {
+    for path in &["example_plys/house_2_ok_ascii.ply", "example_plys/house_2_ok_little_endian.ply"] {
+        let expected = read_file(path);
+        let p = parser::ColumnarParser::new();
+        let mut f = std::fs::File::open(path).unwrap();
+        let (header, payload) = p.read_columnar(&mut f).unwrap();
+        assert_eq!(header, expected.header);
+        for (name, element_def) in &header.elements {
+            for (property_name, property_def) in &element_def.properties {
+                let mut column = ply::Column::new(&property_def.data_type);
+                for entry in &expected.payload[name] {
+                    column.push(entry[property_name].clone()).unwrap();
+                }
+                assert_eq!(payload[name][property_name], column);
+            }
+        }
+    }
+}
 
 mod struct_test_1 {
     use super::ply;
@@ -181,3 +499,34 @@ mod struct_test_1 {
         }
     }
 }
+
+#[test]
+fn read_into_struct_by_index() {
+    #[derive(Debug, PartialEq)]
+    struct Vertex([f32; 3]);
+    impl ply::PropertyAccess for Vertex {
+        fn new() -> Self {
+            Vertex([0.0; 3])
+        }
+        fn set_property(&mut self, key: String, _property: ply::Property) {
+            panic!("Vertex: decoding by name for key {}", key);
+        }
+        fn set_by_index(&mut self, index: usize, _key: &String, property: ply::Property) {
+            match property {
+                ply::Property::Float(v) => self.0[index] = v,
+                p => panic!("Vertex: unexpected value {:?}", p),
+            }
+        }
+    }
+    let path = "example_plys/house_2_ok_little_endian.ply";
+    let mut f = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+    let p = parser::Parser::<Vertex>::new();
+    let header = p.read_header(&mut f).unwrap();
+    let vertices = p.read_payload_for_element(&mut f, &header.elements["vertex"], &header).unwrap();
+    let ply = read_file(path);
+    assert_eq!(vertices.len(), 5);
+    for (v, e) in vertices.iter().zip(ply.payload["vertex"].iter()) {
+        assert_eq!(ply::Property::Float(v.0[0]), e["x"]);
+        assert_eq!(ply::Property::Float(v.0[2]), e["z"]);
+    }
+}
@@ -2,6 +2,7 @@ extern crate ply_rs;
 use ply_rs::*;
 use ply_rs::ply::*;
 use std::io::{ Read, BufReader };
+use std::sync::{ Arc, Mutex };
 
 type Ply = ply::Ply<ply::DefaultElement>;
 
@@ -22,7 +23,7 @@ fn write_buff(ply: &Ply) -> Vec<u8> {
 fn read_write_ply(ply: &Ply) -> Ply {
     println!("writing ply:\n{:?}", ply);
     let ve : Vec<u8> = write_buff(&ply);
-    let txt = String::from_utf8(ve.clone()).unwrap();
+    let txt = String::from_utf8_lossy(&ve);
     println!("written ply:\n{}", txt);
     let mut buff = BufReader::new(&(*ve));
     let new_ply = read_buff(&mut buff);
@@ -104,6 +105,35 @@ fn create_list_elements() -> Ply {
     assert!(ply.make_consistent().is_ok());
     ply
 }
+fn create_double_elements(encoding: Encoding) -> Ply {
+    let mut ply = Ply::new();
+    ply.header.encoding = encoding;
+
+    let mut e = ElementDef::new("vertex".to_string());
+    let p = PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Double));
+    e.properties.add(p);
+    let p = PropertyDef::new("y".to_string(), PropertyType::Scalar(ScalarType::Double));
+    e.properties.add(p);
+    let p = PropertyDef::new("z".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::Double));
+    e.properties.add(p);
+
+    let mut list = Vec::new();
+    let mut pe = KeyMap::new();
+    pe.insert("x".to_string(), Property::Double(0.1 + 0.2));
+    pe.insert("y".to_string(), Property::Double(6378137.123456789));
+    pe.insert("z".to_string(), Property::ListDouble(vec![1e-300, -2.5e300, std::f64::consts::PI]));
+    list.push(pe);
+    let mut pe = KeyMap::new();
+    pe.insert("x".to_string(), Property::Double(-0.000000123456789012345));
+    pe.insert("y".to_string(), Property::Double(5000000.000000001));
+    pe.insert("z".to_string(), Property::ListDouble(vec![std::f64::consts::E]));
+    list.push(pe);
+    ply.payload.insert("vertex".to_string(), list);
+
+    ply.header.elements.add(e);
+    assert!(ply.make_consistent().is_ok());
+    ply
+}
 
 #[test]
 fn write_header_min() {
@@ -129,3 +159,358 @@ fn write_list_elements() {
     let new_ply = read_write_ply(&ply);
     assert_eq!(ply, new_ply);
 }
+#[test]
+fn write_double_elements_ascii() {
+    let ply = create_double_elements(Encoding::Ascii);
+    let new_ply = read_write_ply(&ply);
+    assert_eq!(ply, new_ply);
+}
+#[test]
+fn write_double_elements_big_endian() {
+    let ply = create_double_elements(Encoding::BinaryBigEndian);
+    let new_ply = read_write_ply(&ply);
+    assert_eq!(ply, new_ply);
+}
+#[test]
+fn write_double_elements_little_endian() {
+    let ply = create_double_elements(Encoding::BinaryLittleEndian);
+    let new_ply = read_write_ply(&ply);
+    assert_eq!(ply, new_ply);
+}
+#[test]
+fn write_list_len_overflow_err() {
+    let mut ply = Ply::new();
+    ply.header.encoding = Encoding::BinaryLittleEndian;
+    let mut e = ElementDef::new("face".to_string());
+    e.properties.add(PropertyDef::new("vertex_indices".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::UInt)));
+    ply.header.elements.add(e);
+    let mut pe = KeyMap::new();
+    pe.insert("vertex_indices".to_string(), Property::ListUInt((0..300).collect()));
+    ply.payload.insert("face".to_string(), vec![pe]);
+    let w = writer::Writer::new();
+    let err = w.write_ply(&mut Vec::<u8>::new(), &mut ply).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    if let Property::ListUInt(ref mut l) = ply.payload.get_mut("face").unwrap()[0]["vertex_indices"] {
+        l.truncate(255);
+    }
+    assert!(w.write_ply(&mut Vec::<u8>::new(), &mut ply).is_ok());
+}
+#[test]
+fn write_type_mismatch_err() {
+    let w = writer::Writer::new();
+    for encoding in &[Encoding::Ascii, Encoding::BinaryLittleEndian] {
+        let mut ply = Ply::new();
+        ply.header.encoding = *encoding;
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        e.count = 1;
+        ply.header.elements.add(e);
+        let mut pe = KeyMap::new();
+        pe.insert("x".to_string(), Property::ListInt(vec![1]));
+        ply.payload.insert("vertex".to_string(), vec![pe]);
+        let err = w.write_ply_unchecked(&mut Vec::<u8>::new(), &ply).unwrap_err();
+        assert_eq!(ply::Error::from_io(&err), Some(&ply::Error::TypeMismatch {
+            property: "x".to_string(),
+            expected: PropertyType::Scalar(ScalarType::Float),
+            found: Some("list of int".to_string()),
+        }));
+    }
+}
+#[test]
+fn estimate_size_matches_written() {
+    let w = writer::Writer::new();
+    for encoding in &[Encoding::Ascii, Encoding::BinaryBigEndian, Encoding::BinaryLittleEndian] {
+        let mut ply = create_list_elements();
+        ply.header.encoding = *encoding;
+        let written = write_buff(&ply).len() as u64;
+        assert_eq!(w.estimate_size(&ply).unwrap(), written);
+
+        let ply = create_double_elements(*encoding);
+        let written = write_buff(&ply).len() as u64;
+        assert_eq!(w.estimate_size(&ply).unwrap(), written);
+    }
+}
+#[test]
+fn estimate_size_undeclared_err() {
+    let w = writer::Writer::new();
+    let mut ply = create_list_elements();
+    ply.payload.insert("undeclared".to_string(), vec![KeyMap::new()]);
+    assert!(w.estimate_size(&ply).is_err());
+    ply.payload.remove("undeclared");
+    ply.payload.remove("aList");
+    assert!(w.estimate_size(&ply).is_err());
+}
+#[test]
+fn estimate_size_counts_crcs() {
+    let mut w = writer::Writer::new();
+    let mut ply = create_list_elements();
+    ply.header.obj_infos.push("crc32 vertex 00000000".to_string());
+    let mut written = Vec::<u8>::new();
+    w.write_ply(&mut written, &mut ply).unwrap();
+    assert_eq!(w.estimate_size(&ply).unwrap(), written.len() as u64);
+
+    w.set_element_crcs(true);
+    let mut written = Vec::<u8>::new();
+    w.write_ply(&mut written, &mut ply).unwrap();
+    assert_eq!(w.estimate_size(&ply).unwrap(), written.len() as u64);
+}
+#[test]
+fn progress_callback_reports_and_cancels() {
+    let ply = create_single_elements();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let mut w = writer::Writer::new();
+    let s = seen.clone();
+    w.set_progress_callback(1, 0, move |p| { s.lock().unwrap().push(*p); true });
+    let written = w.write_ply_unchecked(&mut Vec::new(), &ply).unwrap();
+    {
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[1].elements, 2);
+        assert_eq!(seen[1].bytes, written as u64);
+    }
+    assert_eq!(w.estimate_size(&ply).unwrap(), written as u64);
+    assert_eq!(seen.lock().unwrap().len(), 2);
+
+    w.set_progress_callback(1, 0, |p| p.elements < 1);
+    assert!(w.write_ply_unchecked(&mut Vec::new(), &ply).is_err());
+}
+#[test]
+fn write_payload_from_channel() {
+    let ply = create_single_elements();
+    let expected = write_buff(&ply);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let producer = std::thread::spawn(move || {
+        for (x, y) in vec![(-7, 5), (2, 4)] {
+            let mut pe = KeyMap::new();
+            pe.insert("x".to_string(), Property::Int(x));
+            pe.insert("y".to_string(), Property::UInt(y));
+            tx.send(pe).unwrap();
+        }
+    });
+
+    let w = writer::Writer::new();
+    let mut buf = Vec::<u8>::new();
+    w.write_header(&mut buf, &ply.header).unwrap();
+    w.write_payload_of_element_iter(&mut buf, rx, &ply.header.elements["point"], &ply.header).unwrap();
+    producer.join().unwrap();
+    assert_eq!(buf, expected);
+
+    let too_few = vec![ply.payload["point"][0].clone()];
+    assert!(w.write_payload_of_element_iter(&mut Vec::new(), &too_few, &ply.header.elements["point"], &ply.header).is_err());
+}
+#[test]
+fn write_conventional_property_order() {
+    let mut ply = Ply::new();
+    let mut e = ElementDef::new("vertex".to_string());
+    for n in &["red", "z", "y", "x"] {
+        e.properties.add(PropertyDef::new(n.to_string(), PropertyType::Scalar(ScalarType::UChar)));
+    }
+    ply.header.elements.add(e);
+    let mut pe = KeyMap::new();
+    for (n, v) in vec![("red", 4), ("z", 3), ("y", 2), ("x", 1)] {
+        pe.insert(n.to_string(), Property::UChar(v));
+    }
+    ply.payload.insert("vertex".to_string(), vec![pe]);
+
+    let mut w = writer::Writer::new();
+    w.set_property_order(&CONVENTIONAL_PROPERTY_ORDER);
+    let mut buf = Vec::<u8>::new();
+    w.write_ply(&mut buf, &mut ply).unwrap();
+    let txt = String::from_utf8(buf).unwrap();
+    assert!(txt.contains("property uchar x\nproperty uchar y\nproperty uchar z\nproperty uchar red\n"));
+    assert!(txt.contains("end_header\n1 2 3 4"));
+}
+#[test]
+fn write_serialized_elements() {
+    #[repr(C)]
+    struct Vertex { x: f32, y: f32 }
+    impl writer::SerializeElement for Vertex {
+        fn serialize_element(&self, _def: &ElementDef, encoding: &Encoding, out: &mut Vec<u8>) -> std::io::Result<()> {
+            for v in &[self.x, self.y] {
+                match *encoding {
+                    Encoding::Ascii => out.extend_from_slice(format!("{} ", v).as_bytes()),
+                    Encoding::BinaryBigEndian => out.extend_from_slice(&v.to_bits().to_be_bytes()),
+                    Encoding::BinaryLittleEndian => out.extend_from_slice(&v.to_bits().to_le_bytes()),
+                }
+            }
+            Ok(())
+        }
+    }
+    let vertices = vec![Vertex { x: 1.0, y: -2.5 }, Vertex { x: 0.0, y: 3.25 }];
+    let w = writer::Writer::<DefaultElement>::new();
+    for encoding in &[Encoding::Ascii, Encoding::BinaryBigEndian, Encoding::BinaryLittleEndian] {
+        let mut ply = Ply::new();
+        ply.header.encoding = *encoding;
+        let mut e = ElementDef::new("vertex".to_string());
+        e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        e.properties.add(PropertyDef::new("y".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        e.count = vertices.len() as u64;
+        ply.header.elements.add(e);
+
+        let mut buf = Vec::<u8>::new();
+        w.write_header(&mut buf, &ply.header).unwrap();
+        w.write_payload_of_serialized(&mut buf, &vertices, &ply.header.elements["vertex"], &ply.header).unwrap();
+        let read = read_buff(&mut BufReader::new(&(*buf)));
+        assert_eq!(read.payload["vertex"][1]["y"], Property::Float(3.25));
+        assert_eq!(read.payload["vertex"][0]["y"], Property::Float(-2.5));
+
+        // the header now declares a third property, which the records lack
+        ply.header.elements["vertex"].properties.add(PropertyDef::new("z".to_string(), PropertyType::Scalar(ScalarType::Float)));
+        let res = w.write_payload_of_serialized(&mut Vec::new(), &vertices, &ply.header.elements["vertex"], &ply.header);
+        assert_eq!(res.is_err(), *encoding != Encoding::Ascii);
+    }
+}
+#[test]
+fn header_line_to_string() {
+    let p = parser::Parser::<DefaultElement>::new();
+    let w = writer::Writer::<DefaultElement>::new();
+    for l in &["ply", "format binary_little_endian 1.0", "comment made by hand", "obj_info scanner 42",
+               "element vertex 8", "property float x", "property list uchar int vertex_indices", "end_header"] {
+        let line = p.read_header_line(l).unwrap();
+        assert_eq!(&w.line_to_string(&line).unwrap(), l);
+    }
+}
+#[test]
+fn write_partial_with_framing() {
+    let mut ply = create_list_elements();
+    let w = writer::Writer::new();
+    let mut buf = Vec::<u8>::new();
+    w.write_header(&mut buf, &ply.header).unwrap();
+    let header_len = buf.len();
+    for (name, element_def) in &ply.header.elements {
+        // own framing between the blocks, stripped again below
+        buf.extend_from_slice(b"#");
+        w.write_payload_for_element(&mut buf, &ply.payload[name], element_def, &ply.header).unwrap();
+    }
+    let stripped: Vec<u8> = buf[..header_len].iter().chain(buf[header_len..].iter().filter(|&&b| b != b'#')).cloned().collect();
+    assert_eq!(stripped, write_buff(&ply));
+
+    let name = ply.header.elements.keys().next().unwrap().clone();
+    let too_few = &ply.payload[&name][..1];
+    assert!(w.write_payload_for_element(&mut Vec::new(), too_few, &ply.header.elements[&name], &ply.header).is_err());
+    ply.payload.insert("undeclared".to_string(), Vec::new());
+    assert!(w.write_payload(&mut Vec::new(), &ply.payload, &ply.header).is_err());
+    ply.header.comments.push("line\nbreak".to_string());
+    assert!(w.write_header(&mut Vec::new(), &ply.header).is_err());
+}
+#[test]
+fn write_buffered() {
+    let ply = create_list_elements();
+    let mut w = writer::Writer::new();
+    w.set_buffer_size(parser::BufferSize::Adaptive);
+    let mut buf = Vec::<u8>::new();
+    let written = w.write_ply_unchecked(&mut buf, &ply).unwrap();
+    assert_eq!(written, buf.len());
+    assert_eq!(buf, write_buff(&ply));
+}
+#[test]
+fn write_reduced_precision() {
+    let mut ply = Ply::new();
+    let mut e = ElementDef::new("point".to_string());
+    e.properties.add(PropertyDef::new("x".to_string(), PropertyType::Scalar(ScalarType::Double)));
+    e.properties.add(PropertyDef::new("w".to_string(), PropertyType::List(ScalarType::UChar, ScalarType::Double)));
+    ply.header.elements.add(e);
+    let mut point = DefaultElement::new();
+    point.insert("x".to_string(), Property::Double(1234.56789));
+    point.insert("w".to_string(), Property::ListDouble(vec![0.333333333, 2.0]));
+    ply.payload.insert("point".to_string(), vec![point]);
+
+    let mut w = writer::Writer::new();
+    w.set_precision("point", "x", writer::Precision { downcast: true, significant_digits: Some(5) });
+    w.set_precision("point", "w", writer::Precision { downcast: false, significant_digits: Some(2) });
+    let mut buf = Vec::<u8>::new();
+    w.write_ply(&mut buf, &mut ply).unwrap();
+    assert_eq!(ply.header.elements["point"].properties["x"].data_type, PropertyType::Scalar(ScalarType::Float));
+    assert!(String::from_utf8_lossy(&buf).ends_with("end_header\n1234.6 2 0.33 2 \n"));
+
+    ply.header.encoding = Encoding::BinaryLittleEndian;
+    let mut buf = Vec::<u8>::new();
+    w.write_ply(&mut buf, &mut ply).unwrap();
+    let read = read_buff(&mut &buf[..]);
+    assert_eq!(read.payload["point"][0]["x"], Property::Float(1234.6));
+    assert_eq!(read.payload["point"][0]["w"], Property::ListDouble(vec![0.33, 2.0]));
+}
+#[test]
+fn write_file_atomically() {
+    let path = std::env::temp_dir().join("ply_rs_write_file_atomically.ply");
+    let mut ply = create_list_elements();
+    let w = writer::Writer::new();
+    let written = w.write_ply_file(&path, &mut ply).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap().len(), written);
+
+    let mut w = writer::Writer::new();
+    w.set_progress_callback(1, 0, |_| false);
+    assert!(w.write_ply_file(&path, &mut ply).is_err());
+    assert_eq!(std::fs::read(&path).unwrap(), write_buff(&ply));
+    let leftovers = std::fs::read_dir(std::env::temp_dir()).unwrap()
+        .filter(|e| e.as_ref().unwrap().file_name().to_string_lossy().starts_with(".ply_rs_write_file_atomically.ply."))
+        .count();
+    assert_eq!(leftovers, 0);
+    w.set_atomic_file_writes(false);
+    assert!(w.write_ply_file(&path, &mut ply).is_err());
+    assert!(!path.exists());
+    // A target that can't be created must not be removed.
+    let dir = std::env::temp_dir().join("ply_rs_write_file_dir.ply");
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(w.write_ply_file(&dir, &mut ply).is_err());
+    assert!(dir.is_dir());
+    std::fs::remove_dir(&dir).unwrap();
+}
+#[test]
+fn write_streamed() {
+    let mut ply = read_buff(&mut std::fs::File::open("example_plys/house_2_ok_ascii.ply").unwrap());
+    for encoding in &[Encoding::Ascii, Encoding::BinaryLittleEndian, Encoding::BinaryBigEndian] {
+        ply.header.encoding = *encoding;
+        let w = writer::Writer::new();
+        let mut out = w.begin_ply(Vec::<u8>::new(), &ply.header).unwrap();
+        for (name, entries) in &ply.payload {
+            for e in entries {
+                out.write_element(name, e).unwrap();
+            }
+        }
+        let written = out.bytes_written();
+        let buf = out.finish().unwrap();
+        assert_eq!(written, buf.len());
+        assert_eq!(buf, write_buff(&ply));
+    }
+    let w = writer::Writer::new();
+    let mut out = w.begin_ply(Vec::<u8>::new(), &ply.header).unwrap();
+    assert!(out.write_element("face", &ply.payload["face"][0]).is_err());
+    out.write_element("vertex", &ply.payload["vertex"][0]).unwrap();
+    assert!(out.finish().is_err());
+}
+
+struct TypedVertex { x: f32, red: u8 }
+impl_to_element_def!(TypedVertex { x: f32, red: u8 });
+impl PropertyAccess for TypedVertex {
+    fn new() -> Self { TypedVertex { x: 0.0, red: 0 } }
+    fn get_float(&self, key: &String) -> Option<f32> { if key == "x" { Some(self.x) } else { None } }
+    fn get_uchar(&self, key: &String) -> Option<u8> { if key == "red" { Some(self.red) } else { None } }
+}
+struct TypedFace { vertex_indices: Vec<u32> }
+impl_to_element_def!(TypedFace { vertex_indices: Vec<u32> });
+impl PropertyAccess for TypedFace {
+    fn new() -> Self { TypedFace { vertex_indices: Vec::new() } }
+    fn get_list_uint(&self, key: &String) -> Option<&[u32]> {
+        if key == "vertex_indices" { Some(&self.vertex_indices) } else { None }
+    }
+}
+#[test]
+fn write_typed_elements() {
+    let vertices = vec![TypedVertex { x: 0.5, red: 1 }, TypedVertex { x: 1.5, red: 2 }, TypedVertex { x: 2.5, red: 3 }];
+    let faces = vec![TypedFace { vertex_indices: vec![0, 1, 2] }];
+    for encoding in &[Encoding::Ascii, Encoding::BinaryLittleEndian, Encoding::BinaryBigEndian] {
+        let mut buf = Vec::<u8>::new();
+        let written = writer::write_typed(&mut buf, *encoding, &[("vertex", &vertices), ("face", &faces)]).unwrap();
+        assert_eq!(written, buf.len());
+        let ply = read_buff(&mut buf.as_slice());
+        assert_eq!(ply.header.encoding, *encoding);
+        assert_eq!(ply.header.elements["vertex"].count, 3);
+        assert_eq!(ply.payload["vertex"][1]["x"], Property::Float(1.5));
+        assert_eq!(ply.payload["vertex"][2]["red"], Property::UChar(3));
+        assert_eq!(ply.payload["face"][0]["vertex_indices"], Property::ListUInt(vec![0, 1, 2]));
+    }
+    let mut buf = Vec::<u8>::new();
+    assert!(writer::write_typed(&mut buf, Encoding::Ascii, &[("vertex", &vertices), ("vertex", &vertices)]).is_err());
+}